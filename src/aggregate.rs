@@ -0,0 +1,706 @@
+use std::collections::HashMap;
+use std::str::Utf8Error;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use chrono::{DateTime, FixedOffset};
+use serde::{Deserialize, Serialize};
+
+use crate::bounds::CounterBounds;
+use crate::closes::CloseRule;
+use crate::countmin::CountMin;
+use crate::direction::{DirectionStats, Normalizer};
+use crate::distinct::DistinctCounts;
+use crate::hourly::{HourBucket, HourlySeries};
+use crate::ignore::{FlowPattern, Ignored};
+use crate::intern::Interner;
+use crate::lines::{self, DEFAULT_MAX_LINE, Encoding, Line};
+use crate::parser::{EventKind, FlowEvent, LineParser, SkipCounts, SkipReason};
+use crate::record::{FlowKey, KeySpec, NatSide, Record, flow_key};
+use crate::sample::{self, Rng};
+use crate::session::{CorrelationStats, SessionCorrelator};
+use crate::shard::Shards;
+use crate::telemetry::StageTimes;
+use crate::throttle::Throttle;
+use crate::topn::SlidingTopN;
+
+/// Everything a run aggregated, ready to be written out.
+pub struct Aggregated {
+    pub records: HashMap<Arc<str>, Record>,
+    /// Present when the input had open/close events
+    pub session_correlation: Option<CorrelationStats>,
+    /// Empty when the input carried no timestamps
+    pub hourly_series: Vec<HourBucket>,
+    /// Earliest and latest event timestamps seen
+    pub time_range: Option<(DateTime<FixedOffset>, DateTime<FixedOffset>)>,
+    pub distinct: Option<DistinctCounts>,
+    /// Set once the flow cap was reached
+    pub approximation: Option<Approximation>,
+    /// Set when counters went over the sanity limit
+    pub counter_bounds: Option<CounterBounds>,
+    /// Set when events were put in client-to-server orientation
+    pub direction: Option<DirectionStats>,
+    /// The flows set aside by `ignore`, when there are patterns
+    pub ignored: Option<Ignored>,
+}
+
+/// What changed in the records since a consumer last asked.
+#[derive(Default)]
+pub struct Changes {
+    /// Records as they stand now
+    pub records: Vec<Record>,
+    /// Keys of the flows evicted by `--max-flows`, which have no record left
+    pub removed: Vec<Arc<str>>,
+}
+
+/// How a run degraded after reaching `--max-flows`: the busiest flows stay
+/// exact, everything else is only counted in totals and a count-min sketch.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Approximation {
+    pub mode: String,
+    pub max_flows: usize,
+    pub sketch_width: usize,
+    pub sketch_depth: usize,
+    /// Events of flows without an exact record
+    pub overflow_events: u64,
+    pub overflow_bytes: u64,
+    pub overflow_packets: u64,
+    /// Exact records dropped to make room for busier flows
+    pub evicted_flows: u64,
+}
+
+/// Overflow events between rescans for the smallest exact record
+const FLOOR_REFRESH: u64 = 1024;
+
+struct Overflow {
+    sketch: CountMin,
+    stats: Approximation,
+    /// Bytes of the smallest exact record as of the last scan
+    floor: u64,
+    since_scan: u64,
+}
+
+/// Turns raw lines from any input source into per-flow records.
+pub struct Aggregator {
+    parser: LineParser,
+    key_spec: KeySpec,
+    correlator: SessionCorrelator,
+    hourly: HourlySeries,
+    time_range: Option<(DateTime<FixedOffset>, DateTime<FixedOffset>)>,
+    strings: Interner,
+    live: Live,
+    /// Finished records carried over from a state store, journal or seed
+    /// output; live records are merged into them when the run finishes
+    settled: HashMap<Arc<str>, Record>,
+    /// Records of flows matching an `ignore` pattern, kept apart from
+    /// `settled` so that nothing written from it holds them
+    ignored: HashMap<Arc<str>, Record>,
+    /// `--ignore` patterns
+    pub ignore: Vec<FlowPattern>,
+    /// Events added to the live records so far, numbering them
+    sequence: u64,
+    /// Sequence number up to which each consumer of [`Aggregator::changes`]
+    /// has seen them
+    cursors: Vec<u64>,
+    pub connections: u64,
+    pub session_close: u64,
+    /// Lines the parser could not turn into an event, by reason
+    pub skipped: SkipCounts,
+    /// Longest line readers hand over; longer ones are skipped unread
+    pub max_line_length: usize,
+    /// How lines that aren't valid UTF-8 are decoded
+    pub encoding: Encoding,
+    /// Characters replaced (or decoded as latin-1) so far
+    pub replaced_characters: u64,
+    /// Read budget shared by every reader of the run, with `--throttle`
+    pub throttle: Option<Throttle>,
+    /// Logical device names by logged firewall address, so both members of
+    /// an HA pair aggregate as one device
+    pub device_aliases: HashMap<String, String>,
+    /// Sanity limit on counters and how many went over it, unless disabled
+    pub counter_bounds: Option<CounterBounds>,
+    /// Which events count towards `session_close`
+    pub close_rule: CloseRule,
+    /// Put events in client-to-server orientation before keying them
+    pub direction: Option<Normalizer>,
+    /// Count each record's sessions per input they were read from
+    pub record_sources: bool,
+    /// The input being read, when sources are recorded
+    source: Option<Arc<str>>,
+    /// Per-stage timing, only collected when `timed` is set
+    pub timed: bool,
+    pub stage_times: StageTimes,
+    /// Sliding top talkers, fed as events arrive when `serve` exposes them
+    pub talkers: Option<Arc<Mutex<SlidingTopN>>>,
+    /// Per-host distinct ports and peers, when requested
+    pub distinct: Option<DistinctCounts>,
+    /// Keep per-session t-digests per record
+    pub percentiles: bool,
+    /// Keep session timestamps per record for beacon detection
+    pub beacons: bool,
+    /// Raw lines sampled per record (0 for none)
+    pub sample_lines: usize,
+    /// Most exact flow records kept, and the sketch's (width, depth) past that
+    pub max_flows: Option<(usize, usize, usize)>,
+}
+
+/// Where the live records are kept.
+enum Live {
+    Local(Flows),
+    Sharded(Shards),
+}
+
+/// What each live record keeps besides its totals.
+#[derive(Clone, Copy)]
+pub(crate) struct FlowOptions {
+    pub percentiles: bool,
+    pub beacons: bool,
+    pub sample_lines: usize,
+    pub max_flows: Option<(usize, usize, usize)>,
+    /// Side of address translation the key, and so the record, is built from
+    pub nat: NatSide,
+    /// Remember evicted flows until every consumer of changes has seen them
+    pub track_changes: bool,
+}
+
+/// Live records by flow key, with the overflow sketch once the flow cap
+/// is reached: the aggregator's own, or one per shard worker.
+pub(crate) struct Flows {
+    records: HashMap<FlowKey, Record>,
+    /// Sequence number of the event each flow was evicted by, when changes
+    /// are tracked, until every consumer has seen it
+    evicted: HashMap<FlowKey, u64>,
+    rng: Rng,
+    overflow: Option<Overflow>,
+}
+
+impl Aggregator {
+    pub fn new(parser: LineParser, key_spec: KeySpec, session_timeout: u64) -> Self {
+        Aggregator {
+            parser,
+            key_spec,
+            correlator: SessionCorrelator::new(session_timeout),
+            hourly: HourlySeries::default(),
+            time_range: None,
+            strings: Interner::default(),
+            live: Live::Local(Flows::new()),
+            settled: HashMap::new(),
+            ignored: HashMap::new(),
+            ignore: Vec::new(),
+            sequence: 0,
+            cursors: Vec::new(),
+            connections: 0,
+            session_close: 0,
+            skipped: SkipCounts::default(),
+            max_line_length: DEFAULT_MAX_LINE,
+            encoding: Encoding::default(),
+            replaced_characters: 0,
+            throttle: None,
+            device_aliases: HashMap::new(),
+            counter_bounds: None,
+            close_rule: CloseRule::Any,
+            direction: None,
+            record_sources: false,
+            source: None,
+            timed: false,
+            stage_times: StageTimes::default(),
+            talkers: None,
+            distinct: None,
+            percentiles: false,
+            beacons: false,
+            sample_lines: 0,
+            max_flows: None,
+        }
+    }
+
+    /// Keep the live records on `shards` worker threads, each owning the
+    /// flow keys that hash to it, so record upkeep scales past one core.
+    /// Parsing and the run-wide statistics stay on the calling thread.
+    /// Takes the record options as set now, so call it after them and
+    /// [`Aggregator::track_changes`], and before any input.
+    pub fn shard(&mut self, shards: usize) {
+        if shards > 1 {
+            self.live = Live::Sharded(Shards::start(shards, self.flow_options()));
+        }
+    }
+
+    fn flow_options(&self) -> FlowOptions {
+        FlowOptions {
+            percentiles: self.percentiles,
+            beacons: self.beacons,
+            sample_lines: self.sample_lines,
+            max_flows: self.max_flows,
+            nat: self.key_spec.nat,
+            track_changes: !self.cursors.is_empty(),
+        }
+    }
+
+    pub fn ingest(&mut self, line: &str) {
+        // A header names the columns; it isn't a connection
+        if self.parser.header(line) {
+            return;
+        }
+        self.connections += 1;
+
+        let parse_start = self.timed.then(Instant::now);
+        let event = self.parser.parse_line(line);
+        let aggregate_start = self.timed.then(Instant::now);
+        if let (Some(parsed), Some(aggregating)) = (parse_start, aggregate_start) {
+            self.stage_times.parse += aggregating - parsed;
+        }
+
+        match event {
+            Ok(event) => self.aggregate(event, line),
+            Err(reason) => self.skipped.add(reason),
+        }
+        if let Some(start) = aggregate_start {
+            self.stage_times.aggregate += start.elapsed();
+        }
+    }
+
+    /// Name the input the following lines are read from, for
+    /// `record_sources` and the input's CSV header.
+    pub fn set_source(&mut self, source: &str) {
+        self.parser.set_input(source);
+        if self.record_sources {
+            self.source = Some(self.strings.intern(source));
+        }
+    }
+
+    /// Count a line the reader already refused, e.g. one over the length cap.
+    pub fn reject(&mut self, reason: SkipReason) {
+        self.connections += 1;
+        self.skipped.add(reason);
+    }
+
+    /// Count and ingest one line from a `LineReader`,
+    /// decoded per `encoding`; only a strict encoding fails.
+    pub fn ingest_line(&mut self, line: Line<'_>) -> Result<(), Utf8Error> {
+        match line {
+            Line::Text(line) => {
+                let (line, replaced) = lines::decode(line, self.encoding)?;
+                self.replaced_characters += replaced;
+                self.ingest(&line);
+            }
+            Line::TooLong => self.reject(SkipReason::TooLong),
+            Line::Binary => self.reject(SkipReason::Binary),
+        }
+        Ok(())
+    }
+
+    fn aggregate(&mut self, mut event: FlowEvent, line: &str) {
+        // Before correlation too, so a session can close on the other member
+        if let Some(alias) = self.device_aliases.get(&event.firewall) {
+            event.firewall.clone_from(alias);
+        }
+        if let Some(bounds) = &mut self.counter_bounds {
+            bounds.check(&mut event);
+        }
+        if let Some(direction) = &mut self.direction {
+            direction.normalize(&mut event);
+        }
+        event.source.clone_from(&self.source);
+        match event.kind {
+            EventKind::Open => {
+                self.correlator.open(&event);
+                return;
+            }
+            EventKind::Close => self.correlator.close(&mut event),
+            EventKind::Complete => {}
+        }
+
+        if self.close_rule.closes(&event) {
+            self.session_close += 1;
+        }
+
+        if let Some(ts) = event.timestamp {
+            self.time_range = Some(match self.time_range {
+                Some((first, last)) => (first.min(ts), last.max(ts)),
+                None => (ts, ts),
+            });
+        }
+
+        if let Some(talkers) = &self.talkers {
+            let source = self.strings.intern(&event.source_ip);
+            let bytes = event.bytes_in.saturating_add(event.bytes_out);
+            talkers.lock().expect("Unable to lock top talkers").add(Instant::now(), source, bytes);
+        }
+
+        if let Some(distinct) = &mut self.distinct {
+            distinct.add(&event, &mut self.strings);
+        }
+
+        let key = flow_key(&event, &self.key_spec, &mut self.strings);
+        self.hourly.add(&event, &key);
+
+        let options = self.flow_options();
+        self.sequence += 1;
+        match &mut self.live {
+            Live::Local(flows) => flows.update(key, &event, line, self.sequence, &options, &mut self.strings),
+            Live::Sharded(shards) => shards.update(key, event, line, self.sequence),
+        }
+    }
+
+    /// Start from records aggregated earlier; new events for the same flows
+    /// are added to them.
+    pub fn seed(&mut self, records: HashMap<Arc<str>, Record>) {
+        for (key, mut record) in records {
+            record.key = key;
+            self.file(record);
+        }
+    }
+
+    /// Start following the records' changes for one more consumer, such as
+    /// a journal, returning its cursor for [`Aggregator::changes`]. Call it
+    /// before [`Aggregator::shard`].
+    pub fn track_changes(&mut self) -> usize {
+        self.cursors.push(self.sequence);
+        self.cursors.len() - 1
+    }
+
+    /// The records that changed since the consumer at `cursor` last asked,
+    /// as they stand now, and the flows evicted since with nothing left of
+    /// them. Live records go on aggregating untouched, so the flow cap,
+    /// digests and samples keep covering the whole run.
+    pub fn changes(&mut self, cursor: usize) -> Changes {
+        let since = std::mem::replace(&mut self.cursors[cursor], self.sequence);
+        let live = self.live_since(since);
+        let mut changes = Changes::default();
+        for record in live.records {
+            if !self.ignores(&record) {
+                changes.records.push(current(&self.settled, record));
+            }
+        }
+        for key in live.removed {
+            match self.settled.get(&key) {
+                Some(settled) => changes.records.push(settled.clone()),
+                None => changes.removed.push(key),
+            }
+        }
+        changes
+    }
+
+    /// Every record as it stands now, settled and live together, leaving
+    /// out the ignored flows; the live records go on untouched.
+    pub fn records(&mut self) -> HashMap<Arc<str>, Record> {
+        let mut records = self.settled.clone();
+        for record in self.live_since(0).records {
+            if !self.ignores(&record) {
+                settle_into(&mut records, Arc::clone(&record.key), record);
+            }
+        }
+        records
+    }
+
+    /// Totals of the flows set aside so far, live ones included.
+    pub fn ignored(&mut self) -> Ignored {
+        let live = self.live_since(0).records.into_iter().filter(|record| self.ignores(record));
+        let mut live: HashMap<Arc<str>, Record> = live.map(|record| (Arc::clone(&record.key), record)).collect();
+        let mut ignored = Ignored::default();
+        for (key, record) in &self.ignored {
+            match live.remove(key) {
+                Some(more) => ignored.add(&current(&self.ignored, more), &self.ignore),
+                None => ignored.add(record, &self.ignore),
+            };
+        }
+        for record in live.values() {
+            ignored.add(record, &self.ignore);
+        }
+        ignored
+    }
+
+    /// Finished copies of the live records changed after `since`, and the
+    /// keys of the flows evicted after it.
+    fn live_since(&mut self, since: u64) -> Changes {
+        // Evictions every consumer has seen are no longer asked about
+        let forget = self.cursors.iter().copied().min().unwrap_or(self.sequence);
+        let (live, evicted) = match &mut self.live {
+            Live::Local(flows) => flows.since(since, forget),
+            Live::Sharded(shards) => shards.since(since, forget),
+        };
+        Changes {
+            records: live.into_iter().map(|(key, record)| keyed(key, record)).collect(),
+            removed: evicted.iter().map(|key| Arc::from(key.to_string())).collect(),
+        }
+    }
+
+    /// Finish the live records and fold them into the settled ones.
+    fn settle(&mut self) {
+        let live = match &mut self.live {
+            Live::Local(flows) => flows.drain(),
+            Live::Sharded(shards) => shards.drain(),
+        };
+        for (key, record) in live {
+            self.file(keyed(key, record));
+        }
+    }
+
+    /// Whether `record` belongs with the ignored records rather than the
+    /// settled ones. A flow stays on the side it was first filed on.
+    fn ignores(&self, record: &Record) -> bool {
+        let key = &record.key;
+        self.ignored.contains_key(key) || (!self.settled.contains_key(key) && self.ignore.iter().any(|pattern| pattern.matches(record)))
+    }
+
+    /// Fold `record` into the settled records, or into the ignored ones if
+    /// its flow matches an `ignore` pattern.
+    fn file(&mut self, record: Record) {
+        let key = Arc::clone(&record.key);
+        match self.ignores(&record) {
+            true => settle_into(&mut self.ignored, key, record),
+            false => settle_into(&mut self.settled, key, record),
+        }
+    }
+
+    pub fn finish(mut self) -> Aggregated {
+        self.settle();
+        let ignored = (!self.ignore.is_empty()).then(|| self.ignored());
+        let approximation = match self.live {
+            Live::Local(flows) => flows.approximation(),
+            Live::Sharded(shards) => shards.finish(),
+        };
+        Aggregated {
+            ignored,
+            session_correlation: self.correlator.is_active().then(|| self.correlator.finish()),
+            records: self.settled,
+            hourly_series: self.hourly.into_buckets(),
+            time_range: self.time_range,
+            distinct: self.distinct,
+            approximation,
+            counter_bounds: self.counter_bounds.filter(|bounds| bounds.values > 0),
+            direction: self.direction.map(|direction| direction.stats),
+        }
+    }
+}
+
+impl Flows {
+    pub(crate) fn new() -> Self {
+        Flows {
+            records: HashMap::new(),
+            evicted: HashMap::new(),
+            rng: Rng::from_time(),
+            overflow: None,
+        }
+    }
+
+    pub(crate) fn update(&mut self, key: FlowKey, event: &FlowEvent, line: &str, sequence: u64, options: &FlowOptions, strings: &mut Interner) {
+        match self.records.get_mut(&key) {
+            Some(rec) => {
+                rec.add(event);
+                rec.updated = sequence;
+                if options.percentiles {
+                    rec.sample(event);
+                }
+                if options.beacons {
+                    rec.arrive(event);
+                }
+                if options.sample_lines > 0 {
+                    sample::offer(&mut rec.sample_lines, options.sample_lines, rec.count, line, &mut self.rng);
+                }
+            }
+            None => match options.max_flows {
+                Some((max_flows, _, _)) if self.records.len() >= max_flows => self.overflow(key, event, line, sequence, options, strings),
+                _ => self.insert(key, event, line, sequence, options, strings),
+            },
+        }
+    }
+
+    fn insert(&mut self, key: FlowKey, event: &FlowEvent, line: &str, sequence: u64, options: &FlowOptions, strings: &mut Interner) {
+        let mut record = new_record(event, line, options, strings);
+        record.updated = sequence;
+        if !self.evicted.is_empty() {
+            self.evicted.remove(&key);
+        }
+        self.records.insert(key, record);
+    }
+
+    /// Count an event of a flow without an exact record, promoting the flow
+    /// in place of the smallest record once its sketched bytes exceed it.
+    fn overflow(&mut self, key: FlowKey, event: &FlowEvent, line: &str, sequence: u64, options: &FlowOptions, strings: &mut Interner) {
+        let (max_flows, width, depth) = options.max_flows.expect("Overflow without a flow cap");
+        let overflow = self.overflow.get_or_insert_with(|| Overflow {
+            sketch: CountMin::new(width, depth),
+            stats: Approximation {
+                mode: "count-min".to_string(),
+                max_flows,
+                sketch_width: width,
+                sketch_depth: depth,
+                overflow_events: 0,
+                overflow_bytes: 0,
+                overflow_packets: 0,
+                evicted_flows: 0,
+            },
+            floor: 0,
+            since_scan: 0,
+        });
+        let smallest = |records: &HashMap<FlowKey, Record>| {
+            records
+                .iter()
+                .min_by_key(|(_, record)| record.bytes_in.saturating_add(record.bytes_out))
+                .map(|(key, record)| (key.clone(), record.bytes_in.saturating_add(record.bytes_out)))
+        };
+        if overflow.since_scan == 0 {
+            overflow.floor = smallest(&self.records).map_or(0, |(_, bytes)| bytes);
+        }
+        overflow.since_scan = (overflow.since_scan + 1) % FLOOR_REFRESH;
+
+        let bytes = event.bytes_in.saturating_add(event.bytes_out);
+        if overflow.sketch.estimate(&key).saturating_add(bytes) <= overflow.floor {
+            overflow.sketch.add(&key, bytes);
+            overflow.stats.overflow_events += 1;
+            overflow.stats.overflow_bytes = overflow.stats.overflow_bytes.saturating_add(bytes);
+            overflow.stats.overflow_packets = overflow.stats.overflow_packets.saturating_add(event.packets_in.saturating_add(event.packets_out));
+            return;
+        }
+
+        // The flow's earlier events stay in the sketch and overflow totals
+        if let Some((evicted_key, _)) = smallest(&self.records)
+            && let Some(evicted) = self.records.remove(&evicted_key)
+        {
+            let evicted_bytes = evicted.bytes_in.saturating_add(evicted.bytes_out);
+            overflow.sketch.add(&evicted_key, evicted_bytes);
+            overflow.stats.overflow_events += evicted.count;
+            overflow.stats.overflow_bytes = overflow.stats.overflow_bytes.saturating_add(evicted_bytes);
+            overflow.stats.overflow_packets = overflow.stats.overflow_packets.saturating_add(evicted.packets_in.saturating_add(evicted.packets_out));
+            overflow.stats.evicted_flows += 1;
+            if options.track_changes {
+                self.evicted.insert(evicted_key, sequence);
+            }
+        }
+        overflow.since_scan = 0;
+        self.insert(key, event, line, sequence, options, strings);
+    }
+
+    /// Copies of the records changed after `since`, and the flows evicted
+    /// after it; evictions up to `forget` are dropped.
+    pub(crate) fn since(&mut self, since: u64, forget: u64) -> (Vec<(FlowKey, Record)>, Vec<FlowKey>) {
+        let records = self.records.iter().filter(|(_, record)| record.updated > since).map(|(key, record)| (key.clone(), record.clone())).collect();
+        let evicted = self.evicted.iter().filter(|(_, at)| **at > since).map(|(key, _)| key.clone()).collect();
+        self.evicted.retain(|_, at| *at > forget);
+        (records, evicted)
+    }
+
+    pub(crate) fn drain(&mut self) -> Vec<(FlowKey, Record)> {
+        self.records.drain().collect()
+    }
+
+    pub(crate) fn approximation(self) -> Option<Approximation> {
+        self.overflow.map(|overflow| overflow.stats)
+    }
+}
+
+fn new_record(event: &FlowEvent, line: &str, options: &FlowOptions, strings: &mut Interner) -> Record {
+    let mut record = Record::new(event, options.nat, strings);
+    if options.percentiles {
+        record.sample(event);
+    }
+    if options.beacons {
+        record.arrive(event);
+    }
+    if options.sample_lines > 0 {
+        record.sample_lines.push(line.to_string());
+    }
+    record
+}
+
+/// A live record keyed by the string form of its flow key, with its
+/// digests finished.
+fn keyed(key: FlowKey, mut record: Record) -> Record {
+    record.key = Arc::from(key.to_string());
+    record.finish_digests();
+    record
+}
+
+/// `record` added to its flow's record in `settled`, if it has one.
+fn current(settled: &HashMap<Arc<str>, Record>, record: Record) -> Record {
+    match settled.get(&record.key) {
+        Some(existing) => {
+            let mut current = existing.clone();
+            current.merge(record);
+            current
+        }
+        None => record,
+    }
+}
+
+fn settle_into(settled: &mut HashMap<Arc<str>, Record>, key: Arc<str>, record: Record) {
+    match settled.get_mut(&key) {
+        Some(existing) => existing.merge(record),
+        None => {
+            settled.insert(key, record);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::{InputFormat, ParserOptions};
+
+    fn aggregator() -> Aggregator {
+        let options = ParserOptions { pattern: None, kv_aliases: &[], min_fields: 0 };
+        Aggregator::new(LineParser::new(InputFormat::Csv, &options).unwrap(), KeySpec::default(), 60)
+    }
+
+    /// A CSV line of flow `flow` from 10.0.0.`source`, with `bytes` in.
+    fn line(source: u64, flow: u64, bytes: u64) -> String {
+        format!("2025-08-29T11:38:0{flow}+00:00,192.168.29.191,,10.0.0.{source},8.8.8.{flow},443,6,,,1,{bytes},1,0")
+    }
+
+    #[test]
+    fn reading_the_records_mid_run_keeps_the_flow_cap() {
+        for shards in [1, 2] {
+            let mut aggregator = aggregator();
+            aggregator.max_flows = Some((2, 64, 4));
+            aggregator.shard(shards);
+            for file in 1..=3 {
+                for flow in 1..=4 {
+                    aggregator.ingest(&line(file, flow, flow * 100 * file));
+                }
+                // As a partial output is written after each file
+                assert!(aggregator.records().len() <= 2, "{} shards", shards);
+            }
+            let aggregated = aggregator.finish();
+            assert_eq!(aggregated.records.len(), 2, "{} shards", shards);
+            assert!(aggregated.approximation.is_some_and(|approximation| approximation.evicted_flows > 0));
+        }
+    }
+
+    #[test]
+    fn reading_the_records_mid_run_leaves_the_digests_whole() {
+        let mut aggregator = aggregator();
+        aggregator.percentiles = true;
+        for file in 1..=3 {
+            aggregator.ingest(&line(1, 1, file * 100));
+            let records = aggregator.records();
+            let record = records.values().next().unwrap();
+            assert_eq!(record.session_bytes_percentiles.as_ref().map(|percentiles| percentiles.max), Some(file * 100));
+        }
+        let aggregated = aggregator.finish();
+        let record = aggregated.records.values().next().unwrap();
+        assert_eq!(record.count, 3);
+        let percentiles = record.session_bytes_percentiles.as_ref().unwrap();
+        assert_eq!((percentiles.p50, percentiles.max), (200, 300));
+    }
+
+    #[test]
+    fn past_the_flow_cap_only_busier_flows_evict_the_smallest() {
+        let mut aggregator = aggregator();
+        aggregator.max_flows = Some((2, 64, 4));
+        aggregator.ingest(&line(1, 1, 100));
+        aggregator.ingest(&line(1, 2, 200));
+        // Smaller than every exact record: only sketched
+        aggregator.ingest(&line(1, 3, 50));
+        // Busier than the smallest: takes its place
+        aggregator.ingest(&line(1, 4, 1000));
+        let aggregated = aggregator.finish();
+
+        let mut destinations: Vec<_> = aggregated.records.values().map(|record| record.destination_ip.to_string()).collect();
+        destinations.sort();
+        assert_eq!(destinations, ["8.8.8.2", "8.8.8.4"]);
+        let approximation = aggregated.approximation.unwrap();
+        assert_eq!(approximation.evicted_flows, 1);
+        assert_eq!((approximation.overflow_events, approximation.overflow_bytes), (2, 150));
+    }
+}
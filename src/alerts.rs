@@ -0,0 +1,128 @@
+//! Suppression and delivery of rule alerts.
+//!
+//! For rules with `suppress-hours`, the last time each of their keys
+//! alerted is kept in a JSON state file across runs. A key that matches
+//! again within the window is held back and counted as a repeat; the next
+//! alert for that key carries the repeats, so a sustained event produces a
+//! single notification per window instead of one per run. Alerts still
+//! appear in the output metadata with their suppressed counts.
+//!
+//! When more alerts fire in one run than the digest threshold, the webhook
+//! gets them as a single digest instead of one request each.
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use crate::atomic;
+use crate::rules::{Rule, RuleAlert};
+
+/// Keys listed per alert once suppression has run
+const KEYS_LISTED: usize = 10;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+struct Entry {
+    rule: String,
+    key: String,
+    /// Seconds since the epoch the key last alerted
+    last_alerted: u64,
+    /// Matches held back since then
+    repeats: u64,
+}
+
+pub struct AlertState {
+    path: PathBuf,
+    entries: HashMap<(String, String), Entry>,
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs()
+}
+
+impl AlertState {
+    /// Read the state file; a missing or unreadable one starts empty.
+    pub fn load(path: &Path) -> Self {
+        let entries: Vec<Entry> = fs::read_to_string(path)
+            .ok()
+            .and_then(|text| serde_json::from_str(&text).ok())
+            .unwrap_or_default();
+        AlertState {
+            path: path.to_path_buf(),
+            entries: entries.into_iter().map(|entry| ((entry.rule.clone(), entry.key.clone()), entry)).collect(),
+        }
+    }
+
+    /// Hold back the keys of `alerts` that alerted within their rule's
+    /// window; the others alert and carry the repeats held back before.
+    pub fn suppress(&mut self, rules: &[Rule], alerts: &mut [RuleAlert]) {
+        let now = now();
+        let windows: HashMap<&str, u64> = rules
+            .iter()
+            .filter_map(|rule| rule.suppress_for.map(|window| (rule.name.as_str(), window.as_secs())))
+            .collect();
+        // Repeats of an expired key are kept for one more window in case it matches again
+        self.entries.retain(|(rule, _), entry| {
+            windows.get(rule.as_str()).is_some_and(|window| {
+                let age = now.saturating_sub(entry.last_alerted);
+                age < *window || (entry.repeats > 0 && age < window.saturating_mul(2))
+            })
+        });
+
+        for alert in alerts.iter_mut() {
+            if let Some(window) = windows.get(alert.rule.as_str()) {
+                let mut fresh = Vec::new();
+                for key in std::mem::take(&mut alert.keys) {
+                    let entry = self.entries.entry((alert.rule.clone(), key.clone())).or_insert_with(|| Entry {
+                        rule: alert.rule.clone(),
+                        key: key.clone(),
+                        last_alerted: 0,
+                        repeats: 0,
+                    });
+                    if now.saturating_sub(entry.last_alerted) < *window {
+                        entry.repeats += 1;
+                        alert.suppressed_keys += 1;
+                    } else {
+                        alert.repeats += std::mem::take(&mut entry.repeats);
+                        entry.last_alerted = now;
+                        fresh.push(key);
+                    }
+                }
+                alert.keys = fresh;
+            }
+            alert.new_keys = alert.keys.len();
+            alert.keys.truncate(KEYS_LISTED);
+        }
+    }
+
+    pub fn save(&self) {
+        let mut entries: Vec<&Entry> = self.entries.values().collect();
+        entries.sort_by(|a, b| (&a.rule, &a.key).cmp(&(&b.rule, &b.key)));
+        let text = serde_json::to_vec_pretty(&entries).expect("Unable to serialize alert state");
+        if let Err(err) = atomic::write(&self.path, &text) {
+            eprintln!("Unable to save alert state {}: {}", self.path.display(), err);
+        }
+    }
+}
+
+/// POST the alerts that weren't suppressed to `url`: one request each, or a
+/// single digest when there are more than `digest_after`.
+pub fn notify(url: &str, window: u128, alerts: &[RuleAlert], digest_after: usize) {
+    let alerts: Vec<&RuleAlert> = alerts.iter().filter(|alert| alert.notifies()).collect();
+    if alerts.is_empty() {
+        return;
+    }
+    let agent = crate::sink::http_agent();
+    let bodies: Vec<serde_json::Value> = match alerts.len() > digest_after {
+        true => vec![serde_json::json!({ "window": window, "digest": alerts })],
+        false => alerts.iter().map(|alert| serde_json::json!({ "window": window, "alert": alert })).collect(),
+    };
+    for body in bodies {
+        if let Err(err) = agent.post(url).send_json(&body) {
+            eprintln!("Unable to send alerts to {}: {}", url, err);
+        }
+    }
+}
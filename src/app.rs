@@ -0,0 +1,1487 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Read};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::thread;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use chrono::Local;
+use clap::{CommandFactory, FromArgMatches};
+use crate::{
+    check, cli, config, console, explore, tenants,
+    aggregate, alerts, atomic, backpressure, bounds, classify, closes, detect, diff, direction, distinct, enrich, error, flush, geo, graph, hourly, journal, lineage, lines, listen, lock,
+    manifest, merge, misp, notify, parser, payload, prefilter, protect, quarantine, query, record, report, resources,
+    rollup, rules, sink, sites, space, spool, state, summary, tail, telemetry, throttle, topn, trend,
+};
+#[cfg(feature = "kafka")]
+use crate::kafka;
+#[cfg(feature = "io-uring")]
+use crate::uring;
+
+use aggregate::Aggregator;
+use closes::CloseRule;
+use cli::{Cli, Command, LogLevel, Source, StateCommand};
+use console::Console;
+use error::Error;
+use lines::LineReader;
+use payload::{DuplicateFile, FailedFile, FileStages, FileStats, Metadata, Payload, ProcessingPerformance, StageTiming};
+use quarantine::Quarantine;
+use record::{KeySpec, Record};
+use telemetry::Telemetry;
+
+fn generate_output_filename(output_dir: &Path) -> String {
+    let timestamp = Local::now().format("%Y%m%d_%H%M%S").to_string();
+    format!("{}/FDB_DP_v11_{}.json", output_dir.display(), timestamp)
+}
+
+/// What happened to each input besides the records it contributed.
+#[derive(Default)]
+struct Inputs {
+    files_processed: Vec<String>,
+    file_stats: Vec<FileStats>,
+    quarantined: Vec<String>,
+    duplicates: Vec<DuplicateFile>,
+    /// (size, SHA-256) of the files the --seed output or the resumed
+    /// journal counted, and the name each was counted under
+    counted: HashMap<(u64, String), String>,
+    already_counted: Vec<DuplicateFile>,
+    failed: Vec<FailedFile>,
+    /// Runs whose outputs the records were seeded from
+    parent_runs: Vec<String>,
+}
+
+impl Inputs {
+    /// Note the files an earlier run counted, to skip them if they turn up
+    /// again, returning how many couldn't be noted for lack of a hash.
+    fn counted_before<'a>(&mut self, files: impl IntoIterator<Item = &'a FileStats>) -> usize {
+        let mut unhashed = 0;
+        for file in files {
+            match (file.size, &file.sha256) {
+                (Some(size), Some(sha256)) => {
+                    self.counted.insert((size, sha256.clone()), file.file.clone());
+                }
+                _ => unhashed += 1,
+            }
+        }
+        unhashed
+    }
+
+    fn fail(&mut self, err: Error) {
+        eprintln!("{}", err);
+        let lines = match err {
+            Error::Read { lines, .. } => lines,
+            _ => 0,
+        };
+        self.failed.push(FailedFile {
+            file: err.path().map(|path| path.display().to_string()).unwrap_or_default(),
+            lines,
+            error: std::error::Error::source(&err).map(ToString::to_string).unwrap_or_default(),
+        });
+    }
+}
+
+/// Where a run's lines come from, picked by the subcommand.
+enum Input<'a> {
+    Source(Source),
+    Follow(&'a [PathBuf]),
+    Listen,
+}
+
+/// Read every file in `dir` that isn't a duplicate of another, calling
+/// `after_file` once each is done.
+fn read_syslog_dir(
+    dir: &Path,
+    io_uring: Option<usize>,
+    aggregator: &mut Aggregator,
+    inputs: &mut Inputs,
+    quarantine: Option<&Quarantine>,
+    telemetry: &mut Telemetry,
+    mut after_file: impl FnMut(&mut Aggregator, &Inputs, usize),
+) {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+    let candidates: Vec<PathBuf> = entries.flatten().map(|entry| entry.path()).filter(|path| path.is_file()).collect();
+
+    #[cfg(feature = "io-uring")]
+    let io_uring = io_uring.filter(|_| match uring::available() {
+        Ok(()) => true,
+        Err(err) => {
+            eprintln!("Unable to set up io_uring, reading files one at a time: {}", err);
+            false
+        }
+    });
+
+    // Collectors occasionally deliver the same file under two names. Only a
+    // file the size of another input or of a counted file can be a copy, so
+    // only those are read for their hash before being read for their lines
+    let sizes: Vec<Option<u64>> = candidates.iter().map(|path| fs::metadata(path).ok().map(|meta| meta.len())).collect();
+    let mut inputs_of_size: HashMap<u64, usize> = HashMap::new();
+    for size in sizes.iter().flatten() {
+        *inputs_of_size.entry(*size).or_default() += 1;
+    }
+    let counted_sizes: HashSet<u64> = inputs.counted.keys().map(|(size, _)| *size).collect();
+    let to_hash: Vec<PathBuf> = candidates
+        .iter()
+        .zip(&sizes)
+        .filter(|(_, size)| size.is_some_and(|size| inputs_of_size[&size] > 1 || counted_sizes.contains(&size)))
+        .map(|(path, _)| path.clone())
+        .collect();
+    let mut hashes = HashMap::with_capacity(to_hash.len());
+    each_input(to_hash, io_uring, |path, file| {
+        let hashed = file.and_then(|file| match aggregator.throttle.as_mut() {
+            Some(throttle) => manifest::hash_reader(throttle::Throttled::new(file, throttle)),
+            None => manifest::hash_reader(file),
+        });
+        if let Ok(hashed) = hashed {
+            hashes.insert(path, hashed);
+        }
+    });
+    // Content hash -> first file seen with it
+    let mut seen: HashMap<String, String> = HashMap::new();
+    let mut unique = Vec::with_capacity(candidates.len());
+    for filepath in candidates {
+        if let Some((sha256, size)) = hashes.remove(&filepath) {
+            // Already counted in the --seed output or the resumed journal
+            if let Some(original) = inputs.counted.get(&(size, sha256.clone())) {
+                inputs.already_counted.push(DuplicateFile {
+                    file: filepath.display().to_string(),
+                    duplicate_of: original.clone(),
+                });
+                continue;
+            }
+            match seen.get(&sha256) {
+                Some(original) => {
+                    inputs.duplicates.push(DuplicateFile {
+                        file: filepath.display().to_string(),
+                        duplicate_of: original.clone(),
+                    });
+                    continue;
+                }
+                None => {
+                    seen.insert(sha256, filepath.display().to_string());
+                }
+            }
+        }
+        unique.push(filepath);
+    }
+
+    let total = unique.len();
+    each_input(unique, io_uring, |filepath, file| {
+        match file {
+            Ok(file) => {
+                let mut file = manifest::HashingReader::new(file);
+                let failed = inputs.failed.len();
+                read_file(filepath, BufReader::new(&mut file), aggregator, inputs, quarantine, telemetry);
+                // Only a file read in full can be recognized by its hash
+                if inputs.failed.len() == failed
+                    && let Some(stats) = inputs.file_stats.last_mut()
+                {
+                    let (sha256, size) = file.finish();
+                    (stats.size, stats.sha256) = (Some(size), Some(sha256));
+                }
+            }
+            Err(source) => inputs.fail(Error::Open { path: filepath, source }),
+        }
+        after_file(aggregator, inputs, total);
+    });
+}
+
+/// Call `f` with each of `paths` in order and a reader for it, or the error
+/// opening it. With `io_uring` (its read-ahead depth) the next files are
+/// already being read while `f` works on one.
+fn each_input(paths: Vec<PathBuf>, io_uring: Option<usize>, mut f: impl FnMut(PathBuf, io::Result<&mut dyn Read>)) {
+    #[cfg(feature = "io-uring")]
+    if let Some(depth) = io_uring {
+        match uring::ReadAhead::new(paths.clone(), depth) {
+            Ok(mut ahead) => {
+                while let Some((path, file)) = ahead.next() {
+                    match file {
+                        Ok(mut file) => f(path, Ok(&mut file)),
+                        Err(err) => f(path, Err(err)),
+                    }
+                }
+                return;
+            }
+            Err(err) => eprintln!("Unable to set up io_uring, reading files one at a time: {}", err),
+        }
+    }
+    #[cfg(not(feature = "io-uring"))]
+    let _ = io_uring;
+
+    for path in paths {
+        match File::open(&path) {
+            Ok(mut file) => f(path, Ok(&mut file)),
+            Err(err) => f(path, Err(err)),
+        }
+    }
+}
+
+fn read_file(
+    filepath: PathBuf,
+    mut reader: impl BufRead,
+    aggregator: &mut Aggregator,
+    inputs: &mut Inputs,
+    quarantine: Option<&Quarantine>,
+    telemetry: &mut Telemetry,
+) {
+    inputs.files_processed.push(filepath.display().to_string());
+    aggregator.set_source(&filepath.display().to_string());
+    let file_start = SystemTime::now();
+    let file_timer = Instant::now();
+    let lines_before = aggregator.connections;
+    let skipped_before = aggregator.skipped.total;
+    let replaced_before = aggregator.replaced_characters;
+    let (times_before, cpu_before) = (aggregator.stage_times, aggregator.timed.then(resources::cpu_time_seconds).flatten());
+
+    let mut line_reader = LineReader::new(aggregator.max_line_length).throttled(aggregator.throttle.clone());
+    loop {
+        let read_start = aggregator.timed.then(Instant::now);
+        let line = match line_reader.next(&mut reader) {
+            Ok(Some(line)) => line,
+            Ok(None) => match line_reader.finish() {
+                Some(line) => line,
+                None => break,
+            },
+            Err(source) => {
+                let lines = aggregator.connections - lines_before;
+                inputs.fail(Error::Read { path: filepath.clone(), lines, source });
+                break;
+            }
+        };
+        if let Some(start) = read_start {
+            aggregator.stage_times.read += start.elapsed();
+        }
+        if let Err(err) = aggregator.ingest_line(line) {
+            let lines = aggregator.connections - lines_before;
+            let source = io::Error::new(io::ErrorKind::InvalidData, err);
+            inputs.fail(Error::Read { path: filepath.clone(), lines, source });
+            break;
+        }
+    }
+
+    let lines = aggregator.connections - lines_before;
+    let skipped = aggregator.skipped.total - skipped_before;
+    telemetry.span("read", file_start, &[
+        ("file", filepath.display().to_string()),
+        ("lines", lines.to_string()),
+    ]);
+    inputs.file_stats.push(FileStats {
+        file: filepath.display().to_string(),
+        lines,
+        skipped,
+        replaced_characters: aggregator.replaced_characters - replaced_before,
+        duration_seconds: file_timer.elapsed().as_secs_f64(),
+        stages: aggregator.timed.then(|| FileStages::new(&aggregator.stage_times.since(&times_before), resources::cpu_seconds_since(cpu_before))),
+        size: None,
+        sha256: None,
+    });
+
+    if let Some(quarantine) = quarantine {
+        match quarantine.check(&filepath, lines, skipped) {
+            Ok(Some(target)) => {
+                eprintln!("Quarantined {} ({} of {} lines skipped) to {}", filepath.display(), skipped, lines, target.display());
+                inputs.quarantined.push(filepath.display().to_string());
+            }
+            Ok(None) => {}
+            Err(err) => eprintln!("Unable to quarantine {}: {}", filepath.display(), err),
+        }
+    }
+}
+
+/// Run the pipeline on `input`, returning the exit status for the process:
+/// 4 when the output was written without some inputs or companion outputs.
+fn process_syslog_files(start_time: u128, cli: &Cli, input: Input, console: &Console, telemetry: &mut Telemetry) -> i32 {
+    let to_stdout = cli.output.as_deref() == Some("-");
+    let side_outputs = cli.flush_every.is_some() || cli.report.is_some() || cli.graph.is_some() || cli.threat_export.is_some() || !cli.rollup.is_empty() || cli.sites.is_some();
+    if to_stdout && (side_outputs || cli.sign_key.is_some() || !cli.encrypt_to.is_empty()) {
+        eprintln!("--flush-every, --report, --graph, --rollup, --sites, --threat-export, --sign-key and --encrypt-to need a file output, not stdout");
+        process::exit(2);
+    }
+    if cli.io_uring && !cfg!(feature = "io-uring") {
+        eprintln!("--io-uring requires building with the `io-uring` feature");
+        process::exit(2);
+    }
+    if cli.rdap && !(cli.rdap_rate > 0.0 && cli.rdap_rate.is_finite()) {
+        eprintln!("--rdap-rate must be a positive number of lookups per second");
+        process::exit(2);
+    }
+    if cli.listen_overflow == backpressure::Overflow::Spill && cli.listen_spill_dir.is_none() {
+        eprintln!("--listen-overflow spill needs --listen-spill-dir");
+        process::exit(2);
+    }
+    if !(cli.space_margin >= 0.0 && cli.space_margin.is_finite()) {
+        eprintln!("--space-margin must be a percentage of zero or more");
+        process::exit(2);
+    }
+
+    let parser_options = parser::ParserOptions {
+        pattern: cli.pattern.as_deref(),
+        kv_aliases: &cli.kv_aliases,
+        min_fields: cli.min_fields,
+    };
+    let parser = match parser::LineParser::new(cli.input_format, &parser_options) {
+        Ok(parser) => parser,
+        Err(err) => {
+            eprintln!("Invalid input format settings: {}", err);
+            process::exit(2);
+        }
+    };
+
+    // Nothing is written to the output directory when streaming to stdout
+    let _lock = match to_stdout {
+        true => None,
+        false => match lock::RunLock::acquire(&cli.output_dir, Duration::from_secs(cli.lock_stale_after)) {
+            Ok(lock) => Some(lock),
+            Err(err) => {
+                eprintln!("Not starting: {}", err);
+                process::exit(3);
+            }
+        },
+    };
+
+    let assets = cli.enrich_map.as_deref().map(|path| {
+        enrich::assets::AssetMap::load(path).unwrap_or_else(|err| {
+            eprintln!("Unable to read asset map {}: {}", path.display(), err);
+            process::exit(2);
+        })
+    });
+
+    let asns = cli.asn_db.as_deref().map(|path| {
+        enrich::asn::AsnTable::load(path).unwrap_or_else(|err| {
+            eprintln!("Unable to read ASN table {}: {}", path.display(), err);
+            process::exit(2);
+        })
+    });
+
+    let sites = cli.sites.as_deref().map(|path| {
+        sites::SiteMap::load(path).unwrap_or_else(|err| {
+            eprintln!("Unable to read site map {}: {}", path.display(), err);
+            process::exit(2);
+        })
+    });
+
+    let hosts = (!cli.hosts_file.is_empty()).then(|| {
+        enrich::hosts::HostNames::load(&cli.hosts_file).unwrap_or_else(|err| {
+            eprintln!("Unable to read hosts file {}", err);
+            process::exit(2);
+        })
+    });
+
+    let dns = cli.dns_logs.as_deref().map(|dir| {
+        enrich::dns::Resolutions::load(dir).unwrap_or_else(|err| {
+            eprintln!("Unable to read DNS logs {}: {}", dir.display(), err);
+            process::exit(2);
+        })
+    });
+
+    let oui = match &cli.oui_file {
+        Some(path) => enrich::oui::OuiTable::load(path).unwrap_or_else(|err| {
+            eprintln!("Unable to read OUI file {}: {}", path.display(), err);
+            process::exit(2);
+        }),
+        None => enrich::oui::OuiTable::builtin(),
+    };
+
+    let rules = rules::load(&cli.rules).unwrap_or_else(|err| {
+        eprintln!("Invalid rules {}", err);
+        process::exit(2);
+    });
+    if rules::exports(&rules) && cli.threat_export.is_none() {
+        eprintln!("Rules with the export action have no effect without --threat-export");
+    }
+
+    let mut blocklists = (!cli.blocklist.is_empty()).then(|| {
+        enrich::blocklist::Blocklists::load(&cli.blocklist, Duration::from_secs(cli.blocklist_refresh)).unwrap_or_else(|err| {
+            eprintln!("Unable to read blocklist {}", err);
+            process::exit(2);
+        })
+    });
+
+    let key_spec = KeySpec {
+        nat: cli.key_on,
+        dimensions: cli.group_by.clone(),
+    };
+    let mut aggregator = Aggregator::new(parser, key_spec, cli.session_timeout);
+    aggregator.timed = cli.stage_timing || cli.otlp_endpoint.is_some() || cli.pushgateway_url.is_some() || cli.statsd_addr.is_some();
+    aggregator.max_flows = cli.max_flows.map(|max_flows| (max_flows.max(1), cli.cms_width, cli.cms_depth));
+    aggregator.percentiles = cli.percentiles;
+    aggregator.beacons = cli.detect_beacons;
+    aggregator.sample_lines = cli.sample_lines;
+    aggregator.record_sources = cli.record_sources;
+    aggregator.ignore = cli.ignore.clone();
+    aggregator.max_line_length = cli.max_line_length;
+    aggregator.counter_bounds = (cli.counter_limit > 0).then(|| bounds::CounterBounds::new(cli.counter_limit, cli.counter_over_limit));
+    aggregator.encoding = cli.input_encoding;
+    aggregator.close_rule = CloseRule::for_format(cli.input_format, &cli.session_close);
+    if cli.normalize_direction {
+        aggregator.direction = Some(direction::Normalizer::new(classify::Networks::new(&cli.internal_prefix)));
+    }
+    aggregator.device_aliases = cli.device_aliases.iter().cloned().collect();
+    aggregator.throttle = cli.throttle.map(throttle::Throttle::new);
+    if cli.distinct_counts {
+        aggregator.distinct = Some(distinct::DistinctCounts::new(cli.hll_precision));
+    }
+    let mut state = cli.state_dir.as_deref().map(|dir| {
+        let (store, stored) = state::StateStore::open(dir).unwrap_or_else(|err| {
+            eprintln!("Unable to open state store {}: {}", dir.display(), err);
+            process::exit(2);
+        });
+        console.info(format!("Resumed {} flows from the state store.", stored.records.len()));
+        aggregator.connections += stored.counters.connections;
+        aggregator.session_close += stored.counters.session_close;
+        aggregator.seed(stored.records);
+        (store, aggregator.track_changes())
+    });
+    let staged = state::staged_path(&cli.snapshot_file);
+    if staged.exists() {
+        let snapshot = state::AggregationSnapshot::read(&staged).unwrap_or_else(|err| {
+            eprintln!("Unable to read staged snapshot {}: {}", staged.display(), err);
+            process::exit(2);
+        });
+        console.info(format!("Resumed {} flows from the staged snapshot.", snapshot.records.len()));
+        snapshot.restore(&mut aggregator);
+        if let Some((store, cursor)) = &mut state {
+            checkpoint_state(store, &mut aggregator, *cursor, true);
+        }
+        if let Err(err) = fs::remove_file(&staged) {
+            eprintln!("Unable to remove staged snapshot {}: {}", staged.display(), err);
+        }
+    }
+    let mut inputs = Inputs::default();
+    let mut seeded_hours = Vec::new();
+    if let Some(path) = &cli.seed {
+        let seed = load_output(path);
+        console.info(format!("Seeded {} flows from {}.", seed.data.len(), path.display()));
+        aggregator.connections += seed.metadata.total_connections;
+        aggregator.session_close += merge::leading_count(&seed.metadata.session_close);
+        aggregator.seed(seed.data);
+        inputs.parent_runs = lineage::parents([&seed.metadata]);
+        inputs.files_processed = seed.metadata.files_processed;
+        let unhashed = inputs.counted_before(&seed.metadata.processing_performance.files);
+        if unhashed > 0 {
+            eprintln!("{} inputs of {} have no recorded hash, so they are read again if still present", unhashed, path.display());
+        }
+        // Carried along so that a run seeded from this one still knows them
+        inputs.file_stats = seed.metadata.processing_performance.files;
+        seeded_hours = seed.metadata.hourly_series;
+    }
+    let mut journal = cli.journal.as_deref().map(|path| {
+        if cli.journal_resume {
+            let replay = journal::replay(path).unwrap_or_else(|err| {
+                eprintln!("Unable to replay journal {}: {}", path.display(), err);
+                process::exit(2);
+            });
+            console.info(format!("Resumed {} flows and {} files from the journal.", replay.records.len(), replay.files.len()));
+            if replay.uncommitted > 0 {
+                eprintln!("Left out {} flows journaled after the last checkpoint of {}", replay.uncommitted, path.display());
+            }
+            aggregator.connections += replay.connections;
+            aggregator.session_close += replay.session_close;
+            aggregator.seed(replay.records);
+            inputs.counted_before(&replay.files);
+            inputs.files_processed.extend(replay.files.iter().map(|file| file.file.clone()));
+            inputs.file_stats.extend(replay.files);
+        }
+        let journal = journal::Journal::open(path, cli.journal_resume).unwrap_or_else(|err| {
+            eprintln!("Unable to open journal {}: {}", path.display(), err);
+            process::exit(2);
+        });
+        (journal, aggregator.track_changes())
+    });
+    // After the consumers of the records' changes have their cursors
+    aggregator.shard(cli.shards);
+    let journal_every = Duration::from_secs(cli.journal_every.max(1));
+    let quarantine = cli
+        .quarantine_dir
+        .as_deref()
+        .map(|dir| Quarantine::new(dir, cli.quarantine_threshold, cli.quarantine_move));
+
+    #[cfg(feature = "kafka")]
+    let mut kafka_input = None;
+
+    let from_files = matches!(input, Input::Source(Source::Files));
+    let run_id = lineage::new_run_id();
+    // Intermediate outputs are named after the final one, so it is picked now
+    let flushed_output = cli.flush_every.map(|_| cli.output.clone().unwrap_or_else(|| generate_output_filename(&cli.output_dir)));
+    let (timed, mut stages) = (aggregator.timed, Vec::new());
+    let stage_start = move || (Instant::now(), timed.then(resources::cpu_time_seconds).flatten());
+    let (ingest_started, ingest_cpu) = stage_start();
+    match input {
+        Input::Follow(files) => {
+            let tail_start = SystemTime::now();
+            let idle_timeout = Duration::from_secs(cli.tail_idle_timeout);
+            let mut notifier = notify::Notifier::from_env();
+            let mut last_journal = Instant::now();
+            let checkpoint = |aggregator: &mut Aggregator| {
+                notifier.tick(|| format!("Following {} files: {} lines, {} skipped", files.len(), aggregator.connections, aggregator.skipped.total));
+                if let Some((journal, cursor)) = &mut journal
+                    && last_journal.elapsed() >= journal_every
+                {
+                    append_journal(cli, journal, aggregator, *cursor, None);
+                    last_journal = Instant::now();
+                }
+            };
+            let followed = tail::follow(files, cli.tail_from_start, idle_timeout, &mut aggregator, checkpoint);
+            notifier.stopping("Idle; writing the output");
+            for file in &followed {
+                telemetry.span("read", tail_start, &[("file", file.file.clone()), ("lines", file.lines.to_string())]);
+                inputs.files_processed.push(format!("tail:{}", file.file));
+            }
+            inputs.file_stats.extend(followed);
+        }
+        Input::Source(Source::Files) => {
+            let mut flusher = cli.flush_every.map(flush::Flusher::new);
+            let after_file = |aggregator: &mut Aggregator, inputs: &Inputs, total: usize| {
+                if let Some((journal, cursor)) = &mut journal {
+                    append_journal(cli, journal, aggregator, *cursor, inputs.file_stats.last());
+                }
+                let Some(sequence) = flusher.as_mut().and_then(flush::Flusher::file_done) else {
+                    return;
+                };
+                let partial = flush::Partial {
+                    sequence,
+                    files_read: inputs.files_processed.len(),
+                    files_total: total,
+                };
+                let Some(final_output) = &flushed_output else {
+                    return;
+                };
+                let path = flush::partial_path(final_output, sequence);
+                match write_partial(cli, &path, partial, &run_id, start_time, aggregator, inputs) {
+                    Ok(flows) => console.info(format!("Partial output {} ({} flows) written to {}.", sequence, flows, path)),
+                    Err(err) => eprintln!("{}", err),
+                }
+            };
+            let io_uring = cli.io_uring.then_some(cli.io_uring_depth);
+            read_syslog_dir(&cli.input_dir, io_uring, &mut aggregator, &mut inputs, quarantine.as_ref(), telemetry, after_file);
+            for skipped in &inputs.already_counted {
+                console.info(format!("Skipped {}, already counted as {}.", skipped.file, skipped.duplicate_of));
+            }
+        }
+        Input::Listen => {
+            if let Some(addr) = &cli.top_api {
+                let talkers = Arc::new(Mutex::new(topn::SlidingTopN::new(cli.top_capacity)));
+                or_exit(topn::serve(addr, Arc::clone(&talkers), cli.top_n).map_err(|source| Error::TopApi { addr: addr.clone(), source }));
+                aggregator.talkers = Some(talkers);
+                console.info(format!("Serving top talkers on http://{}/top", addr));
+            }
+            let mut notifier = notify::Notifier::from_env();
+            let endpoints: Vec<String> = cli.listen.iter().map(ToString::to_string).collect();
+            let endpoints = endpoints.join(", ");
+            let options = listen::ListenOptions {
+                endpoints: &cli.listen,
+                tls_cert: cli.tls_cert.as_deref(),
+                tls_key: cli.tls_key.as_deref(),
+                idle_timeout: Duration::from_secs(cli.listen_idle_timeout),
+                forward: cli.forward.as_ref(),
+                forward_buffer: cli.forward_buffer,
+                buffer: cli.listen_buffer,
+                overflow: cli.listen_overflow,
+                spill_dir: cli.listen_spill_dir.clone(),
+                prefilter: prefilter::Prefilter {
+                    facilities: cli.listen_facility.clone(),
+                    severity: cli.listen_severity,
+                    programs: cli.listen_program.clone(),
+                },
+                // Often enough to notice `state snapshot` requests promptly
+                checkpoint_every: Some(notifier.ping_every().map_or(Duration::from_secs(1), |every| every.min(Duration::from_secs(1)))),
+            };
+            let sync_every = Duration::from_secs(cli.state_sync.max(1));
+            let compact_every = Duration::from_secs(cli.state_compact);
+            let snapshot_every = cli.snapshot_every.map(|secs| Duration::from_secs(secs.max(1)));
+            let request = state::request_path(&cli.snapshot_file);
+            let (mut last_sync, mut last_compact, mut last_snapshot) = (Instant::now(), Instant::now(), Instant::now());
+            let mut last_journal = Instant::now();
+            let checkpoint = |aggregator: &mut Aggregator| {
+                notifier.tick(|| format!("Listening on {}: {} lines, {} skipped", endpoints, aggregator.connections, aggregator.skipped.total));
+                let requested = request.exists();
+                let snapshot_due = requested || snapshot_every.is_some_and(|every| last_snapshot.elapsed() >= every);
+                if let Some((store, cursor)) = &mut state
+                    && (snapshot_due || last_sync.elapsed() >= sync_every)
+                {
+                    // Also compact once the journal holds more lines than the snapshot has records
+                    let compact = last_compact.elapsed() >= compact_every || store.journal_lines() > store.snapshot_records();
+                    checkpoint_state(store, aggregator, *cursor, compact);
+                    last_sync = Instant::now();
+                    if compact {
+                        last_compact = Instant::now();
+                    }
+                }
+                if let Some((journal, cursor)) = &mut journal
+                    && (snapshot_due || last_journal.elapsed() >= journal_every)
+                {
+                    append_journal(cli, journal, aggregator, *cursor, None);
+                    last_journal = Instant::now();
+                }
+                if snapshot_due {
+                    match state::AggregationSnapshot::take(aggregator, &cli.snapshot_file) {
+                        Ok(flows) => console.info(format!("Snapshot of {} flows written to {}.", flows, cli.snapshot_file.display())),
+                        Err(err) => eprintln!("Unable to write snapshot {}: {}", cli.snapshot_file.display(), err),
+                    }
+                    if requested {
+                        let _ = fs::remove_file(&request);
+                    }
+                    last_snapshot = Instant::now();
+                }
+            };
+            aggregator.set_source(&cli.listen.iter().map(|endpoint| format!("listen:{}", endpoint)).collect::<Vec<_>>().join(","));
+            let listen_start = SystemTime::now();
+            let stats = or_exit(listen::listen(&options, &mut aggregator, checkpoint).map_err(|source| Error::Listen { source }));
+            notifier.stopping("Idle; writing the output");
+            for endpoint in &cli.listen {
+                inputs.files_processed.push(format!("listen:{}", endpoint));
+            }
+            telemetry.span("read", listen_start, &[("messages", stats.received.to_string())]);
+            if let (Some(target), Some(forward)) = (&cli.forward, stats.forward) {
+                console.info(format!("Forwarded {} messages to {}.", forward.forwarded, target));
+                if forward.dropped > 0 {
+                    eprintln!("Dropped {} messages for {} after its buffer filled", forward.dropped, target);
+                }
+            }
+            if stats.buffer.dropped > 0 {
+                eprintln!("Dropped {} received messages after the listener's buffer of {} filled", stats.buffer.dropped, cli.listen_buffer);
+            }
+            if stats.filtered > 0 {
+                console.info(format!("Left {} received messages unparsed for their facility, severity or program.", stats.filtered));
+            }
+            if stats.buffer.spilled > 0 {
+                console.info(format!("Spilled {} received messages to disk after the listener's buffer of {} filled.", stats.buffer.spilled, cli.listen_buffer));
+            }
+        }
+        #[cfg(feature = "kafka")]
+        Input::Source(Source::Kafka) => {
+            let options = kafka::KafkaOptions {
+                brokers: &cli.kafka_brokers,
+                topic: &cli.kafka_topic,
+                group: &cli.kafka_group,
+                idle_timeout: Duration::from_secs(cli.kafka_idle_timeout),
+            };
+            let input = or_exit(kafka::KafkaInput::connect(&options).map_err(|source| Error::Kafka {
+                action: "subscribe to",
+                topic: cli.kafka_topic.clone(),
+                source,
+            }));
+            aggregator.set_source(&format!("kafka:{}", cli.kafka_topic));
+            let consume_start = SystemTime::now();
+            let consumed = input.consume(&mut aggregator);
+            telemetry.span("read", consume_start, &[("topic", cli.kafka_topic.clone()), ("messages", consumed.to_string())]);
+            inputs.files_processed.push(format!("kafka:{}", cli.kafka_topic));
+            kafka_input = (consumed > 0).then_some(input);
+        }
+        #[cfg(not(feature = "kafka"))]
+        Input::Source(Source::Kafka) => {
+            eprintln!("Kafka input requires building with the `kafka` feature");
+            process::exit(2);
+        }
+    }
+
+    if let Some((store, cursor)) = &mut state {
+        checkpoint_state(store, &mut aggregator, *cursor, true);
+    }
+    if let Some((journal, cursor)) = &mut journal {
+        append_journal(cli, journal, &mut aggregator, *cursor, None);
+        console.info(format!("Appended {} lines to the journal.", journal.lines));
+    }
+
+    let connections = aggregator.connections;
+    let session_close = aggregator.session_close;
+    let skipped = aggregator.skipped.clone();
+    telemetry.stage_times(&aggregator.stage_times);
+    telemetry.counter("pipeline.lines", "1", connections, &[]);
+    telemetry.counter("pipeline.sessions", "1", session_close, &[]);
+    let stage_times = aggregator.stage_times;
+    let aggregated = aggregator.finish();
+    if timed {
+        stages.push(StageTiming::since("ingest", ingest_started, ingest_cpu));
+        stages.push(StageTiming::new("read", stage_times.read, None));
+        stages.push(StageTiming::new("parse", stage_times.parse, None));
+        stages.push(StageTiming::new("aggregate", stage_times.aggregate, None));
+    }
+    let (enrich_started, enrich_cpu) = stage_start();
+    let mut master_record = aggregated.records;
+    let ignored = aggregated.ignored;
+    if let Some(ignored) = &ignored {
+        console.info(format!("Set aside {} flows ({} sessions) matching --ignore patterns.", ignored.totals.flows, ignored.totals.sessions));
+    }
+    if let Some(approximation) = &aggregated.approximation {
+        eprintln!(
+            "Reached --max-flows {}: {} events ({} bytes) of smaller flows are only in the totals, {} records evicted",
+            approximation.max_flows, approximation.overflow_events, approximation.overflow_bytes, approximation.evicted_flows
+        );
+    }
+    if let Some(bounds) = &aggregated.counter_bounds {
+        let treatment = match bounds.over_limit {
+            bounds::OverLimit::Clamp => "clamped to it",
+            bounds::OverLimit::Flag => "kept and their records tagged",
+        };
+        eprintln!("{} counters in {} events were over --counter-limit {} and {}", bounds.values, bounds.events, bounds.limit, treatment);
+    }
+    if let Some(direction) = &aggregated.direction {
+        console.info(format!("Reversed {} of {} events with both ports, logged from the server's side.", direction.reversed, direction.oriented));
+    }
+    if let Some(distinct) = &aggregated.distinct {
+        distinct.apply(&mut master_record);
+    }
+    let labels: BTreeMap<String, String> = cli.labels.iter().cloned().collect();
+    label_records(&mut master_record, &labels);
+    if let Some(assets) = &assets {
+        let matched = assets.apply(&mut master_record, &cli.enrich_on);
+        console.info(format!("Matched {} of {} flows against the asset map.", matched, master_record.len()));
+    }
+    if let Some(asns) = &asns {
+        let matched = asns.apply(&mut master_record, &cli.asn_on);
+        console.info(format!("Matched {} of {} flows against {} AS ranges.", matched, master_record.len(), asns.len()));
+    }
+    if let Some(hosts) = &hosts {
+        let labeled = hosts.apply(&mut master_record);
+        console.info(format!("Labeled {} flows with hostnames from {} known addresses.", labeled, hosts.len()));
+    }
+    if let Some(dns) = &dns {
+        let named = dns.apply(&mut master_record);
+        console.info(format!("Named the destination of {} flows from {} DNS resolutions.", named, dns.len()));
+    }
+    let vendors = oui.apply(&mut master_record);
+    if vendors > 0 {
+        console.info(format!("Annotated {} flows with MAC vendors.", vendors));
+    }
+    if cli.rdap {
+        let options = enrich::rdap::RdapOptions {
+            base_url: &cli.rdap_url,
+            cache: &cli.rdap_cache,
+            max_age: Duration::from_secs(cli.rdap_cache_days * 86_400),
+            limit: cli.rdap_limit,
+            interval: Duration::from_secs_f64(1.0 / cli.rdap_rate),
+        };
+        let stats = enrich::rdap::enrich(&mut master_record, &options);
+        console.info(format!(
+            "RDAP: {} networks looked up, {} destinations cached, {} failed, {} deferred to the next run.",
+            stats.looked_up, stats.cached, stats.failed, stats.deferred
+        ));
+    }
+    if let Some(blocklists) = &mut blocklists {
+        blocklists.refresh_if_due();
+        let tagged = blocklists.apply(&mut master_record);
+        console.info(format!("{} flows touch blocklisted addresses.", tagged));
+    }
+    let networks = classify::Networks::new(&cli.internal_prefix);
+    if cli.classify {
+        networks.apply(master_record.values_mut());
+    }
+    if timed {
+        stages.push(StageTiming::since("enrich", enrich_started, enrich_cpu));
+    }
+    let (detect_started, detect_cpu) = stage_start();
+    let mut suspected_scans = Vec::new();
+    if cli.detect_scans {
+        let options = detect::scan::ScanOptions {
+            min_ports: cli.scan_min_ports,
+            min_hosts: cli.scan_min_hosts,
+            max_bytes_per_session: cli.scan_max_bytes,
+        };
+        suspected_scans = detect::scan::detect(master_record.values(), &options);
+        console.info(format!("{} sources look like port scans or host sweeps.", suspected_scans.len()));
+    }
+    let mut suspected_floods = Vec::new();
+    if cli.detect_floods {
+        let options = detect::flood::FloodOptions {
+            min_sources: cli.flood_min_sources,
+            min_packets: cli.flood_min_packets,
+        };
+        suspected_floods = detect::flood::detect(master_record.values(), &options);
+        console.info(format!("{} destinations look flooded.", suspected_floods.len()));
+    }
+    let mut suspected_beacons = Vec::new();
+    if cli.detect_beacons {
+        let options = detect::beacon::BeaconOptions {
+            min_sessions: cli.beacon_min_sessions,
+            max_jitter: cli.beacon_max_jitter,
+            max_bytes_per_session: cli.beacon_max_bytes,
+            min_interval_seconds: cli.beacon_min_interval,
+            networks: networks.clone(),
+        };
+        suspected_beacons = detect::beacon::detect(master_record.values(), &options);
+        console.info(format!("{} flows look like beacons.", suspected_beacons.len()));
+    }
+    let mut exfiltration_watchlist = Vec::new();
+    if cli.detect_exfil {
+        let options = detect::exfil::ExfilOptions {
+            min_bytes_out: cli.exfil_min_bytes,
+            min_ratio: cli.exfil_min_ratio,
+            allow: cli.exfil_allow.clone(),
+            networks: networks.clone(),
+        };
+        exfiltration_watchlist = detect::exfil::detect(master_record.values(), &options);
+        console.info(format!("{} flows are on the exfiltration watchlist.", exfiltration_watchlist.len()));
+    }
+    let mut alerts = rules::evaluate(&rules, &mut master_record);
+    alerts.extend(detect::flood::alert(&suspected_floods, master_record.values()));
+    if !alerts.is_empty() && rules.iter().any(|rule| rule.suppress_for.is_some()) {
+        let mut state = alerts::AlertState::load(&cli.alert_state);
+        state.suppress(&rules, &mut alerts);
+        if !to_stdout {
+            state.save();
+        }
+    }
+    for alert in &alerts {
+        let held = match alert.suppressed_keys {
+            0 => String::new(),
+            held => format!(", {} keys suppressed", held),
+        };
+        console.info(format!("Rule {} ({}) matched {} flows{}.", alert.rule, alert.severity, alert.flows, held));
+    }
+    if let Some(url) = &cli.alert_webhook {
+        alerts::notify(url, start_time, &alerts, cli.alert_digest_after);
+    }
+    if timed {
+        stages.push(StageTiming::since("detect", detect_started, detect_cpu));
+    }
+
+    let end_time = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis();
+    let elapsed_time = (end_time - start_time) as f64 / 1000.0;
+
+    let perf = ProcessingPerformance {
+        connections_per_second: format!("{:.2} connections/second", connections as f64 / elapsed_time),
+        peak_rss_bytes: resources::peak_rss_bytes(),
+        cpu_time_seconds: resources::cpu_time_seconds(),
+        files: inputs.file_stats,
+        stages,
+    };
+
+    let metadata = Metadata {
+        partial: None,
+        run_id,
+        parent_runs: inputs.parent_runs,
+        start_time,
+        end_time,
+        elapsed_time,
+        total_connections: connections,
+        session_close: format!("{} ({:.2}% of total connections)", session_close, (session_close as f64 / connections as f64) * 100.0),
+        flows: master_record.len(),
+        labels,
+        files_processed: inputs.files_processed,
+        skipped_lines: skipped,
+        quarantined_files: inputs.quarantined,
+        failed_files: inputs.failed,
+        duplicate_files: inputs.duplicates,
+        already_counted: inputs.already_counted,
+        matched_indicators: enrich::blocklist::summarize(master_record.values()),
+        approximation: aggregated.approximation,
+        counter_bounds: aggregated.counter_bounds,
+        session_close_rule: CloseRule::for_format(cli.input_format, &cli.session_close).to_string(),
+        flow_direction: aggregated.direction,
+        ignored,
+        below_minimum: None,
+        top_k: None,
+        distinct_counts: aggregated.distinct.as_ref().map(|distinct| distinct.top(cli.distinct_top)),
+        traffic_classes: classify::totals(master_record.values()),
+        suspected_scans,
+        suspected_floods,
+        suspected_beacons,
+        exfiltration_watchlist,
+        alerts,
+        sinks: Vec::new(),
+        processing_performance: perf,
+        session_correlation: aggregated.session_correlation,
+        port_breakdown: summary::group_by(master_record.values(), |record| &record.destination_port),
+        protocol_breakdown: summary::group_by(master_record.values(), |record| &record.protocol),
+        hourly_series: hourly::combine(seeded_hours.into_iter().chain(aggregated.hourly_series)),
+    };
+
+    let mut payload = Payload {
+        metadata,
+        data: master_record,
+    };
+    // After the metadata's breakdowns and the detectors have seen every flow
+    if cli.min_bytes > 0 || cli.min_count > 1 {
+        let dropped = summary::drop_below(&mut payload.data, cli.min_bytes, cli.min_count);
+        console.info(format!("Left {} flows of {} sessions under the minimum out of the payload.", dropped.flows, dropped.sessions));
+        payload.metadata.flows = payload.data.len();
+        payload.metadata.below_minimum = Some(dropped);
+    }
+    if let Some(limit) = cli.top_k {
+        let top = query::keep_top(&mut payload.data, limit, cli.top_k_by);
+        if top.other.flows > 0 {
+            console.info(format!("Summed {} flows past the top {} into the other bucket.", top.other.flows, limit));
+        }
+        payload.metadata.flows = payload.data.len();
+        payload.metadata.top_k = Some(top);
+    }
+
+    telemetry.counter("pipeline.flows", "1", payload.data.len() as u64, &[]);
+
+    // Sinks go first so their outcome can be recorded in the output itself
+    let (deliver_started, deliver_cpu) = stage_start();
+    payload.metadata.sinks = deliver_to_sinks(cli, &payload, (connections, session_close), console, telemetry);
+    if timed {
+        let stages = &mut payload.metadata.processing_performance.stages;
+        stages.push(StageTiming::since("deliver", deliver_started, deliver_cpu));
+        // The write itself can't be in the output it writes, so this is a
+        // serialization to nowhere of the same payload
+        let (serialize_started, serialize_cpu) = stage_start();
+        payload.size(cli.output_format, !to_stdout);
+        payload.metadata.processing_performance.stages.push(StageTiming::since("serialize", serialize_started, serialize_cpu));
+    }
+
+    let serialize_start = SystemTime::now();
+    let time_range = aggregated.time_range.map(|(first, last)| (first.to_rfc3339(), last.to_rfc3339()));
+    let output_file = or_exit(write_output(cli, flushed_output, &payload, time_range));
+    telemetry.span("serialize", serialize_start, &[("file", output_file.clone())]);
+
+    console.summary(&payload, &output_file);
+
+    if !to_stdout {
+        let output_dir = Path::new(&output_file).parent().filter(|dir| !dir.as_os_str().is_empty()).unwrap_or(Path::new("."));
+        if let Err(err) = trend::TrendEntry::of(&payload).append_to(output_dir) {
+            eprintln!("Unable to append to the trend ledger: {}", err);
+        }
+    }
+
+    if cli.sign_key.is_some() || !cli.encrypt_to.is_empty() {
+        match protect::apply(Path::new(&output_file), cli.sign_key.as_deref(), &cli.encrypt_to) {
+            Ok(written) => {
+                for file in written {
+                    console.info(format!("Wrote {}.", file.display()));
+                }
+            }
+            Err(err) => {
+                eprintln!("Unable to sign or encrypt {}: {}", output_file, err);
+                process::exit(2);
+            }
+        }
+    }
+
+    // A companion output that can't be written doesn't stop the others
+    let mut failed_outputs = 0;
+    let mut companion = |file: &str, contents: String, written: String| match atomic::write(Path::new(file), contents.as_bytes()) {
+        Ok(()) => console.info(written),
+        Err(source) => {
+            eprintln!("{}", Error::Write { path: file.into(), source });
+            failed_outputs += 1;
+        }
+    };
+
+    if let Some(format) = cli.report {
+        let report_file = format!("{}.{}", output_file.trim_end_matches(".json"), format.extension());
+        companion(&report_file, report::render(&payload, format, &report_numbers(cli)), format!("Report written to {}.", report_file));
+    }
+
+    if let Some(format) = cli.graph {
+        let graph_file = format!("{}.{}", output_file.trim_end_matches(".json"), format.extension());
+        let graph = graph::render(payload.data.values(), format, cli.graph_min_bytes);
+        companion(&graph_file, graph, format!("Talker graph written to {}.", graph_file));
+    }
+
+    if !cli.rollup.is_empty() {
+        let rollup_file = format!("{}.rollup.json", output_file.trim_end_matches(".json"));
+        let levels = rollup::build(payload.data.values(), &cli.rollup, &cli.rollup_v6);
+        let pairs: Vec<String> = levels.iter().map(|level| format!("{} at /{}", level.pairs.len(), level.ipv4_prefix)).collect();
+        companion(&rollup_file, rollup::render(&levels), format!("Subnet rollups ({}) written to {}.", pairs.join(", "), rollup_file));
+    }
+
+    // On by default, so skipped rather than refused when writing to stdout
+    if !cli.no_geo_rollups && !to_stdout {
+        let countries = geo::by_country(payload.data.values());
+        if !countries.is_empty() {
+            let countries_file = format!("{}.countries.json", output_file.trim_end_matches(".json"));
+            companion(&countries_file, geo::render(&countries), format!("Country rollups ({} source, {} destination) written to {}.", countries.source.len(), countries.destination.len(), countries_file));
+        }
+        let asns = geo::by_asn(payload.data.values());
+        if !asns.is_empty() {
+            let asns_file = format!("{}.asns.json", output_file.trim_end_matches(".json"));
+            companion(&asns_file, geo::render(&asns), format!("AS rollups ({} source, {} destination) written to {}.", asns.source.len(), asns.destination.len(), asns_file));
+        }
+    }
+
+    if let Some(sites) = &sites {
+        let matrix_file = format!("{}.sites.json", output_file.trim_end_matches(".json"));
+        let matrices = sites.matrices(payload.data.values());
+        companion(&matrix_file, sites::render(&matrices), format!("Site matrices for {} prefixes written to {}.", sites.len(), matrix_file));
+    }
+
+    if let Some(format) = cli.threat_export {
+        let export_file = format!("{}.{}", output_file.trim_end_matches(".json"), format.extension());
+        let flagged = payload.data.values().filter(|record| misp::flagged(record)).count();
+        companion(&export_file, misp::render(&payload, format), format!("Exported {} flagged flows to {}.", flagged, export_file));
+    }
+
+    if from_files && cli.after_processing != spool::AfterProcessing::None {
+        let duplicates = payload.metadata.duplicate_files.iter().chain(&payload.metadata.already_counted).map(|duplicate| &duplicate.file);
+        for file in payload.metadata.files_processed.iter().chain(duplicates) {
+            if let Err(err) = cli.after_processing.apply(Path::new(file)) {
+                eprintln!("Unable to archive or delete {}: {}", file, err);
+            }
+        }
+    }
+
+    #[cfg(feature = "kafka")]
+    if let Some(input) = kafka_input {
+        // The output is written either way; uncommitted messages are consumed again next run
+        or_exit(input.commit().map_err(|source| Error::Kafka {
+            action: "commit the consumed offsets of",
+            topic: cli.kafka_topic.clone(),
+            source,
+        }));
+    }
+
+    // The output was written, but is missing inputs or companions
+    match !payload.metadata.failed_files.is_empty() || failed_outputs > 0 {
+        true => 4,
+        false => 0,
+    }
+}
+
+fn state_command(cli: &Cli, action: &StateCommand, console: &Console) {
+    match action {
+        StateCommand::Export => {
+            let Some(dir) = cli.state_dir.as_deref() else {
+                eprintln!("state export needs --state-dir");
+                process::exit(2);
+            };
+            let (_, stored) = state::StateStore::open(dir).unwrap_or_else(|err| {
+                eprintln!("Unable to open state store {}: {}", dir.display(), err);
+                process::exit(2);
+            });
+            let mut payload = Payload::describing(stored.records);
+            // Stores written before the counters were kept have none
+            let counters = stored.counters;
+            if counters.connections > 0 {
+                payload.metadata.total_connections = counters.connections;
+                payload.metadata.session_close = format!(
+                    "{} ({:.2}% of total connections)",
+                    counters.session_close,
+                    (counters.session_close as f64 / counters.connections as f64) * 100.0
+                );
+            }
+            let output_file = or_exit(write_output(cli, None, &payload, None));
+            console.summary(&payload, &output_file);
+        }
+        StateCommand::Snapshot { timeout } => {
+            let request = state::request_path(&cli.snapshot_file);
+            or_exit(fs::write(&request, b"").map_err(Error::write(&request)));
+            let deadline = Instant::now() + Duration::from_secs(*timeout);
+            while request.exists() {
+                if Instant::now() >= deadline {
+                    let _ = fs::remove_file(&request);
+                    eprintln!("No running serve took a snapshot to {} within {}s", cli.snapshot_file.display(), timeout);
+                    process::exit(1);
+                }
+                thread::sleep(Duration::from_millis(200));
+            }
+            console.info(format!("Snapshot written to {}.", cli.snapshot_file.display()));
+        }
+        StateCommand::Restore { file } => {
+            let file = file.as_deref().unwrap_or(&cli.snapshot_file);
+            let snapshot = state::AggregationSnapshot::read(file).unwrap_or_else(|err| {
+                eprintln!("Unable to read snapshot {}: {}", file.display(), err);
+                process::exit(2);
+            });
+            let staged = state::staged_path(&cli.snapshot_file);
+            let contents = or_exit(fs::read(file).map_err(|source| Error::Open { path: file.into(), source }));
+            or_exit(atomic::write(&staged, &contents).map_err(Error::write(&staged)));
+            console.info(format!("Staged {} flows from {}; the next run resumes from them.", snapshot.records.len(), file.display()));
+        }
+    }
+}
+
+/// Write the records aggregated so far as intermediate output `partial`,
+/// returning how many flows it has. The live records are copied rather than
+/// settled, so the flow cap and per-record statistics hold across partials.
+fn write_partial(cli: &Cli, path: &str, partial: flush::Partial, run_id: &str, start_time: u128, aggregator: &mut Aggregator, inputs: &Inputs) -> error::Result<usize> {
+    let mut payload = Payload::describing(aggregator.records());
+    let labels: BTreeMap<String, String> = cli.labels.iter().cloned().collect();
+    label_records(&mut payload.data, &labels);
+    let (connections, session_close) = (aggregator.connections, aggregator.session_close);
+    let metadata = &mut payload.metadata;
+    metadata.partial = Some(partial);
+    metadata.run_id = run_id.to_string();
+    metadata.parent_runs = inputs.parent_runs.clone();
+    metadata.labels = labels;
+    metadata.start_time = start_time;
+    metadata.elapsed_time = (metadata.end_time - start_time) as f64 / 1000.0;
+    metadata.total_connections = connections;
+    metadata.session_close = format!("{} ({:.2}% of total connections)", session_close, (session_close as f64 / connections as f64) * 100.0);
+    metadata.files_processed = inputs.files_processed.clone();
+    metadata.skipped_lines = aggregator.skipped.clone();
+    metadata.ignored = (!aggregator.ignore.is_empty()).then(|| aggregator.ignored());
+    let written = ensure_space(cli, Path::new(path), &payload).and_then(|()| {
+        atomic::write_with(Path::new(path), |out| payload.write_to(out, cli.output_format, true)).map_err(Error::write(path))
+    });
+    written.map(|()| payload.data.len())
+}
+
+/// Attach the run's `--label`s to every record.
+fn label_records(records: &mut HashMap<Arc<str>, Record>, labels: &BTreeMap<String, String>) {
+    if labels.is_empty() {
+        return;
+    }
+    for record in records.values_mut() {
+        record.labels.extend(labels.iter().map(|(key, value)| (key.clone(), value.clone())));
+    }
+}
+
+/// Append the flows changed since the journal's last entry to it.
+fn append_journal(cli: &Cli, journal: &mut journal::Journal, aggregator: &mut Aggregator, cursor: usize, file: Option<&FileStats>) {
+    if let Err(err) = journal.append(aggregator, cursor, file) {
+        let path = cli.journal.as_deref().unwrap_or(Path::new(""));
+        eprintln!("Unable to append to journal {}: {}", path.display(), err);
+    }
+}
+
+/// Journal the records changed since the store's last checkpoint, or
+/// rewrite the whole store when `compact` is set.
+fn checkpoint_state(store: &mut state::StateStore, aggregator: &mut Aggregator, cursor: usize, compact: bool) {
+    let counters = state::Counters::of(aggregator);
+    let result = match compact {
+        true => {
+            // Everything is in the new snapshot, so the changes are only skipped past
+            aggregator.changes(cursor);
+            store.compact(&aggregator.records(), counters)
+        }
+        false => store.append(&aggregator.changes(cursor), counters),
+    };
+    if let Err(err) = result {
+        eprintln!("Unable to checkpoint the aggregation state: {}", err);
+    }
+}
+
+/// Write the payload to stdout or to its output file (recording that in the
+/// manifest) through its [`sink::file::FileSink`], returning where it went.
+/// The file is `output_file` if given, otherwise `--output` or a new
+/// timestamped one.
+fn write_output(cli: &Cli, output_file: Option<String>, payload: &Payload, time_range: Option<(String, String)>) -> error::Result<String> {
+    let output_file = match cli.output.as_deref() {
+        Some("-") => "-".to_string(),
+        _ => output_file.or_else(|| cli.output.clone()).unwrap_or_else(|| generate_output_filename(&cli.output_dir)),
+    };
+    let space_margin = (!cli.no_space_check).then_some(cli.space_margin);
+    let mut file = sink::file::FileSink::new(&output_file, &payload.metadata, cli.output_format, space_margin, time_range);
+    let records: Vec<&Record> = payload.data.values().collect();
+    let batch = sink::Batch {
+        window: payload.metadata.start_time,
+        run_id: &payload.metadata.run_id,
+        connections: payload.metadata.total_connections,
+        session_close: merge::leading_count(&payload.metadata.session_close),
+        records: &records,
+    };
+    // Not retried: a full disk or a missing directory stays that way
+    let sink: &mut dyn sink::Sink = &mut file;
+    let written = sink.write_batch(&batch).and_then(|_| sink.flush());
+    written.map_err(|err| file.error(err))?;
+    Ok(match output_file.as_str() {
+        "-" => "<stdout>".to_string(),
+        _ => output_file,
+    })
+}
+
+/// Fail before writing `payload` to `path` if its filesystem hasn't room
+/// for it plus `--space-margin`. A filesystem that can't be asked is
+/// written to regardless.
+fn ensure_space(cli: &Cli, path: &Path, payload: &Payload) -> error::Result<()> {
+    if cli.no_space_check {
+        return Ok(());
+    }
+    let dir = path.parent().filter(|dir| !dir.as_os_str().is_empty()).unwrap_or(Path::new("."));
+    let Ok(available) = space::available(dir) else {
+        return Ok(());
+    };
+    let needed = space::needed(payload.size(cli.output_format, true), cli.space_margin);
+    match needed > available {
+        true => Err(Error::NoSpace { path: path.into(), needed, available }),
+        false => Ok(()),
+    }
+}
+
+fn report_numbers(cli: &Cli) -> report::NumberFormat {
+    report::NumberFormat { units: cli.report_units, grouping: cli.report_grouping }
+}
+
+/// The sinks the binary delivers to, by the name used in their statuses.
+/// A new destination is a `sink::Sink` implementation, its options and an
+/// entry here; each builds only when its options are given.
+fn sink_registry() -> sink::Registry<Cli> {
+    let mut registry = sink::Registry::<Cli>::default();
+    registry.register("redis", |cli| Some(Box::new(sink::redis::RedisSink::new(cli.redis_addr.as_deref()?, &cli.redis_stream))));
+    registry.register("clickhouse", |cli| {
+        Some(Box::new(sink::clickhouse::ClickHouseSink::new(
+            cli.clickhouse_url.as_deref()?,
+            &cli.clickhouse_table,
+            cli.clickhouse_user.as_deref(),
+            cli.clickhouse_password.as_deref(),
+            cli.clickhouse_batch_size,
+        )))
+    });
+    registry.register("influx", |cli| {
+        Some(Box::new(sink::influx::InfluxSink::new(cli.influx_url.as_deref()?, cli.influx_token.as_deref(), cli.influx_top_ports)))
+    });
+    registry.register("elasticsearch", |cli| {
+        Some(Box::new(sink::elasticsearch::ElasticsearchSink::new(
+            cli.elasticsearch_url.as_deref()?,
+            &cli.elasticsearch_index,
+            cli.elasticsearch_api_key.as_deref(),
+            cli.elasticsearch_batch_size,
+        )))
+    });
+    registry.register("http", |cli| {
+        Some(Box::new(sink::http::HttpSink::new(cli.http_url.as_deref()?, cli.http_token.as_deref(), cli.http_format, cli.http_gzip, cli.http_batch_size)))
+    });
+    registry
+}
+
+fn sink_retry(cli: &Cli) -> sink::Retry {
+    sink::Retry {
+        retries: cli.sink_retries,
+        backoff: Duration::from_millis(cli.sink_backoff_ms),
+    }
+}
+
+/// Deliver the records to every configured sink, each with its own retries,
+/// returning how each one went. Deliveries that are given up on go to the
+/// dead-letter directory when one is set.
+fn deliver_to_sinks(cli: &Cli, payload: &Payload, counts: (u64, u64), console: &Console, telemetry: &mut Telemetry) -> Vec<sink::SinkStatus> {
+    let (connections, session_close) = counts;
+    let records: Vec<&Record> = payload.data.values().collect();
+    let batch = sink::Batch {
+        window: payload.metadata.start_time,
+        run_id: &payload.metadata.run_id,
+        connections,
+        session_close,
+        records: &records,
+    };
+    let retry = sink_retry(cli);
+    let mut statuses = Vec::new();
+
+    for mut sink in sink_registry().configured(cli) {
+        let sink_start = SystemTime::now();
+        let (mut status, undelivered) = retry.deliver(&mut *sink, &batch);
+        telemetry.span("sink", sink_start, &[("sink", status.sink.clone())]);
+
+        match (status.delivered, &status.error) {
+            (true, _) => console.info(format!("Wrote {} entries to {} {}.", status.written.unwrap_or_default(), status.sink, status.target)),
+            (false, error) => eprintln!(
+                "Giving up on {} {} after {} attempts, with {} of {} records undelivered: {}",
+                status.sink,
+                status.target,
+                status.attempts,
+                undelivered.records.len(),
+                records.len(),
+                error.as_deref().unwrap_or_default()
+            ),
+        }
+        if !status.delivered
+            && let Some(dir) = &cli.dead_letter_dir
+        {
+            match sink::deadletter::store(dir, &status, &undelivered) {
+                Ok(path) => {
+                    console.info(format!("Kept the undelivered records in {}.", path.display()));
+                    status.dead_letter = Some(path);
+                }
+                Err(err) => eprintln!("Unable to write dead letter for {}: {}", status.sink, err),
+            }
+        }
+        statuses.push(status);
+    }
+    statuses
+}
+
+/// Replay the dead-letter directory against the configured sinks, removing
+/// each entry once it is delivered.
+fn redeliver(cli: &Cli, console: &Console) {
+    let Some(dir) = &cli.dead_letter_dir else {
+        eprintln!("redeliver needs --dead-letter-dir");
+        process::exit(2);
+    };
+    let pending = or_exit(sink::deadletter::pending(dir).map_err(|source| Error::Open { path: dir.clone(), source }));
+    let registry = sink_registry();
+    let retry = sink_retry(cli);
+    let mut delivered = 0;
+
+    for path in &pending {
+        let letter = match sink::deadletter::load(path) {
+            Ok(letter) => letter,
+            Err(err) => {
+                eprintln!("Unable to read dead letter {}: {}", path.display(), err);
+                continue;
+            }
+        };
+        console.info(format!("Redelivering {} (to {} {} failed: {}).", path.display(), letter.sink, letter.target, letter.error));
+        let Some(mut sink) = registry.build(&letter.sink, cli) else {
+            eprintln!("Skipping {}: the {} sink is not configured", path.display(), letter.sink);
+            continue;
+        };
+        let records = letter.records();
+        let (status, undelivered) = retry.deliver(&mut *sink, &letter.batch(&records));
+        if status.delivered {
+            or_exit(fs::remove_file(path).map_err(|source| Error::Remove { path: path.clone(), source }));
+            console.info(format!("Redelivered {} entries from {} to {} {}.", status.written.unwrap_or_default(), path.display(), status.sink, status.target));
+            delivered += 1;
+            continue;
+        }
+        eprintln!("Unable to redeliver {}: {}", path.display(), status.error.as_deref().unwrap_or_default());
+        // Only what is still undelivered is tried again next time
+        if undelivered.records.len() < records.len()
+            && let Err(err) = sink::deadletter::store(dir, &status, &undelivered)
+        {
+            eprintln!("Unable to keep the rest of dead letter {}: {}", path.display(), err);
+        }
+    }
+
+    console.info(format!("{} of {} dead letters redelivered.", delivered, pending.len()));
+    if delivered < pending.len() {
+        process::exit(1);
+    }
+}
+
+/// The value, or the run ends with the error.
+fn or_exit<T>(result: error::Result<T>) -> T {
+    result.unwrap_or_else(|err| {
+        eprintln!("{}", err);
+        process::exit(1);
+    })
+}
+
+fn load_output(path: &Path) -> Payload {
+    Payload::load(path).unwrap_or_else(|err| {
+        eprintln!("Unable to read output {}: {}", path.display(), err);
+        process::exit(1);
+    })
+}
+
+fn verify_outputs(output_dir: &Path, files: &[String]) {
+    let manifest_file = output_dir.join(manifest::MANIFEST_FILE);
+    let results = or_exit(manifest::verify(output_dir, files).map_err(|source| Error::Open { path: manifest_file, source }));
+    let mut failed = 0;
+    for (entry, verdict) in &results {
+        if *verdict != manifest::Verdict::Ok {
+            failed += 1;
+        }
+        println!("{:?}\t{}", verdict, entry.file);
+    }
+    println!("{} of {} outputs verified.", results.len() - failed, results.len());
+    if failed > 0 {
+        process::exit(1);
+    }
+}
+
+/// Run the command line the process was started with.
+pub fn run() {
+    let args = match config::with_config_file(std::env::args_os().collect()) {
+        Ok(args) => args,
+        Err(err) => {
+            eprintln!("Invalid config file: {}", err);
+            process::exit(2);
+        }
+    };
+    let args = config::with_environment(args).unwrap_or_else(|err| {
+        eprintln!("Invalid environment variable {}", err);
+        process::exit(2);
+    });
+    let matches = Cli::command().get_matches_from(&args.args);
+    let cli = Cli::from_arg_matches(&matches).unwrap_or_else(|err| err.exit());
+    let level = cli.log_level.unwrap_or(LogLevel::Info);
+    let quiet = cli.quiet || (!cli.verbose && level <= LogLevel::Warn);
+    let verbose = cli.verbose || (!cli.quiet && level == LogLevel::Debug);
+    let console = Console::new(quiet, verbose, cli.output.as_deref() == Some("-"));
+    let pipeline = matches!(cli.command, None | Some(Command::Process | Command::Watch { .. } | Command::Serve | Command::Redeliver | Command::CheckConfig { .. }));
+    if !args.tenants.is_empty() && pipeline {
+        if cli.output.as_deref() == Some("-") {
+            eprintln!("Tenants can't share stdout; give each an output file or run one with --tenant");
+            process::exit(2);
+        }
+        process::exit(tenants::supervise(&args.tenants, &console));
+    }
+    let input = match &cli.command {
+        Some(Command::Process) | None => Input::Source(cli.source),
+        Some(Command::Watch { files }) => Input::Follow(files),
+        Some(Command::Serve) => {
+            if cli.listen.is_empty() {
+                eprintln!("serve needs at least one --listen endpoint");
+                process::exit(2);
+            }
+            Input::Listen
+        }
+        Some(Command::Query { file, filter, sort, limit }) => {
+            let payload = load_output(file);
+            print!("{}", query::render(&query::select(payload.data.values(), filter, *sort, *limit)));
+            return;
+        }
+        Some(Command::Explore { file }) => {
+            or_exit(explore::run(&load_output(file)).map_err(|source| Error::Explore { source }));
+            return;
+        }
+        Some(Command::Merge { files }) => {
+            let payload = merge::merge(files.iter().map(|file| load_output(file)).collect());
+            let output_file = or_exit(write_output(&cli, None, &payload, None));
+            console.summary(&payload, &output_file);
+            return;
+        }
+        Some(Command::Diff { old, new, limit }) => {
+            print!("{}", diff::render(&load_output(old), &load_output(new), *limit));
+            return;
+        }
+        Some(Command::Report { file, format }) => {
+            let report = report::render(&load_output(file), *format, &report_numbers(&cli));
+            match &cli.output {
+                Some(path) if path != "-" => {
+                    or_exit(atomic::write(Path::new(path), report.as_bytes()).map_err(Error::write(path)));
+                    console.info(format!("Report written to {}.", path));
+                }
+                _ => print!("{}", report),
+            }
+            return;
+        }
+        Some(Command::Verify { files }) => {
+            verify_outputs(&cli.output_dir, files);
+            return;
+        }
+        Some(Command::Trend { weeks }) => {
+            match trend::load(&cli.output_dir) {
+                Ok(entries) => print!("{}", trend::render(&entries, *weeks)),
+                Err(err) => {
+                    eprintln!("Unable to read the trend ledger: {}", err);
+                    process::exit(1);
+                }
+            }
+            return;
+        }
+        Some(Command::State { action }) => {
+            state_command(&cli, action, &console);
+            return;
+        }
+        Some(Command::Redeliver) => {
+            redeliver(&cli, &console);
+            return;
+        }
+        Some(Command::CheckConfig { sample, connect }) => {
+            let ok = check::run(&cli, &matches, &args, sample.as_deref(), *connect);
+            process::exit(if ok { 0 } else { 1 });
+        }
+    };
+
+    let start = SystemTime::now();
+    let start_time = start.duration_since(UNIX_EPOCH).unwrap().as_millis();
+    let mut telemetry = Telemetry::new(start);
+    telemetry.tenant = cli.tenant.clone();
+    // Exporters run whatever the status, as failed runs need reporting most
+    let status = process_syslog_files(start_time, &cli, input, &console, &mut telemetry);
+
+    if let Some(endpoint) = &cli.otlp_endpoint
+        && let Err(err) = telemetry.export(endpoint)
+    {
+        eprintln!("Unable to export telemetry to {}: {}", endpoint, err);
+    }
+    if let Some(url) = &cli.pushgateway_url
+        && let Err(err) = telemetry.push_gateway(url, &cli.pushgateway_job)
+    {
+        eprintln!("Unable to push metrics to {}: {}", url, err);
+    }
+    if let Some(addr) = &cli.statsd_addr
+        && let Err(err) = telemetry.statsd(addr, cli.statsd_format)
+    {
+        eprintln!("Unable to send metrics to StatsD at {}: {}", addr, err);
+    }
+    if status != 0 {
+        process::exit(status);
+    }
+}
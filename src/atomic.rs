@@ -0,0 +1,101 @@
+//! Atomic file replacement: write to a hidden temporary file in the same
+//! directory, flush it to disk, then rename it into place, so readers only
+//! ever see either no file or the complete one.
+//!
+//! Line-oriented files that are appended to instead, like journals, are
+//! opened with [`open_lines`], which first cuts off a last line a crash
+//! left unterminated so that the next line doesn't run into it.
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+pub fn write_with<F>(path: &Path, write: F) -> io::Result<()>
+where
+    F: FnOnce(&mut BufWriter<File>) -> io::Result<()>,
+{
+    let mut staged = Staged::create(path)?;
+    write(staged.out())?;
+    staged.commit()
+}
+
+/// A replacement for a file being written next to it, for writes that
+/// can't be done in one closure. It is put in place by [`Staged::commit`];
+/// dropped before that, it is removed.
+pub struct Staged {
+    path: PathBuf,
+    tmp: PathBuf,
+    out: Option<BufWriter<File>>,
+}
+
+impl Staged {
+    pub fn create(path: &Path) -> io::Result<Self> {
+        let tmp = temp_path(path);
+        let out = BufWriter::new(File::create(&tmp)?);
+        Ok(Staged { path: path.to_path_buf(), tmp, out: Some(out) })
+    }
+
+    pub fn out(&mut self) -> &mut BufWriter<File> {
+        self.out.as_mut().expect("a staged file is written until it is committed")
+    }
+
+    /// Flush the replacement to disk and rename it over the file.
+    pub fn commit(mut self) -> io::Result<()> {
+        let out = self.out.take().expect("a staged file is committed once");
+        let result = (|| {
+            let file = out.into_inner().map_err(|err| err.into_error())?;
+            file.sync_all()?;
+            fs::rename(&self.tmp, &self.path)
+        })();
+        if result.is_err() {
+            let _ = fs::remove_file(&self.tmp);
+        }
+        result
+    }
+}
+
+impl Drop for Staged {
+    fn drop(&mut self) {
+        if self.out.take().is_some() {
+            let _ = fs::remove_file(&self.tmp);
+        }
+    }
+}
+
+pub fn write(path: &Path, contents: &[u8]) -> io::Result<()> {
+    write_with(path, |out| out.write_all(contents))
+}
+
+/// Open the line-oriented file at `path` for appending, creating it and
+/// truncating it to its last complete line.
+pub fn open_lines(path: &Path) -> io::Result<File> {
+    let mut file = OpenOptions::new().create(true).read(true).append(true).open(path)?;
+    let len = file.metadata()?.len();
+    let complete = complete_len(&mut file, len)?;
+    if complete < len {
+        file.set_len(complete)?;
+    }
+    Ok(file)
+}
+
+/// Length of `file` up to and including its last newline.
+fn complete_len(file: &mut File, len: u64) -> io::Result<u64> {
+    let mut chunk = [0u8; 8192];
+    let mut end = len;
+    while end > 0 {
+        let start = end.saturating_sub(chunk.len() as u64);
+        let bytes = &mut chunk[..(end - start) as usize];
+        file.seek(SeekFrom::Start(start))?;
+        file.read_exact(bytes)?;
+        if let Some(at) = bytes.iter().rposition(|&byte| byte == b'\n') {
+            return Ok(start + at as u64 + 1);
+        }
+        end = start;
+    }
+    Ok(0)
+}
+
+fn temp_path(path: &Path) -> PathBuf {
+    let name = path.file_name().map(|name| name.to_string_lossy()).unwrap_or_default();
+    path.with_file_name(format!(".{}.tmp", name))
+}
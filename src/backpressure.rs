@@ -0,0 +1,382 @@
+//! Bounded buffering between the listener's receiving threads and the
+//! aggregator.
+//!
+//! Received messages wait in a queue of fixed capacity. When a burst
+//! arrives faster than it can be aggregated and the queue is full, the
+//! [`Overflow`] policy decides what happens: receivers block (TCP and RELP
+//! senders then slow down; UDP datagrams are dropped by the kernel), the
+//! oldest queued message is dropped and counted (a RELP sender is told its
+//! message was refused), or messages spill to a file and are read back in
+//! order once the aggregator catches up. Either way memory stays bounded.
+
+use std::collections::VecDeque;
+use std::fs::{self, File, OpenOptions};
+use std::io;
+use std::os::unix::fs::FileExt;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::mpsc::{RecvTimeoutError, Sender as AckSender};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use clap::ValueEnum;
+
+use crate::parser::SkipReason;
+
+/// What to do with a message that arrives while the buffer is full.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum Overflow {
+    /// Wait for room, pushing back on stream senders
+    #[default]
+    Block,
+    /// Drop the oldest buffered message to make room, and count it
+    DropOldest,
+    /// Append to a file in the spill directory until the queue drains
+    Spill,
+}
+
+/// A received message, plus a way to tell RELP senders what became of it.
+pub(crate) struct Message {
+    /// The message, or why it was refused before reaching the parser
+    pub line: Result<Vec<u8>, SkipReason>,
+    pub ack: Option<AckSender<Delivery>>,
+}
+
+/// What became of a message whose sender waits for an acknowledgement.
+/// A closed ack channel means the listener is shutting down.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Delivery {
+    /// Handed to the aggregator, or safely spilled
+    Taken,
+    /// Dropped under `drop-oldest` to make room
+    Dropped,
+}
+
+/// How the buffer coped, for the end-of-run summary.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct BufferStats {
+    /// Messages dropped under `drop-oldest`
+    pub dropped: u64,
+    /// Messages written to the spill file
+    pub spilled: u64,
+    /// Most messages queued in memory at once
+    pub peak: usize,
+}
+
+struct State {
+    queue: VecDeque<Message>,
+    spill: Option<Spill>,
+    stats: BufferStats,
+    senders: usize,
+    closed: bool,
+}
+
+struct Shared {
+    state: Mutex<State>,
+    /// Signalled when a message is queued or the last sender goes away
+    arrived: Condvar,
+    /// Signalled when a message is taken
+    taken: Condvar,
+    capacity: usize,
+    overflow: Overflow,
+}
+
+impl Shared {
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().expect("listener buffer poisoned")
+    }
+}
+
+/// Create a buffer of `capacity` messages. `spill_dir` is where the spill
+/// file goes under [`Overflow::Spill`].
+pub(crate) fn buffer(capacity: usize, overflow: Overflow, spill_dir: Option<&Path>) -> io::Result<(Sender, Receiver)> {
+    let spill = match (overflow, spill_dir) {
+        (Overflow::Spill, Some(dir)) => Some(Spill::create(dir)?),
+        (Overflow::Spill, None) => return Err(io::Error::new(io::ErrorKind::InvalidInput, "spilling needs a spill directory")),
+        _ => None,
+    };
+    let shared = Arc::new(Shared {
+        state: Mutex::new(State {
+            queue: VecDeque::new(),
+            spill,
+            stats: BufferStats::default(),
+            senders: 1,
+            closed: false,
+        }),
+        arrived: Condvar::new(),
+        taken: Condvar::new(),
+        capacity: capacity.max(1),
+        overflow,
+    });
+    Ok((Sender(Arc::clone(&shared)), Receiver(shared)))
+}
+
+pub(crate) struct Sender(Arc<Shared>);
+
+impl Sender {
+    /// Buffer `message` per the overflow policy, handing it back once the
+    /// receiving side is gone.
+    pub fn send(&self, message: Message) -> Result<(), Message> {
+        let shared = &self.0;
+        let mut state = shared.lock();
+        loop {
+            if state.closed {
+                return Err(message);
+            }
+            let spilling = state.spill.as_ref().is_some_and(|spill| spill.pending > 0);
+            if state.queue.len() < shared.capacity && !spilling {
+                break;
+            }
+            match shared.overflow {
+                Overflow::Block => state = shared.taken.wait(state).expect("listener buffer poisoned"),
+                Overflow::DropOldest => {
+                    if let Some(dropped) = state.queue.pop_front()
+                        && let Some(ack) = dropped.ack
+                    {
+                        let _ = ack.send(Delivery::Dropped);
+                    }
+                    state.stats.dropped += 1;
+                    break;
+                }
+                Overflow::Spill => {
+                    let spill = state.spill.as_mut().expect("spill policy without a spill file");
+                    match spill.push(&message.line) {
+                        Ok(()) => {
+                            state.stats.spilled += 1;
+                            // On disk counts as taken
+                            if let Some(ack) = message.ack {
+                                let _ = ack.send(Delivery::Taken);
+                            }
+                            shared.arrived.notify_one();
+                            return Ok(());
+                        }
+                        // A full disk falls back to waiting for room
+                        Err(err) => {
+                            eprintln!("Unable to spill a received message: {}", err);
+                            state = shared.taken.wait(state).expect("listener buffer poisoned");
+                        }
+                    }
+                }
+            }
+        }
+        state.queue.push_back(message);
+        state.stats.peak = state.stats.peak.max(state.queue.len());
+        shared.arrived.notify_one();
+        Ok(())
+    }
+}
+
+impl Clone for Sender {
+    fn clone(&self) -> Self {
+        self.0.lock().senders += 1;
+        Sender(Arc::clone(&self.0))
+    }
+}
+
+impl Drop for Sender {
+    fn drop(&mut self) {
+        let mut state = self.0.lock();
+        state.senders -= 1;
+        if state.senders == 0 {
+            self.0.arrived.notify_all();
+        }
+    }
+}
+
+pub(crate) struct Receiver(Arc<Shared>);
+
+impl Receiver {
+    /// The oldest buffered message, waiting up to `timeout` for one.
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<Message, RecvTimeoutError> {
+        let shared = &self.0;
+        let deadline = Instant::now() + timeout;
+        let mut state = shared.lock();
+        loop {
+            if let Some(message) = state.queue.pop_front() {
+                shared.taken.notify_one();
+                return Ok(message);
+            }
+            if let Some(spill) = &mut state.spill
+                && let Some(line) = spill.pop()
+            {
+                shared.taken.notify_one();
+                return Ok(Message { line, ack: None });
+            }
+            if state.senders == 0 {
+                return Err(RecvTimeoutError::Disconnected);
+            }
+            let left = deadline.saturating_duration_since(Instant::now());
+            if left.is_zero() {
+                return Err(RecvTimeoutError::Timeout);
+            }
+            state = shared.arrived.wait_timeout(state, left).expect("listener buffer poisoned").0;
+        }
+    }
+
+    pub fn stats(&self) -> BufferStats {
+        self.0.lock().stats
+    }
+}
+
+impl Drop for Receiver {
+    fn drop(&mut self) {
+        let mut state = self.0.lock();
+        state.closed = true;
+        // Blocked receivers give up; nothing reads the queue or spill any more
+        state.queue.clear();
+        state.spill = None;
+        self.0.taken.notify_all();
+    }
+}
+
+/// Messages written past a full queue, read back in order.
+///
+/// Entries are a tag byte (0 for a message, or the reason it was refused),
+/// a little-endian `u32` length and the message bytes. The file is emptied
+/// whenever everything in it has been read, and removed at the end.
+struct Spill {
+    path: PathBuf,
+    file: File,
+    written: u64,
+    read: u64,
+    pending: u64,
+}
+
+impl Spill {
+    fn create(dir: &Path) -> io::Result<Spill> {
+        fs::create_dir_all(dir)?;
+        let path = dir.join(format!("listen-spill-{}.bin", process::id()));
+        let file = OpenOptions::new().read(true).write(true).create(true).truncate(true).open(&path)?;
+        Ok(Spill { path, file, written: 0, read: 0, pending: 0 })
+    }
+
+    fn push(&mut self, line: &Result<Vec<u8>, SkipReason>) -> io::Result<()> {
+        let (tag, bytes): (u8, &[u8]) = match line {
+            Ok(bytes) => (0, bytes),
+            Err(SkipReason::TooLong) => (1, &[]),
+            Err(SkipReason::Binary) => (2, &[]),
+            Err(_) => (3, &[]),
+        };
+        let mut entry = Vec::with_capacity(5 + bytes.len());
+        entry.push(tag);
+        entry.extend_from_slice(&(bytes.len() as u32).to_le_bytes());
+        entry.extend_from_slice(bytes);
+        self.file.write_all_at(&entry, self.written)?;
+        self.written += entry.len() as u64;
+        self.pending += 1;
+        Ok(())
+    }
+
+    fn pop(&mut self) -> Option<Result<Vec<u8>, SkipReason>> {
+        if self.pending == 0 {
+            return None;
+        }
+        let mut header = [0u8; 5];
+        let entry = self.file.read_exact_at(&mut header, self.read).and_then(|()| {
+            let len = u32::from_le_bytes([header[1], header[2], header[3], header[4]]) as usize;
+            let mut bytes = vec![0; len];
+            self.file.read_exact_at(&mut bytes, self.read + 5)?;
+            Ok(bytes)
+        });
+        let line = match entry {
+            Ok(bytes) => {
+                self.read += 5 + bytes.len() as u64;
+                self.pending -= 1;
+                match header[0] {
+                    0 => Ok(bytes),
+                    1 => Err(SkipReason::TooLong),
+                    2 => Err(SkipReason::Binary),
+                    _ => Err(SkipReason::Unrecognized),
+                }
+            }
+            Err(err) => {
+                // What can't be read back is lost; start the file over
+                eprintln!("Unable to read spilled messages from {}: {}", self.path.display(), err);
+                self.pending = 0;
+                self.reset();
+                return None;
+            }
+        };
+        if self.pending == 0 {
+            self.reset();
+        }
+        Some(line)
+    }
+
+    fn reset(&mut self) {
+        self.written = 0;
+        self.read = 0;
+        if let Err(err) = self.file.set_len(0) {
+            eprintln!("Unable to empty spill file {}: {}", self.path.display(), err);
+        }
+    }
+}
+
+impl Drop for Spill {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+
+    #[test]
+    fn dropping_the_oldest_tells_its_sender() {
+        let (tx, rx) = buffer(1, Overflow::DropOldest, None).unwrap();
+        let (ack_tx, ack_rx) = mpsc::channel();
+        tx.send(Message { line: Ok(b"first".to_vec()), ack: Some(ack_tx) }).ok().unwrap();
+        tx.send(Message { line: Ok(b"second".to_vec()), ack: None }).ok().unwrap();
+        assert_eq!(ack_rx.recv(), Ok(Delivery::Dropped));
+        let message = rx.recv_timeout(Duration::ZERO).ok().unwrap();
+        assert_eq!(message.line, Ok(b"second".to_vec()));
+        assert_eq!(rx.stats().dropped, 1);
+    }
+
+    #[test]
+    fn blocking_waits_for_room() {
+        let (tx, rx) = buffer(1, Overflow::Block, None).unwrap();
+        tx.send(Message { line: Ok(b"first".to_vec()), ack: None }).ok().unwrap();
+        let sender = {
+            let tx = tx.clone();
+            std::thread::spawn(move || tx.send(Message { line: Ok(b"second".to_vec()), ack: None }).is_ok())
+        };
+        // The second message only goes in once the first is taken
+        std::thread::sleep(Duration::from_millis(50));
+        assert_eq!(rx.stats().peak, 1);
+        assert_eq!(rx.recv_timeout(Duration::ZERO).ok().unwrap().line, Ok(b"first".to_vec()));
+        assert!(sender.join().unwrap());
+        assert_eq!(rx.recv_timeout(Duration::from_secs(5)).ok().unwrap().line, Ok(b"second".to_vec()));
+        assert_eq!(rx.stats().dropped, 0);
+    }
+
+    #[test]
+    fn spilled_messages_are_read_back_in_order() {
+        let dir = std::env::temp_dir().join(format!("syslog_processor-spill-{}", process::id()));
+        let (tx, rx) = buffer(1, Overflow::Spill, Some(&dir)).unwrap();
+        let (ack_tx, ack_rx) = mpsc::channel();
+        tx.send(Message { line: Ok(b"queued".to_vec()), ack: None }).ok().unwrap();
+        tx.send(Message { line: Ok(b"spilled".to_vec()), ack: Some(ack_tx) }).ok().unwrap();
+        tx.send(Message { line: Err(SkipReason::TooLong), ack: None }).ok().unwrap();
+        // Spilling counts as taken
+        assert_eq!(ack_rx.recv(), Ok(Delivery::Taken));
+        drop(tx);
+
+        let mut lines = Vec::new();
+        while let Ok(message) = rx.recv_timeout(Duration::ZERO) {
+            lines.push(message.line);
+        }
+        assert_eq!(lines, [Ok(b"queued".to_vec()), Ok(b"spilled".to_vec()), Err(SkipReason::TooLong)]);
+        assert_eq!(rx.stats().spilled, 2);
+        drop(rx);
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 0);
+        fs::remove_dir(&dir).unwrap();
+    }
+
+    #[test]
+    fn spilling_needs_a_directory() {
+        assert!(buffer(1, Overflow::Spill, None).is_err());
+    }
+}
@@ -0,0 +1,71 @@
+//! Sanity bounds on logged counters.
+//!
+//! Some firmware occasionally logs nonsense counters, like a session of
+//! 2^63 bytes, and a single such line would dominate every total it is
+//! added to. Counters above the limit are either clamped to it or kept and
+//! their records tagged, and either way counted. Aggregation itself adds
+//! with saturation, so a total pegs at `u64::MAX` rather than wrapping.
+
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+
+use crate::parser::FlowEvent;
+
+/// Bytes, packets or milliseconds no single session plausibly logs.
+pub const DEFAULT_LIMIT: u64 = 1 << 50;
+
+/// Tag of records that took a counter over the limit under [`OverLimit::Flag`].
+pub const TAG: &str = "counter-over-limit";
+
+/// What to do with a counter above the limit.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum OverLimit {
+    /// Count it as the limit instead
+    #[default]
+    Clamp,
+    /// Count it as logged, and tag the record
+    Flag,
+}
+
+/// Counters over the limit in a run, reported in the output's metadata.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+#[serde(rename_all = "camelCase")]
+pub struct CounterBounds {
+    pub limit: u64,
+    pub over_limit: OverLimit,
+    /// Counter values over the limit
+    pub values: u64,
+    /// Events with at least one of them
+    pub events: u64,
+}
+
+impl CounterBounds {
+    pub fn new(limit: u64, over_limit: OverLimit) -> Self {
+        CounterBounds { limit, over_limit, values: 0, events: 0 }
+    }
+
+    /// Apply the bounds to `event`'s counters.
+    pub fn check(&mut self, event: &mut FlowEvent) {
+        let mut over = 0;
+        let counters = [&mut event.packets_in, &mut event.bytes_in, &mut event.packets_out, &mut event.bytes_out];
+        for counter in counters.into_iter().chain(event.duration_ms.as_mut()) {
+            if *counter > self.limit {
+                over += 1;
+                if self.over_limit == OverLimit::Clamp {
+                    *counter = self.limit;
+                }
+            }
+        }
+        if over > 0 {
+            self.values += over;
+            self.events += 1;
+            event.over_limit = self.over_limit == OverLimit::Flag;
+        }
+    }
+
+    pub fn merge(&mut self, other: &CounterBounds) {
+        self.values += other.values;
+        self.events += other.events;
+    }
+}
@@ -0,0 +1,181 @@
+//! `check-config`: catch option mistakes before a long run starts.
+//!
+//! The options are parsed exactly as a run would parse them, the input
+//! format is tried on a sample file, and with `--connect` every configured
+//! sink and collector is sent a TCP connection. The effective configuration
+//! is printed with where each value came from.
+
+use std::fs::{self, File};
+use std::io::BufReader;
+use std::net::{TcpStream, ToSocketAddrs};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use clap::ArgMatches;
+
+use crate::cli::Cli;
+use crate::listen::Transport;
+use crate::lines::{self, Encoding, Line, LineReader};
+use crate::parser::{self, SkipCounts, SkipReason};
+use crate::config;
+
+/// Lines of the sample file tried against the input format
+const SAMPLE_LINES: usize = 10_000;
+
+/// Print the effective configuration and the result of each check,
+/// returning whether all of them passed.
+pub fn run(cli: &Cli, matches: &ArgMatches, args: &config::Args, sample: Option<&Path>, connect: bool) -> bool {
+    println!("Effective configuration:");
+    for (name, value, source) in config::effective(matches, args) {
+        println!("  {:<26} {:<32} ({})", name, value, source);
+    }
+    println!();
+
+    let mut ok = true;
+    let parser_options = parser::ParserOptions {
+        pattern: cli.pattern.as_deref(),
+        kv_aliases: &cli.kv_aliases,
+        min_fields: cli.min_fields,
+    };
+    match parser::LineParser::new(cli.input_format, &parser_options) {
+        Ok(mut parser) => ok &= check_sample(&mut parser, sample, &cli.input_dir, cli.max_line_length, cli.input_encoding),
+        Err(err) => {
+            println!("FAIL  input format: {}", err);
+            ok = false;
+        }
+    }
+
+    if connect {
+        for (name, target) in targets(cli) {
+            match reachable(&target) {
+                Ok(()) => println!("ok    {} {} accepts connections", name, target),
+                Err(err) => {
+                    println!("FAIL  {} {}: {}", name, target, err);
+                    ok = false;
+                }
+            }
+        }
+    }
+
+    println!("{}", if ok { "Configuration OK." } else { "Configuration has problems." });
+    ok
+}
+
+fn check_sample(parser: &mut parser::LineParser, sample: Option<&Path>, input_dir: &Path, max_line_length: usize, encoding: Encoding) -> bool {
+    let Some(path) = sample.map(Path::to_path_buf).or_else(|| first_input(input_dir)) else {
+        println!("skip  no sample file to try the input format on");
+        return true;
+    };
+    let file = match File::open(&path) {
+        Ok(file) => file,
+        Err(err) => {
+            println!("FAIL  sample {}: {}", path.display(), err);
+            return false;
+        }
+    };
+
+    let mut lines = 0;
+    let mut skipped = SkipCounts::default();
+    let mut reader = BufReader::new(file);
+    let mut line_reader = LineReader::new(max_line_length);
+    while lines < SAMPLE_LINES as u64 {
+        let Ok(Some(line)) = line_reader.next(&mut reader) else {
+            break;
+        };
+        let parsed = match line {
+            Line::Text(line) => match lines::decode(line, encoding) {
+                Ok((line, _)) if parser.header(&line) => continue,
+                Ok((line, _)) => parser.parse_line(&line).map(drop),
+                Err(_) => break,
+            },
+            Line::TooLong => Err(SkipReason::TooLong),
+            Line::Binary => Err(SkipReason::Binary),
+        };
+        lines += 1;
+        if let Err(reason) = parsed {
+            skipped.add(reason);
+        }
+    }
+
+    let parsed = lines - skipped.total;
+    let reasons = [
+        ("short line", skipped.short_line),
+        ("empty counters", skipped.empty_counters),
+        ("parse failure", skipped.parse_failure),
+        ("missing field", skipped.missing_field),
+        ("unrecognized", skipped.unrecognized),
+        ("too long", skipped.too_long),
+        ("binary", skipped.binary),
+        ("undecodable", skipped.undecodable),
+    ]
+    .iter()
+    .filter(|(_, count)| *count > 0)
+    .map(|(reason, count)| format!("{} {}", reason, count))
+    .collect::<Vec<_>>()
+    .join(", ");
+    let verdict = if parsed > 0 { "ok  " } else { "FAIL" };
+    println!("{}  sample {}: {} of {} lines parsed", verdict, path.display(), parsed, lines);
+    if !reasons.is_empty() {
+        println!("      skipped: {}", reasons);
+    }
+    parsed > 0
+}
+
+fn first_input(dir: &Path) -> Option<PathBuf> {
+    let mut files: Vec<_> = fs::read_dir(dir)
+        .ok()?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.is_file())
+        .collect();
+    files.sort();
+    files.into_iter().next()
+}
+
+/// Network destinations the options point at, as `(what, host:port)`.
+fn targets(cli: &Cli) -> Vec<(&'static str, String)> {
+    let mut targets = Vec::new();
+    if let Some(addr) = &cli.redis_addr {
+        targets.push(("redis", addr.clone()));
+    }
+    let urls = [
+        ("clickhouse", &cli.clickhouse_url),
+        ("influx", &cli.influx_url),
+        ("elasticsearch", &cli.elasticsearch_url),
+        ("http", &cli.http_url),
+        ("otlp", &cli.otlp_endpoint),
+    ];
+    for (name, url) in urls {
+        if let Some(url) = url {
+            targets.push((name, authority(url)));
+        }
+    }
+    // UDP has no connection to try
+    if let Some(forward) = &cli.forward
+        && forward.transport == Transport::Tcp
+    {
+        targets.push(("forward", forward.addr.clone()));
+    }
+    targets
+}
+
+/// `host:port` of a URL, filling in the scheme's default port.
+fn authority(url: &str) -> String {
+    let (scheme, rest) = url.split_once("://").unwrap_or(("http", url));
+    let host = rest.split(['/', '?']).next().unwrap_or_default();
+    let host = host.rsplit_once('@').map_or(host, |(_, host)| host);
+    match host.rsplit_once(':') {
+        Some((_, port)) if port.parse::<u16>().is_ok() => host.to_string(),
+        _ => format!("{}:{}", host, if scheme == "https" { 443 } else { 80 }),
+    }
+}
+
+fn reachable(target: &str) -> std::io::Result<()> {
+    let mut last_error = None;
+    for addr in target.to_socket_addrs()? {
+        match TcpStream::connect_timeout(&addr, Duration::from_secs(5)) {
+            Ok(_) => return Ok(()),
+            Err(err) => last_error = Some(err),
+        }
+    }
+    Err(last_error.unwrap_or_else(|| std::io::Error::new(std::io::ErrorKind::NotFound, "no addresses")))
+}
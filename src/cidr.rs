@@ -0,0 +1,116 @@
+//! IPv4/IPv6 address blocks.
+
+use std::fmt;
+use std::net::IpAddr;
+use std::str::FromStr;
+
+/// An address block, held as the masked network address widened to 128
+/// bits plus its prefix length.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Cidr {
+    network: u128,
+    prefix: u32,
+    v4: bool,
+}
+
+fn bits(address: IpAddr) -> (u128, bool) {
+    match address {
+        IpAddr::V4(address) => (u32::from(address) as u128, true),
+        IpAddr::V6(address) => (u128::from(address), false),
+    }
+}
+
+fn mask(prefix: u32, v4: bool) -> u128 {
+    let width = if v4 { 32 } else { 128 };
+    match prefix {
+        0 => 0,
+        prefix => (!0u128 >> (128 - width)) & !((1u128 << (width - prefix)) - 1),
+    }
+}
+
+impl Cidr {
+    pub fn prefix(&self) -> u32 {
+        self.prefix
+    }
+
+    pub fn is_v4(&self) -> bool {
+        self.v4
+    }
+
+    pub fn contains(&self, address: IpAddr) -> bool {
+        let (address, v4) = bits(address);
+        v4 == self.v4 && address & mask(self.prefix, v4) == self.network
+    }
+
+    /// The block of `prefix` bits that holds `address`.
+    pub fn of(address: IpAddr, prefix: u32) -> Cidr {
+        let (address, v4) = bits(address);
+        let prefix = prefix.min(if v4 { 32 } else { 128 });
+        Cidr {
+            network: address & mask(prefix, v4),
+            prefix,
+            v4,
+        }
+    }
+}
+
+impl FromStr for Cidr {
+    type Err = String;
+
+    /// `a.b.c.d/n`, `x::y/n`, or a bare address as a single-host block.
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let (address, prefix) = match value.split_once('/') {
+            Some((address, prefix)) => (address, Some(prefix)),
+            None => (value, None),
+        };
+        let address: IpAddr = address.trim().parse().map_err(|_| format!("`{}` is not an IP address or CIDR block", value))?;
+        let width = if address.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(prefix) => prefix
+                .trim()
+                .parse::<u32>()
+                .ok()
+                .filter(|prefix| *prefix <= width)
+                .ok_or_else(|| format!("`{}` has an invalid prefix length", value))?,
+            None => width,
+        };
+        Ok(Cidr::of(address, prefix))
+    }
+}
+
+impl fmt::Display for Cidr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let address = match self.v4 {
+            true => IpAddr::from((self.network as u32).to_be_bytes()),
+            false => IpAddr::from(self.network.to_be_bytes()),
+        };
+        write!(f, "{}/{}", address, self.prefix)
+    }
+}
+
+/// Whether `address` is globally routable, i.e. not private, loopback,
+/// link-local, shared (CGNAT), multicast, documentation or unspecified.
+pub fn is_public(address: IpAddr) -> bool {
+    match address {
+        IpAddr::V4(address) => {
+            let [a, b, ..] = address.octets();
+            !(address.is_private()
+                || address.is_loopback()
+                || address.is_link_local()
+                || address.is_broadcast()
+                || address.is_documentation()
+                || address.is_unspecified()
+                || address.is_multicast()
+                || (a == 100 && (64..128).contains(&b)))
+        }
+        IpAddr::V6(address) => {
+            let first = address.segments()[0];
+            !(address.is_loopback()
+                || address.is_unspecified()
+                || address.is_multicast()
+                || (first & 0xfe00) == 0xfc00
+                || (first & 0xffc0) == 0xfe80
+                || first == 0x2001 && address.segments()[1] == 0x0db8)
+        }
+    }
+}
@@ -0,0 +1,68 @@
+//! Direction of traffic relative to the internal network.
+//!
+//! Addresses in RFC 1918 and unique-local (`fc00::/7`) space are internal,
+//! plus any `--internal-prefix` blocks, e.g. public ranges the organisation
+//! owns. Each flow gets one of the class names below as a tag, and the
+//! output metadata totals the flows per class.
+
+use std::collections::BTreeMap;
+use std::net::IpAddr;
+
+use crate::cidr::Cidr;
+use crate::record::Record;
+use crate::summary::{self, Totals};
+
+pub const CLASSES: [&str; 4] = ["internal-to-internal", "internal-to-external", "external-to-internal", "external-to-external"];
+
+const PRIVATE: [&str; 4] = ["10.0.0.0/8", "172.16.0.0/12", "192.168.0.0/16", "fc00::/7"];
+
+#[derive(Debug, Clone)]
+pub struct Networks {
+    internal: Vec<Cidr>,
+}
+
+impl Networks {
+    pub fn new(extra: &[Cidr]) -> Self {
+        let mut internal: Vec<Cidr> = PRIVATE.iter().map(|block| block.parse().expect("valid built-in block")).collect();
+        internal.extend_from_slice(extra);
+        Networks { internal }
+    }
+
+    pub fn is_internal(&self, address: &str) -> bool {
+        address
+            .parse::<IpAddr>()
+            .is_ok_and(|address| self.internal.iter().any(|block| block.contains(address)))
+    }
+
+    /// The class of the flow; addresses that don't parse count as external.
+    pub fn class(&self, record: &Record) -> &'static str {
+        match (self.is_internal(&record.source_ip), self.is_internal(&record.destination_ip)) {
+            (true, true) => CLASSES[0],
+            (true, false) => CLASSES[1],
+            (false, true) => CLASSES[2],
+            (false, false) => CLASSES[3],
+        }
+    }
+
+    /// Tag every record with its class.
+    pub fn apply<'a>(&self, records: impl IntoIterator<Item = &'a mut Record>) {
+        for record in records {
+            let class = self.class(record);
+            if !record.tags.iter().any(|tag| tag == class) {
+                record.tags.push(class.to_string());
+            }
+        }
+    }
+}
+
+/// The class tag `apply` gave the record, if any.
+fn tagged_class(record: &Record) -> Option<&str> {
+    record.tags.iter().map(String::as_str).find(|tag| CLASSES.contains(tag))
+}
+
+/// Totals per class of the tagged records.
+pub fn totals<'a>(records: impl IntoIterator<Item = &'a Record>) -> BTreeMap<String, Totals> {
+    summary::group_by(records.into_iter().filter(|record| tagged_class(record).is_some()), |record| {
+        tagged_class(record).unwrap_or_default()
+    })
+}
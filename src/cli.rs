@@ -0,0 +1,787 @@
+use std::path::PathBuf;
+
+use clap::{Parser, Subcommand, ValueEnum};
+
+use crate::backpressure::Overflow;
+use crate::bounds::{self, OverLimit};
+use crate::cidr::Cidr;
+use crate::closes::{self, CloseRule};
+use crate::enrich::Side;
+use crate::flush::FlushEvery;
+use crate::hll;
+use crate::ignore::FlowPattern;
+use crate::journal;
+use crate::lines::{self, Encoding};
+use crate::graph::GraphFormat;
+use crate::listen::Endpoint;
+use crate::misp::ThreatExport;
+use crate::parser::InputFormat;
+use crate::payload::OutputFormat;
+use crate::prefilter::{self, Severity};
+use crate::query::{Filter, SortKey};
+use crate::record::{self, Dimension, NatSide};
+use crate::report::{ByteUnits, Grouping, ReportFormat};
+use crate::rollup;
+use crate::space;
+use crate::spool::AfterProcessing;
+use crate::telemetry::StatsdFormat;
+use crate::throttle::Rate;
+
+/// How much a run prints; an alternative to `--quiet` and `--verbose`
+/// for deployments that configure a level.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+pub enum LogLevel {
+    /// Only errors and warnings, like `--quiet`
+    Error,
+    /// Same as `error`; warnings always print
+    Warn,
+    /// Status messages and the summary
+    Info,
+    /// Also per-file statistics, like `--verbose`
+    Debug,
+}
+
+/// Where raw log lines are read from.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum Source {
+    /// Every file in the syslog directory
+    #[default]
+    Files,
+    /// A Kafka topic, consumed until idle (requires the `kafka` feature)
+    Kafka,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Aggregate the configured --source once and write the output (the default)
+    Process,
+    /// Follow live log files, handling truncation and rotation, until they go idle
+    Watch {
+        #[arg(required = true, value_name = "FILE")]
+        files: Vec<PathBuf>,
+    },
+    /// Receive syslog on the --listen endpoints until idle
+    Serve,
+    /// List the flows of an output that match the filters
+    Query {
+        file: PathBuf,
+        #[command(flatten)]
+        filter: Filter,
+        #[arg(long, value_enum, default_value_t = SortKey::Bytes)]
+        sort: SortKey,
+        /// Show at most this many flows (0 for all)
+        #[arg(long, default_value_t = 20)]
+        limit: usize,
+    },
+    /// Filter, sort and group the flows of an output at an interactive prompt
+    Explore { file: PathBuf },
+    /// Combine outputs into one, summing flows with the same key
+    Merge {
+        #[arg(required = true, num_args = 2.., value_name = "FILE")]
+        files: Vec<PathBuf>,
+    },
+    /// Show the flows that appeared, disappeared or changed between two outputs
+    Diff {
+        old: PathBuf,
+        new: PathBuf,
+        /// Show at most this many changes (0 for all)
+        #[arg(long, default_value_t = 20)]
+        limit: usize,
+    },
+    /// Render the report of an existing output
+    Report {
+        file: PathBuf,
+        #[arg(long, value_enum, default_value_t = ReportFormat::Md)]
+        format: ReportFormat,
+    },
+    /// Check outputs against the SHA-256 and size recorded in the manifest
+    Verify {
+        /// Only verify these outputs (default: every manifest entry)
+        files: Vec<String>,
+    },
+    /// Show week-over-week changes of the headline numbers kept in the trend ledger
+    Trend {
+        /// Show at most this many of the latest weeks
+        #[arg(long, default_value_t = 8)]
+        weeks: usize,
+    },
+    /// Export, snapshot or restore the aggregation state
+    State {
+        #[command(subcommand)]
+        action: StateCommand,
+    },
+    /// Send the deliveries kept in --dead-letter-dir to the sinks configured now
+    Redeliver,
+    /// Validate the options, try the input format on a sample file and print the effective configuration
+    CheckConfig {
+        /// Input file to try the input format on (default: the first file in the input directory)
+        #[arg(long)]
+        sample: Option<PathBuf>,
+        /// Also check that every configured sink and collector accepts connections
+        #[arg(long)]
+        connect: bool,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum StateCommand {
+    /// Write the --state-dir records as an output payload
+    Export,
+    /// Ask the running `serve` to write its current window to --snapshot-file, and wait for it
+    Snapshot {
+        /// Give up after this many seconds
+        #[arg(long, default_value_t = 30)]
+        timeout: u64,
+    },
+    /// Stage a snapshot for the next run to resume from (not needed with --state-dir, which resumes by itself)
+    Restore {
+        /// Snapshot to stage (default: --snapshot-file)
+        file: Option<PathBuf>,
+    },
+}
+
+#[derive(Parser, Debug)]
+#[command(version, about = "Aggregate firewall syslog sessions into per-flow totals", args_override_self = true)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    /// TOML file of options keyed by long option name; options given on the command line override it.
+    /// Options can also be set as `RDP_<NAME>` environment variables (e.g. `RDP_INPUT_DIR`), under both
+    #[arg(global = true, long, value_name = "FILE")]
+    pub config: Option<PathBuf>,
+
+    /// Run only this tenant of the options file's `[tenant.NAME]` tables
+    #[arg(global = true, long, value_name = "NAME")]
+    pub tenant: Option<String>,
+
+    /// Layout of the input log lines
+    #[arg(global = true, long, value_enum, default_value_t = InputFormat::Csv)]
+    pub input_format: InputFormat,
+
+    /// What counts as a session close for an input format, as `<format>=<rule>` with the rule auto, end-reason, fin-rst, match:<word>,..., any or never (repeatable; filterlog defaults to fin-rst, the rest to auto)
+    #[arg(global = true, long, value_name = "FORMAT=RULE", value_parser = closes::parse_setting)]
+    pub session_close: Vec<(InputFormat, CloseRule)>,
+
+    /// Regex with named capture groups (or Grok `%{IP:source_ip}` references) for `--input-format regex`
+    #[arg(global = true, long, required_if_eq("input_format", "regex"))]
+    pub pattern: Option<String>,
+
+    /// Extra key aliases for `--input-format kv`, as `field=name1,name2`; may be repeated
+    #[arg(global = true, long = "kv-alias", value_name = "FIELD=NAMES")]
+    pub kv_aliases: Vec<String>,
+
+    /// Skip lines with fewer columns or key/value pairs than this (the format's own minimum always applies)
+    #[arg(global = true, long, default_value_t = 0)]
+    pub min_fields: usize,
+
+    /// Skip lines longer than this many bytes without buffering them, and count them as too long
+    #[arg(global = true, long, value_name = "BYTES", default_value_t = lines::DEFAULT_MAX_LINE)]
+    pub max_line_length: usize,
+
+    /// Treat counters above this (bytes, packets or milliseconds of one session) as bogus; 0 turns the check off
+    #[arg(global = true, long, value_name = "N", default_value_t = bounds::DEFAULT_LIMIT)]
+    pub counter_limit: u64,
+
+    /// Clamp counters above --counter-limit to it, or keep them and tag their records
+    #[arg(global = true, long, value_enum, default_value_t = OverLimit::Clamp)]
+    pub counter_over_limit: OverLimit,
+
+    /// Attach a static label to the output's metadata and every record, e.g. `datacenter=fra1`; may be repeated
+    #[arg(global = true, long = "label", value_name = "KEY=VALUE", value_parser = record::parse_label)]
+    pub labels: Vec<(String, String)>,
+
+    /// Aggregate a firewall address under a logical device name, e.g. `10.0.0.2=fw-ha-fra1` for both members of
+    /// an HA pair so failover doesn't split their records; may be repeated
+    #[arg(global = true, long = "device-alias", value_name = "ADDR=NAME", value_parser = record::parse_label)]
+    pub device_aliases: Vec<(String, String)>,
+
+    /// Read input files at most this fast, e.g. `20MB/s` or `5000lines/s`, to leave disk bandwidth to other
+    /// services on a shared log server; the limit holds across every reader of the run
+    #[arg(global = true, long, value_name = "RATE")]
+    pub throttle: Option<Rate>,
+
+    /// How to decode lines that aren't valid UTF-8, e.g. 8-bit vendor characters in hostnames
+    #[arg(global = true, long, value_enum, default_value_t = Encoding::Utf8)]
+    pub input_encoding: Encoding,
+
+    /// Left-join the columns of this asset inventory CSV onto records by IP address
+    #[arg(global = true, long, value_name = "CSV")]
+    pub enrich_map: Option<PathBuf>,
+
+    /// Addresses the asset inventory is matched against (comma-separated)
+    #[arg(global = true, long, value_enum, value_delimiter = ',', default_value = "source-ip")]
+    pub enrich_on: Vec<Side>,
+
+    /// Label addresses with hostnames from a dnsmasq leases file or hosts file; may be repeated
+    #[arg(global = true, long, value_name = "FILE")]
+    pub hosts_file: Vec<PathBuf>,
+
+    /// Directory of DNS query logs (dnsmasq or Zeek dns.log) to name flow destinations from
+    #[arg(global = true, long, value_name = "DIR")]
+    pub dns_logs: Option<PathBuf>,
+
+    /// IEEE oui.txt or oui.csv extending the built-in MAC vendor table
+    #[arg(global = true, long, value_name = "FILE")]
+    pub oui_file: Option<PathBuf>,
+
+    /// Tag flows whose source or destination is on this IP/CIDR blocklist (file or http(s) URL); may be repeated
+    #[arg(global = true, long, value_name = "FILE|URL")]
+    pub blocklist: Vec<String>,
+
+    /// Seconds after which blocklist URLs are fetched again before matching
+    #[arg(global = true, long, default_value_t = 3600)]
+    pub blocklist_refresh: u64,
+
+    /// Attach the AS and country of addresses from this iptoasn.com ip2asn TSV (optionally .gz)
+    #[arg(global = true, long, value_name = "TSV")]
+    pub asn_db: Option<PathBuf>,
+
+    /// Addresses looked up in the --asn-db (comma-separated)
+    #[arg(global = true, long, value_enum, value_delimiter = ',', default_value = "source-ip,destination-ip")]
+    pub asn_on: Vec<Side>,
+
+    /// Don't write the per-country and per-AS rollups that enriched flows otherwise get next to a file output
+    #[arg(global = true, long)]
+    pub no_geo_rollups: bool,
+
+    /// Look up the network owner of public destination addresses through RDAP
+    #[arg(global = true, long)]
+    pub rdap: bool,
+
+    /// RDAP service the address is appended to
+    #[arg(global = true, long, default_value = "https://rdap.org/ip/")]
+    pub rdap_url: String,
+
+    /// Cache of RDAP answers, reused across runs
+    #[arg(global = true, long, default_value = "./output/rdap-cache.json")]
+    pub rdap_cache: PathBuf,
+
+    /// Days a cached RDAP answer is trusted before it is looked up again
+    #[arg(global = true, long, default_value_t = 30)]
+    pub rdap_cache_days: u64,
+
+    /// Most RDAP lookups per run; further addresses wait for the next run
+    #[arg(global = true, long, default_value_t = 100)]
+    pub rdap_limit: usize,
+
+    /// Most RDAP lookups per second
+    #[arg(global = true, long, default_value_t = 1.0)]
+    pub rdap_rate: f64,
+
+    /// Start from the records of this earlier output and add this run's input on top; files of the same size and content as one it already covers are skipped
+    #[arg(global = true, long, value_name = "FILE")]
+    pub seed: Option<PathBuf>,
+
+    /// Keep the aggregation state in this directory, so records accumulate across runs and restarts
+    #[arg(global = true, long, value_name = "DIR")]
+    pub state_dir: Option<PathBuf>,
+
+    /// Seconds between state checkpoints while serving
+    #[arg(global = true, long, default_value_t = 60)]
+    pub state_sync: u64,
+
+    /// Seconds between compactions of the state journal while serving (every run ends with one)
+    #[arg(global = true, long, default_value_t = 3600)]
+    pub state_compact: u64,
+
+    /// Where `serve` writes snapshots of its current window
+    #[arg(global = true, long, value_name = "FILE", default_value = "./output/aggregation-snapshot.json")]
+    pub snapshot_file: PathBuf,
+
+    /// Also snapshot the current window every this many seconds while serving
+    #[arg(global = true, long, value_name = "SECS")]
+    pub snapshot_every: Option<u64>,
+
+    /// Append every new or changed flow to this JSONL journal while processing, after each input file
+    #[arg(global = true, long, value_name = "FILE", conflicts_with = "state_dir")]
+    pub journal: Option<PathBuf>,
+
+    /// Seconds between journal entries while following or listening
+    #[arg(global = true, long, value_name = "SECS", default_value_t = journal::DEFAULT_EVERY, requires = "journal")]
+    pub journal_every: u64,
+
+    /// Replay the journal up to its last checkpoint, skip the files it covers and keep appending to it
+    #[arg(global = true, long, requires = "journal")]
+    pub journal_resume: bool,
+
+    /// Keep up to this many raw lines per record as evidence (reservoir-sampled)
+    #[arg(global = true, long, default_value_t = 0)]
+    pub sample_lines: usize,
+
+    /// Break each record's sessions down by the input file (or listener or topic) they came from, for auditing
+    #[arg(global = true, long)]
+    pub record_sources: bool,
+
+    /// Report p50/p90/p99/max of per-session bytes and durations for each record (t-digest)
+    #[arg(global = true, long)]
+    pub percentiles: bool,
+
+    /// Keep at most this many exact flow records; further flows are counted in a count-min sketch
+    /// and only displace the smallest record once they outgrow it
+    #[arg(global = true, long)]
+    pub max_flows: Option<usize>,
+
+    /// Keep flow records on this many worker threads, split by flow key hash (1 keeps them on the
+    /// parsing thread); --max-flows is divided evenly between them
+    #[arg(global = true, long, default_value_t = 1)]
+    pub shards: usize,
+
+    /// Counters per row of the overflow count-min sketch
+    #[arg(global = true, long, default_value_t = 65_536)]
+    pub cms_width: usize,
+
+    /// Rows of the overflow count-min sketch
+    #[arg(global = true, long, default_value_t = 4)]
+    pub cms_depth: usize,
+
+    /// Track approximate distinct ports and peers per host (HyperLogLog) and attach them to records
+    #[arg(global = true, long)]
+    pub distinct_counts: bool,
+
+    /// HyperLogLog precision: 2^N registers per sketch once a host has many peers
+    #[arg(global = true, long, default_value_t = hll::DEFAULT_PRECISION, value_parser = clap::value_parser!(u8).range(4..=16))]
+    pub hll_precision: u8,
+
+    /// Hosts listed per distinct count in the metadata
+    #[arg(global = true, long, default_value_t = 20)]
+    pub distinct_top: usize,
+
+    /// Tag flows internal-to-internal, internal-to-external, external-to-internal or
+    /// external-to-external and total them in `trafficClasses`
+    #[arg(global = true, long)]
+    pub classify: bool,
+
+    /// Orient TCP/UDP events from client to server by TCP flags, first sight, ports and --internal-prefix, so bytes-out is always client to server
+    #[arg(global = true, long)]
+    pub normalize_direction: bool,
+
+    /// Address block counted as internal besides RFC 1918 and fc00::/7 (repeatable)
+    #[arg(global = true, long)]
+    pub internal_prefix: Vec<Cidr>,
+
+    /// Flag sources probing many ports or hosts with tiny flows (`suspectedScans`)
+    #[arg(global = true, long)]
+    pub detect_scans: bool,
+
+    /// Distinct ports a source must probe to count as a port scan
+    #[arg(global = true, long, default_value_t = 100)]
+    pub scan_min_ports: usize,
+
+    /// Distinct hosts a source must probe to count as a host sweep
+    #[arg(global = true, long, default_value_t = 50)]
+    pub scan_min_hosts: usize,
+
+    /// Largest average bytes per session of a probe flow
+    #[arg(global = true, long, default_value_t = 200)]
+    pub scan_max_bytes: u64,
+
+    /// Flag destinations reached by many sources or packets (`suspectedFloods`), alerting under the `flood` rule
+    #[arg(global = true, long)]
+    pub detect_floods: bool,
+
+    /// Distinct sources that make a destination look flooded
+    #[arg(global = true, long, default_value_t = 1000)]
+    pub flood_min_sources: usize,
+
+    /// Packets received that make a destination look flooded
+    #[arg(global = true, long, default_value_t = 1_000_000)]
+    pub flood_min_packets: u64,
+
+    /// Flag periodic low-volume flows to external destinations (`suspectedBeacons`); needs timestamped input
+    #[arg(global = true, long)]
+    pub detect_beacons: bool,
+
+    /// Fewest sessions a flow needs to be judged as a beacon
+    #[arg(global = true, long, default_value_t = 6)]
+    pub beacon_min_sessions: u64,
+
+    /// Largest interval jitter (standard deviation over mean) of a beacon
+    #[arg(global = true, long, default_value_t = 0.1)]
+    pub beacon_max_jitter: f64,
+
+    /// Largest average bytes per session of a beacon
+    #[arg(global = true, long, default_value_t = 4096)]
+    pub beacon_max_bytes: u64,
+
+    /// Shortest mean interval, in seconds, of a beacon
+    #[arg(global = true, long, default_value_t = 10.0)]
+    pub beacon_min_interval: f64,
+
+    /// List outbound-heavy flows to external destinations (`exfiltrationWatchlist`)
+    #[arg(global = true, long)]
+    pub detect_exfil: bool,
+
+    /// Fewest bytes out for a flow to be listed
+    #[arg(global = true, long, default_value_t = 100 * 1024 * 1024)]
+    pub exfil_min_bytes: u64,
+
+    /// Smallest ratio of bytes out to bytes in for a flow to be listed
+    #[arg(global = true, long, default_value_t = 10.0)]
+    pub exfil_min_ratio: f64,
+
+    /// Destination address or CIDR block never listed, e.g. backup targets (repeatable)
+    #[arg(global = true, long)]
+    pub exfil_allow: Vec<Cidr>,
+
+    /// YAML detection rules evaluated over the final records; may be repeated
+    #[arg(global = true, long, value_name = "FILE")]
+    pub rules: Vec<PathBuf>,
+
+    /// POST every rule alert as JSON to this URL
+    #[arg(global = true, long, value_name = "URL")]
+    pub alert_webhook: Option<String>,
+
+    /// Alerts in one run above which the webhook gets a single digest
+    #[arg(global = true, long, default_value_t = 5)]
+    pub alert_digest_after: usize,
+
+    /// When each suppressed rule key last alerted, kept across runs
+    #[arg(global = true, long, default_value = "./output/alert-state.json")]
+    pub alert_state: PathBuf,
+
+    /// Seconds a session open waits for its close before it is counted as expired
+    #[arg(global = true, long, default_value_t = 3600)]
+    pub session_timeout: u64,
+
+    /// Extra dimensions to split flow aggregates by (comma-separated)
+    #[arg(global = true, long, value_enum, value_delimiter = ',')]
+    pub group_by: Vec<Dimension>,
+
+    /// Build flow keys from pre- or post-NAT addresses
+    #[arg(global = true, long, value_enum, default_value_t = NatSide::PreNat)]
+    pub key_on: NatSide,
+
+    /// Where to read raw log lines from
+    #[arg(global = true, long, value_enum, default_value_t = Source::Files)]
+    pub source: Source,
+
+    /// Kafka bootstrap servers
+    #[arg(global = true, long, default_value = "localhost:9092")]
+    pub kafka_brokers: String,
+
+    /// Kafka topic carrying raw log lines
+    #[arg(global = true, long, default_value = "firewall-syslog")]
+    pub kafka_topic: String,
+
+    /// Kafka consumer group
+    #[arg(global = true, long, default_value = "syslog_processor")]
+    pub kafka_group: String,
+
+    /// Stop consuming after this many seconds without a message
+    #[arg(global = true, long, default_value_t = 30)]
+    pub kafka_idle_timeout: u64,
+
+    /// Endpoint `serve` receives syslog on: udp://, tcp://, tls:// or relp:// host:port; may be repeated
+    #[arg(global = true, long, value_name = "URL")]
+    pub listen: Vec<Endpoint>,
+
+    /// PEM certificate chain for tls:// listeners
+    #[arg(global = true, long)]
+    pub tls_cert: Option<PathBuf>,
+
+    /// PEM private key for tls:// listeners
+    #[arg(global = true, long)]
+    pub tls_key: Option<PathBuf>,
+
+    /// Stop listening after this many seconds without a message
+    #[arg(global = true, long, default_value_t = 30)]
+    pub listen_idle_timeout: u64,
+
+    /// Serve sliding 1/5/15-minute top talkers as JSON on this host:port (`GET /top`)
+    #[arg(global = true, long, value_name = "ADDR")]
+    pub top_api: Option<String>,
+
+    /// Sources tracked per 10-second slice of the top talkers
+    #[arg(global = true, long, default_value_t = 200)]
+    pub top_capacity: usize,
+
+    /// Top talkers returned per window unless the request asks for `?n=`
+    #[arg(global = true, long, default_value_t = 10)]
+    pub top_n: usize,
+
+    /// Also relay every received raw message to this udp:// or tcp:// collector
+    #[arg(global = true, long, value_name = "URL", requires = "listen")]
+    pub forward: Option<Endpoint>,
+
+    /// Messages buffered for --forward while its target is unreachable
+    #[arg(global = true, long, default_value_t = 100_000)]
+    pub forward_buffer: usize,
+
+    /// Received messages held in memory while aggregation falls behind
+    #[arg(global = true, long, default_value_t = 100_000)]
+    pub listen_buffer: usize,
+
+    /// What to do with messages arriving while the listener's buffer is full
+    #[arg(global = true, long, value_enum, default_value_t = Overflow::Block)]
+    pub listen_overflow: Overflow,
+
+    /// Directory for messages spilled by `--listen-overflow spill`
+    #[arg(global = true, long, value_name = "DIR")]
+    pub listen_spill_dir: Option<PathBuf>,
+
+    /// Only parse received messages of this syslog facility (e.g. `local7` or `23`); may be repeated
+    #[arg(global = true, long, value_name = "FACILITY", value_parser = prefilter::parse_facility, requires = "listen")]
+    pub listen_facility: Vec<u8>,
+
+    /// Only parse received messages at least this severe
+    #[arg(global = true, long, value_enum, value_name = "SEVERITY", requires = "listen")]
+    pub listen_severity: Option<Severity>,
+
+    /// Only parse received messages from this program (syslog tag or APP-NAME, e.g. `filterlog`); may be repeated
+    #[arg(global = true, long, value_name = "NAME", requires = "listen")]
+    pub listen_program: Vec<String>,
+
+    /// Read files followed by `watch` from their start rather than only lines written from now on
+    #[arg(global = true, long)]
+    pub tail_from_start: bool,
+
+    /// Stop following after this many seconds without a new line
+    #[arg(global = true, long, default_value_t = 60)]
+    pub tail_idle_timeout: u64,
+
+    /// Also XADD every record to a Redis stream at this address (host:port)
+    #[arg(global = true, long)]
+    pub redis_addr: Option<String>,
+
+    /// Redis stream the records are added to
+    #[arg(global = true, long, default_value = "syslog_processor:flows")]
+    pub redis_stream: String,
+
+    /// Also insert every record into ClickHouse through its HTTP interface (e.g. http://localhost:8123)
+    #[arg(global = true, long)]
+    pub clickhouse_url: Option<String>,
+
+    /// ClickHouse table the flows are inserted into
+    #[arg(global = true, long, default_value = "flows")]
+    pub clickhouse_table: String,
+
+    #[arg(global = true, long)]
+    pub clickhouse_user: Option<String>,
+
+    #[arg(global = true, long)]
+    pub clickhouse_password: Option<String>,
+
+    /// Rows per INSERT
+    #[arg(global = true, long, default_value_t = 10000)]
+    pub clickhouse_batch_size: usize,
+
+    /// Also write per-window series in InfluxDB line protocol to this write URL
+    #[arg(global = true, long)]
+    pub influx_url: Option<String>,
+
+    /// InfluxDB API token, sent as `Authorization: Token ...`
+    #[arg(global = true, long)]
+    pub influx_token: Option<String>,
+
+    /// Number of destination ports reported in the `syslog_port` series
+    #[arg(global = true, long, default_value_t = 20)]
+    pub influx_top_ports: usize,
+
+    /// Also index every record into Elasticsearch or OpenSearch through the `_bulk` API (e.g. http://localhost:9200)
+    #[arg(global = true, long)]
+    pub elasticsearch_url: Option<String>,
+
+    /// Index the flow documents are written to
+    #[arg(global = true, long, default_value = "syslog-flows")]
+    pub elasticsearch_index: String,
+
+    /// Elasticsearch API key, sent as `Authorization: ApiKey ...`
+    #[arg(global = true, long)]
+    pub elasticsearch_api_key: Option<String>,
+
+    /// Documents per `_bulk` request
+    #[arg(global = true, long, default_value_t = 5000)]
+    pub elasticsearch_batch_size: usize,
+
+    /// Also POST the records to this URL
+    #[arg(global = true, long, value_name = "URL")]
+    pub http_url: Option<String>,
+
+    /// Body of the HTTP sink's requests: one JSON document per run, or NDJSON records in requests of --http-batch-size
+    #[arg(global = true, long, value_enum, default_value_t = OutputFormat::Json)]
+    pub http_format: OutputFormat,
+
+    /// Records per NDJSON request to the HTTP sink
+    #[arg(global = true, long, default_value_t = 5000)]
+    pub http_batch_size: usize,
+
+    /// Send the HTTP sink's requests gzip-compressed, with `Content-Encoding: gzip`
+    #[arg(global = true, long)]
+    pub http_gzip: bool,
+
+    /// Token sent to the HTTP sink as `Authorization: Bearer ...`
+    #[arg(global = true, long)]
+    pub http_token: Option<String>,
+
+    /// Times a failed sink delivery is retried before it is given up
+    #[arg(global = true, long, default_value_t = 3)]
+    pub sink_retries: u32,
+
+    /// Milliseconds before the first sink retry, doubled for each one after
+    #[arg(global = true, long, default_value_t = 1000)]
+    pub sink_backoff_ms: u64,
+
+    /// Keep the records of sink deliveries that still fail after retrying here, for `redeliver`
+    #[arg(global = true, long)]
+    pub dead_letter_dir: Option<PathBuf>,
+
+    /// Time each stage of the run (reading, parsing, aggregating, enrichment, ...) and record it in the output
+    #[arg(global = true, long)]
+    pub stage_timing: bool,
+
+    /// Export pipeline spans and stage metrics to this OTLP/HTTP collector (e.g. http://localhost:4318)
+    #[arg(global = true, long)]
+    pub otlp_endpoint: Option<String>,
+
+    /// Push the run's metrics to this Prometheus pushgateway when it ends (e.g. http://localhost:9091)
+    #[arg(global = true, long, value_name = "URL")]
+    pub pushgateway_url: Option<String>,
+
+    /// Job name the run's metrics are grouped under on the pushgateway
+    #[arg(global = true, long, value_name = "NAME", default_value = "syslog_processor")]
+    pub pushgateway_job: String,
+
+    /// Send the run's metrics to this StatsD daemon when it ends (e.g. 127.0.0.1:8125)
+    #[arg(global = true, long, value_name = "HOST:PORT")]
+    pub statsd_addr: Option<String>,
+
+    /// Whether StatsD metrics carry their attributes in the name or as DogStatsD tags
+    #[arg(global = true, long, value_enum, default_value_t = StatsdFormat::Statsd)]
+    pub statsd_format: StatsdFormat,
+
+    /// Also write a human-readable report next to the JSON output
+    #[arg(global = true, long, value_enum)]
+    pub report: Option<ReportFormat>,
+
+    /// Units of byte counts in reports: binary (GiB), decimal (GB) or plain bytes
+    #[arg(global = true, long, value_enum, default_value_t = ByteUnits::Iec)]
+    pub report_units: ByteUnits,
+
+    /// Thousands separator of numbers in reports; `period` also makes the decimal mark a comma
+    #[arg(global = true, long, value_enum, default_value_t = Grouping::None)]
+    pub report_grouping: Grouping,
+
+    /// Also export the source -> destination talker graph
+    #[arg(global = true, long, value_enum)]
+    pub graph: Option<GraphFormat>,
+
+    /// Also export the flagged flows (blocklist matches) for MISP or as STIX 2.1
+    #[arg(global = true, long, value_enum)]
+    pub threat_export: Option<ThreatExport>,
+
+    /// Also write subnet-pair totals at these IPv4 prefix lengths, e.g. `/24,/16`
+    #[arg(global = true, long, value_delimiter = ',', value_parser = rollup::parse_v4_prefix)]
+    pub rollup: Vec<u32>,
+
+    /// IPv6 prefix lengths paired with the `--rollup` ones by position
+    #[arg(global = true, long, value_delimiter = ',', value_parser = rollup::parse_v6_prefix, default_value = "/64,/48")]
+    pub rollup_v6: Vec<u32>,
+
+    /// Prefix-to-site mapping (`prefix name`, names like `region/site/zone`);
+    /// writes site-to-site traffic matrices next to the output
+    #[arg(global = true, long)]
+    pub sites: Option<PathBuf>,
+
+    /// Leave out graph edges carrying fewer bytes than this
+    #[arg(global = true, long, default_value_t = 0)]
+    pub graph_min_bytes: u64,
+
+    /// Suppress the end-of-run summary and status messages
+    #[arg(global = true, short, long, conflicts_with = "verbose")]
+    pub quiet: bool,
+
+    /// Include per-file statistics in the end-of-run summary
+    #[arg(global = true, short, long)]
+    pub verbose: bool,
+
+    /// Output level; `error` and `warn` act as --quiet and `debug` as --verbose unless one of those is given
+    #[arg(global = true, long, value_enum)]
+    pub log_level: Option<LogLevel>,
+
+    /// Seconds after which another run's lock file is considered stale
+    #[arg(global = true, long, default_value_t = 6 * 3600)]
+    pub lock_stale_after: u64,
+
+    /// Write an ed25519 detached signature (`<output>.sig`) using this PKCS#8 PEM private key
+    #[arg(global = true, long)]
+    pub sign_key: Option<PathBuf>,
+
+    /// Also write an age-encrypted copy (`<output>.age`) for this recipient; may be repeated
+    #[arg(global = true, long = "encrypt-to", value_name = "RECIPIENT")]
+    pub encrypt_to: Vec<String>,
+
+    /// Directory whose files `process` reads
+    #[arg(global = true, long, value_name = "DIR", default_value = "./syslog")]
+    pub input_dir: PathBuf,
+
+    /// Also write the records so far every `<n>-files` or interval (`10m`, `90s`, `2h`) of a batch run,
+    /// as `<output>.partial-NNNN.json` marked partial; percentiles and beacons in the final output then
+    /// only cover the time since the last one
+    #[arg(global = true, long, value_name = "EVERY")]
+    pub flush_every: Option<FlushEvery>,
+
+    /// Read input files through io_uring with reads for several files in flight (Linux; requires the `io-uring` feature)
+    #[arg(global = true, long)]
+    pub io_uring: bool,
+
+    /// Files read ahead at once with --io-uring
+    #[arg(global = true, long, default_value_t = 16)]
+    pub io_uring_depth: usize,
+
+    /// Directory for timestamped outputs, the output manifest, the trend ledger and the run lock
+    #[arg(global = true, long, value_name = "DIR", default_value = "./output")]
+    pub output_dir: PathBuf,
+
+    /// Sum flows matching this SOURCE,DESTINATION[,PORT[,PROTOCOL[,FIREWALL]]] pattern into the metadata's ignored bucket instead of the output, e.g. `10.0.5.0/24,10.0.9.20,2049` (repeatable; `*` matches anything)
+    #[arg(global = true, long, value_name = "PATTERN")]
+    pub ignore: Vec<FlowPattern>,
+
+    /// Leave flows with fewer bytes (in plus out) than this out of the payload; their totals stay in the metadata
+    #[arg(global = true, long, value_name = "BYTES", default_value_t = 0)]
+    pub min_bytes: u64,
+
+    /// Leave flows with fewer sessions than this out of the payload; their totals stay in the metadata
+    #[arg(global = true, long, value_name = "N", default_value_t = 0)]
+    pub min_count: u64,
+
+    /// Emit only the K largest flows, by --by, and sum the rest into the metadata's other bucket
+    #[arg(global = true, long, value_name = "K")]
+    pub top_k: Option<usize>,
+
+    /// What --top-k ranks flows by
+    #[arg(global = true, long = "by", value_name = "KEY", value_enum, default_value_t = SortKey::Bytes, requires = "top_k")]
+    pub top_k_by: SortKey,
+
+    /// Write the payload here instead of a timestamped file in the output directory; `-` streams it to stdout
+    #[arg(global = true, short, long, value_name = "PATH")]
+    pub output: Option<String>,
+
+    /// Payload layout; JSON is pretty-printed when written to a file
+    #[arg(global = true, long, value_enum, default_value_t = OutputFormat::Json)]
+    pub output_format: OutputFormat,
+
+    /// Refuse to write an output unless its filesystem has room for it plus this percentage more
+    #[arg(global = true, long, value_name = "PERCENT", default_value_t = space::DEFAULT_MARGIN)]
+    pub space_margin: f64,
+
+    /// Write outputs without checking the filesystem has room for them first
+    #[arg(global = true, long)]
+    pub no_space_check: bool,
+
+    /// Copy input files whose skipped-line share exceeds --quarantine-threshold into this directory
+    #[arg(global = true, long)]
+    pub quarantine_dir: Option<PathBuf>,
+
+    /// Percentage of skipped lines above which a file is quarantined
+    #[arg(global = true, long, default_value_t = 50.0)]
+    pub quarantine_threshold: f64,
+
+    /// Move quarantined files instead of copying them
+    #[arg(global = true, long, requires = "quarantine_dir")]
+    pub quarantine_move: bool,
+
+    /// What to do with input files once the output is written: `none`, `delete` or `move:<dir>`
+    #[arg(global = true, long, value_name = "ACTION", default_value = "none")]
+    pub after_processing: AfterProcessing,
+}
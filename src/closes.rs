@@ -0,0 +1,169 @@
+//! What counts as a session close.
+//!
+//! The run's `sessionClose` count used to take every event with counters,
+//! which packet logs like filterlog inflate to one per packet. Each input
+//! format has a rule instead, settable with `--session-close
+//! <format>=<rule>`:
+//!
+//! - `auto`: by the event type when the line logs one (types naming a
+//!   close, end, teardown, timeout or stop), else every event — the
+//!   default for session-based formats, whose lines mostly are closes;
+//! - `end-reason`: events with an end reason, or closes matched to their
+//!   open;
+//! - `fin-rst`: TCP events with FIN or RST among their flags — the default
+//!   for filterlog;
+//! - `match:<word>,...`: events whose type or end reason contains one of
+//!   the words, case-insensitively;
+//! - `any`: every event, as before;
+//! - `never`.
+
+use std::fmt;
+use std::str::FromStr;
+
+use crate::parser::{EventKind, FlowEvent, InputFormat};
+
+/// Words in an event type that mark the end of a session.
+const CLOSE_TYPES: &[&str] = &["close", "end", "teardown", "timeout", "stop"];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CloseRule {
+    Auto,
+    EndReason,
+    FinRst,
+    Matching(Vec<String>),
+    Any,
+    Never,
+}
+
+impl CloseRule {
+    /// The rule `format` gets unless one is configured.
+    pub fn default_for(format: InputFormat) -> Self {
+        match format {
+            InputFormat::Filterlog => CloseRule::FinRst,
+            _ => CloseRule::Auto,
+        }
+    }
+
+    /// The rule configured for `format` in `rules`, or its default.
+    pub fn for_format(format: InputFormat, rules: &[(InputFormat, CloseRule)]) -> Self {
+        rules.iter().rev().find(|(of, _)| *of == format).map_or_else(|| CloseRule::default_for(format), |(_, rule)| rule.clone())
+    }
+
+    /// Whether `event` closed a session.
+    pub fn closes(&self, event: &FlowEvent) -> bool {
+        let contains = |value: &Option<String>, words: &[&str]| {
+            value.as_deref().is_some_and(|value| {
+                let value = value.to_ascii_lowercase();
+                words.iter().any(|word| value.contains(word))
+            })
+        };
+        match self {
+            CloseRule::Auto => event.event_type.is_none() || contains(&event.event_type, CLOSE_TYPES),
+            CloseRule::EndReason => {
+                event.kind == EventKind::Close || event.end_reason.as_deref().is_some_and(|reason| !reason.trim().is_empty())
+            }
+            CloseRule::FinRst => event.tcp_flags.as_deref().is_some_and(|flags| flags.to_ascii_uppercase().contains(['F', 'R'])),
+            CloseRule::Matching(words) => {
+                let words: Vec<&str> = words.iter().map(String::as_str).collect();
+                contains(&event.event_type, &words) || contains(&event.end_reason, &words)
+            }
+            CloseRule::Any => true,
+            CloseRule::Never => false,
+        }
+    }
+}
+
+impl FromStr for CloseRule {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        if let Some(words) = value.strip_prefix("match:") {
+            let words: Vec<String> = words.split(',').map(|word| word.trim().to_ascii_lowercase()).filter(|word| !word.is_empty()).collect();
+            if words.is_empty() {
+                return Err("`match:` needs at least one word".to_string());
+            }
+            return Ok(CloseRule::Matching(words));
+        }
+        Ok(match value {
+            "auto" => CloseRule::Auto,
+            "end-reason" => CloseRule::EndReason,
+            "fin-rst" => CloseRule::FinRst,
+            "any" => CloseRule::Any,
+            "never" => CloseRule::Never,
+            _ => return Err(format!("expected auto, end-reason, fin-rst, match:<word>,..., any or never, got `{}`", value)),
+        })
+    }
+}
+
+impl fmt::Display for CloseRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CloseRule::Auto => f.write_str("auto"),
+            CloseRule::EndReason => f.write_str("end-reason"),
+            CloseRule::FinRst => f.write_str("fin-rst"),
+            CloseRule::Matching(words) => write!(f, "match:{}", words.join(",")),
+            CloseRule::Any => f.write_str("any"),
+            CloseRule::Never => f.write_str("never"),
+        }
+    }
+}
+
+/// A `<format>=<rule>` setting, e.g. `kv=match:close,timeout`.
+pub fn parse_setting(value: &str) -> Result<(InputFormat, CloseRule), String> {
+    let (format, rule) = value.split_once('=').ok_or_else(|| format!("expected <format>=<rule>, got `{}`", value))?;
+    let format = <InputFormat as clap::ValueEnum>::from_str(format.trim(), true)?;
+    Ok((format, rule.trim().parse()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(event_type: Option<&str>, end_reason: Option<&str>, tcp_flags: Option<&str>) -> FlowEvent {
+        FlowEvent {
+            event_type: event_type.map(str::to_string),
+            end_reason: end_reason.map(str::to_string),
+            tcp_flags: tcp_flags.map(str::to_string),
+            ..FlowEvent::default()
+        }
+    }
+
+    #[test]
+    fn rules_decide_which_events_close_a_session() {
+        let matching: CloseRule = "match:Timeout, deny".parse().unwrap();
+        let cases = [
+            (CloseRule::Auto, event(None, None, None), true),
+            (CloseRule::Auto, event(Some("RT_FLOW_SESSION_CLOSE"), None, None), true),
+            (CloseRule::Auto, event(Some("traffic-end"), None, None), true),
+            (CloseRule::Auto, event(Some("RT_FLOW_SESSION_CREATE"), None, None), false),
+            (CloseRule::EndReason, event(None, Some("tcp-fin"), None), true),
+            (CloseRule::EndReason, event(None, Some("  "), None), false),
+            (CloseRule::EndReason, FlowEvent { kind: EventKind::Close, ..FlowEvent::default() }, true),
+            (CloseRule::FinRst, event(None, None, Some("FA")), true),
+            (CloseRule::FinRst, event(None, None, Some("r")), true),
+            (CloseRule::FinRst, event(None, None, Some("S")), false),
+            (CloseRule::FinRst, event(None, None, None), false),
+            (matching.clone(), event(Some("session-timeout"), None, None), true),
+            (matching.clone(), event(None, Some("Policy DENY"), None), true),
+            (matching, event(Some("close"), Some("tcp-fin"), None), false),
+            (CloseRule::Any, event(Some("create"), None, None), true),
+            (CloseRule::Never, event(None, None, None), false),
+        ];
+        for (rule, event, expected) in cases {
+            assert_eq!(rule.closes(&event), expected, "{} {:?}", rule, event);
+        }
+    }
+
+    #[test]
+    fn settings_parse_and_the_last_one_for_a_format_wins() {
+        assert_eq!("match:a,,b".parse::<CloseRule>().unwrap().to_string(), "match:a,b");
+        assert!("match:, ".parse::<CloseRule>().is_err());
+        assert!("sometimes".parse::<CloseRule>().is_err());
+        assert!(parse_setting("kv").is_err());
+
+        let rules = [parse_setting("kv=never").unwrap(), parse_setting("KV = any").unwrap()];
+        assert_eq!(CloseRule::for_format(InputFormat::Kv, &rules), CloseRule::Any);
+        assert_eq!(CloseRule::for_format(InputFormat::Filterlog, &rules), CloseRule::FinRst);
+        assert_eq!(CloseRule::for_format(InputFormat::Csv, &rules), CloseRule::Auto);
+    }
+}
@@ -0,0 +1,267 @@
+//! Options file.
+//!
+//! `--config <file>` reads a TOML table keyed by long option name
+//! (`input-format = "kv"`, `group_by = ["vlan"]`, `quiet = true`, and
+//! tables such as `[label]` or `[device-alias]` for `KEY=VALUE` options).
+//! Its values
+//! are applied as if given first on the command line, so flags given there
+//! still win; list options from both places are combined.
+//!
+//! Below both, `RDP_<NAME>` environment variables (`RDP_INPUT_DIR=/logs`,
+//! `RDP_QUIET=true`) set the options neither of them gave, for deployments
+//! configured through the environment. `RDP_CONFIG` names the options file.
+//!
+//! `[tenant.NAME]` tables each describe a separate pipeline with its own
+//! inputs, filters and destinations. `--tenant NAME` runs one of them: its
+//! options replace the top-level ones of the same name, its state and
+//! output paths move into a directory of its own unless it sets them, and
+//! every record is labelled `tenant=NAME`. Without `--tenant`, a run of a
+//! file with tenants runs each of them (see `tenants`).
+
+use std::collections::HashSet;
+use std::env;
+use std::ffi::OsString;
+use std::fs;
+use std::path::Path;
+
+use clap::{ArgMatches, CommandFactory};
+use clap::parser::ValueSource;
+use toml::Value;
+
+use crate::cli::Cli;
+
+/// Options whose values are not echoed back by `check-config`.
+const SECRETS: &[&str] = &["clickhouse_password", "influx_token", "elasticsearch_api_key", "http_token"];
+
+/// Prefix of the environment variables that set options.
+const ENV_PREFIX: &str = "RDP_";
+
+/// Options naming where a run keeps its state and outputs, which each
+/// tenant gets to itself unless it sets them: a directory gains a
+/// subdirectory named after the tenant, a file moves into one.
+const ISOLATED: &[&str] = &["output-dir", "state-dir", "snapshot-file", "alert-state", "dead-letter-dir", "quarantine-dir", "listen-spill-dir"];
+
+/// Command-line arguments with the options file (if any) spliced in.
+pub struct Args {
+    pub args: Vec<OsString>,
+    /// Options that were set by the file, by argument id
+    pub from_file: HashSet<String>,
+    /// Options that were set by the environment, by argument id
+    pub from_env: HashSet<String>,
+    /// The file's tenants, when it has some and `--tenant` picked none
+    pub tenants: Vec<String>,
+}
+
+/// Find `--config` among `args` and insert the file's options right after
+/// the program name. Without `--config`, `RDP_CONFIG` names the file. With
+/// `--tenant`, that tenant's options are applied over the file's others.
+pub fn with_config_file(mut args: Vec<OsString>) -> Result<Args, String> {
+    let path = flag_value(&args, "--config");
+    let Some(path) = path.or_else(|| env::var(format!("{}CONFIG", ENV_PREFIX)).ok()) else {
+        return Ok(Args { args, from_file: HashSet::new(), from_env: HashSet::new(), tenants: Vec::new() });
+    };
+
+    let text = fs::read_to_string(&path).map_err(|err| format!("{}: {}", path, err))?;
+    let table: toml::Table = text.parse().map_err(|err| format!("{}: {}", path, err))?;
+    let mut table: toml::Table = table.into_iter().map(|(key, value)| (key.replace('_', "-"), value)).collect();
+    let mut tenants = match table.remove("tenant") {
+        None => toml::Table::new(),
+        Some(Value::Table(tenants)) if tenants.values().all(Value::is_table) => tenants,
+        Some(_) => return Err(format!("{}: `tenant` must hold [tenant.NAME] tables", path)),
+    };
+    let names = match flag_value(&args, "--tenant") {
+        Some(name) => {
+            let Some(Value::Table(tenant)) = tenants.remove(&name) else {
+                return Err(format!("{}: no [tenant.{}] table", path, name));
+            };
+            apply_tenant(&mut table, &name, tenant)?;
+            Vec::new()
+        }
+        None => tenants.keys().cloned().collect(),
+    };
+
+    let command = Cli::command();
+    let mut from_file = HashSet::new();
+    let mut injected = Vec::new();
+    for (key, value) in &table {
+        let Some(arg) = command.get_arguments().find(|arg| arg.get_long() == Some(key)) else {
+            return Err(format!("{}: unknown option `{}`", path, key));
+        };
+        let flag = format!("--{}", key);
+        match value {
+            Value::Boolean(true) => injected.push(flag.clone()),
+            Value::Boolean(false) => {}
+            Value::Array(values) => {
+                for value in values {
+                    injected.push(flag.clone());
+                    injected.push(scalar(key, value)?);
+                }
+            }
+            // `[label]` with `datacenter = "fra1"` is `--label datacenter=fra1`
+            Value::Table(entries) => {
+                for (name, value) in entries {
+                    injected.push(flag.clone());
+                    injected.push(format!("{}={}", name, scalar(key, value)?));
+                }
+            }
+            value => {
+                injected.push(flag.clone());
+                injected.push(scalar(key, value)?);
+            }
+        }
+        // Flags repeated on the command line win, so they aren't from the file
+        let given = args.iter().any(|arg| {
+            let arg = arg.to_string_lossy();
+            arg == flag || arg.starts_with(&format!("{}=", flag))
+        });
+        if !given {
+            from_file.insert(arg.get_id().to_string());
+        }
+    }
+
+    let rest = args.split_off(1.min(args.len()));
+    args.extend(injected.into_iter().map(OsString::from));
+    args.extend(rest);
+    Ok(Args { args, from_file, from_env: HashSet::new(), tenants: names })
+}
+
+/// The value `flag` is given on the command line, if it is.
+fn flag_value(args: &[OsString], flag: &str) -> Option<String> {
+    let mut value = None;
+    let mut iter = args.iter().skip(1);
+    while let Some(arg) = iter.next() {
+        let arg = arg.to_string_lossy();
+        if arg == flag {
+            value = iter.next().map(|value| value.to_string_lossy().into_owned());
+        } else if let Some(given) = arg.strip_prefix(flag).and_then(|rest| rest.strip_prefix('=')) {
+            value = Some(given.to_string());
+        } else if arg == "--" {
+            break;
+        }
+    }
+    value
+}
+
+/// Apply tenant `name`'s options over the top-level ones in `table`.
+fn apply_tenant(table: &mut toml::Table, name: &str, tenant: toml::Table) -> Result<(), String> {
+    let tenant: toml::Table = tenant.into_iter().map(|(key, value)| (key.replace('_', "-"), value)).collect();
+    let command = Cli::command();
+    for option in ISOLATED {
+        if tenant.contains_key(*option) {
+            continue;
+        }
+        let shared = match table.get(*option) {
+            Some(value) => Some(scalar(option, value)?),
+            None => command
+                .get_arguments()
+                .find(|arg| arg.get_long() == Some(option))
+                .and_then(|arg| arg.get_default_values().first())
+                .map(|value| value.to_string_lossy().into_owned()),
+        };
+        let Some(shared) = shared else {
+            continue;
+        };
+        let shared = Path::new(&shared);
+        let own = match option.ends_with("-dir") {
+            true => shared.join(name),
+            false => shared.parent().unwrap_or(Path::new("")).join(name).join(shared.file_name().unwrap_or_default()),
+        };
+        table.insert(option.to_string(), Value::String(own.to_string_lossy().into_owned()));
+    }
+    for (key, value) in tenant {
+        match (table.get_mut(&key), value) {
+            (Some(Value::Table(shared)), Value::Table(own)) => shared.extend(own),
+            (_, value) => {
+                table.insert(key, value);
+            }
+        }
+    }
+    match table.entry("label").or_insert_with(|| Value::Table(toml::Table::new())) {
+        Value::Table(labels) => {
+            labels.entry("tenant").or_insert_with(|| Value::String(name.to_string()));
+        }
+        Value::Array(labels) => {
+            if !labels.iter().any(|label| label.as_str().is_some_and(|label| label.starts_with("tenant="))) {
+                labels.push(Value::String(format!("tenant={}", name)));
+            }
+        }
+        _ => return Err("`label` must be a table or a list of KEY=VALUE".to_string()),
+    }
+    Ok(())
+}
+
+/// Insert the options set in the environment that neither the command line
+/// nor the options file gave, right after the program name.
+pub fn with_environment(mut args: Args) -> Result<Args, String> {
+    let mut injected = Vec::new();
+    for arg in Cli::command().get_arguments() {
+        let Some(long) = arg.get_long() else {
+            continue;
+        };
+        let name = format!("{}{}", ENV_PREFIX, long.replace('-', "_").to_uppercase());
+        let Ok(value) = env::var(&name) else {
+            continue;
+        };
+        let flag = format!("--{}", long);
+        let short = arg.get_short().map(|short| format!("-{}", short));
+        let given = args.args.iter().skip(1).any(|arg| {
+            let arg = arg.to_string_lossy();
+            arg == flag || arg.starts_with(&format!("{}=", flag)) || short.as_deref() == Some(&*arg)
+        });
+        if given {
+            continue;
+        }
+
+        if arg.get_action().takes_values() {
+            injected.push(format!("{}={}", flag, value));
+        } else {
+            match value.to_ascii_lowercase().as_str() {
+                "1" | "true" | "yes" | "on" => injected.push(flag),
+                "" | "0" | "false" | "no" | "off" => continue,
+                _ => return Err(format!("{}: expected true or false, not `{}`", name, value)),
+            }
+        }
+        args.from_env.insert(arg.get_id().to_string());
+    }
+
+    let rest = args.args.split_off(1.min(args.args.len()));
+    args.args.extend(injected.into_iter().map(OsString::from));
+    args.args.extend(rest);
+    Ok(args)
+}
+
+fn scalar(key: &str, value: &Value) -> Result<String, String> {
+    match value {
+        Value::String(value) => Ok(value.clone()),
+        Value::Integer(value) => Ok(value.to_string()),
+        Value::Float(value) => Ok(value.to_string()),
+        Value::Boolean(value) => Ok(value.to_string()),
+        _ => Err(format!("`{}` must be a string, number, boolean or list of those", key)),
+    }
+}
+
+/// Every option with its effective value and where that came from, as
+/// `(name, value, source)`.
+pub fn effective(matches: &ArgMatches, args: &Args) -> Vec<(String, String, &'static str)> {
+    let mut options = Vec::new();
+    for arg in Cli::command().get_arguments() {
+        let id = arg.get_id().as_str();
+        let Ok(Some(values)) = matches.try_get_raw(id) else {
+            continue;
+        };
+        let values: Vec<_> = values.map(|value| value.to_string_lossy().into_owned()).collect();
+        let value = match SECRETS.contains(&id) {
+            true => "********".to_string(),
+            false => values.join(","),
+        };
+        let source = match matches.value_source(id) {
+            Some(ValueSource::DefaultValue) => "default",
+            _ if args.from_file.contains(id) => "config",
+            _ if args.from_env.contains(id) => "environment",
+            _ => "command line",
+        };
+        options.push((id.replace('_', "-"), value, source));
+    }
+    options.sort();
+    options
+}
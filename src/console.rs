@@ -0,0 +1,99 @@
+//! End-of-run console output.
+//!
+//! Status lines and the run summary go to stdout unless `--quiet` is set, or
+//! to stderr when stdout carries the payload itself, and are colorized only
+//! when that stream is a terminal and `NO_COLOR` isn't set. Errors are always
+//! written to stderr by the caller.
+
+use std::env;
+use std::fmt::Display;
+use std::io::{self, IsTerminal};
+
+use crate::payload::Payload;
+
+const BOLD: &str = "\x1b[1m";
+const GREEN: &str = "\x1b[32m";
+const YELLOW: &str = "\x1b[33m";
+const RED: &str = "\x1b[31m";
+const DIM: &str = "\x1b[2m";
+const RESET: &str = "\x1b[0m";
+
+pub struct Console {
+    quiet: bool,
+    verbose: bool,
+    color: bool,
+    stderr: bool,
+}
+
+impl Console {
+    pub fn new(quiet: bool, verbose: bool, stderr: bool) -> Self {
+        let terminal = if stderr { io::stderr().is_terminal() } else { io::stdout().is_terminal() };
+        Console {
+            quiet,
+            verbose,
+            color: terminal && env::var_os("NO_COLOR").is_none(),
+            stderr,
+        }
+    }
+
+    /// A one-line status message, e.g. a sink confirming delivery.
+    pub fn info(&self, message: impl Display) {
+        if !self.quiet {
+            self.line(&message.to_string());
+        }
+    }
+
+    pub fn summary(&self, payload: &Payload, output_file: &str) {
+        if self.quiet {
+            return;
+        }
+        let metadata = &payload.metadata;
+        let lines = metadata.total_connections;
+        let skipped = metadata.skipped_lines.total;
+        let skipped_pct = if lines == 0 { 0.0 } else { skipped as f64 / lines as f64 * 100.0 };
+        let skipped_color = if skipped > 0 { YELLOW } else { GREEN };
+
+        self.line(&self.paint(BOLD, &format!("Run complete in {:.3} s", metadata.elapsed_time)));
+        self.row("Files", &metadata.files_processed.len().to_string());
+        if !metadata.failed_files.is_empty() {
+            self.row("Failed", &self.paint(RED, &metadata.failed_files.len().to_string()));
+        }
+        self.row("Lines", &lines.to_string());
+        self.row("Skipped", &self.paint(skipped_color, &format!("{} ({:.2}%)", skipped, skipped_pct)));
+        self.row("Flows", &self.paint(GREEN, &metadata.flows.to_string()));
+        self.row("Output", output_file);
+        if !metadata.run_id.is_empty() {
+            self.row("Run", &self.paint(DIM, &metadata.run_id));
+        }
+
+        if self.verbose {
+            for file in &metadata.processing_performance.files {
+                self.line(&format!(
+                    "    {}",
+                    self.paint(DIM, &format!("{}: {} lines, {} skipped, {:.3} s", file.file, file.lines, file.skipped, file.duration_seconds))
+                ));
+            }
+            let stages = &metadata.processing_performance.stages;
+            if !stages.is_empty() {
+                let times: Vec<String> = stages.iter().map(|stage| format!("{} {:.3} s", stage.stage, stage.wall_seconds)).collect();
+                self.line(&format!("    {}", self.paint(DIM, &format!("stages: {}", times.join(", ")))));
+            }
+        }
+    }
+
+    fn row(&self, label: &str, value: &str) {
+        self.line(&format!("  {:<8} {}", label, value));
+    }
+
+    fn line(&self, text: &str) {
+        if self.stderr {
+            eprintln!("{}", text);
+        } else {
+            println!("{}", text);
+        }
+    }
+
+    fn paint(&self, color: &str, text: &str) -> String {
+        if self.color { format!("{}{}{}", color, text, RESET) } else { text.to_string() }
+    }
+}
@@ -0,0 +1,42 @@
+//! Count-min sketch of byte totals, for flows past the `--max-flows` cap.
+//!
+//! Each of `depth` rows adds a flow's bytes to one of `width` counters; the
+//! smallest of a flow's counters never undercounts it and overcounts by at
+//! most e/width of the sketched total with probability 1 - e^-depth.
+
+use std::hash::{DefaultHasher, Hash, Hasher};
+
+pub struct CountMin {
+    width: usize,
+    depth: usize,
+    counters: Vec<u64>,
+}
+
+impl CountMin {
+    pub fn new(width: usize, depth: usize) -> Self {
+        let width = width.max(1);
+        let depth = depth.max(1);
+        CountMin { width, depth, counters: vec![0; width * depth] }
+    }
+
+    fn cells(&self, key: &impl Hash) -> Vec<usize> {
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+        let hash = hasher.finish();
+        // Row hashes derived from one (Kirsch-Mitzenmacher) are independent enough
+        let (a, b) = (hash & 0xffff_ffff, (hash >> 32) | 1);
+        (0..self.depth)
+            .map(|row| row * self.width + (a.wrapping_add(b.wrapping_mul(row as u64)) % self.width as u64) as usize)
+            .collect()
+    }
+
+    pub fn estimate(&self, key: &impl Hash) -> u64 {
+        self.cells(key).into_iter().map(|cell| self.counters[cell]).min().unwrap_or(0)
+    }
+
+    pub fn add(&mut self, key: &impl Hash, value: u64) {
+        for cell in self.cells(key) {
+            self.counters[cell] = self.counters[cell].saturating_add(value);
+        }
+    }
+}
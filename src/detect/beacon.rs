@@ -0,0 +1,168 @@
+//! Beaconing: low-volume flows to external destinations whose sessions
+//! arrive at near-constant intervals, as command-and-control check-ins do.
+//!
+//! With `--detect-beacons` every record keeps its sessions' timestamps
+//! while aggregating; they become the record's `inter-arrival` statistics
+//! when the run finishes. Regularity is the coefficient of variation of the
+//! intervals (`jitter`), so a 60 s beacon with a few seconds of jitter
+//! scores the same as a 10 min one with proportionally more. Flows without
+//! timestamps, or with a single session, are never flagged.
+
+use serde::{Deserialize, Serialize};
+
+use crate::classify::Networks;
+use crate::record::Record;
+
+/// Timestamps kept per flow; later sessions still count, but not here
+const MAX_ARRIVALS: usize = 10_000;
+
+/// Session start times of one flow, in milliseconds since the epoch.
+#[derive(Debug, Clone, Default)]
+pub struct Arrivals {
+    times: Vec<i64>,
+}
+
+impl Arrivals {
+    pub fn note(&mut self, millis: i64) {
+        if self.times.len() < MAX_ARRIVALS {
+            self.times.push(millis);
+        }
+    }
+
+    pub fn finish(mut self) -> Option<Periodicity> {
+        if self.times.len() < 2 {
+            return None;
+        }
+        // Files aren't necessarily read in time order
+        self.times.sort_unstable();
+        let intervals: Vec<f64> = self.times.windows(2).map(|pair| (pair[1] - pair[0]) as f64 / 1000.0).collect();
+        let mean = intervals.iter().sum::<f64>() / intervals.len() as f64;
+        let variance = intervals.iter().map(|interval| (interval - mean).powi(2)).sum::<f64>() / intervals.len() as f64;
+        let stddev = variance.sqrt();
+        Some(Periodicity {
+            intervals: intervals.len(),
+            mean_seconds: mean,
+            stddev_seconds: stddev,
+            jitter: if mean > 0.0 { stddev / mean } else { 0.0 },
+        })
+    }
+}
+
+/// Regularity of a flow's session arrivals.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub struct Periodicity {
+    pub intervals: usize,
+    pub mean_seconds: f64,
+    pub stddev_seconds: f64,
+    /// Standard deviation over mean of the intervals; 0 is perfectly periodic
+    pub jitter: f64,
+}
+
+pub struct BeaconOptions {
+    /// Fewest sessions for a flow to be judged at all
+    pub min_sessions: u64,
+    pub max_jitter: f64,
+    pub max_bytes_per_session: u64,
+    /// Shorter mean intervals are bursts, not check-ins
+    pub min_interval_seconds: f64,
+    pub networks: Networks,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct SuspectedBeacon {
+    pub flow: String,
+    pub source: String,
+    pub destination: String,
+    pub port: String,
+    pub sessions: u64,
+    pub bytes_per_session: u64,
+    pub interval_seconds: f64,
+    pub jitter: f64,
+}
+
+pub fn detect<'a>(records: impl IntoIterator<Item = &'a Record>, options: &BeaconOptions) -> Vec<SuspectedBeacon> {
+    let mut beacons: Vec<SuspectedBeacon> = records
+        .into_iter()
+        .filter_map(|record| {
+            let periodicity = record.inter_arrival?;
+            let bytes_per_session = record.bytes_in.saturating_add(record.bytes_out) / record.count.max(1);
+            let suspected = record.count >= options.min_sessions
+                && periodicity.jitter <= options.max_jitter
+                && periodicity.mean_seconds >= options.min_interval_seconds
+                && bytes_per_session <= options.max_bytes_per_session
+                && super::external(&record.destination_ip, &options.networks);
+            suspected.then(|| SuspectedBeacon {
+                flow: record.key.to_string(),
+                source: record.source_ip.to_string(),
+                destination: record.destination_ip.to_string(),
+                port: record.destination_port.to_string(),
+                sessions: record.count,
+                bytes_per_session,
+                interval_seconds: periodicity.mean_seconds,
+                jitter: periodicity.jitter,
+            })
+        })
+        .collect();
+    beacons.sort_by(|a, b| a.jitter.total_cmp(&b.jitter).then_with(|| b.sessions.cmp(&a.sessions)).then_with(|| a.flow.cmp(&b.flow)));
+    beacons
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::detect::tests::flow;
+
+    fn arrivals(times: &[i64]) -> Option<Periodicity> {
+        let mut arrivals = Arrivals::default();
+        for time in times {
+            arrivals.note(*time);
+        }
+        arrivals.finish()
+    }
+
+    #[test]
+    fn periodicity_comes_from_the_sorted_intervals() {
+        assert_eq!(arrivals(&[]), None);
+        assert_eq!(arrivals(&[1_000]), None);
+        let periodic = arrivals(&[120_000, 0, 60_000, 180_000]).unwrap();
+        assert_eq!((periodic.intervals, periodic.mean_seconds, periodic.jitter), (3, 60.0, 0.0));
+        let irregular = arrivals(&[0, 10_000, 100_000]).unwrap();
+        assert_eq!((irregular.mean_seconds, irregular.stddev_seconds), (50.0, 40.0));
+        assert!((irregular.jitter - 0.8).abs() < 1e-9);
+    }
+
+    #[test]
+    fn regular_small_sessions_to_external_hosts_are_beacons() {
+        let options = BeaconOptions {
+            min_sessions: 4,
+            max_jitter: 0.1,
+            max_bytes_per_session: 500,
+            min_interval_seconds: 10.0,
+            networks: Networks::new(&[]),
+        };
+        let beacon = |destination: &str, times: &[i64], bytes: u64| {
+            let mut record = flow("10.0.0.1", destination, "443");
+            record.count = times.len() as u64;
+            record.bytes_out = bytes;
+            record.inter_arrival = arrivals(times);
+            record
+        };
+        let minutely: Vec<i64> = (0..6).map(|minute| minute * 60_000).collect();
+        let records = [
+            beacon("8.8.8.8", &minutely, 1200),
+            // Internal, too busy, too few, too fast and too irregular
+            beacon("10.0.0.2", &minutely, 1200),
+            beacon("8.8.4.4", &minutely, 600_000),
+            beacon("1.1.1.1", &minutely[..3], 300),
+            beacon("9.9.9.9", &[0, 1_000, 2_000, 3_000, 4_000], 100),
+            beacon("1.0.0.1", &[0, 10_000, 100_000, 110_000, 300_000], 100),
+        ];
+
+        let beacons = detect(&records, &options);
+        assert_eq!(beacons.len(), 1);
+        let found = &beacons[0];
+        assert_eq!((found.destination.as_str(), found.sessions, found.bytes_per_session, found.interval_seconds), ("8.8.8.8", 6, 200, 60.0));
+    }
+}
@@ -0,0 +1,94 @@
+//! Exfiltration watchlist: flows to external destinations that send far
+//! more than they receive.
+//!
+//! Flows need both a volume (`min_bytes_out`) and an asymmetry
+//! (`min_ratio` of bytes out over bytes in) to be listed; destinations on
+//! the allowlist, e.g. offsite backup targets, never are.
+
+use serde::{Deserialize, Serialize};
+
+use crate::cidr::Cidr;
+use crate::classify::Networks;
+use crate::record::Record;
+
+pub struct ExfilOptions {
+    pub min_bytes_out: u64,
+    pub min_ratio: f64,
+    pub allow: Vec<Cidr>,
+    pub networks: Networks,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct OutboundFlow {
+    pub flow: String,
+    pub source: String,
+    pub destination: String,
+    pub port: String,
+    pub sessions: u64,
+    pub bytes_out: u64,
+    pub bytes_in: u64,
+    /// Bytes out over bytes in, with no bytes in counted as one
+    pub ratio: f64,
+}
+
+pub fn detect<'a>(records: impl IntoIterator<Item = &'a Record>, options: &ExfilOptions) -> Vec<OutboundFlow> {
+    let mut flows: Vec<OutboundFlow> = records
+        .into_iter()
+        .filter(|record| record.bytes_out >= options.min_bytes_out && super::external(&record.destination_ip, &options.networks))
+        .filter(|record| {
+            let destination = record.destination_ip.parse().ok();
+            !options.allow.iter().any(|block| destination.is_some_and(|address| block.contains(address)))
+        })
+        .filter_map(|record| {
+            let ratio = record.bytes_out as f64 / record.bytes_in.max(1) as f64;
+            (ratio >= options.min_ratio).then(|| OutboundFlow {
+                flow: record.key.to_string(),
+                source: record.source_ip.to_string(),
+                destination: record.destination_ip.to_string(),
+                port: record.destination_port.to_string(),
+                sessions: record.count,
+                bytes_out: record.bytes_out,
+                bytes_in: record.bytes_in,
+                ratio,
+            })
+        })
+        .collect();
+    flows.sort_by(|a, b| b.bytes_out.cmp(&a.bytes_out).then_with(|| a.flow.cmp(&b.flow)));
+    flows
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::detect::tests::flow;
+
+    #[test]
+    fn large_lopsided_uploads_to_external_hosts_are_listed() {
+        let options = ExfilOptions {
+            min_bytes_out: 1_000_000,
+            min_ratio: 10.0,
+            allow: vec!["198.51.100.0/24".parse().unwrap()],
+            networks: Networks::new(&[]),
+        };
+        let upload = |destination: &str, bytes_out: u64, bytes_in: u64| {
+            let mut record = flow("10.0.0.1", destination, "443");
+            record.bytes_out = bytes_out;
+            record.bytes_in = bytes_in;
+            record
+        };
+        let records = [
+            upload("8.8.8.8", 5_000_000, 1_000),
+            upload("9.9.9.9", 2_000_000, 0),
+            // Internal, allowlisted, too small and too balanced
+            upload("10.0.0.2", 5_000_000, 1_000),
+            upload("198.51.100.7", 5_000_000, 1_000),
+            upload("1.1.1.1", 999_999, 0),
+            upload("1.0.0.1", 5_000_000, 1_000_000),
+        ];
+
+        let flows = detect(&records, &options);
+        let found: Vec<(&str, f64)> = flows.iter().map(|flow| (flow.destination.as_str(), flow.ratio)).collect();
+        assert_eq!(found, [("8.8.8.8", 5_000.0), ("9.9.9.9", 2_000_000.0)]);
+    }
+}
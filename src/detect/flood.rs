@@ -0,0 +1,141 @@
+//! Volumetric floods: destinations reached by an abnormal number of
+//! distinct sources or receiving an abnormal number of packets.
+//!
+//! Findings go into the `suspectedFloods` section and, as one alert under
+//! the `flood` rule name, through the same webhook as rule alerts.
+
+use std::collections::{HashMap, HashSet};
+
+use serde::{Deserialize, Serialize};
+
+use crate::record::Record;
+use crate::rules::RuleAlert;
+
+pub struct FloodOptions {
+    pub min_sources: usize,
+    pub min_packets: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct SuspectedFlood {
+    pub destination: String,
+    pub distinct_sources: usize,
+    /// Packets sent to the destination
+    pub packets_in: u64,
+    /// Bytes sent to the destination
+    pub bytes_in: u64,
+    pub sessions: u64,
+    pub flows: usize,
+    /// Most targeted destination ports, busiest first
+    pub top_ports: Vec<String>,
+}
+
+/// Name the findings are alerted under
+pub const RULE: &str = "flood";
+
+/// Ports listed per finding and flows listed in the alert
+const LISTED: usize = 5;
+
+#[derive(Default)]
+struct Destination<'a> {
+    sources: HashSet<&'a str>,
+    ports: HashMap<&'a str, u64>,
+    packets: u64,
+    bytes: u64,
+    sessions: u64,
+    flows: usize,
+}
+
+pub fn detect<'a>(records: impl IntoIterator<Item = &'a Record>, options: &FloodOptions) -> Vec<SuspectedFlood> {
+    let mut destinations: HashMap<&str, Destination> = HashMap::new();
+    for record in records {
+        let destination = destinations.entry(&record.destination_ip).or_default();
+        destination.sources.insert(&record.source_ip);
+        let port = destination.ports.entry(&record.destination_port).or_default();
+        *port = port.saturating_add(record.packets_out);
+        destination.packets = destination.packets.saturating_add(record.packets_out);
+        destination.bytes = destination.bytes.saturating_add(record.bytes_out);
+        destination.sessions += record.count;
+        destination.flows += 1;
+    }
+
+    let mut floods: Vec<SuspectedFlood> = destinations
+        .into_iter()
+        .filter(|(_, destination)| destination.sources.len() >= options.min_sources || destination.packets >= options.min_packets)
+        .map(|(address, destination)| {
+            let mut ports: Vec<(&str, u64)> = destination.ports.into_iter().collect();
+            ports.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(b.0)));
+            SuspectedFlood {
+                destination: address.to_string(),
+                distinct_sources: destination.sources.len(),
+                packets_in: destination.packets,
+                bytes_in: destination.bytes,
+                sessions: destination.sessions,
+                flows: destination.flows,
+                top_ports: ports.into_iter().take(LISTED).map(|(port, _)| port.to_string()).collect(),
+            }
+        })
+        .collect();
+    floods.sort_by(|a, b| b.packets_in.cmp(&a.packets_in).then_with(|| a.destination.cmp(&b.destination)));
+    floods
+}
+
+/// One alert covering all `floods`, keyed by destination address.
+pub fn alert<'a>(floods: &[SuspectedFlood], records: impl IntoIterator<Item = &'a Record>) -> Option<RuleAlert> {
+    if floods.is_empty() {
+        return None;
+    }
+    let targets: HashSet<&str> = floods.iter().map(|flood| flood.destination.as_str()).collect();
+    let mut flows: Vec<&Record> = records.into_iter().filter(|record| targets.contains(&*record.destination_ip)).collect();
+    flows.sort_by(|a, b| b.packets_out.cmp(&a.packets_out).then_with(|| a.key.cmp(&b.key)));
+    let keys: Vec<String> = floods.iter().map(|flood| flood.destination.clone()).collect();
+    Some(RuleAlert {
+        rule: RULE.to_string(),
+        description: Some(format!("{} destinations with flood-like traffic", floods.len())),
+        severity: "high".to_string(),
+        flows: flows.len(),
+        sessions: flows.iter().map(|record| record.count).sum(),
+        bytes: flows.iter().map(|record| record.bytes_in.saturating_add(record.bytes_out)).fold(0, u64::saturating_add),
+        flow_keys: flows.iter().take(LISTED).map(|record| record.key.to_string()).collect(),
+        new_keys: keys.len(),
+        keys,
+        suppressed_keys: 0,
+        repeats: 0,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::detect::tests::flow;
+
+    #[test]
+    fn destinations_with_many_sources_or_packets_are_flooded() {
+        let mut records = Vec::new();
+        for source in 1..=4 {
+            let mut record = flow(&format!("198.51.100.{}", source), "10.0.0.80", "80");
+            record.packets_out = 10;
+            record.bytes_out = 600;
+            records.push(record);
+        }
+        let mut heavy = flow("198.51.100.9", "10.0.0.53", "53");
+        heavy.packets_out = 5000;
+        records.push(heavy);
+        let mut quiet = flow("198.51.100.1", "10.0.0.25", "25");
+        quiet.packets_out = 20;
+        records.push(quiet);
+
+        let options = FloodOptions { min_sources: 4, min_packets: 1000 };
+        let floods = detect(&records, &options);
+        let found: Vec<(&str, usize, u64)> =
+            floods.iter().map(|flood| (flood.destination.as_str(), flood.distinct_sources, flood.packets_in)).collect();
+        assert_eq!(found, [("10.0.0.53", 1, 5000), ("10.0.0.80", 4, 40)]);
+        assert_eq!((floods[1].bytes_in, floods[1].top_ports.as_slice()), (2400, ["80".to_string()].as_slice()));
+
+        let raised = alert(&floods, &records).unwrap();
+        assert_eq!((raised.rule.as_str(), raised.flows, raised.new_keys), (RULE, 5, 2));
+        assert_eq!(raised.flow_keys[0], "198.51.100.9_10.0.0.53_53_6");
+        assert!(alert(&[], &records).is_none());
+    }
+}
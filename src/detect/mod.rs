@@ -0,0 +1,53 @@
+//! Heuristic detections over the run's final records.
+//!
+//! Each detection looks at the whole window's flows and adds its own
+//! metadata section with the numbers behind every finding, so analysts can
+//! judge a hit without re-running anything.
+
+use std::net::IpAddr;
+
+use crate::cidr;
+use crate::classify::Networks;
+
+pub mod beacon;
+pub mod exfil;
+pub mod flood;
+pub mod scan;
+
+/// Whether `address` is a globally routable destination outside the
+/// internal prefixes.
+fn external(address: &str, networks: &Networks) -> bool {
+    !networks.is_internal(address) && address.parse::<IpAddr>().is_ok_and(cidr::is_public)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::intern::Interner;
+    use crate::parser::FlowEvent;
+    use crate::record::{NatSide, Record};
+
+    /// One session from `source` to `destination` on `port`, without counters.
+    pub(crate) fn flow(source: &str, destination: &str, port: &str) -> Record {
+        let event = FlowEvent {
+            source_ip: source.to_string(),
+            destination_ip: destination.to_string(),
+            destination_port: port.to_string(),
+            protocol: "6".to_string(),
+            ..FlowEvent::default()
+        };
+        let mut record = Record::new(&event, NatSide::PreNat, &mut Interner::default());
+        record.key = format!("{}_{}_{}_6", source, destination, port).into();
+        record.count = 1;
+        record
+    }
+
+    #[test]
+    fn only_public_addresses_outside_the_internal_prefixes_are_external() {
+        let networks = Networks::new(&["203.0.113.0/24".parse().unwrap()]);
+        let cases = [("8.8.8.8", true), ("10.1.2.3", false), ("203.0.113.7", false), ("127.0.0.1", false), ("not an address", false)];
+        for (address, expected) in cases {
+            assert_eq!(external(address, &networks), expected, "{}", address);
+        }
+    }
+}
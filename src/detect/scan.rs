@@ -0,0 +1,174 @@
+//! Port scans and host sweeps: sources reaching many distinct ports or
+//! hosts while moving almost no data per session.
+//!
+//! Only "tiny" flows count towards a finding, those averaging at most
+//! `max_bytes_per_session`; a busy server talking to many clients is not a
+//! scanner.
+
+use std::collections::{HashMap, HashSet};
+
+use serde::{Deserialize, Serialize};
+
+use crate::record::Record;
+
+pub struct ScanOptions {
+    pub min_ports: usize,
+    pub min_hosts: usize,
+    pub max_bytes_per_session: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum ScanKind {
+    /// Many ports on few hosts
+    PortScan,
+    /// The same ports on many hosts
+    HostSweep,
+    Both,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct SuspectedScan {
+    pub source: String,
+    pub kind: ScanKind,
+    /// Distinct destination ports of the tiny flows
+    pub distinct_ports: usize,
+    /// Distinct destination addresses of the tiny flows
+    pub distinct_hosts: usize,
+    pub tiny_flows: usize,
+    /// All of the source's flows, for comparison
+    pub flows: usize,
+    pub sessions: u64,
+    pub bytes: u64,
+    /// Most probed ports, by the number of hosts probed on them
+    pub top_ports: Vec<ProbedPort>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ProbedPort {
+    pub port: String,
+    pub hosts: usize,
+}
+
+/// Ports listed per finding
+const TOP_PORTS: usize = 10;
+
+#[derive(Default)]
+struct Source {
+    ports: HashMap<String, HashSet<String>>,
+    hosts: HashSet<String>,
+    tiny_flows: usize,
+    flows: usize,
+    sessions: u64,
+    bytes: u64,
+}
+
+pub fn detect<'a>(records: impl IntoIterator<Item = &'a Record>, options: &ScanOptions) -> Vec<SuspectedScan> {
+    let mut sources: HashMap<&str, Source> = HashMap::new();
+    for record in records {
+        let source = sources.entry(&record.source_ip).or_default();
+        let bytes = record.bytes_in.saturating_add(record.bytes_out);
+        source.flows += 1;
+        source.sessions += record.count;
+        source.bytes = source.bytes.saturating_add(bytes);
+        if bytes > options.max_bytes_per_session.saturating_mul(record.count.max(1)) {
+            continue;
+        }
+        source.tiny_flows += 1;
+        source.hosts.insert(record.destination_ip.to_string());
+        source
+            .ports
+            .entry(record.destination_port.to_string())
+            .or_default()
+            .insert(record.destination_ip.to_string());
+    }
+
+    let mut scans: Vec<SuspectedScan> = sources
+        .into_iter()
+        .filter_map(|(address, source)| {
+            let port_scan = source.ports.len() >= options.min_ports;
+            let host_sweep = source.hosts.len() >= options.min_hosts;
+            let kind = match (port_scan, host_sweep) {
+                (true, true) => ScanKind::Both,
+                (true, false) => ScanKind::PortScan,
+                (false, true) => ScanKind::HostSweep,
+                (false, false) => return None,
+            };
+            let mut ports: Vec<ProbedPort> = source
+                .ports
+                .iter()
+                .map(|(port, hosts)| ProbedPort { port: port.clone(), hosts: hosts.len() })
+                .collect();
+            ports.sort_by(|a, b| b.hosts.cmp(&a.hosts).then_with(|| a.port.cmp(&b.port)));
+            Some(SuspectedScan {
+                source: address.to_string(),
+                kind,
+                distinct_ports: source.ports.len(),
+                distinct_hosts: source.hosts.len(),
+                tiny_flows: source.tiny_flows,
+                flows: source.flows,
+                sessions: source.sessions,
+                bytes: source.bytes,
+                top_ports: ports.into_iter().take(TOP_PORTS).collect(),
+            })
+        })
+        .collect();
+    scans.sort_by(|a, b| {
+        (b.distinct_ports + b.distinct_hosts)
+            .cmp(&(a.distinct_ports + a.distinct_hosts))
+            .then_with(|| a.source.cmp(&b.source))
+    });
+    scans
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::detect::tests::flow;
+
+    fn options() -> ScanOptions {
+        ScanOptions { min_ports: 3, min_hosts: 3, max_bytes_per_session: 100 }
+    }
+
+    #[test]
+    fn sources_probing_many_ports_or_hosts_are_flagged() {
+        let mut records = Vec::new();
+        for port in ["22", "23", "80", "443"] {
+            records.push(flow("10.0.0.1", "10.0.0.100", port));
+        }
+        for host in 1..=3 {
+            records.push(flow("10.0.0.2", &format!("10.0.1.{}", host), "445"));
+        }
+        // Plenty of ports, but each one moved real data
+        for port in ["8080", "8443", "9000"] {
+            let mut record = flow("10.0.0.3", "10.0.0.100", port);
+            record.bytes_out = 5000;
+            records.push(record);
+        }
+
+        let scans = detect(&records, &options());
+        let found: Vec<(&str, &ScanKind, usize, usize)> = scans
+            .iter()
+            .map(|scan| (scan.source.as_str(), &scan.kind, scan.distinct_ports, scan.distinct_hosts))
+            .collect();
+        assert_eq!(found, [("10.0.0.1", &ScanKind::PortScan, 4, 1), ("10.0.0.2", &ScanKind::HostSweep, 1, 3)]);
+        assert_eq!(scans[1].top_ports[0].port, "445");
+        assert_eq!(scans[1].top_ports[0].hosts, 3);
+    }
+
+    #[test]
+    fn tiny_is_judged_per_session() {
+        let mut records = Vec::new();
+        for (host, port) in [(1, "22"), (2, "23"), (3, "80")] {
+            let mut record = flow("10.0.0.1", &format!("10.0.1.{}", host), port);
+            // 250 bytes over three sessions is still under 100 a session
+            record.count = 3;
+            record.bytes_in = 250;
+            records.push(record);
+        }
+        let scans = detect(&records, &options());
+        assert_eq!(scans.len(), 1);
+        assert_eq!((&scans[0].kind, scans[0].tiny_flows, scans[0].sessions), (&ScanKind::Both, 3, 9));
+    }
+}
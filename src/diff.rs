@@ -0,0 +1,82 @@
+//! Comparing two outputs flow by flow.
+//!
+//! Flows only in the newer output are `new`, flows only in the older one
+//! `gone`, and flows in both whose bytes or sessions differ `changed`. The
+//! listing is ordered by how much each flow's byte total moved.
+
+use std::collections::BTreeSet;
+use std::sync::Arc;
+
+use crate::payload::Payload;
+use crate::query::text_table;
+use crate::record::Record;
+use crate::report::format_bytes;
+
+fn bytes(record: Option<&Record>) -> u64 {
+    record.map_or(0, |record| record.bytes_in.saturating_add(record.bytes_out))
+}
+
+fn signed_bytes(delta: i128) -> String {
+    let sign = if delta < 0 { "-" } else { "+" };
+    format!("{}{}", sign, format_bytes(delta.unsigned_abs() as u64))
+}
+
+/// Summary counts and the `limit` largest changes (0 for all).
+pub fn render(old: &Payload, new: &Payload, limit: usize) -> String {
+    let keys: BTreeSet<&Arc<str>> = old.data.keys().chain(new.data.keys()).collect();
+    let mut changes = Vec::new();
+    let (mut added, mut gone, mut changed) = (0, 0, 0);
+
+    for key in keys {
+        let (before, after) = (old.data.get(key), new.data.get(key));
+        let change = match (before, after) {
+            (None, Some(_)) => "new",
+            (Some(_), None) => "gone",
+            (Some(before), Some(after)) if bytes(Some(before)) != bytes(Some(after)) || before.count != after.count => "changed",
+            _ => continue,
+        };
+        match change {
+            "new" => added += 1,
+            "gone" => gone += 1,
+            _ => changed += 1,
+        }
+        let delta = bytes(after) as i128 - bytes(before) as i128;
+        changes.push((change, before.or(after).expect("flow is in one of the outputs"), before, after, delta));
+    }
+    changes.sort_by(|a, b| b.4.abs().cmp(&a.4.abs()).then_with(|| a.1.key.cmp(&b.1.key)));
+    if limit > 0 {
+        changes.truncate(limit);
+    }
+
+    let total = |payload: &Payload| payload.data.values().map(|record| bytes(Some(record))).sum::<u64>();
+    let (old_total, new_total) = (total(old), total(new));
+    let mut out = format!(
+        "Flows: {} -> {} ({} new, {} gone, {} changed)\nBytes: {} -> {} ({})\n\n",
+        old.data.len(),
+        new.data.len(),
+        added,
+        gone,
+        changed,
+        format_bytes(old_total),
+        format_bytes(new_total),
+        signed_bytes(new_total as i128 - old_total as i128),
+    );
+
+    let rows: Vec<Vec<String>> = changes
+        .iter()
+        .map(|(change, record, before, after, delta)| {
+            vec![
+                change.to_string(),
+                record.source_ip.to_string(),
+                record.destination_ip.to_string(),
+                record.destination_port.to_string(),
+                record.protocol.to_string(),
+                format_bytes(bytes(*before)),
+                format_bytes(bytes(*after)),
+                signed_bytes(*delta),
+            ]
+        })
+        .collect();
+    out.push_str(&text_table(&["Change", "Source", "Destination", "Port", "Proto", "Bytes before", "Bytes after", "Difference"], &rows, 5));
+    out
+}
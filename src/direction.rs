@@ -0,0 +1,173 @@
+//! Orienting events from the client to the server.
+//!
+//! Packet logs like filterlog, and some devices' session logs, record
+//! whichever side sent first or last as the source, so replies land in
+//! flows of their own keyed by the client's ephemeral port. Each TCP or UDP
+//! event with both ports is oriented by, in order:
+//!
+//! 1. the TCP flags of a handshake packet: a lone SYN comes from the
+//!    client, a SYN-ACK from the server;
+//! 2. the first event seen between the two sides, whose source is taken as
+//!    the client from then on;
+//! 3. ports: a well-known or registered service port marks the server, and
+//!    of two such ports the lower one;
+//! 4. networks: when the ports don't tell, the internal side is the client.
+//!
+//! An event found to come from the server is reversed: addresses, ports,
+//! NAT, zones, interfaces and MACs change sides, and so do the counters, so
+//! that `*_out` is always client to server and `*_in` server to client. An
+//! event of a single packet carries it in the direction it was logged,
+//! whichever counter the format put it in.
+
+use std::collections::HashSet;
+
+use serde::{Deserialize, Serialize};
+
+use crate::classify::Networks;
+use crate::parser::FlowEvent;
+
+/// Registered service ports above 1023 that are more likely a server than
+/// an ephemeral client port.
+const SERVICE_PORTS: &[u16] = &[
+    1433, 1521, 1883, 2049, 2375, 2376, 3128, 3306, 3389, 5060, 5061, 5432, 5672, 5900, 5985, 5986, 6379, 6443, 8000,
+    8080, 8443, 8883, 9000, 9092, 9200, 9300, 11211, 27017,
+];
+
+/// Client/server pairs remembered for first-seen orientation; past this,
+/// later pairs fall through to the port and network rules.
+const MAX_PAIRS: usize = 1 << 20;
+
+/// How the events of a run were oriented, reported in the output's metadata.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct DirectionStats {
+    /// TCP and UDP events with both ports
+    pub oriented: u64,
+    /// Those logged from the server's side, and reversed
+    pub reversed: u64,
+    /// Events oriented by each rule
+    pub by_flags: u64,
+    pub by_first_seen: u64,
+    pub by_ports: u64,
+    /// Also counts the events left as logged because neither side is internal, or both are
+    pub by_networks: u64,
+}
+
+impl DirectionStats {
+    pub fn merge(&mut self, other: &DirectionStats) {
+        self.oriented += other.oriented;
+        self.reversed += other.reversed;
+        self.by_flags += other.by_flags;
+        self.by_first_seen += other.by_first_seen;
+        self.by_ports += other.by_ports;
+        self.by_networks += other.by_networks;
+    }
+}
+
+pub struct Normalizer {
+    networks: Networks,
+    /// (client, server, server port) of the pairs seen so far
+    pairs: HashSet<(String, String, String)>,
+    pub stats: DirectionStats,
+}
+
+impl Normalizer {
+    pub fn new(networks: Networks) -> Self {
+        Normalizer { networks, pairs: HashSet::new(), stats: DirectionStats::default() }
+    }
+
+    /// Put `event` in client-to-server orientation.
+    pub fn normalize(&mut self, event: &mut FlowEvent) {
+        if !matches!(event.protocol.trim().to_ascii_lowercase().as_str(), "6" | "17" | "tcp" | "udp") {
+            return;
+        }
+        let Some(source_port) = event.source_port.clone().filter(|port| !port.is_empty()) else {
+            return;
+        };
+        if event.destination_port.is_empty() {
+            return;
+        }
+        self.stats.oriented += 1;
+        if event.packets_in.saturating_add(event.packets_out) == 1 {
+            event.packets_out = 1;
+            event.packets_in = 0;
+            event.bytes_out = event.bytes_out.saturating_add(event.bytes_in);
+            event.bytes_in = 0;
+        }
+
+        let reverse = self.sent_by_server(event, &source_port);
+        let pair = match reverse {
+            true => (event.destination_ip.clone(), event.source_ip.clone(), source_port),
+            false => (event.source_ip.clone(), event.destination_ip.clone(), event.destination_port.clone()),
+        };
+        if self.pairs.len() < MAX_PAIRS {
+            self.pairs.insert(pair);
+        }
+        if reverse {
+            self.stats.reversed += 1;
+            reverse_event(event);
+        }
+    }
+
+    /// Whether `event`, as logged, was sent by the server.
+    fn sent_by_server(&mut self, event: &FlowEvent, source_port: &str) -> bool {
+        // Only a handshake packet's flags; a session's accumulated ones don't tell
+        if let Some(flags) = &event.tcp_flags {
+            let mut letters: Vec<char> = flags.chars().filter(char::is_ascii_alphabetic).map(|c| c.to_ascii_uppercase()).collect();
+            letters.sort_unstable();
+            letters.dedup();
+            if let Some(reverse) = match letters.as_slice() {
+                ['S'] => Some(false),
+                ['A', 'S'] => Some(true),
+                _ => None,
+            } {
+                self.stats.by_flags += 1;
+                return reverse;
+            }
+        }
+
+        let (source, destination) = (&event.source_ip, &event.destination_ip);
+        if self.pairs.contains(&(destination.clone(), source.clone(), source_port.to_string())) {
+            self.stats.by_first_seen += 1;
+            return true;
+        }
+        if self.pairs.contains(&(source.clone(), destination.clone(), event.destination_port.clone())) {
+            self.stats.by_first_seen += 1;
+            return false;
+        }
+
+        let (source_port, destination_port) = (source_port.trim().parse::<u16>().ok(), event.destination_port.trim().parse::<u16>().ok());
+        if let (Some(source_port), Some(destination_port)) = (source_port, destination_port)
+            && let Some(reverse) = match (is_service(source_port), is_service(destination_port)) {
+                (true, false) => Some(true),
+                (false, true) => Some(false),
+                (true, true) if source_port != destination_port => Some(source_port < destination_port),
+                _ => None,
+            }
+        {
+            self.stats.by_ports += 1;
+            return reverse;
+        }
+
+        self.stats.by_networks += 1;
+        !self.networks.is_internal(source) && self.networks.is_internal(destination)
+    }
+}
+
+fn is_service(port: u16) -> bool {
+    port < 1024 || SERVICE_PORTS.binary_search(&port).is_ok()
+}
+
+/// Swap `event`'s sides.
+fn reverse_event(event: &mut FlowEvent) {
+    std::mem::swap(&mut event.source_ip, &mut event.destination_ip);
+    let source_port = event.source_port.take().unwrap_or_default();
+    event.source_port = Some(std::mem::replace(&mut event.destination_port, source_port));
+    std::mem::swap(&mut event.packets_in, &mut event.packets_out);
+    std::mem::swap(&mut event.bytes_in, &mut event.bytes_out);
+    std::mem::swap(&mut event.nat_source_ip, &mut event.nat_destination_ip);
+    std::mem::swap(&mut event.nat_source_port, &mut event.nat_destination_port);
+    std::mem::swap(&mut event.ingress_zone, &mut event.egress_zone);
+    std::mem::swap(&mut event.ingress_interface, &mut event.egress_interface);
+    std::mem::swap(&mut event.source_mac, &mut event.destination_mac);
+}
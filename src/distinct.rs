@@ -0,0 +1,122 @@
+//! Approximate distinct counts per host, for scan and sweep detection.
+//!
+//! While events are aggregated, every source keeps HyperLogLog sketches of
+//! the destination ports and destination addresses it contacted, and every
+//! destination one of the sources that contacted it. The estimates are
+//! attached to each record and the hosts with the most distinct values are
+//! listed in the metadata.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+
+use crate::hll::HyperLogLog;
+use crate::intern::Interner;
+use crate::parser::FlowEvent;
+use crate::record::Record;
+
+struct SourceSketches {
+    ports: HyperLogLog,
+    destinations: HyperLogLog,
+}
+
+pub struct DistinctCounts {
+    precision: u8,
+    sources: HashMap<Arc<str>, SourceSketches>,
+    destinations: HashMap<Arc<str>, HyperLogLog>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct SourceCardinality {
+    pub address: String,
+    pub ports: u64,
+    pub destinations: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct DestinationCardinality {
+    pub address: String,
+    pub sources: u64,
+}
+
+/// The hosts with the most distinct peers or ports.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct Cardinality {
+    pub sources: Vec<SourceCardinality>,
+    pub destinations: Vec<DestinationCardinality>,
+}
+
+impl DistinctCounts {
+    pub fn new(precision: u8) -> Self {
+        DistinctCounts {
+            precision,
+            sources: HashMap::new(),
+            destinations: HashMap::new(),
+        }
+    }
+
+    pub fn add(&mut self, event: &FlowEvent, strings: &mut Interner) {
+        let precision = self.precision;
+        let source = self.sources.entry(strings.intern(&event.source_ip)).or_insert_with(|| SourceSketches {
+            ports: HyperLogLog::new(precision),
+            destinations: HyperLogLog::new(precision),
+        });
+        if !event.destination_port.is_empty() {
+            source.ports.insert(&event.destination_port);
+        }
+        source.destinations.insert(&event.destination_ip);
+        self.destinations
+            .entry(strings.intern(&event.destination_ip))
+            .or_insert_with(|| HyperLogLog::new(precision))
+            .insert(&event.source_ip);
+    }
+
+    pub fn source(&self, address: &str) -> Option<(u64, u64)> {
+        self.sources.get(address).map(|sketches| (sketches.ports.estimate(), sketches.destinations.estimate()))
+    }
+
+    pub fn destination(&self, address: &str) -> Option<u64> {
+        self.destinations.get(address).map(HyperLogLog::estimate)
+    }
+
+    /// Attach the source's and destination's estimates to every record.
+    pub fn apply(&self, records: &mut HashMap<Arc<str>, Record>) {
+        for record in records.values_mut() {
+            if let Some((ports, destinations)) = self.source(&record.source_ip) {
+                record.source_distinct_ports = Some(ports);
+                record.source_distinct_destinations = Some(destinations);
+            }
+            record.destination_distinct_sources = self.destination(&record.destination_ip);
+        }
+    }
+
+    /// The `limit` sources with the most distinct ports or destinations and
+    /// destinations with the most distinct sources.
+    pub fn top(&self, limit: usize) -> Cardinality {
+        let mut sources: Vec<SourceCardinality> = self
+            .sources
+            .iter()
+            .map(|(address, sketches)| SourceCardinality {
+                address: address.to_string(),
+                ports: sketches.ports.estimate(),
+                destinations: sketches.destinations.estimate(),
+            })
+            .collect();
+        sources.sort_by(|a, b| b.ports.max(b.destinations).cmp(&a.ports.max(a.destinations)).then_with(|| a.address.cmp(&b.address)));
+        sources.truncate(limit);
+
+        let mut destinations: Vec<DestinationCardinality> = self
+            .destinations
+            .iter()
+            .map(|(address, sketch)| DestinationCardinality { address: address.to_string(), sources: sketch.estimate() })
+            .collect();
+        destinations.sort_by(|a, b| b.sources.cmp(&a.sources).then_with(|| a.address.cmp(&b.address)));
+        destinations.truncate(limit);
+
+        Cardinality { sources, destinations }
+    }
+}
@@ -0,0 +1,100 @@
+//! Autonomous system and country of addresses, from an IP-to-ASN table.
+//!
+//! The table is the tab-separated `ip2asn` format published by iptoasn.com
+//! (`range_start range_end AS_number country_code AS_description`, IPv4
+//! and IPv6 ranges in one file), optionally gzipped. Ranges the table marks
+//! as not routed (AS 0) are left out. Records get `<side>-asn` like
+//! `AS13335`, `<side>-as-name` and, unless an earlier enrichment set it,
+//! `<side>-country`.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read};
+use std::net::IpAddr;
+use std::path::Path;
+use std::sync::Arc;
+
+use flate2::read::GzDecoder;
+
+use super::Side;
+use crate::record::Record;
+
+struct Range {
+    start: IpAddr,
+    end: IpAddr,
+    asn: u32,
+    country: Option<String>,
+    name: Option<String>,
+}
+
+pub struct AsnTable {
+    /// Sorted by start address, IPv4 before IPv6
+    ranges: Vec<Range>,
+}
+
+impl AsnTable {
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let file = File::open(path)?;
+        let reader: Box<dyn Read> = match path.extension().is_some_and(|extension| extension == "gz") {
+            true => Box::new(GzDecoder::new(file)),
+            false => Box::new(file),
+        };
+        let mut ranges = Vec::new();
+        for line in BufReader::new(reader).lines() {
+            let line = line?;
+            let cells: Vec<&str> = line.split('\t').map(str::trim).collect();
+            if cells.len() < 3 {
+                continue;
+            }
+            let (Ok(start), Ok(end), Ok(asn)) = (cells[0].parse::<IpAddr>(), cells[1].parse::<IpAddr>(), cells[2].parse::<u32>()) else {
+                continue;
+            };
+            if asn == 0 || start.is_ipv4() != end.is_ipv4() {
+                continue;
+            }
+            let text = |index: usize| cells.get(index).filter(|cell| !cell.is_empty() && **cell != "None" && **cell != "Not routed").map(|cell| cell.to_string());
+            ranges.push(Range { start, end, asn, country: text(3), name: text(4) });
+        }
+        if ranges.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "no routed ranges in the ASN table"));
+        }
+        ranges.sort_by_key(|range| range.start);
+        Ok(AsnTable { ranges })
+    }
+
+    pub fn len(&self) -> usize {
+        self.ranges.len()
+    }
+
+    fn lookup(&self, address: &str) -> Option<&Range> {
+        let address = address.parse::<IpAddr>().ok()?;
+        let index = self.ranges.partition_point(|range| range.start <= address).checked_sub(1)?;
+        let range = &self.ranges[index];
+        (range.start.is_ipv4() == address.is_ipv4() && address <= range.end).then_some(range)
+    }
+
+    /// Attach the AS and country of the records' `sides` addresses,
+    /// returning how many records matched.
+    pub fn apply(&self, records: &mut HashMap<Arc<str>, Record>, sides: &[Side]) -> usize {
+        let mut matched = 0;
+        for record in records.values_mut() {
+            let mut hit = false;
+            for side in sides {
+                let Some(range) = self.lookup(side.address(record)) else {
+                    continue;
+                };
+                hit = true;
+                let prefix = side.prefix();
+                record.enrichment.insert(format!("{}-asn", prefix), format!("AS{}", range.asn));
+                if let Some(name) = &range.name {
+                    record.enrichment.insert(format!("{}-as-name", prefix), name.clone());
+                }
+                if let Some(country) = &range.country {
+                    record.enrichment.entry(format!("{}-country", prefix)).or_insert_with(|| country.clone());
+                }
+            }
+            matched += hit as usize;
+        }
+        matched
+    }
+}
@@ -0,0 +1,102 @@
+//! Left join of an asset inventory CSV onto records by IP address.
+//!
+//! The first line names the columns. The address column is the one called
+//! `ip`, `address` or `ip-address` (the first column otherwise); every
+//! other non-empty cell is copied onto matching records, with the column
+//! name lower-cased and hyphenated (`Business Unit` becomes
+//! `source-business-unit`).
+
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+
+use super::Side;
+use crate::record::Record;
+
+pub struct AssetMap {
+    columns: Vec<String>,
+    /// Address -> the row's other cells, in `columns` order
+    rows: HashMap<String, Vec<String>>,
+}
+
+impl AssetMap {
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let text = fs::read_to_string(path)?;
+        let mut lines = text.lines().filter(|line| !line.trim().is_empty());
+        let header = lines
+            .next()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "asset map is empty"))?;
+        let header: Vec<String> = split_row(header).iter().map(|name| column_name(name)).collect();
+        let key = header
+            .iter()
+            .position(|name| matches!(name.as_str(), "ip" | "address" | "ip-address"))
+            .unwrap_or(0);
+
+        let columns = header.iter().enumerate().filter(|(index, _)| *index != key).map(|(_, name)| name.clone()).collect();
+        let mut rows = HashMap::new();
+        for line in lines {
+            let mut cells = split_row(line);
+            if cells.len() <= key {
+                continue;
+            }
+            let address = cells.remove(key).trim().to_string();
+            cells.resize(header.len() - 1, String::new());
+            rows.insert(address, cells);
+        }
+        Ok(AssetMap { columns, rows })
+    }
+
+    /// Copy the inventory columns onto the records whose `sides` addresses
+    /// are listed, returning how many records matched.
+    pub fn apply(&self, records: &mut HashMap<Arc<str>, Record>, sides: &[Side]) -> usize {
+        let mut matched = 0;
+        for record in records.values_mut() {
+            let mut hit = false;
+            for side in sides {
+                let Some(cells) = self.rows.get(side.address(record)) else {
+                    continue;
+                };
+                hit = true;
+                for (column, value) in self.columns.iter().zip(cells) {
+                    if !value.is_empty() {
+                        record.enrichment.insert(format!("{}-{}", side.prefix(), column), value.clone());
+                    }
+                }
+            }
+            matched += hit as usize;
+        }
+        matched
+    }
+}
+
+fn column_name(name: &str) -> String {
+    name.trim()
+        .to_lowercase()
+        .split(|c: char| c.is_whitespace() || c == '_' || c == '-')
+        .filter(|part| !part.is_empty())
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
+/// Split one CSV row, honouring double-quoted cells with `""` escapes.
+fn split_row(line: &str) -> Vec<String> {
+    let mut cells = Vec::new();
+    let mut cell = String::new();
+    let mut quoted = false;
+    let mut chars = line.trim_end_matches('\r').chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                cell.push('"');
+                chars.next();
+            }
+            '"' => quoted = !quoted,
+            ',' if !quoted => cells.push(std::mem::take(&mut cell).trim().to_string()),
+            c => cell.push(c),
+        }
+    }
+    cells.push(cell.trim().to_string());
+    cells
+}
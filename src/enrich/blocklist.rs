@@ -0,0 +1,195 @@
+//! Threat-intel blocklist matching.
+//!
+//! Lists are plain text with one address or CIDR block per line; anything
+//! after the first word and `#`/`;` comments are ignored, so Spamhaus DROP
+//! style files load as they are. Lists can be local files or `http(s)://`
+//! URLs; URLs are fetched again when `refresh` has passed since the last
+//! fetch, keeping the previous entries if that fails, so long `serve` and
+//! `watch` runs match against current indicators.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fs;
+use std::io;
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+use super::Side;
+use crate::cidr::Cidr;
+use crate::record::Record;
+
+/// A record address found on a blocklist.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct IndicatorMatch {
+    /// `source` or `destination`
+    pub side: String,
+    pub indicator: String,
+    pub list: String,
+}
+
+/// How much traffic touched one indicator.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct IndicatorSummary {
+    pub indicator: String,
+    pub list: String,
+    pub flows: u64,
+    pub sessions: u64,
+    pub bytes: u64,
+}
+
+struct List {
+    source: String,
+    entries: Vec<Cidr>,
+    fetched_at: Instant,
+}
+
+impl List {
+    fn is_url(source: &str) -> bool {
+        source.starts_with("http://") || source.starts_with("https://")
+    }
+
+    fn load(source: &str) -> io::Result<List> {
+        let text = match List::is_url(source) {
+            true => crate::sink::http_agent()
+                .get(source)
+                .call()
+                .and_then(|mut response| response.body_mut().read_to_string())
+                .map_err(io::Error::other)?,
+            false => fs::read_to_string(source)?,
+        };
+        let entries = text
+            .lines()
+            .filter_map(|line| line.split(['#', ';']).next()?.split_whitespace().next())
+            .filter_map(|entry| entry.parse().ok())
+            .collect();
+        Ok(List {
+            source: source.to_string(),
+            entries,
+            fetched_at: Instant::now(),
+        })
+    }
+}
+
+pub struct Blocklists {
+    lists: Vec<List>,
+    refresh: Duration,
+    /// Block -> the first list it came from
+    index: HashMap<Cidr, usize>,
+    /// Prefix lengths present, longest first, per address family
+    prefixes: BTreeMap<bool, BTreeSet<std::cmp::Reverse<u32>>>,
+}
+
+impl Blocklists {
+    pub fn load(sources: &[String], refresh: Duration) -> io::Result<Self> {
+        let mut lists = Vec::new();
+        for source in sources {
+            let list = List::load(source).map_err(|err| io::Error::new(err.kind(), format!("{}: {}", source, err)))?;
+            if list.entries.is_empty() {
+                eprintln!("Blocklist {} has no addresses or CIDR blocks", source);
+            }
+            lists.push(list);
+        }
+        let mut blocklists = Blocklists {
+            lists,
+            refresh,
+            index: HashMap::new(),
+            prefixes: BTreeMap::new(),
+        };
+        blocklists.reindex();
+        Ok(blocklists)
+    }
+
+    fn reindex(&mut self) {
+        self.index.clear();
+        self.prefixes.clear();
+        for (position, list) in self.lists.iter().enumerate() {
+            for entry in &list.entries {
+                self.index.entry(*entry).or_insert(position);
+                self.prefixes.entry(entry.is_v4()).or_default().insert(std::cmp::Reverse(entry.prefix()));
+            }
+        }
+    }
+
+    /// Fetch the URL lists again whose refresh interval has passed.
+    pub fn refresh_if_due(&mut self) {
+        let mut changed = false;
+        for list in &mut self.lists {
+            if !List::is_url(&list.source) || list.fetched_at.elapsed() < self.refresh {
+                continue;
+            }
+            match List::load(&list.source) {
+                Ok(fresh) => {
+                    *list = fresh;
+                    changed = true;
+                }
+                Err(err) => eprintln!("Unable to refresh blocklist {}, keeping the previous copy: {}", list.source, err),
+            }
+        }
+        if changed {
+            self.reindex();
+        }
+    }
+
+    /// The most specific listed block holding `address`, with its list.
+    fn lookup(&self, address: IpAddr) -> Option<(Cidr, &str)> {
+        let prefixes = self.prefixes.get(&address.is_ipv4())?;
+        prefixes.iter().find_map(|std::cmp::Reverse(prefix)| {
+            let block = Cidr::of(address, *prefix);
+            self.index.get(&block).map(|list| (block, self.lists[*list].source.as_str()))
+        })
+    }
+
+    /// Tag records whose source or destination is listed, returning how
+    /// many were.
+    pub fn apply(&self, records: &mut HashMap<Arc<str>, Record>) -> usize {
+        let mut tagged = 0;
+        for record in records.values_mut() {
+            for side in [Side::SourceIp, Side::DestinationIp] {
+                let Ok(address) = side.address(record).parse::<IpAddr>() else {
+                    continue;
+                };
+                let Some((block, list)) = self.lookup(address) else {
+                    continue;
+                };
+                // Single hosts read better without their /32 or /128
+                let indicator = match block.prefix() == if block.is_v4() { 32 } else { 128 } {
+                    true => address.to_string(),
+                    false => block.to_string(),
+                };
+                record.matched_indicators.push(IndicatorMatch {
+                    side: side.prefix().to_string(),
+                    indicator,
+                    list: list.to_string(),
+                });
+            }
+            tagged += !record.matched_indicators.is_empty() as usize;
+        }
+        tagged
+    }
+}
+
+/// Traffic per matched indicator across tagged records, most bytes first.
+pub fn summarize<'a>(records: impl IntoIterator<Item = &'a Record>) -> Vec<IndicatorSummary> {
+    let mut summaries: HashMap<(&str, &str), IndicatorSummary> = HashMap::new();
+    for record in records {
+        for matched in &record.matched_indicators {
+            let summary = summaries.entry((&matched.indicator, &matched.list)).or_insert_with(|| IndicatorSummary {
+                indicator: matched.indicator.clone(),
+                list: matched.list.clone(),
+                flows: 0,
+                sessions: 0,
+                bytes: 0,
+            });
+            summary.flows += 1;
+            summary.sessions += record.count;
+            summary.bytes = summary.bytes.saturating_add(record.bytes_in.saturating_add(record.bytes_out));
+        }
+    }
+
+    let mut summaries: Vec<_> = summaries.into_values().collect();
+    summaries.sort_by(|a, b| b.bytes.cmp(&a.bytes).then_with(|| a.indicator.cmp(&b.indicator)));
+    summaries
+}
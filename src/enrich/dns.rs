@@ -0,0 +1,164 @@
+//! Domain names for flows from the DNS query logs of the same period.
+//!
+//! Two log formats are read, recognised per line:
+//!
+//! - dnsmasq / Pi-hole with `log-queries`: `query[A] NAME from CLIENT`
+//!   followed by `reply NAME is ADDRESS` (or `cached`). CNAME chains are
+//!   followed back to the name the client asked for.
+//! - Zeek `dns.log`, as TSV with a `#fields` header or as JSON lines, using
+//!   `id.orig_h`, `query` and `answers`.
+//!
+//! Files are read in name order and later resolutions replace earlier ones,
+//! so each record gets the most recent name its source resolved to its
+//! destination, as `destination-domain`.
+
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::net::IpAddr;
+use std::path::Path;
+use std::sync::Arc;
+
+use serde_json::Value;
+
+use crate::record::Record;
+
+#[derive(Default)]
+pub struct Resolutions {
+    /// (client, answer address) -> queried name
+    names: HashMap<(IpAddr, IpAddr), String>,
+    /// dnsmasq: name -> client of its latest query
+    pending: HashMap<String, IpAddr>,
+    /// dnsmasq: client and asked name of the reply lines being read
+    chain: Option<(IpAddr, String)>,
+    /// Zeek TSV: column positions of client, query and answers
+    zeek_columns: Option<(usize, usize, usize)>,
+}
+
+impl Resolutions {
+    pub fn load(dir: &Path) -> io::Result<Self> {
+        let mut files: Vec<_> = fs::read_dir(dir)?
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| path.is_file())
+            .collect();
+        files.sort();
+
+        let mut resolutions = Resolutions::default();
+        for path in files {
+            let text = fs::read_to_string(&path)?;
+            resolutions.zeek_columns = None;
+            resolutions.chain = None;
+            for line in text.lines() {
+                resolutions.read_line(line);
+            }
+        }
+        resolutions.pending.clear();
+        Ok(resolutions)
+    }
+
+    pub fn len(&self) -> usize {
+        self.names.len()
+    }
+
+    fn read_line(&mut self, line: &str) {
+        if let Some(fields) = line.strip_prefix("#fields") {
+            let columns: Vec<&str> = fields.split('\t').filter(|field| !field.is_empty()).collect();
+            let position = |name: &str| columns.iter().position(|column| *column == name);
+            self.zeek_columns = match (position("id.orig_h"), position("query"), position("answers")) {
+                (Some(client), Some(query), Some(answers)) => Some((client, query, answers)),
+                _ => None,
+            };
+            return;
+        }
+        if line.starts_with('#') {
+            return;
+        }
+        if line.trim_start().starts_with('{') {
+            self.read_zeek_json(line);
+        } else if let Some((client, query, answers)) = self.zeek_columns {
+            let cells: Vec<&str> = line.split('\t').collect();
+            if let (Some(client), Some(query), Some(answers)) = (cells.get(client), cells.get(query), cells.get(answers)) {
+                self.resolved(client, query, answers.split(','));
+            }
+        } else {
+            self.read_dnsmasq(line);
+        }
+    }
+
+    fn read_zeek_json(&mut self, line: &str) {
+        let Ok(entry) = serde_json::from_str::<Value>(line) else {
+            return;
+        };
+        let (Some(client), Some(query)) = (entry["id.orig_h"].as_str(), entry["query"].as_str()) else {
+            return;
+        };
+        let answers = entry["answers"].as_array().map(Vec::as_slice).unwrap_or_default();
+        self.resolved(client, query, answers.iter().filter_map(Value::as_str));
+    }
+
+    fn resolved<'a>(&mut self, client: &str, query: &str, answers: impl Iterator<Item = &'a str>) {
+        let Ok(client) = client.parse::<IpAddr>() else {
+            return;
+        };
+        for answer in answers {
+            if let Ok(address) = answer.trim().parse::<IpAddr>() {
+                self.names.insert((client, address), query.trim_end_matches('.').to_lowercase());
+            }
+        }
+    }
+
+    fn read_dnsmasq(&mut self, line: &str) {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let at = words.iter().position(|word| word.starts_with("query[") || *word == "reply" || *word == "cached");
+        let Some(at) = at else {
+            self.chain = None;
+            return;
+        };
+        match &words[at..] {
+            [query, name, "from", client, ..] if query.starts_with("query[") => {
+                self.chain = None;
+                // `log-queries=extra` logs the client as `address/port`
+                if let Ok(client) = client.split('/').next().unwrap_or_default().parse::<IpAddr>() {
+                    self.pending.insert(name.to_lowercase(), client);
+                }
+            }
+            [_, name, "is", answer, ..] => {
+                let name = name.to_lowercase();
+                // Replies for the targets of a CNAME follow it directly and
+                // belong to the name the client asked for
+                let asked = match self.pending.get(&name) {
+                    Some(client) => Some((*client, name)),
+                    None => self.chain.clone(),
+                };
+                let Some((client, asked)) = asked else {
+                    return;
+                };
+                if *answer == "<CNAME>" {
+                    self.chain = Some((client, asked));
+                } else {
+                    if let Ok(address) = answer.parse::<IpAddr>() {
+                        self.names.insert((client, address), asked.clone());
+                    }
+                    self.chain = Some((client, asked));
+                }
+            }
+            _ => self.chain = None,
+        }
+    }
+
+    /// Add `destination-domain` to records whose source resolved their
+    /// destination, returning how many got one.
+    pub fn apply(&self, records: &mut HashMap<Arc<str>, Record>) -> usize {
+        let mut labeled = 0;
+        for record in records.values_mut() {
+            let (Ok(source), Ok(destination)) = (record.source_ip.parse::<IpAddr>(), record.destination_ip.parse::<IpAddr>()) else {
+                continue;
+            };
+            if let Some(name) = self.names.get(&(source, destination)) {
+                record.enrichment.insert("destination-domain".to_string(), name.clone());
+                labeled += 1;
+            }
+        }
+        labeled
+    }
+}
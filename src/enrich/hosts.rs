@@ -0,0 +1,75 @@
+//! Hostnames for addresses from dnsmasq lease files and hosts files.
+//!
+//! Each line is recognised on its own: a dnsmasq lease is
+//! `<expiry> <mac> <ip> <hostname> <client-id>`, a hosts entry is
+//! `<ip> <name> [aliases...]` with `#` comments. Leases without a hostname
+//! (`*`) and loopback entries are ignored; later files and lines win.
+//! Both sides of a flow get a `source-hostname` / `destination-hostname`
+//! unless the asset map already provided one.
+
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::net::IpAddr;
+use std::path::Path;
+use std::sync::Arc;
+
+use super::Side;
+use crate::record::Record;
+
+#[derive(Default)]
+pub struct HostNames {
+    names: HashMap<IpAddr, String>,
+}
+
+impl HostNames {
+    pub fn load(paths: &[impl AsRef<Path>]) -> io::Result<Self> {
+        let mut hosts = HostNames::default();
+        for path in paths {
+            let path = path.as_ref();
+            let text = fs::read_to_string(path).map_err(|err| io::Error::new(err.kind(), format!("{}: {}", path.display(), err)))?;
+            for line in text.lines() {
+                if let Some((address, name)) = parse_line(line) {
+                    hosts.names.insert(address, name.to_string());
+                }
+            }
+        }
+        Ok(hosts)
+    }
+
+    pub fn len(&self) -> usize {
+        self.names.len()
+    }
+
+    /// Label both addresses of each record, returning how many records got
+    /// at least one hostname.
+    pub fn apply(&self, records: &mut HashMap<Arc<str>, Record>) -> usize {
+        let mut labeled = 0;
+        for record in records.values_mut() {
+            let mut hit = false;
+            for side in [Side::SourceIp, Side::DestinationIp] {
+                let Some(name) = side.address(record).parse().ok().and_then(|address| self.names.get(&address)) else {
+                    continue;
+                };
+                hit = true;
+                record
+                    .enrichment
+                    .entry(format!("{}-hostname", side.prefix()))
+                    .or_insert_with(|| name.clone());
+            }
+            labeled += hit as usize;
+        }
+        labeled
+    }
+}
+
+fn parse_line(line: &str) -> Option<(IpAddr, &str)> {
+    let line = line.split('#').next().unwrap_or_default();
+    let fields: Vec<&str> = line.split_whitespace().collect();
+    let (address, name) = match fields.as_slice() {
+        [expiry, _mac, address, name, ..] if expiry.parse::<u64>().is_ok() => (address.parse::<IpAddr>().ok()?, *name),
+        [address, name, ..] => (address.parse::<IpAddr>().ok()?, *name),
+        _ => return None,
+    };
+    (name != "*" && !address.is_loopback() && !address.is_unspecified()).then_some((address, name))
+}
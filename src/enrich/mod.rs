@@ -0,0 +1,42 @@
+//! Context attached to finished records from outside the logs.
+//!
+//! Enrichment runs once on the aggregated records, so its cost grows with
+//! the number of flows rather than the number of lines. Values are stored in
+//! each record's `enrichment` map under `<side>-<name>` keys, e.g.
+//! `source-hostname` or `destination-owner`.
+
+pub mod asn;
+pub mod assets;
+pub mod blocklist;
+pub mod dns;
+pub mod hosts;
+pub mod oui;
+pub mod rdap;
+
+use clap::ValueEnum;
+
+use crate::record::Record;
+
+/// Which address of a flow an enrichment is looked up by.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Side {
+    SourceIp,
+    DestinationIp,
+}
+
+impl Side {
+    pub fn address(self, record: &Record) -> &str {
+        match self {
+            Side::SourceIp => &record.source_ip,
+            Side::DestinationIp => &record.destination_ip,
+        }
+    }
+
+    /// Prefix of the enrichment keys filled in for this side.
+    pub fn prefix(self) -> &'static str {
+        match self {
+            Side::SourceIp => "source",
+            Side::DestinationIp => "destination",
+        }
+    }
+}
@@ -0,0 +1,161 @@
+//! Vendor names for the MAC addresses some formats log.
+//!
+//! A small table of common OUIs (virtualisation, network gear, single-board
+//! computers, consumer devices) is built in; `--oui-file` adds or overrides
+//! entries from the IEEE `oui.txt` or `oui.csv` download. Addresses with the
+//! locally-administered bit set are randomised or assigned by software, so
+//! they are labeled as such rather than looked up.
+
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::record::Record;
+
+const BUILTIN: &[(&str, &str)] = &[
+    ("00:00:0c", "Cisco"),
+    ("00:03:93", "Apple"),
+    ("00:04:4b", "NVIDIA"),
+    ("00:04:f2", "Polycom"),
+    ("00:05:69", "VMware"),
+    ("00:05:85", "Juniper Networks"),
+    ("00:09:0f", "Fortinet"),
+    ("00:09:5b", "Netgear"),
+    ("00:0b:82", "Grandstream"),
+    ("00:0b:86", "Aruba Networks"),
+    ("00:0c:29", "VMware"),
+    ("00:0c:42", "MikroTik"),
+    ("00:0d:93", "Apple"),
+    ("00:0d:b9", "PC Engines"),
+    ("00:0e:58", "Sonos"),
+    ("00:10:18", "Broadcom"),
+    ("00:11:32", "Synology"),
+    ("00:13:10", "Cisco-Linksys"),
+    ("00:14:22", "Dell"),
+    ("00:14:6c", "Netgear"),
+    ("00:14:bf", "Cisco-Linksys"),
+    ("00:15:5d", "Microsoft Hyper-V"),
+    ("00:15:65", "Yealink"),
+    ("00:16:3e", "Xen"),
+    ("00:17:88", "Philips Lighting"),
+    ("00:17:f2", "Apple"),
+    ("00:18:0a", "Cisco Meraki"),
+    ("00:1a:11", "Google"),
+    ("00:1a:1e", "Aruba Networks"),
+    ("00:1b:17", "Palo Alto Networks"),
+    ("00:1b:21", "Intel"),
+    ("00:1b:63", "Apple"),
+    ("00:1c:42", "Parallels"),
+    ("00:1c:7f", "Check Point"),
+    ("00:1d:0f", "TP-Link"),
+    ("00:1e:67", "Intel"),
+    ("00:1f:33", "Netgear"),
+    ("00:21:6a", "Intel"),
+    ("00:24:d7", "Intel"),
+    ("00:25:90", "Supermicro"),
+    ("00:25:b5", "Cisco"),
+    ("00:26:b9", "Dell"),
+    ("00:26:bb", "Apple"),
+    ("00:27:22", "Ubiquiti"),
+    ("00:30:48", "Supermicro"),
+    ("00:50:56", "VMware"),
+    ("00:50:f2", "Microsoft"),
+    ("00:a0:40", "Apple"),
+    ("00:e0:4c", "Realtek"),
+    ("04:18:d6", "Ubiquiti"),
+    ("08:00:27", "VirtualBox"),
+    ("18:b4:30", "Nest Labs"),
+    ("24:a4:3c", "Ubiquiti"),
+    ("28:cd:c1", "Raspberry Pi"),
+    ("3c:5a:b4", "Google"),
+    ("3c:fd:fe", "Intel"),
+    ("44:65:0d", "Amazon"),
+    ("4c:5e:0c", "MikroTik"),
+    ("50:c7:bf", "TP-Link"),
+    ("80:2a:a8", "Ubiquiti"),
+    ("a0:36:9f", "Intel"),
+    ("ac:1f:6b", "Supermicro"),
+    ("b8:27:eb", "Raspberry Pi"),
+    ("dc:a6:32", "Raspberry Pi"),
+    ("e4:5f:01", "Raspberry Pi"),
+    ("e4:8d:8c", "MikroTik"),
+    ("f0:9f:c2", "Ubiquiti"),
+    ("f4:f5:d8", "Google"),
+];
+
+pub struct OuiTable {
+    vendors: HashMap<String, String>,
+}
+
+impl OuiTable {
+    pub fn builtin() -> Self {
+        let vendors = BUILTIN.iter().map(|(prefix, vendor)| (prefix.to_string(), vendor.to_string())).collect();
+        OuiTable { vendors }
+    }
+
+    /// The built-in table plus the entries of an IEEE `oui.txt` (`00-50-56
+    /// (hex) VMware, Inc.`) or `oui.csv` (`MA-L,005056,"VMware, Inc.",...`).
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let mut table = OuiTable::builtin();
+        let text = fs::read_to_string(path)?;
+        let before = table.vendors.len();
+        for line in text.lines() {
+            if let Some((prefix, vendor)) = parse_line(line) {
+                table.vendors.insert(prefix, vendor);
+            }
+        }
+        if table.vendors.len() == before {
+            eprintln!("OUI file {} has no recognisable entries", path.display());
+        }
+        Ok(table)
+    }
+
+    pub fn vendor(&self, mac: &str) -> Option<&str> {
+        let first = u8::from_str_radix(mac.get(0..2)?, 16).ok()?;
+        if first & 0x02 != 0 {
+            return Some("locally administered");
+        }
+        self.vendors.get(mac.get(0..8)?).map(String::as_str)
+    }
+
+    /// Add `source-mac-vendor` / `destination-mac-vendor` to records that
+    /// carry MAC addresses, returning how many got one.
+    pub fn apply(&self, records: &mut HashMap<Arc<str>, Record>) -> usize {
+        let mut labeled = 0;
+        for record in records.values_mut() {
+            let mut hit = false;
+            for (side, mac) in [("source", &record.source_mac), ("destination", &record.destination_mac)] {
+                if let Some(vendor) = mac.as_deref().and_then(|mac| self.vendor(mac)) {
+                    record.enrichment.insert(format!("{}-mac-vendor", side), vendor.to_string());
+                    hit = true;
+                }
+            }
+            labeled += hit as usize;
+        }
+        labeled
+    }
+}
+
+fn parse_line(line: &str) -> Option<(String, String)> {
+    let (assignment, vendor) = match line.split_once("(hex)") {
+        Some((assignment, vendor)) => (assignment.trim().replace('-', ""), vendor.trim().to_string()),
+        None => {
+            let mut cells = line.splitn(3, ',');
+            let _registry = cells.next()?;
+            let assignment = cells.next()?.trim().to_string();
+            let rest = cells.next()?.trim();
+            let vendor = match rest.strip_prefix('"') {
+                Some(quoted) => quoted.split('"').next()?.to_string(),
+                None => rest.split(',').next()?.to_string(),
+            };
+            (assignment, vendor)
+        }
+    };
+    if assignment.len() != 6 || !assignment.bytes().all(|b| b.is_ascii_hexdigit()) || vendor.is_empty() {
+        return None;
+    }
+    let assignment = assignment.to_ascii_lowercase();
+    Some((format!("{}:{}:{}", &assignment[0..2], &assignment[2..4], &assignment[4..6]), vendor))
+}
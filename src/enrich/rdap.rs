@@ -0,0 +1,195 @@
+//! Network owner lookup for external destinations through RDAP.
+//!
+//! Only public destination addresses are looked up, busiest first, up to a
+//! per-run limit and no faster than the configured rate. Answers cover a
+//! whole registered network, so they are cached by address range in a JSON
+//! file and reused for every address in that range until they expire.
+//! Records get `destination-network-owner`, `destination-network` and
+//! `destination-country` where the registry provides them.
+
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::net::IpAddr;
+use std::path::Path;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::atomic;
+use crate::cidr::is_public;
+use crate::record::Record;
+
+pub struct RdapOptions<'a> {
+    /// Base URL the address is appended to, e.g. `https://rdap.org/ip/`
+    pub base_url: &'a str,
+    pub cache: &'a Path,
+    pub max_age: Duration,
+    /// Most lookups per run; the rest wait for a later run
+    pub limit: usize,
+    /// Minimum time between two lookups
+    pub interval: Duration,
+}
+
+/// A registered network as answered by RDAP.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+struct Network {
+    start: String,
+    end: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    handle: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    owner: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    country: Option<String>,
+    /// Seconds since the epoch the answer was fetched
+    fetched: u64,
+}
+
+impl Network {
+    fn contains(&self, address: IpAddr) -> bool {
+        match (self.start.parse::<IpAddr>(), self.end.parse::<IpAddr>()) {
+            (Ok(start), Ok(end)) => start.is_ipv4() == address.is_ipv4() && start <= address && address <= end,
+            _ => false,
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct RdapStats {
+    pub looked_up: usize,
+    pub cached: usize,
+    pub failed: usize,
+    /// Addresses left for a later run by the lookup limit
+    pub deferred: usize,
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs()
+}
+
+fn load_cache(path: &Path, max_age: Duration) -> Vec<Network> {
+    let Ok(text) = fs::read_to_string(path) else {
+        return Vec::new();
+    };
+    let networks: Vec<Network> = serde_json::from_str(&text).unwrap_or_default();
+    let oldest = now().saturating_sub(max_age.as_secs());
+    networks.into_iter().filter(|network| network.fetched >= oldest).collect()
+}
+
+/// Text of the first `fn` vCard property of the entity holding `role`.
+fn entity_name(entities: &Value, role: &str) -> Option<String> {
+    entities.as_array()?.iter().find_map(|entity| {
+        let has_role = entity["roles"].as_array()?.iter().any(|value| value.as_str() == Some(role));
+        if !has_role {
+            return None;
+        }
+        entity["vcardArray"][1].as_array()?.iter().find_map(|property| {
+            (property[0].as_str() == Some("fn")).then(|| property[3].as_str().map(str::to_string)).flatten()
+        })
+    })
+}
+
+fn fetch(base_url: &str, address: IpAddr) -> io::Result<Network> {
+    let answer: Value = crate::sink::http_agent()
+        .get(&format!("{}{}", base_url, address))
+        .header("Accept", "application/rdap+json")
+        .call()
+        .and_then(|mut response| response.body_mut().read_json())
+        .map_err(io::Error::other)?;
+
+    let text = |field: &str| answer[field].as_str().map(str::to_string);
+    let (Some(start), Some(end)) = (text("startAddress"), text("endAddress")) else {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "RDAP answer has no address range"));
+    };
+    Ok(Network {
+        start,
+        end,
+        handle: text("handle"),
+        name: text("name"),
+        owner: entity_name(&answer["entities"], "registrant").or_else(|| entity_name(&answer["entities"], "administrative")),
+        country: text("country"),
+        fetched: now(),
+    })
+}
+
+/// Attach network owners to the records' public destinations.
+pub fn enrich(records: &mut HashMap<Arc<str>, Record>, options: &RdapOptions) -> RdapStats {
+    let mut networks = load_cache(options.cache, options.max_age);
+    let mut stats = RdapStats::default();
+
+    // Busiest destinations first, so the lookup limit is spent where it matters
+    let mut bytes: HashMap<IpAddr, u64> = HashMap::new();
+    for record in records.values() {
+        if let Ok(address) = record.destination_ip.parse::<IpAddr>()
+            && is_public(address)
+        {
+            let total = bytes.entry(address).or_insert(0);
+            *total = total.saturating_add(record.bytes_in.saturating_add(record.bytes_out));
+        }
+    }
+    let mut addresses: Vec<_> = bytes.into_iter().collect();
+    addresses.sort_by(|(a, a_bytes), (b, b_bytes)| b_bytes.cmp(a_bytes).then_with(|| a.cmp(b)));
+
+    let mut last_lookup: Option<Instant> = None;
+    for (address, _) in addresses {
+        if networks.iter().any(|network| network.contains(address)) {
+            stats.cached += 1;
+            continue;
+        }
+        if stats.looked_up + stats.failed >= options.limit {
+            stats.deferred += 1;
+            continue;
+        }
+        if let Some(last) = last_lookup {
+            thread::sleep(options.interval.saturating_sub(last.elapsed()));
+        }
+        last_lookup = Some(Instant::now());
+        match fetch(options.base_url, address) {
+            Ok(network) => {
+                stats.looked_up += 1;
+                networks.push(network);
+            }
+            Err(err) => {
+                eprintln!("RDAP lookup of {} failed: {}", address, err);
+                stats.failed += 1;
+            }
+        }
+    }
+
+    for record in records.values_mut() {
+        let Ok(address) = record.destination_ip.parse::<IpAddr>() else {
+            continue;
+        };
+        let Some(network) = networks.iter().find(|network| network.contains(address)) else {
+            continue;
+        };
+        let fields = [
+            ("destination-network-owner", network.owner.as_ref().or(network.name.as_ref())),
+            ("destination-network", network.handle.as_ref().or(network.name.as_ref())),
+            ("destination-country", network.country.as_ref()),
+        ];
+        for (key, value) in fields {
+            if let Some(value) = value {
+                record.enrichment.insert(key.to_string(), value.clone());
+            }
+        }
+    }
+
+    if let Some(parent) = options.cache.parent()
+        && !parent.as_os_str().is_empty()
+    {
+        let _ = fs::create_dir_all(parent);
+    }
+    let cache = serde_json::to_vec_pretty(&networks).expect("Unable to serialize RDAP cache");
+    if let Err(err) = atomic::write(options.cache, &cache) {
+        eprintln!("Unable to save RDAP cache {}: {}", options.cache.display(), err);
+    }
+    stats
+}
@@ -0,0 +1,63 @@
+//! Failures of a processing run.
+//!
+//! An input that can't be read costs only its own lines: it is recorded in
+//! the output's `failedFiles` and the run carries on with the rest. An
+//! output that can't be written ends the run, but with a message and an
+//! exit status rather than a panic.
+
+use std::io;
+use std::path::PathBuf;
+
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("unable to open {}: {source}", path.display())]
+    Open { path: PathBuf, source: io::Error },
+    #[error("unable to read {} after {lines} lines: {source}", path.display())]
+    Read { path: PathBuf, lines: u64, source: io::Error },
+    #[error("unable to create output directory {}: {source}", path.display())]
+    CreateDir { path: PathBuf, source: io::Error },
+    #[error("unable to write {}: {source}", path.display())]
+    Write { path: PathBuf, source: io::Error },
+    #[error("not enough space to write {}: it needs about {needed} bytes with the margin, and only {available} are free", path.display())]
+    NoSpace { path: PathBuf, needed: u64, available: u64 },
+    #[error("unable to remove {}: {source}", path.display())]
+    Remove { path: PathBuf, source: io::Error },
+    #[error("unable to update the output manifest in {}: {source}", path.display())]
+    Manifest { path: PathBuf, source: io::Error },
+    #[error("unable to serve the top talkers API on {addr}: {source}")]
+    TopApi { addr: String, source: io::Error },
+    #[error("unable to start the syslog listener: {source}")]
+    Listen { source: io::Error },
+    #[error("unable to read explore commands: {source}")]
+    Explore { source: io::Error },
+    #[cfg(feature = "kafka")]
+    #[error("unable to {action} Kafka topic {topic}: {source}")]
+    Kafka { action: &'static str, topic: String, source: rdkafka::error::KafkaError },
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl Error {
+    /// The file or directory the failure is about.
+    pub fn path(&self) -> Option<&PathBuf> {
+        match self {
+            Error::Open { path, .. }
+            | Error::Read { path, .. }
+            | Error::CreateDir { path, .. }
+            | Error::Write { path, .. }
+            | Error::NoSpace { path, .. }
+            | Error::Remove { path, .. }
+            | Error::Manifest { path, .. } => Some(path),
+            Error::TopApi { .. } | Error::Listen { .. } | Error::Explore { .. } => None,
+            #[cfg(feature = "kafka")]
+            Error::Kafka { .. } => None,
+        }
+    }
+
+    pub fn write(path: impl Into<PathBuf>) -> impl FnOnce(io::Error) -> Error {
+        let path = path.into();
+        move |source| Error::Write { path, source }
+    }
+}
@@ -0,0 +1,129 @@
+//! `explore`: an interactive prompt over the flows of one output.
+//!
+//! Filters accumulate until cleared and apply to both the flow listing and
+//! the group roll-ups, so a session narrows down step by step:
+//!
+//! ```text
+//! > filter destination-ip=10.0.0.0/8
+//! > group port
+//! > filter port=445
+//! > sort sessions
+//! > show 50
+//! ```
+
+use std::io::{self, BufRead, Write};
+
+use clap::ValueEnum;
+
+use crate::payload::Payload;
+use crate::query::{self, Filter, SortKey};
+use crate::record::Record;
+use crate::report::format_bytes;
+use crate::summary;
+
+const HELP: &str = "\
+Commands:
+  filter FIELD=VALUE   narrow to matching flows (firewall, source-ip, destination-ip, port, protocol);
+                       addresses take a CIDR block
+  clear                drop all filters
+  sort KEY             order flows by bytes, packets, sessions or key
+  show [N]             list the top N flows (default 20)
+  group FIELD [N]      totals per firewall, source-ip, destination-ip, port or protocol
+  status               show the current filters and how many flows match
+  help                 this text
+  quit                 leave
+";
+
+pub fn run(payload: &Payload) -> io::Result<()> {
+    let mut filter = Filter::default();
+    let mut sort = SortKey::Bytes;
+    let stdin = io::stdin();
+    let mut out = io::stdout();
+
+    writeln!(out, "{} flows loaded. Type `help` for commands.", payload.data.len())?;
+    loop {
+        write!(out, "> ")?;
+        out.flush()?;
+        let mut line = String::new();
+        if stdin.lock().read_line(&mut line)? == 0 {
+            writeln!(out)?;
+            return Ok(());
+        }
+        let mut words = line.split_whitespace();
+        let Some(command) = words.next() else {
+            continue;
+        };
+        let argument = words.next();
+
+        match command {
+            "quit" | "exit" | "q" => return Ok(()),
+            "help" | "?" => write!(out, "{}", HELP)?,
+            "clear" => filter = Filter::default(),
+            "filter" => match argument.and_then(|argument| argument.split_once('=')) {
+                Some((field, value)) => {
+                    if let Err(err) = filter.set(field, value) {
+                        writeln!(out, "{}", err)?;
+                    }
+                }
+                None => writeln!(out, "usage: filter FIELD=VALUE")?,
+            },
+            "sort" => match argument.map(|key| SortKey::from_str(key, true)) {
+                Some(Ok(key)) => sort = key,
+                _ => writeln!(out, "usage: sort bytes|packets|sessions|key")?,
+            },
+            "show" => {
+                let limit = argument.and_then(|limit| limit.parse().ok()).unwrap_or(20);
+                let flows = query::select(payload.data.values(), &filter, sort, limit);
+                write!(out, "{}", query::render(&flows))?;
+            }
+            "group" => {
+                let limit = words.next().and_then(|limit| limit.parse().ok()).unwrap_or(20);
+                match argument.and_then(group_field) {
+                    Some(field) => write!(out, "{}", group(payload, &filter, field, limit))?,
+                    None => writeln!(out, "usage: group firewall|source-ip|destination-ip|port|protocol [N]")?,
+                }
+            }
+            "status" => {
+                let matching = payload.data.values().filter(|record| filter.matches(record)).count();
+                let filters = filter.describe();
+                let filters = if filters.is_empty() { "none".to_string() } else { filters.join(" ") };
+                let sort = sort.to_possible_value().map(|value| value.get_name().to_string()).unwrap_or_default();
+                writeln!(out, "filters: {}; sort: {}; {} of {} flows match", filters, sort, matching, payload.data.len())?;
+            }
+            _ => writeln!(out, "unknown command `{}`; type `help`", command)?,
+        }
+    }
+}
+
+fn group_field(field: &str) -> Option<fn(&Record) -> &str> {
+    Some(match field {
+        "firewall" => |record: &Record| &*record.firewall,
+        "source-ip" => |record: &Record| &*record.source_ip,
+        "destination-ip" => |record: &Record| &*record.destination_ip,
+        "port" => |record: &Record| &*record.destination_port,
+        "protocol" => |record: &Record| &*record.protocol,
+        _ => return None,
+    })
+}
+
+/// Totals of the matching flows per value of `field`, busiest first.
+fn group(payload: &Payload, filter: &Filter, field: fn(&Record) -> &str, limit: usize) -> String {
+    let matching = payload.data.values().filter(|record| filter.matches(record));
+    let mut groups: Vec<_> = summary::group_by(matching, field).into_iter().collect();
+    groups.sort_by(|(a_key, a), (b_key, b)| b.bytes().cmp(&a.bytes()).then_with(|| a_key.cmp(b_key)));
+    groups.truncate(limit);
+
+    let rows: Vec<Vec<String>> = groups
+        .into_iter()
+        .map(|(value, totals)| {
+            vec![
+                value,
+                totals.flows.to_string(),
+                totals.sessions.to_string(),
+                format_bytes(totals.bytes_in),
+                format_bytes(totals.bytes_out),
+            ]
+        })
+        .collect();
+    query::text_table(&["Value", "Flows", "Sessions", "Bytes in", "Bytes out"], &rows, 1)
+}
@@ -0,0 +1,93 @@
+//! Intermediate outputs of long batch runs.
+//!
+//! With `--flush-every`, the records aggregated so far are written out
+//! every so many input files or minutes, next to where the final output
+//! goes and marked `partial` in their metadata, so consumers can start on
+//! a day's data before the whole archive has been read. Each one covers
+//! everything since the start of the run; the final output supersedes
+//! them all.
+
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FlushEvery {
+    Files(usize),
+    Interval(Duration),
+}
+
+impl FromStr for FlushEvery {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("expected `<n>-files` or a duration like `10m`, `90s` or `2h`, got `{}`", value);
+        if let Some(files) = value.strip_suffix("-files") {
+            return match files.parse() {
+                Ok(files) if files > 0 => Ok(FlushEvery::Files(files)),
+                _ => Err(invalid()),
+            };
+        }
+        let (amount, unit) = value.split_at(value.len().saturating_sub(1));
+        let seconds = match unit {
+            "s" => 1,
+            "m" => 60,
+            "h" => 3600,
+            _ => return Err(invalid()),
+        };
+        match amount.parse::<u64>() {
+            Ok(amount) if amount > 0 => Ok(FlushEvery::Interval(Duration::from_secs(amount * seconds))),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+/// Set on an intermediate output's metadata.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Partial {
+    /// 1 for the run's first intermediate output
+    pub sequence: u32,
+    pub files_read: usize,
+    pub files_total: usize,
+}
+
+pub struct Flusher {
+    every: FlushEvery,
+    files: usize,
+    last: Instant,
+    sequence: u32,
+}
+
+impl Flusher {
+    pub fn new(every: FlushEvery) -> Self {
+        Flusher {
+            every,
+            files: 0,
+            last: Instant::now(),
+            sequence: 0,
+        }
+    }
+
+    /// Note a finished input file, returning the sequence number of the
+    /// intermediate output that is due now, if one is.
+    pub fn file_done(&mut self) -> Option<u32> {
+        self.files += 1;
+        let due = match self.every {
+            FlushEvery::Files(files) => self.files.is_multiple_of(files),
+            FlushEvery::Interval(interval) => self.last.elapsed() >= interval,
+        };
+        if !due {
+            return None;
+        }
+        self.last = Instant::now();
+        self.sequence += 1;
+        Some(self.sequence)
+    }
+}
+
+/// Where intermediate output `sequence` of the final output `output` goes.
+pub fn partial_path(output: &str, sequence: u32) -> String {
+    format!("{}.partial-{:04}.json", output.trim_end_matches(".json"), sequence)
+}
@@ -0,0 +1,202 @@
+//! Relaying raw received messages to another collector.
+//!
+//! Messages are queued in a bounded buffer and written by a background
+//! thread, so a slow or unreachable downstream never stalls aggregation. When
+//! the connection drops the thread reconnects with backoff while the buffer
+//! absorbs new messages; once it is full the newest are dropped and counted.
+//! TCP uses octet-counted framing (RFC 6587), UDP one datagram per message.
+//! Each message is relayed as the bytes of the datagram or frame it came in,
+//! before any decoding or trimming.
+
+use std::io::{self, Write};
+use std::net::{TcpStream, UdpSocket};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, SyncSender, TrySendError};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use crate::listen::{Endpoint, Transport};
+
+const MAX_BACKOFF: Duration = Duration::from_secs(5);
+/// How long `finish` keeps trying to deliver what is still buffered
+const DRAIN_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Default)]
+pub struct ForwardStats {
+    pub forwarded: u64,
+    pub dropped: u64,
+}
+
+pub struct Forwarder {
+    tx: Option<SyncSender<Vec<u8>>>,
+    thread: Option<JoinHandle<u64>>,
+    closing: Arc<AtomicBool>,
+    dropped: Arc<AtomicU64>,
+}
+
+impl Forwarder {
+    pub fn start(endpoint: &Endpoint, buffer: usize) -> io::Result<Self> {
+        if !matches!(endpoint.transport, Transport::Udp | Transport::Tcp) {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "forwarding supports udp:// and tcp:// targets"));
+        }
+        let (tx, rx) = mpsc::sync_channel(buffer);
+        let closing = Arc::new(AtomicBool::new(false));
+        let endpoint = endpoint.clone();
+        let thread_closing = Arc::clone(&closing);
+        let thread = thread::spawn(move || relay(endpoint, rx, thread_closing));
+        Ok(Forwarder {
+            tx: Some(tx),
+            thread: Some(thread),
+            closing,
+            dropped: Arc::new(AtomicU64::new(0)),
+        })
+    }
+
+    pub fn send(&self, message: &[u8]) {
+        if let Some(tx) = &self.tx
+            && let Err(TrySendError::Full(_) | TrySendError::Disconnected(_)) = tx.try_send(message.to_vec())
+        {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Deliver what is still buffered (giving up after a grace period if the
+    /// target stays unreachable) and stop the relay thread.
+    pub fn finish(mut self) -> ForwardStats {
+        self.tx = None;
+        self.closing.store(true, Ordering::Relaxed);
+        let forwarded = self.thread.take().and_then(|thread| thread.join().ok()).unwrap_or_default();
+        ForwardStats {
+            forwarded,
+            dropped: self.dropped.load(Ordering::Relaxed),
+        }
+    }
+}
+
+enum Connection {
+    Udp(UdpSocket),
+    Tcp(TcpStream),
+}
+
+impl Connection {
+    fn open(endpoint: &Endpoint) -> io::Result<Self> {
+        match endpoint.transport {
+            Transport::Udp => {
+                let socket = UdpSocket::bind("0.0.0.0:0")?;
+                socket.connect(&endpoint.addr)?;
+                Ok(Connection::Udp(socket))
+            }
+            _ => {
+                let stream = TcpStream::connect(&endpoint.addr)?;
+                stream.set_write_timeout(Some(Duration::from_secs(30)))?;
+                Ok(Connection::Tcp(stream))
+            }
+        }
+    }
+
+    fn send(&mut self, message: &[u8]) -> io::Result<()> {
+        match self {
+            Connection::Udp(socket) => socket.send(message).map(|_| ()),
+            Connection::Tcp(stream) => {
+                write!(stream, "{} ", message.len())?;
+                stream.write_all(message)
+            }
+        }
+    }
+}
+
+fn relay(endpoint: Endpoint, rx: Receiver<Vec<u8>>, closing: Arc<AtomicBool>) -> u64 {
+    let mut connection: Option<Connection> = None;
+    let mut forwarded = 0;
+    let mut backoff = Duration::from_millis(100);
+    let mut drain_deadline = None;
+
+    loop {
+        let message = match rx.recv_timeout(Duration::from_millis(250)) {
+            Ok(message) => message,
+            Err(RecvTimeoutError::Timeout) => continue,
+            Err(RecvTimeoutError::Disconnected) => return forwarded,
+        };
+
+        // Hold on to the message until it is written or we give up at shutdown
+        loop {
+            if closing.load(Ordering::Relaxed) {
+                let deadline = *drain_deadline.get_or_insert_with(|| Instant::now() + DRAIN_TIMEOUT);
+                if Instant::now() > deadline {
+                    eprintln!("Giving up forwarding to {} with messages still buffered", endpoint);
+                    return forwarded;
+                }
+            }
+
+            let result = match connection.as_mut() {
+                Some(connection) => connection.send(&message),
+                None => Connection::open(&endpoint).and_then(|mut opened| {
+                    let sent = opened.send(&message);
+                    connection = Some(opened);
+                    sent
+                }),
+            };
+            match result {
+                Ok(()) => {
+                    forwarded += 1;
+                    backoff = Duration::from_millis(100);
+                    break;
+                }
+                Err(err) => {
+                    // Report the first failure of each outage, not every retry
+                    if connection.take().is_some() || backoff == Duration::from_millis(100) {
+                        eprintln!("Unable to forward to {}: {}", endpoint, err);
+                    }
+                    thread::sleep(backoff);
+                    backoff = (backoff * 2).min(MAX_BACKOFF);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+    use std::net::TcpListener;
+
+    use super::*;
+
+    const RAW: &[u8] = b"<134>fw1: caf\xe9 src=10.0.0.1\r\n";
+
+    fn endpoint(url: String) -> Endpoint {
+        url.parse().unwrap()
+    }
+
+    #[test]
+    fn datagrams_are_relayed_byte_for_byte() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        receiver.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        let forwarder = Forwarder::start(&endpoint(format!("udp://{}", receiver.local_addr().unwrap())), 8).unwrap();
+        forwarder.send(RAW);
+        let stats = forwarder.finish();
+
+        let mut buf = [0; 256];
+        let n = receiver.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..n], RAW);
+        assert_eq!((stats.forwarded, stats.dropped), (1, 0));
+    }
+
+    #[test]
+    fn tcp_frames_are_octet_counted_around_the_raw_bytes() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let forwarder = Forwarder::start(&endpoint(format!("tcp://{}", listener.local_addr().unwrap())), 8).unwrap();
+        forwarder.send(RAW);
+        forwarder.send(b"second");
+        forwarder.finish();
+
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut received = Vec::new();
+        stream.read_to_end(&mut received).unwrap();
+        let mut expected = format!("{} ", RAW.len()).into_bytes();
+        expected.extend_from_slice(RAW);
+        expected.extend_from_slice(b"6 second");
+        assert_eq!(received, expected);
+    }
+}
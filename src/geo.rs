@@ -0,0 +1,70 @@
+//! Country and autonomous-system roll-ups.
+//!
+//! Flows are totalled per country and per AS of their source and of their
+//! destination, by the `<side>-country` and `<side>-asn` values enrichment
+//! left on them (`--asn-db`, `--rdap`, or columns of the same name in
+//! `--enrich-map`). Addresses without one, typically internal ones, aren't
+//! counted on that side. The two are written next to the output as
+//! companion files for compliance and peering views.
+
+use std::collections::BTreeMap;
+
+use serde::Serialize;
+
+use crate::record::Record;
+use crate::summary::Totals;
+
+#[derive(Serialize, Debug, Default)]
+pub struct Group {
+    /// The AS's registered name, for AS roll-ups
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(flatten)]
+    pub totals: Totals,
+}
+
+/// Totals per value on either side of the flows.
+#[derive(Serialize, Debug, Default)]
+pub struct Rollup {
+    pub source: BTreeMap<String, Group>,
+    pub destination: BTreeMap<String, Group>,
+}
+
+impl Rollup {
+    pub fn is_empty(&self) -> bool {
+        self.source.is_empty() && self.destination.is_empty()
+    }
+}
+
+/// Group `records` by the `<side>-<key>` enrichment, naming each group by
+/// `<side>-<name>` where it is set.
+fn build<'a>(records: impl IntoIterator<Item = &'a Record>, key: &str, name: Option<&str>) -> Rollup {
+    let mut rollup = Rollup::default();
+    for record in records {
+        for (side, groups) in [("source", &mut rollup.source), ("destination", &mut rollup.destination)] {
+            let Some(value) = record.enrichment.get(&format!("{}-{}", side, key)) else {
+                continue;
+            };
+            let group = groups.entry(value.clone()).or_default();
+            if group.name.is_none()
+                && let Some(name) = name.and_then(|name| record.enrichment.get(&format!("{}-{}", side, name)))
+            {
+                group.name = Some(name.clone());
+            }
+            group.totals.add(record);
+        }
+    }
+    rollup
+}
+
+pub fn by_country<'a>(records: impl IntoIterator<Item = &'a Record>) -> Rollup {
+    build(records, "country", None)
+}
+
+pub fn by_asn<'a>(records: impl IntoIterator<Item = &'a Record>) -> Rollup {
+    build(records, "asn", Some("as-name"))
+}
+
+pub fn render(rollup: &Rollup) -> String {
+    serde_json::to_string_pretty(rollup).expect("Unable to serialize rollups")
+}
@@ -0,0 +1,95 @@
+//! Export of the source → destination talker graph.
+//!
+//! Flows are collapsed into one edge per source/destination pair, weighted by
+//! total bytes in both directions. Edges below the byte threshold are left
+//! out, along with any node that only they referenced, to keep large runs
+//! renderable in Graphviz or Gephi.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use clap::ValueEnum;
+
+use crate::record::Record;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum GraphFormat {
+    Dot,
+    Graphml,
+}
+
+impl GraphFormat {
+    pub fn extension(self) -> &'static str {
+        match self {
+            GraphFormat::Dot => "dot",
+            GraphFormat::Graphml => "graphml",
+        }
+    }
+}
+
+#[derive(Default)]
+struct Edge {
+    bytes: u64,
+    sessions: u64,
+}
+
+pub fn render<'a>(records: impl IntoIterator<Item = &'a Record>, format: GraphFormat, min_bytes: u64) -> String {
+    let mut edges: BTreeMap<(&str, &str), Edge> = BTreeMap::new();
+    for record in records {
+        let edge = edges.entry((&record.source_ip, &record.destination_ip)).or_default();
+        edge.bytes = edge.bytes.saturating_add(record.bytes_in.saturating_add(record.bytes_out));
+        edge.sessions += record.count;
+    }
+    edges.retain(|_, edge| edge.bytes >= min_bytes);
+
+    match format {
+        GraphFormat::Dot => render_dot(&edges),
+        GraphFormat::Graphml => render_graphml(&edges),
+    }
+}
+
+fn render_dot(edges: &BTreeMap<(&str, &str), Edge>) -> String {
+    let mut out = String::from("digraph talkers {\n");
+    for ((source, destination), edge) in edges {
+        out.push_str(&format!(
+            "  \"{}\" -> \"{}\" [weight={}, label=\"{} bytes\", sessions={}];\n",
+            escape_dot(source), escape_dot(destination), edge.bytes, edge.bytes, edge.sessions
+        ));
+    }
+    out.push_str("}\n");
+    out
+}
+
+fn render_graphml(edges: &BTreeMap<(&str, &str), Edge>) -> String {
+    let nodes: BTreeSet<&str> = edges.keys().flat_map(|(source, destination)| [*source, *destination]).collect();
+
+    let mut out = String::from(concat!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n",
+        "<graphml xmlns=\"http://graphml.graphdrawing.org/xmlns\">\n",
+        "  <key id=\"bytes\" for=\"edge\" attr.name=\"bytes\" attr.type=\"long\"/>\n",
+        "  <key id=\"sessions\" for=\"edge\" attr.name=\"sessions\" attr.type=\"long\"/>\n",
+        "  <graph id=\"talkers\" edgedefault=\"directed\">\n",
+    ));
+    for node in &nodes {
+        out.push_str(&format!("    <node id=\"{}\"/>\n", escape_xml(node)));
+    }
+    for ((source, destination), edge) in edges {
+        out.push_str(&format!(
+            "    <edge source=\"{}\" target=\"{}\">\n      <data key=\"bytes\">{}</data>\n      <data key=\"sessions\">{}</data>\n    </edge>\n",
+            escape_xml(source), escape_xml(destination), edge.bytes, edge.sessions
+        ));
+    }
+    out.push_str("  </graph>\n</graphml>\n");
+    out
+}
+
+fn escape_dot(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+fn escape_xml(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
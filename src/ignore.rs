@@ -0,0 +1,177 @@
+//! Known chatty flows set aside from the output.
+//!
+//! Backups to a NAS, monitoring polls and the like can dwarf everything
+//! else in an output while telling nobody anything. Flows matching an
+//! `--ignore` pattern are kept apart from the other records as they are
+//! aggregated, so no output, partial, journal entry or state store holds
+//! them, and summed into one bucket in the metadata, so every byte is still
+//! accounted for. As they are only kept for the run, a run resumed from a
+//! journal or a state store sums only the ones it saw itself.
+//!
+//! A pattern is a `SOURCE,DESTINATION[,PORT[,PROTOCOL[,FIREWALL]]]` tuple.
+//! Addresses are an address, a CIDR block, or a wildcard like `10.0.5.*`;
+//! the port is a number or a `low-high` range; the protocol a number or
+//! `tcp`/`udp`/`icmp`. `*` or an empty field matches anything, so
+//! `10.0.5.0/24,10.0.9.20,2049` is the backup servers' NFS traffic to the
+//! NAS.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::net::IpAddr;
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+use crate::cidr::Cidr;
+use crate::record::Record;
+use crate::summary::Totals;
+
+#[derive(Debug, Clone)]
+enum Field {
+    Any,
+    Block(Cidr),
+    /// Literal text with `*` wildcards
+    Glob(String),
+    Ports(u16, u16),
+}
+
+impl Field {
+    fn address(value: &str) -> Result<Self, String> {
+        match value {
+            "" | "*" => Ok(Field::Any),
+            value if value.contains('/') => value.parse().map(Field::Block).map_err(|err| format!("`{}`: {}", value, err)),
+            value => Ok(Field::Glob(value.to_string())),
+        }
+    }
+
+    fn port(value: &str) -> Result<Self, String> {
+        let port = |value: &str| value.trim().parse::<u16>().map_err(|_| format!("`{}` is not a port or port range", value));
+        match value {
+            "" | "*" => Ok(Field::Any),
+            value => match value.split_once('-') {
+                Some((low, high)) => Ok(Field::Ports(port(low)?, port(high)?)),
+                None => port(value).map(|port| Field::Ports(port, port)),
+            },
+        }
+    }
+
+    fn protocol(value: &str) -> Self {
+        match value.to_ascii_lowercase().as_str() {
+            "" | "*" => Field::Any,
+            "icmp" => Field::Glob("1".to_string()),
+            "tcp" => Field::Glob("6".to_string()),
+            "udp" => Field::Glob("17".to_string()),
+            other => Field::Glob(other.to_string()),
+        }
+    }
+
+    fn matches(&self, value: &str) -> bool {
+        match self {
+            Field::Any => true,
+            Field::Block(block) => value.parse::<IpAddr>().is_ok_and(|address| block.contains(address)),
+            Field::Glob(pattern) => glob(pattern, value),
+            Field::Ports(low, high) => value.trim().parse::<u16>().is_ok_and(|port| (*low..=*high).contains(&port)),
+        }
+    }
+}
+
+/// Whether `value` matches `pattern`, where `*` stands for any run of
+/// characters.
+fn glob(pattern: &str, value: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or_default();
+    let Some(mut rest) = value.strip_prefix(first) else {
+        return false;
+    };
+    let parts: Vec<&str> = parts.collect();
+    let Some((last, middle)) = parts.split_last() else {
+        return rest.is_empty();
+    };
+    for part in middle {
+        match rest.find(part) {
+            Some(at) => rest = &rest[at + part.len()..],
+            None => return false,
+        }
+    }
+    rest.len() >= last.len() && rest.ends_with(last)
+}
+
+/// One `--ignore` pattern.
+#[derive(Debug, Clone)]
+pub struct FlowPattern {
+    text: String,
+    source: Field,
+    destination: Field,
+    port: Field,
+    protocol: Field,
+    firewall: Field,
+}
+
+impl FlowPattern {
+    pub fn matches(&self, record: &Record) -> bool {
+        self.source.matches(&record.source_ip)
+            && self.destination.matches(&record.destination_ip)
+            && self.port.matches(&record.destination_port)
+            && self.protocol.matches(&record.protocol)
+            && self.firewall.matches(&record.firewall)
+    }
+}
+
+impl FromStr for FlowPattern {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let fields: Vec<&str> = value.split(',').map(str::trim).collect();
+        if fields.len() < 2 || fields.len() > 5 {
+            return Err(format!("expected SOURCE,DESTINATION[,PORT[,PROTOCOL[,FIREWALL]]], got `{}`", value));
+        }
+        let field = |index: usize| fields.get(index).copied().unwrap_or_default();
+        Ok(FlowPattern {
+            text: fields.join(","),
+            source: Field::address(field(0))?,
+            destination: Field::address(field(1))?,
+            port: Field::port(field(2))?,
+            protocol: Field::protocol(field(3)),
+            firewall: match field(4) {
+                "" | "*" => Field::Any,
+                firewall => Field::Glob(firewall.to_string()),
+            },
+        })
+    }
+}
+
+impl fmt::Display for FlowPattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.text)
+    }
+}
+
+/// The flows set aside by `--ignore`, reported in the output's metadata.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct Ignored {
+    #[serde(flatten)]
+    pub totals: Totals,
+    /// Totals per pattern; a flow matching several counts for the first
+    pub by_pattern: BTreeMap<String, Totals>,
+}
+
+impl Ignored {
+    /// Count `record` under the first of `patterns` it matches, returning
+    /// whether there was one.
+    pub fn add(&mut self, record: &Record, patterns: &[FlowPattern]) -> bool {
+        let Some(pattern) = patterns.iter().find(|pattern| pattern.matches(record)) else {
+            return false;
+        };
+        self.totals.add(record);
+        self.by_pattern.entry(pattern.to_string()).or_default().add(record);
+        true
+    }
+
+    pub fn merge(&mut self, other: &Ignored) {
+        self.totals.merge(&other.totals);
+        for (pattern, totals) in &other.by_pattern {
+            self.by_pattern.entry(pattern.clone()).or_default().merge(totals);
+        }
+    }
+}
@@ -0,0 +1,24 @@
+//! Shared copies of strings that repeat across many records.
+//!
+//! The same firewall, addresses, ports and protocols show up in thousands of
+//! flow records; interning them keeps one allocation per distinct value
+//! instead of one per record.
+
+use std::collections::HashSet;
+use std::sync::Arc;
+
+#[derive(Default)]
+pub struct Interner {
+    strings: HashSet<Arc<str>>,
+}
+
+impl Interner {
+    pub fn intern(&mut self, value: &str) -> Arc<str> {
+        if let Some(existing) = self.strings.get(value) {
+            return Arc::clone(existing);
+        }
+        let value: Arc<str> = Arc::from(value);
+        self.strings.insert(Arc::clone(&value));
+        value
+    }
+}
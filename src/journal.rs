@@ -0,0 +1,259 @@
+//! A JSONL journal of the flows as they change during a run.
+//!
+//! With `--journal`, every flow that is new or changed since the last entry
+//! is appended whole, one per line, after each input file (and every
+//! `--journal-every` seconds while following or listening), followed by a
+//! checkpoint line with the run's counters so far. A consumer tailing the
+//! file sees the flows long before the output is written, and keeping the
+//! last line of each key reproduces the aggregation. A flow `--max-flows`
+//! evicted gets a line of its own, so it is dropped again too.
+//!
+//! Flows after the last checkpoint may belong to a file that was still
+//! being read when the run died, so `--journal-resume` replays only up to
+//! that point and skips the input files the journal already covers, by
+//! their size and content rather than their name.
+
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use crate::aggregate::Aggregator;
+use crate::atomic;
+use crate::payload::FileStats;
+use crate::record::Record;
+
+/// Seconds between entries while following or listening, by default.
+pub const DEFAULT_EVERY: u64 = 10;
+
+/// One journal line.
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+enum Entry<R> {
+    /// A flow as it stands now; later lines for its key replace it
+    Flow(R),
+    /// Key of a flow evicted since its last line
+    Removed(Arc<str>),
+    Checkpoint(Checkpoint),
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Checkpoint {
+    /// Milliseconds since the epoch
+    at: u128,
+    connections: u64,
+    session_close: u64,
+    /// Input file finished just before this checkpoint
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    file: Option<String>,
+    /// Its stats, with the size and hash a resumed run recognizes it by
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    stats: Option<FileStats>,
+}
+
+pub struct Journal {
+    out: BufWriter<File>,
+    /// Lines appended by this run
+    pub lines: u64,
+}
+
+/// What a replayed journal held at its last checkpoint.
+#[derive(Default)]
+pub struct Replay {
+    pub records: HashMap<Arc<str>, Record>,
+    pub connections: u64,
+    pub session_close: u64,
+    pub files: Vec<FileStats>,
+    /// Flow lines after the last checkpoint, left out
+    pub uncommitted: usize,
+}
+
+impl Journal {
+    /// Open the journal at `path`, emptying it unless `keep` is set, in which
+    /// case a line cut short by a crash is cut off first.
+    pub fn open(path: &Path, keep: bool) -> io::Result<Self> {
+        if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            fs::create_dir_all(dir)?;
+        }
+        let file = match keep {
+            true => atomic::open_lines(path)?,
+            false => File::create(path)?,
+        };
+        Ok(Journal { out: BufWriter::new(file), lines: 0 })
+    }
+
+    /// Append the flows that changed since the consumer at `cursor` of the
+    /// aggregator's changes last asked, then a checkpoint, returning how
+    /// many flows were appended.
+    pub fn append(&mut self, aggregator: &mut Aggregator, cursor: usize, file: Option<&FileStats>) -> io::Result<usize> {
+        let changes = aggregator.changes(cursor);
+        for record in &changes.records {
+            self.line(&Entry::Flow(record))?;
+        }
+        for key in changes.removed {
+            self.line(&Entry::Removed(key))?;
+        }
+        let checkpoint = Checkpoint {
+            at: SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_millis(),
+            connections: aggregator.connections,
+            session_close: aggregator.session_close,
+            file: file.map(|file| file.file.clone()),
+            stats: file.cloned(),
+        };
+        self.line(&Entry::<&Record>::Checkpoint(checkpoint))?;
+        self.out.flush()?;
+        self.out.get_ref().sync_data()?;
+        Ok(changes.records.len())
+    }
+
+    fn line(&mut self, entry: &Entry<&Record>) -> io::Result<()> {
+        serde_json::to_writer(&mut self.out, entry)?;
+        self.out.write_all(b"\n")?;
+        self.lines += 1;
+        Ok(())
+    }
+}
+
+/// Read the journal at `path` back up to its last checkpoint. A missing
+/// journal replays as empty.
+pub fn replay(path: &Path) -> io::Result<Replay> {
+    let mut replay = Replay::default();
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(replay),
+        Err(err) => return Err(err),
+    };
+    let mut pending: Vec<Entry<Record>> = Vec::new();
+    for line in BufReader::new(file).lines() {
+        // A line cut short by a crash can only be the last one: resuming
+        // cuts it off before appending
+        match serde_json::from_str::<Entry<Record>>(&line?) {
+            Ok(Entry::Checkpoint(checkpoint)) => {
+                for entry in pending.drain(..) {
+                    match entry {
+                        Entry::Flow(record) => {
+                            replay.records.insert(Arc::clone(&record.key), record);
+                        }
+                        Entry::Removed(key) => {
+                            replay.records.remove(&key);
+                        }
+                        Entry::Checkpoint(_) => {}
+                    }
+                }
+                replay.connections = checkpoint.connections;
+                replay.session_close = checkpoint.session_close;
+                if let Some(file) = checkpoint.file {
+                    replay.files.push(checkpoint.stats.unwrap_or(FileStats { file, ..FileStats::default() }));
+                }
+            }
+            Ok(entry) => pending.push(entry),
+            Err(_) => {}
+        }
+    }
+    replay.uncommitted = pending.iter().filter(|entry| matches!(entry, Entry::Flow(_))).count();
+    Ok(replay)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::parser::{InputFormat, LineParser, ParserOptions};
+    use crate::record::KeySpec;
+
+    fn aggregator() -> Aggregator {
+        let options = ParserOptions { pattern: None, kv_aliases: &[], min_fields: 0 };
+        Aggregator::new(LineParser::new(InputFormat::Csv, &options).unwrap(), KeySpec::default(), 60)
+    }
+
+    /// A CSV line of flow `flow` of input file `file`, busier the higher `flow`.
+    fn line(file: u64, flow: u64) -> String {
+        format!("2025-08-29T11:38:0{flow}+00:00,192.168.29.191,,10.0.{file}.{flow},8.8.{file}.{flow},443,6,,,{flow},{},{flow},{}", flow * 100 * file, flow * 50)
+    }
+
+    fn path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("syslog_processor-journal-{}-{}.jsonl", name, std::process::id()))
+    }
+
+    #[test]
+    fn journaling_keeps_the_flow_cap() {
+        let path = path("cap");
+        let mut aggregator = aggregator();
+        aggregator.max_flows = Some((2, 64, 4));
+        let cursor = aggregator.track_changes();
+        let mut journal = Journal::open(&path, false).unwrap();
+        for file in 1..=3 {
+            for flow in 1..=4 {
+                aggregator.ingest(&line(file, flow));
+            }
+            journal.append(&mut aggregator, cursor, None).unwrap();
+        }
+        let replayed = replay(&path).unwrap();
+        let aggregated = aggregator.finish();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(aggregated.records.len(), 2);
+        let mut kept: Vec<&Arc<str>> = aggregated.records.keys().collect();
+        let mut journaled: Vec<&Arc<str>> = replayed.records.keys().collect();
+        kept.sort();
+        journaled.sort();
+        assert_eq!(journaled, kept);
+        assert_eq!((replayed.connections, replayed.uncommitted), (12, 0));
+    }
+
+    #[test]
+    fn entries_only_hold_what_changed() {
+        let path = path("changed");
+        let mut aggregator = aggregator();
+        let cursor = aggregator.track_changes();
+        let mut journal = Journal::open(&path, false).unwrap();
+        aggregator.ingest(&line(1, 1));
+        aggregator.ingest(&line(1, 2));
+        assert_eq!(journal.append(&mut aggregator, cursor, None).unwrap(), 2);
+        assert_eq!(journal.append(&mut aggregator, cursor, None).unwrap(), 0);
+        aggregator.ingest(&line(1, 2));
+        assert_eq!(journal.append(&mut aggregator, cursor, None).unwrap(), 1);
+
+        // Later lines of a key replace earlier ones, and the records went on aggregating
+        let replayed = replay(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        let counts: Vec<u64> = aggregator.finish().records.values().map(|record| record.count).collect();
+        assert_eq!(counts.iter().sum::<u64>(), 3);
+        assert_eq!(replayed.records.values().map(|record| record.count).sum::<u64>(), 3);
+    }
+
+    #[test]
+    fn a_torn_last_line_is_left_out_and_cut_off_on_resume() {
+        let path = path("torn");
+        let mut aggregator = aggregator();
+        let cursor = aggregator.track_changes();
+        let mut journal = Journal::open(&path, false).unwrap();
+        aggregator.ingest(&line(1, 1));
+        journal.append(&mut aggregator, cursor, None).unwrap();
+        aggregator.ingest(&line(1, 2));
+        journal.line(&Entry::Flow(&aggregator.changes(cursor).records[0])).unwrap();
+        journal.out.write_all(b"{\"flow\":{\"key\":\"10.0.1.3").unwrap();
+        drop(journal);
+
+        // The flow after the last checkpoint is uncommitted, the torn line unreadable
+        let replayed = replay(&path).unwrap();
+        assert_eq!((replayed.records.len(), replayed.connections, replayed.uncommitted), (1, 1, 1));
+
+        let mut journal = Journal::open(&path, true).unwrap();
+        aggregator.ingest(&line(1, 3));
+        journal.append(&mut aggregator, cursor, None).unwrap();
+        drop(journal);
+        let contents = fs::read_to_string(&path).unwrap();
+        let replayed = replay(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert!(contents.lines().all(|line| serde_json::from_str::<Entry<Record>>(line).is_ok()));
+        assert!(replayed.records.keys().any(|key| key.contains("10.0.1.3")));
+        assert_eq!((replayed.connections, replayed.uncommitted), (3, 0));
+    }
+}
@@ -0,0 +1,96 @@
+//! Kafka consumer input.
+//!
+//! Lines are consumed from a topic as part of a consumer group until the
+//! topic has been idle for the configured timeout. Offsets are committed only
+//! after the payload has been written, so a crash before that point replays
+//! the messages instead of losing them. That holds across a rebalance too:
+//! the offsets of revoked partitions aren't committed, since the payload
+//! holding their messages hasn't been written yet, so their next owner
+//! consumes those messages again and both outputs count them.
+
+use std::time::{Duration, Instant};
+
+use rdkafka::ClientContext;
+use rdkafka::config::ClientConfig;
+use rdkafka::consumer::{BaseConsumer, CommitMode, Consumer, ConsumerContext, Rebalance};
+use rdkafka::error::KafkaResult;
+use rdkafka::message::Message;
+
+use crate::aggregate::Aggregator;
+
+pub struct KafkaOptions<'a> {
+    pub brokers: &'a str,
+    pub topic: &'a str,
+    pub group: &'a str,
+    pub idle_timeout: Duration,
+}
+
+struct RebalanceContext;
+
+impl ClientContext for RebalanceContext {}
+
+impl ConsumerContext for RebalanceContext {
+    fn pre_rebalance(&self, _consumer: &BaseConsumer<Self>, rebalance: &Rebalance<'_>) {
+        if let Rebalance::Revoke(partitions) = rebalance {
+            eprintln!("Kafka rebalance: {} partition(s) revoked; their messages since the last commit will be consumed again", partitions.count());
+        }
+    }
+
+    fn post_rebalance(&self, _consumer: &BaseConsumer<Self>, rebalance: &Rebalance<'_>) {
+        if let Rebalance::Assign(partitions) = rebalance {
+            eprintln!("Kafka rebalance: {} partition(s) assigned", partitions.count());
+        }
+    }
+}
+
+pub struct KafkaInput {
+    consumer: BaseConsumer<RebalanceContext>,
+    idle_timeout: Duration,
+}
+
+impl KafkaInput {
+    pub fn connect(options: &KafkaOptions) -> KafkaResult<Self> {
+        let consumer: BaseConsumer<RebalanceContext> = ClientConfig::new()
+            .set("bootstrap.servers", options.brokers)
+            .set("group.id", options.group)
+            .set("enable.auto.commit", "false")
+            .set("auto.offset.reset", "earliest")
+            .create_with_context(RebalanceContext)?;
+        consumer.subscribe(&[options.topic])?;
+
+        Ok(KafkaInput {
+            consumer,
+            idle_timeout: options.idle_timeout,
+        })
+    }
+
+    /// Feed messages into the aggregator until none arrive for the idle
+    /// timeout. Returns the number of messages consumed.
+    pub fn consume(&self, aggregator: &mut Aggregator) -> u64 {
+        let mut consumed = 0;
+        let mut last_message = Instant::now();
+
+        while last_message.elapsed() < self.idle_timeout {
+            match self.consumer.poll(Duration::from_millis(500)) {
+                Some(Ok(message)) => {
+                    last_message = Instant::now();
+                    consumed += 1;
+                    if let Some(Ok(payload)) = message.payload_view::<str>() {
+                        for line in payload.lines() {
+                            aggregator.ingest(line);
+                        }
+                    }
+                }
+                Some(Err(err)) => eprintln!("Kafka consumer error: {}", err),
+                None => {}
+            }
+        }
+
+        consumed
+    }
+
+    /// Commit the offsets of everything consumed so far.
+    pub fn commit(&self) -> KafkaResult<()> {
+        self.consumer.commit_consumer_state(CommitMode::Sync)
+    }
+}
@@ -0,0 +1,85 @@
+//! Aggregation of firewall syslog sessions into per-flow totals.
+//!
+//! The `syslog_processor` binary drives everything here from the command
+//! line. The library exposes the same building blocks for programs that
+//! want their own pipeline: [`parse_events`] turns any reader into a
+//! stream of parsed events, [`Aggregator`] is the built-in per-flow
+//! aggregation, and [`Payload`] is the output document.
+
+mod aggregate;
+mod alerts;
+mod app;
+mod atomic;
+mod backpressure;
+mod bounds;
+mod check;
+mod cidr;
+mod classify;
+mod cli;
+mod closes;
+mod config;
+mod console;
+mod countmin;
+mod detect;
+mod diff;
+mod direction;
+mod distinct;
+mod enrich;
+mod error;
+mod explore;
+mod flush;
+mod forward;
+mod geo;
+mod graph;
+mod hll;
+mod hourly;
+mod ignore;
+mod intern;
+mod journal;
+#[cfg(feature = "kafka")]
+mod kafka;
+mod lineage;
+mod lines;
+mod listen;
+mod lock;
+mod manifest;
+mod merge;
+mod misp;
+mod notify;
+mod parser;
+mod payload;
+mod prefilter;
+mod protect;
+mod sample;
+mod quarantine;
+mod query;
+mod record;
+mod report;
+mod resources;
+mod rollup;
+mod rules;
+mod session;
+mod shard;
+mod sites;
+mod sink;
+mod space;
+mod spool;
+mod state;
+mod summary;
+mod tail;
+mod tdigest;
+mod telemetry;
+mod tenants;
+mod throttle;
+mod topn;
+mod trend;
+#[cfg(feature = "io-uring")]
+mod uring;
+
+pub use aggregate::Aggregator;
+pub use parser::{FlowEvent, InputFormat, ParseError, parse_events};
+pub use payload::Payload;
+
+/// The command line, for the `syslog_processor` binary.
+#[doc(hidden)]
+pub use app::run;
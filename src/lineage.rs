@@ -0,0 +1,43 @@
+//! Run IDs for tracing numbers back to the run that produced them.
+//!
+//! Every run gets a random UUID, kept in its output's metadata and written
+//! along with every sink entry. An output seeded from or merged out of
+//! others also lists their run IDs, so a figure downstream can be followed
+//! back through each run that contributed to it.
+
+use std::fs::File;
+use std::io::Read;
+use std::process;
+
+use crate::payload::Metadata;
+use crate::sample::Rng;
+
+/// A new random (version 4) UUID.
+pub fn new_run_id() -> String {
+    let mut bytes = [0u8; 16];
+    let random = File::open("/dev/urandom").and_then(|mut urandom| urandom.read_exact(&mut bytes));
+    if random.is_err() {
+        let mut rng = Rng::from_time();
+        let pid = process::id() as u64;
+        bytes[..8].copy_from_slice(&(rng.next_u64() ^ pid).to_be_bytes());
+        bytes[8..].copy_from_slice(&rng.next_u64().to_be_bytes());
+    }
+    bytes[6] = (bytes[6] & 0x0f) | 0x40;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+
+    let hex: String = bytes.iter().map(|byte| format!("{:02x}", byte)).collect();
+    format!("{}-{}-{}-{}-{}", &hex[..8], &hex[8..12], &hex[12..16], &hex[16..20], &hex[20..])
+}
+
+/// The run IDs an output built from `inputs` descends from: each input's
+/// own ID, in order and without repeats. Inputs written before run IDs
+/// existed contribute nothing.
+pub fn parents<'a>(inputs: impl IntoIterator<Item = &'a Metadata>) -> Vec<String> {
+    let mut parents: Vec<String> = Vec::new();
+    for metadata in inputs {
+        if !metadata.run_id.is_empty() && !parents.contains(&metadata.run_id) {
+            parents.push(metadata.run_id.clone());
+        }
+    }
+    parents
+}
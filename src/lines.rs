@@ -0,0 +1,174 @@
+//! Reading lines with a length cap.
+//!
+//! A corrupted input can hold a single enormous "line", e.g. a preallocated
+//! log that is nothing but NUL bytes, and `BufRead::lines` would buffer all
+//! of it before the parser ever sees a byte. [`LineReader`] keeps at most
+//! `max` bytes of a line and discards the rest up to the next newline, and
+//! flags lines containing NUL bytes as binary instead of handing them on.
+//!
+//! Text that isn't valid UTF-8, typically 8-bit vendor characters in
+//! hostnames, is decoded according to an [`Encoding`] rather than dropped.
+
+use std::borrow::Cow;
+use std::io::{self, BufRead};
+use std::str::Utf8Error;
+
+use clap::ValueEnum;
+
+use crate::throttle::Throttle;
+
+/// Longest line kept by default, far above any real syslog message.
+pub const DEFAULT_MAX_LINE: usize = 64 * 1024;
+
+/// How line bytes that aren't valid UTF-8 are turned into text.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum Encoding {
+    /// Replace each invalid sequence with U+FFFD
+    #[default]
+    Utf8,
+    /// Decode lines that aren't valid UTF-8 as latin-1 (ISO 8859-1)
+    Latin1,
+    /// Refuse them: the file stops being read and is reported as failed,
+    /// except a followed file, where they are skipped
+    Strict,
+}
+
+/// Decode one line, along with how many characters had to be replaced or
+/// decoded as latin-1.
+pub fn decode(bytes: &[u8], encoding: Encoding) -> Result<(Cow<'_, str>, u64), Utf8Error> {
+    let err = match std::str::from_utf8(bytes) {
+        Ok(text) => return Ok((Cow::Borrowed(text), 0)),
+        Err(err) => err,
+    };
+    match encoding {
+        Encoding::Strict => Err(err),
+        Encoding::Latin1 => {
+            let replaced = bytes.iter().filter(|byte| !byte.is_ascii()).count() as u64;
+            Ok((Cow::Owned(bytes.iter().map(|&byte| byte as char).collect()), replaced))
+        }
+        Encoding::Utf8 => {
+            let mut text = String::with_capacity(bytes.len() + 2);
+            let mut replaced = 0;
+            for chunk in bytes.utf8_chunks() {
+                text.push_str(chunk.valid());
+                if !chunk.invalid().is_empty() {
+                    text.push(char::REPLACEMENT_CHARACTER);
+                    replaced += 1;
+                }
+            }
+            Ok((Cow::Owned(text), replaced))
+        }
+    }
+}
+
+/// One line read by a [`LineReader`].
+#[derive(Debug, PartialEq, Eq)]
+pub enum Line<'a> {
+    /// The line's bytes, without the trailing `\n` or `\r\n`
+    Text(&'a [u8]),
+    /// Longer than the cap; its bytes were discarded
+    TooLong,
+    /// Contains a NUL byte, so it isn't text
+    Binary,
+}
+
+/// Splits a reader into lines of at most `max` bytes. A line that isn't
+/// terminated yet stays buffered across calls, so a followed file can be
+/// read again once more of it has been written.
+pub struct LineReader {
+    max: usize,
+    line: Vec<u8>,
+    overflowed: bool,
+    /// The buffered line was returned and is dropped on the next call
+    returned: bool,
+    /// Bytes consumed from readers so far
+    pub consumed: u64,
+    throttle: Option<Throttle>,
+}
+
+impl LineReader {
+    pub fn new(max: usize) -> Self {
+        LineReader {
+            max,
+            line: Vec::new(),
+            overflowed: false,
+            returned: false,
+            consumed: 0,
+            throttle: None,
+        }
+    }
+
+    /// Pace reading with `throttle`, if set.
+    pub fn throttled(mut self, throttle: Option<Throttle>) -> Self {
+        self.throttle = throttle;
+        self
+    }
+
+    /// The next newline-terminated line, `None` once `reader` has no more
+    /// data. An unterminated last line is only returned by [`finish`].
+    ///
+    /// [`finish`]: LineReader::finish
+    pub fn next<R: BufRead + ?Sized>(&mut self, reader: &mut R) -> io::Result<Option<Line<'_>>> {
+        if self.returned {
+            self.clear();
+        }
+        loop {
+            let available = match reader.fill_buf() {
+                Ok(available) => available,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) => return Err(err),
+            };
+            if available.is_empty() {
+                return Ok(None);
+            }
+            let newline = available.iter().position(|&byte| byte == b'\n');
+            let content = &available[..newline.unwrap_or(available.len())];
+            if !self.overflowed {
+                if self.line.len() + content.len() > self.max {
+                    self.overflowed = true;
+                    self.line = Vec::new();
+                } else {
+                    self.line.extend_from_slice(content);
+                }
+            }
+            let used = newline.map_or(available.len(), |newline| newline + 1);
+            reader.consume(used);
+            self.consumed += used as u64;
+            if let Some(throttle) = &mut self.throttle {
+                throttle.read(used, newline.is_some() as usize);
+            }
+            if newline.is_some() {
+                self.returned = true;
+                return Ok(Some(self.line()));
+            }
+        }
+    }
+
+    /// The unterminated line left at the end of the input, if any.
+    pub fn finish(&mut self) -> Option<Line<'_>> {
+        if self.returned {
+            self.clear();
+        }
+        if self.line.is_empty() && !self.overflowed {
+            return None;
+        }
+        self.returned = true;
+        Some(self.line())
+    }
+
+    /// Drop whatever part of a line is buffered, e.g. when a followed file
+    /// is reopened.
+    pub fn clear(&mut self) {
+        self.line.clear();
+        self.overflowed = false;
+        self.returned = false;
+    }
+
+    fn line(&self) -> Line<'_> {
+        if self.overflowed {
+            return Line::TooLong;
+        }
+        let line = self.line.strip_suffix(b"\r").unwrap_or(&self.line);
+        if line.contains(&0) { Line::Binary } else { Line::Text(line) }
+    }
+}
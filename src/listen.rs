@@ -0,0 +1,498 @@
+//! Network syslog receiver.
+//!
+//! Endpoints are given as `udp://`, `tcp://`, `tls://` (RFC 5425) or
+//! `relp://` URLs. Stream transports accept both octet-counted (RFC 6587
+//! `LEN SP MSG`) and newline-delimited framing; RELP messages are only
+//! acknowledged once they have been handed to the aggregator. Raw messages
+//! can also be relayed to another collector as they arrive, and kept from
+//! the parser by their syslog header; see [`crate::prefilter`]. Listening
+//! stops once no message has arrived for the idle timeout. Messages waiting
+//! to be aggregated are held in a bounded buffer; see [`crate::backpressure`].
+
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream, UdpSocket};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::{ServerConfig, ServerConnection, StreamOwned};
+
+use crate::aggregate::Aggregator;
+use crate::backpressure::{self, BufferStats, Delivery, Message, Overflow, Sender};
+use crate::forward::{ForwardStats, Forwarder};
+use crate::lines::{self, Encoding, Line, LineReader};
+use crate::parser::SkipReason;
+use crate::prefilter::Prefilter;
+
+/// Longest octet-counted frame accepted, to bound memory per connection
+const MAX_FRAME: usize = 64 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Transport {
+    Udp,
+    Tcp,
+    Tls,
+    Relp,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Endpoint {
+    pub transport: Transport,
+    pub addr: String,
+}
+
+impl FromStr for Endpoint {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let (scheme, addr) = value
+            .split_once("://")
+            .ok_or_else(|| format!("expected <udp|tcp|tls|relp>://host:port, got `{}`", value))?;
+        let transport = match scheme {
+            "udp" => Transport::Udp,
+            "tcp" => Transport::Tcp,
+            "tls" => Transport::Tls,
+            "relp" => Transport::Relp,
+            _ => return Err(format!("unknown listener transport `{}`", scheme)),
+        };
+        Ok(Endpoint { transport, addr: addr.to_string() })
+    }
+}
+
+impl std::fmt::Display for Endpoint {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let scheme = match self.transport {
+            Transport::Udp => "udp",
+            Transport::Tcp => "tcp",
+            Transport::Tls => "tls",
+            Transport::Relp => "relp",
+        };
+        write!(f, "{}://{}", scheme, self.addr)
+    }
+}
+
+pub struct ListenOptions<'a> {
+    pub endpoints: &'a [Endpoint],
+    /// PEM certificate chain and private key, required for `tls://`
+    pub tls_cert: Option<&'a Path>,
+    pub tls_key: Option<&'a Path>,
+    pub idle_timeout: Duration,
+    /// Relay every raw message here as well
+    pub forward: Option<&'a Endpoint>,
+    /// Messages held for the relay while its target is unreachable
+    pub forward_buffer: usize,
+    /// How often `checkpoint` is called while listening
+    pub checkpoint_every: Option<Duration>,
+    /// Messages held in memory while the aggregator is behind
+    pub buffer: usize,
+    pub overflow: Overflow,
+    /// Where messages spill under `Overflow::Spill`
+    pub spill_dir: Option<PathBuf>,
+    /// Which messages are parsed; the rest are still forwarded
+    pub prefilter: Prefilter,
+}
+
+#[derive(Debug, Default)]
+pub struct ListenStats {
+    pub received: u64,
+    /// Messages the prefilter kept from the parser
+    pub filtered: u64,
+    pub forward: Option<ForwardStats>,
+    pub buffer: BufferStats,
+}
+
+/// Bind every endpoint and feed received messages into the aggregator until
+/// the idle timeout passes without one, calling `checkpoint` with the
+/// aggregator every `checkpoint_every`.
+pub fn listen(options: &ListenOptions, aggregator: &mut Aggregator, mut checkpoint: impl FnMut(&mut Aggregator)) -> io::Result<ListenStats> {
+    let (tx, rx) = backpressure::buffer(options.buffer, options.overflow, options.spill_dir.as_deref())?;
+    let forwarder = options.forward.map(|target| Forwarder::start(target, options.forward_buffer)).transpose()?;
+
+    let tls = match options.endpoints.iter().any(|endpoint| endpoint.transport == Transport::Tls) {
+        true => Some(tls_config(options.tls_cert, options.tls_key)?),
+        false => None,
+    };
+
+    for endpoint in options.endpoints {
+        match endpoint.transport {
+            Transport::Udp => {
+                let socket = UdpSocket::bind(&endpoint.addr).map_err(|err| bind_error(endpoint, err))?;
+                let tx = tx.clone();
+                thread::spawn(move || receive_udp(socket, tx));
+            }
+            transport => {
+                let listener = TcpListener::bind(&endpoint.addr).map_err(|err| bind_error(endpoint, err))?;
+                let tx = tx.clone();
+                let tls = tls.clone();
+                let max_line_length = aggregator.max_line_length;
+                thread::spawn(move || accept(listener, transport, tls, max_line_length, tx));
+            }
+        }
+    }
+    drop(tx);
+
+    let (mut received, mut filtered) = (0, 0);
+    let mut last_message = Instant::now();
+    let mut last_checkpoint = Instant::now();
+    loop {
+        if let Some(every) = options.checkpoint_every
+            && last_checkpoint.elapsed() >= every
+        {
+            checkpoint(aggregator);
+            last_checkpoint = Instant::now();
+        }
+        let idle = options.idle_timeout.saturating_sub(last_message.elapsed());
+        let remaining = match options.checkpoint_every {
+            Some(every) => idle.min(every.saturating_sub(last_checkpoint.elapsed())),
+            None => idle,
+        };
+        match rx.recv_timeout(remaining) {
+            Ok(message) => {
+                match &message.line {
+                    Ok(line) => {
+                        // Relayed as received, whatever its encoding or line ending
+                        if let Some(forwarder) = &forwarder {
+                            forwarder.send(line);
+                        }
+                        // A message is never refused for its encoding
+                        let encoding = match aggregator.encoding {
+                            Encoding::Strict => Encoding::Utf8,
+                            encoding => encoding,
+                        };
+                        let (line, replaced) = lines::decode(line, encoding).expect("only strict decoding fails");
+                        aggregator.replaced_characters += replaced;
+                        let line = line.trim_end_matches(['\r', '\n']);
+                        match options.prefilter.accepts(line) {
+                            true => aggregator.ingest(line),
+                            false => filtered += 1,
+                        }
+                    }
+                    Err(reason) => aggregator.reject(*reason),
+                }
+                if let Some(ack) = message.ack {
+                    let _ = ack.send(Delivery::Taken);
+                }
+                received += 1;
+                last_message = Instant::now();
+            }
+            Err(RecvTimeoutError::Timeout) if last_message.elapsed() < options.idle_timeout => {}
+            Err(RecvTimeoutError::Timeout | RecvTimeoutError::Disconnected) => break,
+        }
+    }
+    Ok(ListenStats {
+        received,
+        filtered,
+        forward: forwarder.map(Forwarder::finish),
+        buffer: rx.stats(),
+    })
+}
+
+fn bind_error(endpoint: &Endpoint, err: io::Error) -> io::Error {
+    io::Error::new(err.kind(), format!("{}: {}", endpoint, err))
+}
+
+fn tls_config(cert: Option<&Path>, key: Option<&Path>) -> io::Result<Arc<ServerConfig>> {
+    let invalid = |err: &dyn std::fmt::Display| io::Error::new(io::ErrorKind::InvalidInput, err.to_string());
+    let (Some(cert), Some(key)) = (cert, key) else {
+        return Err(invalid(&"tls:// listeners need --tls-cert and --tls-key"));
+    };
+
+    let certs = CertificateDer::pem_file_iter(cert)
+        .map_err(|err| invalid(&err))?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|err| invalid(&err))?;
+    let key = PrivateKeyDer::from_pem_file(key).map_err(|err| invalid(&err))?;
+    let config = ServerConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+        .with_safe_default_protocol_versions()
+        .map_err(|err| invalid(&err))?
+        .with_no_client_auth()
+        .with_single_cert(certs, key)
+        .map_err(|err| invalid(&err))?;
+    Ok(Arc::new(config))
+}
+
+fn receive_udp(socket: UdpSocket, tx: Sender) {
+    let mut buf = vec![0; MAX_FRAME];
+    while let Ok(n) = socket.recv(&mut buf) {
+        if tx.send(Message { line: Ok(buf[..n].to_vec()), ack: None }).is_err() {
+            break;
+        }
+    }
+}
+
+fn accept(listener: TcpListener, transport: Transport, tls: Option<Arc<ServerConfig>>, max_line_length: usize, tx: Sender) {
+    for stream in listener.incoming().flatten() {
+        let tx = tx.clone();
+        let tls = tls.clone();
+        thread::spawn(move || {
+            let result = match (transport, tls) {
+                (Transport::Tls, Some(config)) => match ServerConnection::new(config) {
+                    Ok(connection) => receive_stream(BufReader::new(StreamOwned::new(connection, stream)), max_line_length, &tx),
+                    Err(err) => Err(io::Error::other(err)),
+                },
+                (Transport::Relp, _) => receive_relp(stream, &tx),
+                _ => receive_stream(BufReader::new(stream), max_line_length, &tx),
+            };
+            if let Err(err) = result
+                && err.kind() != io::ErrorKind::UnexpectedEof
+            {
+                eprintln!("Syslog connection closed: {}", err);
+            }
+        });
+    }
+}
+
+fn receive_stream<R: BufRead>(mut reader: R, max_line_length: usize, tx: &Sender) -> io::Result<()> {
+    let mut lines = LineReader::new(max_line_length);
+    while let Some(line) = read_frame(&mut reader, &mut lines)? {
+        if tx.send(Message { line, ack: None }).is_err() {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// Read one octet-counted or newline-terminated message, `None` at end of
+/// stream. Newline-terminated ones go through `lines`, so an overlong or
+/// binary line is refused rather than buffered.
+fn read_frame<R: BufRead>(reader: &mut R, lines: &mut LineReader) -> io::Result<Option<Result<Vec<u8>, SkipReason>>> {
+    let buffered = reader.fill_buf()?;
+    if buffered.is_empty() {
+        return Ok(None);
+    }
+    // Octet counting starts with `digits SP`; plain lines (syslog `<PRI>` or
+    // a bare CSV record) never do
+    let digits = buffered.iter().take_while(|byte| byte.is_ascii_digit()).count();
+    if digits > 0 && buffered.get(digits) == Some(&b' ') {
+        let len: usize = read_token(reader)?.parse().map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "bad frame length"))?;
+        if len > MAX_FRAME {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "frame too long"));
+        }
+        let mut frame = vec![0; len];
+        reader.read_exact(&mut frame)?;
+        return Ok(Some(Ok(frame)));
+    }
+
+    let line = match lines.next(reader)? {
+        Some(line) => line,
+        None => match lines.finish() {
+            Some(line) => line,
+            None => return Ok(None),
+        },
+    };
+    Ok(Some(match line {
+        Line::Text(line) => Ok(line.to_vec()),
+        Line::TooLong => Err(SkipReason::TooLong),
+        Line::Binary => Err(SkipReason::Binary),
+    }))
+}
+
+/// Read up to the next space or newline, consuming the delimiter.
+fn read_token<R: BufRead>(reader: &mut R) -> io::Result<String> {
+    let mut token = Vec::new();
+    let mut byte = [0];
+    loop {
+        reader.read_exact(&mut byte)?;
+        if byte[0] == b' ' || byte[0] == b'\n' || token.len() > 32 {
+            break;
+        }
+        token.push(byte[0]);
+    }
+    Ok(String::from_utf8_lossy(&token).into_owned())
+}
+
+/// RELP (`TXNR SP COMMAND SP DATALEN [SP DATA] LF`) session: answer `open`
+/// with our offers, acknowledge each `syslog` frame once the aggregator has
+/// it (or refuse it if the buffer dropped it), and confirm `close`.
+fn receive_relp(stream: TcpStream, tx: &Sender) -> io::Result<()> {
+    let mut writer = stream.try_clone()?;
+    let mut reader = BufReader::new(stream);
+
+    loop {
+        if reader.fill_buf()?.is_empty() {
+            return Ok(());
+        }
+        let txnr = read_token(&mut reader)?;
+        let command = read_token(&mut reader)?;
+        let len: usize = read_token(&mut reader)?.parse().map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "bad RELP length"))?;
+        if len > MAX_FRAME {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "RELP frame too long"));
+        }
+        let mut data = vec![0; len];
+        reader.read_exact(&mut data)?;
+        if len > 0 {
+            // Trailing LF after the data (the length token consumed the one for empty frames)
+            reader.read_exact(&mut [0])?;
+        }
+
+        match command.as_str() {
+            "open" => {
+                let offers = "200 OK\nrelp_version=0\nrelp_software=syslog_processor\ncommands=syslog";
+                respond(&mut writer, &txnr, offers)?;
+            }
+            "syslog" => {
+                let (ack_tx, ack_rx) = mpsc::channel();
+                let delivery = match tx.send(Message { line: Ok(data), ack: Some(ack_tx) }) {
+                    Ok(()) => ack_rx.recv().ok(),
+                    Err(_) => None,
+                };
+                match delivery {
+                    Some(Delivery::Taken) => respond(&mut writer, &txnr, "200 OK")?,
+                    // The sender may retry it; the session carries on
+                    Some(Delivery::Dropped) => respond(&mut writer, &txnr, "500 message dropped, listener buffer full")?,
+                    None => {
+                        respond(&mut writer, &txnr, "500 shutting down")?;
+                        return Ok(());
+                    }
+                }
+            }
+            "close" => {
+                respond(&mut writer, &txnr, "")?;
+                writeln!(writer, "0 serverclose 0")?;
+                return Ok(());
+            }
+            _ => respond(&mut writer, &txnr, "500 unknown command")?,
+        }
+    }
+}
+
+fn respond(writer: &mut impl Write, txnr: &str, data: &str) -> io::Result<()> {
+    match data {
+        "" => writeln!(writer, "{} rsp 0", txnr),
+        data => writeln!(writer, "{} rsp {} {}", txnr, data.len(), data),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read_response(reader: &mut impl BufRead) -> String {
+        let mut line = String::new();
+        reader.read_line(&mut line).unwrap();
+        line.trim_end().to_string()
+    }
+
+    #[test]
+    fn endpoints_parse_and_display_as_urls() {
+        let endpoint: Endpoint = "relp://0.0.0.0:2514".parse().unwrap();
+        assert_eq!(endpoint, Endpoint { transport: Transport::Relp, addr: "0.0.0.0:2514".to_string() });
+        assert_eq!(endpoint.to_string(), "relp://0.0.0.0:2514");
+        assert!("0.0.0.0:514".parse::<Endpoint>().is_err());
+        assert!("sctp://0.0.0.0:514".parse::<Endpoint>().is_err());
+    }
+
+    #[test]
+    fn stream_frames_are_octet_counted_or_newline_delimited() {
+        let mut stream = b"5 hello9 two\nlines<134>plain line\n".to_vec();
+        stream.extend_from_slice(b"a line far too long to take\nbin\0ary\nunterminated");
+        let mut reader = io::Cursor::new(stream);
+        let mut lines = LineReader::new(16);
+        let mut frames = Vec::new();
+        while let Some(frame) = read_frame(&mut reader, &mut lines).unwrap() {
+            frames.push(frame.map(|frame| String::from_utf8(frame).unwrap()));
+        }
+        let expected = [
+            Ok("hello"),
+            Ok("two\nlines"),
+            Ok("<134>plain line"),
+            Err(SkipReason::TooLong),
+            Err(SkipReason::Binary),
+            Ok("unterminated"),
+        ];
+        assert_eq!(frames, expected.map(|frame| frame.map(str::to_string)));
+
+        let mut reader = io::Cursor::new(format!("{} x", MAX_FRAME + 1));
+        assert!(read_frame(&mut reader, &mut lines).is_err());
+    }
+
+    /// A RELP session over a local connection, with whatever it receives
+    /// taken at once.
+    fn relp_session() -> (TcpStream, BufReader<TcpStream>, backpressure::Receiver, thread::JoinHandle<io::Result<()>>) {
+        let (tx, rx) = backpressure::buffer(16, Overflow::Block, None).unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+        let session = thread::spawn(move || receive_relp(stream, &tx));
+        (client.try_clone().unwrap(), BufReader::new(client), rx, session)
+    }
+
+    #[test]
+    fn relp_frames_are_acknowledged_by_transaction_number() {
+        let (mut writer, mut reader, rx, session) = relp_session();
+        let taker = thread::spawn(move || {
+            let mut taken = Vec::new();
+            while let Ok(message) = rx.recv_timeout(Duration::from_secs(5)) {
+                taken.push(message.line.unwrap());
+                message.ack.unwrap().send(Delivery::Taken).unwrap();
+            }
+            taken
+        });
+
+        writer.write_all(b"1 open 0\n").unwrap();
+        assert_eq!(read_response(&mut reader), "1 rsp 68 200 OK");
+        assert_eq!(read_response(&mut reader), "relp_version=0");
+        assert_eq!(read_response(&mut reader), "relp_software=syslog_processor");
+        assert_eq!(read_response(&mut reader), "commands=syslog");
+        writer.write_all(b"2 syslog 9 two\nlines\n3 syslog 0\n4 starttls 0\n5 close 0\n").unwrap();
+        assert_eq!(read_response(&mut reader), "2 rsp 6 200 OK");
+        assert_eq!(read_response(&mut reader), "3 rsp 6 200 OK");
+        assert_eq!(read_response(&mut reader), "4 rsp 19 500 unknown command");
+        assert_eq!(read_response(&mut reader), "5 rsp 0");
+        assert_eq!(read_response(&mut reader), "0 serverclose 0");
+        session.join().unwrap().unwrap();
+        assert_eq!(taker.join().unwrap(), [b"two\nlines".to_vec(), Vec::new()]);
+    }
+
+    #[test]
+    fn an_oversized_relp_frame_ends_the_session() {
+        let (mut writer, _, _rx, session) = relp_session();
+        writer.write_all(format!("1 syslog {} x\n", MAX_FRAME + 1).as_bytes()).unwrap();
+        assert_eq!(session.join().unwrap().unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn a_relp_message_dropped_by_the_buffer_is_refused_and_the_session_kept() {
+        let (tx, rx) = backpressure::buffer(1, Overflow::DropOldest, None).unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+        let session = {
+            let tx = tx.clone();
+            thread::spawn(move || receive_relp(stream, &tx))
+        };
+        let mut writer = client.try_clone().unwrap();
+        let mut reader = BufReader::new(client);
+
+        writer.write_all(b"1 open 14 relp_version=0\n").unwrap();
+        assert!(read_response(&mut reader).starts_with("1 rsp "));
+        while !read_response(&mut reader).starts_with("commands=") {}
+
+        writer.write_all(b"2 syslog 5 first\n").unwrap();
+        while rx.stats().peak == 0 {
+            thread::sleep(Duration::from_millis(1));
+        }
+        // Another receiver's message pushes the first one out
+        tx.send(Message { line: Ok(b"other".to_vec()), ack: None }).ok().unwrap();
+        assert_eq!(read_response(&mut reader), "2 rsp 41 500 message dropped, listener buffer full");
+
+        writer.write_all(b"3 syslog 6 second\n").unwrap();
+        let mut message = rx.recv_timeout(Duration::from_secs(5)).ok().unwrap();
+        while message.ack.is_none() {
+            message = rx.recv_timeout(Duration::from_secs(5)).ok().unwrap();
+        }
+        assert_eq!(message.line, Ok(b"second".to_vec()));
+        message.ack.unwrap().send(Delivery::Taken).unwrap();
+        assert_eq!(read_response(&mut reader), "3 rsp 6 200 OK");
+
+        writer.write_all(b"4 close 0\n").unwrap();
+        assert_eq!(read_response(&mut reader), "4 rsp 0");
+        assert_eq!(read_response(&mut reader), "0 serverclose 0");
+        session.join().unwrap().unwrap();
+    }
+}
@@ -0,0 +1,77 @@
+//! Advisory lock preventing overlapping runs on the same output directory.
+//!
+//! The lock file is created exclusively and records the owner's pid and start
+//! time. A lock is considered stale, and taken over, when its owner process no
+//! longer exists on this host or when it is older than the configured limit;
+//! a lock file that can't be read at all is treated as stale after the limit too.
+
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const LOCK_FILE: &str = ".syslog_processor.lock";
+
+pub struct RunLock {
+    path: PathBuf,
+}
+
+impl RunLock {
+    pub fn acquire(dir: &Path, stale_after: Duration) -> io::Result<RunLock> {
+        fs::create_dir_all(dir)?;
+        let path = dir.join(LOCK_FILE);
+
+        match Self::create(&path) {
+            Err(err) if err.kind() == io::ErrorKind::AlreadyExists => {
+                if !is_stale(&path, stale_after) {
+                    let owner = fs::read_to_string(&path).unwrap_or_default();
+                    return Err(io::Error::new(
+                        io::ErrorKind::AlreadyExists,
+                        format!("another run holds {} ({})", path.display(), owner.trim()),
+                    ));
+                }
+                eprintln!("Removing stale lock {}", path.display());
+                fs::remove_file(&path)?;
+                Self::create(&path)
+            }
+            result => result,
+        }
+    }
+
+    fn create(path: &Path) -> io::Result<RunLock> {
+        let mut file = OpenOptions::new().write(true).create_new(true).open(path)?;
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+        writeln!(file, "pid={} started={}", process::id(), now)?;
+        Ok(RunLock { path: path.to_path_buf() })
+    }
+}
+
+impl Drop for RunLock {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+fn is_stale(path: &Path, stale_after: Duration) -> bool {
+    let too_old = fs::metadata(path)
+        .and_then(|meta| meta.modified())
+        .ok()
+        .and_then(|modified| modified.elapsed().ok())
+        .is_some_and(|age| age > stale_after);
+    if too_old {
+        return true;
+    }
+
+    let owner_pid = fs::read_to_string(path).ok().and_then(|contents| {
+        contents
+            .split_whitespace()
+            .find_map(|field| field.strip_prefix("pid="))
+            .and_then(|pid| pid.parse::<u32>().ok())
+    });
+    match owner_pid {
+        // Without procfs we can't tell, so only the age limit applies
+        Some(pid) if Path::new("/proc/self").exists() => !Path::new(&format!("/proc/{}", pid)).exists(),
+        _ => false,
+    }
+}
@@ -0,0 +1,168 @@
+//! Output manifest and verification.
+//!
+//! Every run appends one JSON line to `manifest.jsonl` in the output
+//! directory describing the file it wrote. `verify` re-hashes the outputs
+//! and compares them against their manifest entries.
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+pub const MANIFEST_FILE: &str = "manifest.jsonl";
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ManifestEntry {
+    /// File name relative to the output directory
+    pub file: String,
+    pub sha256: String,
+    pub size: u64,
+    pub records: usize,
+    /// Earliest and latest event timestamps covered, when the input had them
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub time_range: Option<(String, String)>,
+}
+
+impl ManifestEntry {
+    /// Describe `path` from the digest taken while it was written, so the
+    /// output doesn't have to be read back.
+    pub fn for_written(path: &Path, (sha256, size): (String, u64), records: usize, time_range: Option<(String, String)>) -> Self {
+        ManifestEntry {
+            file: path.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default(),
+            sha256,
+            size,
+            records,
+            time_range,
+        }
+    }
+
+    pub fn append_to(&self, dir: &Path) -> io::Result<()> {
+        let mut manifest = OpenOptions::new().create(true).append(true).open(dir.join(MANIFEST_FILE))?;
+        let mut line = serde_json::to_vec(self)?;
+        line.push(b'\n');
+        manifest.write_all(&line)
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Verdict {
+    Ok,
+    Missing,
+    SizeMismatch,
+    HashMismatch,
+}
+
+/// Check every manifest entry (or only those naming one of `only`) against
+/// the file on disk.
+pub fn verify(dir: &Path, only: &[String]) -> io::Result<Vec<(ManifestEntry, Verdict)>> {
+    let manifest = File::open(dir.join(MANIFEST_FILE))?;
+    let mut results = Vec::new();
+
+    for line in BufReader::new(manifest).lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let entry: ManifestEntry = serde_json::from_str(&line)?;
+        if !only.is_empty() && !only.iter().any(|name| Path::new(name).file_name() == Some(entry.file.as_ref())) {
+            continue;
+        }
+
+        let verdict = match hash_file(&dir.join(&entry.file)) {
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Verdict::Missing,
+            Err(err) => return Err(err),
+            Ok((_, size)) if size != entry.size => Verdict::SizeMismatch,
+            Ok((sha256, _)) if sha256 != entry.sha256 => Verdict::HashMismatch,
+            Ok(_) => Verdict::Ok,
+        };
+        results.push((entry, verdict));
+    }
+
+    Ok(results)
+}
+
+/// Passes writes through while hashing them.
+pub struct HashingWriter<W> {
+    inner: W,
+    hasher: Sha256,
+    size: u64,
+}
+
+impl<W: Write> HashingWriter<W> {
+    pub fn new(inner: W) -> Self {
+        HashingWriter { inner, hasher: Sha256::new(), size: 0 }
+    }
+
+    /// Hex SHA-256 and byte count of everything written.
+    pub fn finish(self) -> (String, u64) {
+        (hex(self.hasher), self.size)
+    }
+}
+
+impl<W: Write> Write for HashingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.hasher.update(&buf[..n]);
+        self.size += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Passes reads through while hashing them.
+pub struct HashingReader<R> {
+    inner: R,
+    hasher: Sha256,
+    size: u64,
+}
+
+impl<R: Read> HashingReader<R> {
+    pub fn new(inner: R) -> Self {
+        HashingReader { inner, hasher: Sha256::new(), size: 0 }
+    }
+
+    /// Hex SHA-256 and byte count of everything read.
+    pub fn finish(self) -> (String, u64) {
+        (hex(self.hasher), self.size)
+    }
+}
+
+impl<R: Read> Read for HashingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.hasher.update(&buf[..n]);
+        self.size += n as u64;
+        Ok(n)
+    }
+}
+
+fn hex(hasher: Sha256) -> String {
+    hasher.finalize().iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Hex SHA-256 and size of a file on disk.
+pub fn hash_file(path: &Path) -> io::Result<(String, u64)> {
+    hash_reader(File::open(path)?)
+}
+
+/// Hex SHA-256 and size of everything `reader` yields.
+pub fn hash_reader(mut reader: impl Read) -> io::Result<(String, u64)> {
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0; 64 * 1024];
+    let mut size = 0;
+    loop {
+        let n = reader.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+        size += n as u64;
+    }
+    Ok((hex(hasher), size))
+}
@@ -0,0 +1,207 @@
+//! Combining outputs, e.g. the per-firewall runs of one window or the
+//! hourly windows of a day, into a single one.
+//!
+//! Records with the same key are summed and the breakdowns rebuilt from the
+//! merged records. Hourly buckets of the same hour are summed too, so their
+//! distinct-flow counts become an upper bound when the same flow appears in
+//! more than one input.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::classify;
+use crate::enrich::blocklist;
+use crate::hourly::{self, HourBucket};
+use crate::lineage;
+use crate::payload::{Metadata, Payload, ProcessingPerformance};
+use crate::record::{self, Record};
+use crate::session::CorrelationStats;
+use crate::summary;
+
+pub fn merge(payloads: Vec<Payload>) -> Payload {
+    let mut data: HashMap<Arc<str>, Record> = HashMap::new();
+    let mut metadata: Option<Metadata> = None;
+    let mut session_close = 0;
+    let mut hours: Vec<HourBucket> = Vec::new();
+    let parent_runs = lineage::parents(payloads.iter().map(|payload| &payload.metadata));
+
+    for payload in payloads {
+        for (key, record) in payload.data {
+            match data.get_mut(&key) {
+                Some(merged) => merged.merge(record),
+                None => {
+                    data.insert(key, record);
+                }
+            }
+        }
+
+        let input = payload.metadata;
+        session_close += leading_count(&input.session_close);
+        hours.extend(input.hourly_series.iter().cloned());
+
+        metadata = Some(match metadata {
+            None => input,
+            Some(mut merged) => {
+                merged.start_time = merged.start_time.min(input.start_time);
+                merged.end_time = merged.end_time.max(input.end_time);
+                merged.elapsed_time += input.elapsed_time;
+                merged.total_connections += input.total_connections;
+                record::merge_labels(&mut merged.labels, input.labels);
+                merged.files_processed.extend(input.files_processed);
+                merged.skipped_lines.merge(&input.skipped_lines);
+                merged.counter_bounds = match (merged.counter_bounds, input.counter_bounds) {
+                    (Some(mut a), Some(b)) => {
+                        a.merge(&b);
+                        Some(a)
+                    }
+                    (a, b) => a.or(b),
+                };
+                merged.flow_direction = match (merged.flow_direction, input.flow_direction) {
+                    (Some(mut a), Some(b)) => {
+                        a.merge(&b);
+                        Some(a)
+                    }
+                    (a, b) => a.or(b),
+                };
+                merged.ignored = match (merged.ignored, input.ignored) {
+                    (Some(mut a), Some(b)) => {
+                        a.merge(&b);
+                        Some(a)
+                    }
+                    (a, b) => a.or(b),
+                };
+                merged.below_minimum = match (merged.below_minimum, input.below_minimum) {
+                    (Some(mut a), Some(b)) => {
+                        a.merge(&b);
+                        Some(a)
+                    }
+                    (a, b) => a.or(b),
+                };
+                // The others of either side stay others; the merged flows aren't cut again
+                merged.top_k = match (merged.top_k, input.top_k) {
+                    (Some(mut a), Some(b)) => {
+                        a.other.merge(&b.other);
+                        a.limit = a.limit.max(b.limit);
+                        Some(a)
+                    }
+                    (a, b) => a.or(b),
+                };
+                merged.quarantined_files.extend(input.quarantined_files);
+                merged.failed_files.extend(input.failed_files);
+                merged.duplicate_files.extend(input.duplicate_files);
+                merged.already_counted.extend(input.already_counted);
+                merged.alerts.extend(input.alerts);
+                merged.suspected_scans.extend(input.suspected_scans);
+                merged.suspected_floods.extend(input.suspected_floods);
+                merged.suspected_beacons.extend(input.suspected_beacons);
+                merged.exfiltration_watchlist.extend(input.exfiltration_watchlist);
+                merged.processing_performance.files.extend(input.processing_performance.files);
+                merged.session_correlation = match (merged.session_correlation, input.session_correlation) {
+                    (Some(a), Some(b)) => Some(CorrelationStats {
+                        opened: a.opened + b.opened,
+                        matched: a.matched + b.matched,
+                        unmatched_closes: a.unmatched_closes + b.unmatched_closes,
+                        expired_opens: a.expired_opens + b.expired_opens,
+                        open_at_end: a.open_at_end + b.open_at_end,
+                    }),
+                    (a, b) => a.or(b),
+                };
+                merged
+            }
+        });
+    }
+
+    let mut metadata = metadata.expect("merge needs at least one output");
+    // The merge is a run of its own
+    metadata.run_id = lineage::new_run_id();
+    metadata.parent_runs = parent_runs;
+    let connections = metadata.total_connections;
+    metadata.session_close = format!("{} ({:.2}% of total connections)", session_close, (session_close as f64 / connections as f64) * 100.0);
+    metadata.flows = data.len();
+    // Delivery outcomes and resource use belong to the runs that were merged
+    metadata.sinks.clear();
+    // Per-run sketches aren't kept, so host cardinalities can't be combined
+    metadata.distinct_counts = None;
+    metadata.processing_performance = ProcessingPerformance {
+        connections_per_second: format!("{:.2} connections/second", connections as f64 / metadata.elapsed_time),
+        peak_rss_bytes: None,
+        cpu_time_seconds: None,
+        files: std::mem::take(&mut metadata.processing_performance.files),
+        stages: Vec::new(),
+    };
+    metadata.port_breakdown = summary::group_by(data.values(), |record| &record.destination_port);
+    metadata.protocol_breakdown = summary::group_by(data.values(), |record| &record.protocol);
+    metadata.hourly_series = hourly::combine(hours);
+    metadata.matched_indicators = blocklist::summarize(data.values());
+    metadata.traffic_classes = classify::totals(data.values());
+
+    Payload { metadata, data }
+}
+
+/// The session count at the start of a `"N (x% of total connections)"` value.
+pub fn leading_count(value: &str) -> u64 {
+    value.split_whitespace().next().and_then(|count| count.parse().ok()).unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intern::Interner;
+    use crate::parser::FlowEvent;
+    use crate::record::NatSide;
+
+    fn payload(run_id: &str, start_time: u128, connections: u64, flows: &[(&str, u64)]) -> Payload {
+        let mut data = HashMap::new();
+        for (destination, bytes) in flows {
+            let event = FlowEvent {
+                source_ip: "10.0.0.1".to_string(),
+                destination_ip: destination.to_string(),
+                destination_port: "443".to_string(),
+                protocol: "6".to_string(),
+                bytes_in: *bytes,
+                ..FlowEvent::default()
+            };
+            let mut record = Record::new(&event, NatSide::PreNat, &mut Interner::default());
+            record.key = Arc::from(format!("10.0.0.1_{}_443_6", destination));
+            data.insert(Arc::clone(&record.key), record);
+        }
+        let metadata = Metadata {
+            run_id: run_id.to_string(),
+            start_time,
+            end_time: start_time + 1000,
+            elapsed_time: 1.0,
+            total_connections: connections,
+            session_close: format!("{} (50.00% of total connections)", connections / 2),
+            files_processed: vec![format!("{}.log", run_id)],
+            ..Default::default()
+        };
+        Payload { metadata, data }
+    }
+
+    #[test]
+    fn records_of_the_same_key_are_summed() {
+        let first = payload("run-a", 5000, 4, &[("8.8.8.8", 100), ("1.1.1.1", 10)]);
+        let second = payload("run-b", 2000, 6, &[("8.8.8.8", 50)]);
+        let merged = merge(vec![first, second]);
+
+        assert_eq!(merged.data.len(), 2);
+        let shared = &merged.data["10.0.0.1_8.8.8.8_443_6"];
+        assert_eq!((shared.count, shared.bytes_in), (2, 150));
+        let metadata = &merged.metadata;
+        assert_eq!((metadata.start_time, metadata.end_time, metadata.elapsed_time), (2000, 6000, 2.0));
+        assert_eq!((metadata.total_connections, metadata.flows), (10, 2));
+        assert_eq!(metadata.session_close, "5 (50.00% of total connections)");
+        assert_eq!(metadata.files_processed, ["run-a.log", "run-b.log"]);
+        assert_eq!(metadata.parent_runs, ["run-a", "run-b"]);
+        assert!(!metadata.run_id.is_empty() && metadata.run_id != "run-a");
+        let port = &metadata.port_breakdown["443"];
+        assert_eq!((port.flows, port.sessions, port.bytes_in), (2, 3, 160));
+    }
+
+    #[test]
+    fn the_session_count_leads_the_close_value() {
+        assert_eq!(leading_count("12 (40.00% of total connections)"), 12);
+        assert_eq!(leading_count(""), 0);
+        assert_eq!(leading_count("n/a"), 0);
+    }
+}
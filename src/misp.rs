@@ -0,0 +1,192 @@
+//! Export of flagged flows for threat-sharing platforms.
+//!
+//! Flows are flagged when their source or destination matched a blocklist or
+//! a rule with the `export` action matched them.
+//! They are written either as a MISP event (one `network-connection` object
+//! per flow plus an `ip-src`/`ip-dst` attribute per matched address) or as a
+//! STIX 2.1 bundle of `ipv4-addr`/`ipv6-addr` and `network-traffic`
+//! observables. Identifiers are derived from the exported values, so
+//! exporting the same flows twice yields the same UUIDs and re-imports
+//! update rather than duplicate.
+
+use std::collections::BTreeMap;
+
+use chrono::DateTime;
+use clap::ValueEnum;
+use serde_json::{Value, json};
+use sha2::{Digest, Sha256};
+
+use crate::payload::Payload;
+use crate::record::Record;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ThreatExport {
+    /// MISP event JSON
+    Misp,
+    /// STIX 2.1 bundle
+    Stix,
+}
+
+impl ThreatExport {
+    pub fn extension(self) -> &'static str {
+        match self {
+            ThreatExport::Misp => "misp.json",
+            ThreatExport::Stix => "stix.json",
+        }
+    }
+}
+
+pub fn flagged(record: &Record) -> bool {
+    !record.matched_indicators.is_empty() || record.exported
+}
+
+/// Name-based UUID (RFC 9562 version 8) from the SHA-256 of `name`.
+fn uuid(name: &str) -> String {
+    let digest = Sha256::digest(name.as_bytes());
+    let mut bytes = [0u8; 16];
+    bytes.copy_from_slice(&digest[..16]);
+    bytes[6] = (bytes[6] & 0x0f) | 0x80;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+    let hex: String = bytes.iter().map(|byte| format!("{:02x}", byte)).collect();
+    format!("{}-{}-{}-{}-{}", &hex[0..8], &hex[8..12], &hex[12..16], &hex[16..20], &hex[20..32])
+}
+
+fn protocol_name(protocol: &str) -> &str {
+    match protocol {
+        "1" => "icmp",
+        "6" => "tcp",
+        "17" => "udp",
+        "58" => "ipv6-icmp",
+        other => other,
+    }
+}
+
+pub fn render(payload: &Payload, format: ThreatExport) -> String {
+    let mut flows: Vec<&Record> = payload.data.values().filter(|record| flagged(record)).collect();
+    flows.sort_by(|a, b| a.key.cmp(&b.key));
+    let started = DateTime::from_timestamp_millis(payload.metadata.start_time as i64).unwrap_or_default();
+
+    let document = match format {
+        ThreatExport::Misp => misp_event(&flows, started, payload.metadata.start_time),
+        ThreatExport::Stix => stix_bundle(&flows, started, payload.metadata.start_time),
+    };
+    serde_json::to_string_pretty(&document).expect("Unable to serialize threat export")
+}
+
+fn comment(record: &Record) -> String {
+    let mut indicators: Vec<_> = record
+        .matched_indicators
+        .iter()
+        .map(|matched| format!("{} {} on {}", matched.side, matched.indicator, matched.list))
+        .collect();
+    indicators.extend(record.matched_rules.iter().map(|rule| format!("rule {}", rule)));
+    format!(
+        "{} sessions, {} bytes; matched {}",
+        record.count,
+        record.bytes_in.saturating_add(record.bytes_out),
+        indicators.join(", ")
+    )
+}
+
+fn misp_event(flows: &[&Record], started: DateTime<chrono::Utc>, window: u128) -> Value {
+    let timestamp = started.timestamp().to_string();
+    let mut attributes = BTreeMap::new();
+    let mut objects = Vec::new();
+
+    for record in flows {
+        for matched in &record.matched_indicators {
+            let (kind, address) = match matched.side.as_str() {
+                "source" => ("ip-src", &*record.source_ip),
+                _ => ("ip-dst", &*record.destination_ip),
+            };
+            attributes.entry((kind, address.to_string())).or_insert_with(|| {
+                json!({
+                    "uuid": uuid(&format!("misp-attribute:{}:{}:{}", window, kind, address)),
+                    "type": kind,
+                    "category": "Network activity",
+                    "value": address,
+                    "to_ids": true,
+                    "comment": format!("Listed as {} on {}", matched.indicator, matched.list),
+                    "timestamp": timestamp,
+                })
+            });
+        }
+
+        let mut object_attributes = vec![
+            json!({"object_relation": "ip-src", "type": "ip-src", "value": &*record.source_ip}),
+            json!({"object_relation": "ip-dst", "type": "ip-dst", "value": &*record.destination_ip}),
+        ];
+        if !record.destination_port.is_empty() {
+            object_attributes.push(json!({"object_relation": "dst-port", "type": "port", "value": &*record.destination_port}));
+        }
+        if !record.protocol.is_empty() {
+            object_attributes.push(json!({"object_relation": "layer4-protocol", "type": "text", "value": protocol_name(&record.protocol)}));
+        }
+        objects.push(json!({
+            "uuid": uuid(&format!("misp-object:{}:{}", window, record.key)),
+            "name": "network-connection",
+            "meta-category": "network",
+            "template_uuid": "af16764b-f8e5-4603-9de1-de34d272f80b",
+            "comment": comment(record),
+            "timestamp": timestamp,
+            "Attribute": object_attributes,
+        }));
+    }
+
+    json!({
+        "Event": {
+            "uuid": uuid(&format!("misp-event:{}", window)),
+            "info": format!("Firewall flows to or from listed indicators, {}", started.format("%Y-%m-%d %H:%M UTC")),
+            "date": started.format("%Y-%m-%d").to_string(),
+            "timestamp": timestamp,
+            "threat_level_id": "3",
+            "analysis": "0",
+            "distribution": "0",
+            "Attribute": attributes.into_values().collect::<Vec<_>>(),
+            "Object": objects,
+        }
+    })
+}
+
+fn stix_bundle(flows: &[&Record], started: DateTime<chrono::Utc>, window: u128) -> Value {
+    let mut addresses = BTreeMap::new();
+    let mut objects = Vec::new();
+    let mut address_ref = |address: &str| {
+        let kind = if address.contains(':') { "ipv6-addr" } else { "ipv4-addr" };
+        let id = format!("{}--{}", kind, uuid(&format!("stix:{}:{}", kind, address)));
+        addresses
+            .entry(id.clone())
+            .or_insert_with(|| json!({"type": kind, "spec_version": "2.1", "id": id, "value": address}));
+        id
+    };
+
+    for record in flows {
+        let source = address_ref(&record.source_ip);
+        let destination = address_ref(&record.destination_ip);
+        let mut traffic = json!({
+            "type": "network-traffic",
+            "spec_version": "2.1",
+            "id": format!("network-traffic--{}", uuid(&format!("stix:network-traffic:{}:{}", window, record.key))),
+            "start": started.to_rfc3339_opts(chrono::SecondsFormat::Millis, true),
+            "src_ref": source,
+            "dst_ref": destination,
+            "protocols": [protocol_name(&record.protocol)],
+            "src_byte_count": record.bytes_out,
+            "dst_byte_count": record.bytes_in,
+            "src_packets": record.packets_out,
+            "dst_packets": record.packets_in,
+        });
+        if let Ok(port) = record.destination_port.parse::<u16>() {
+            traffic["dst_port"] = json!(port);
+        }
+        objects.push(traffic);
+    }
+
+    let mut all: Vec<Value> = addresses.into_values().collect();
+    all.extend(objects);
+    json!({
+        "type": "bundle",
+        "id": format!("bundle--{}", uuid(&format!("stix-bundle:{}", window))),
+        "objects": all,
+    })
+}
@@ -0,0 +1,124 @@
+//! Readiness, status and watchdog notifications for systemd services.
+//!
+//! Under a `Type=notify` unit systemd passes a datagram socket in
+//! `NOTIFY_SOCKET`; the long-running modes report `READY=1` once they are
+//! taking input, keep a one-line `STATUS=` current for `systemctl status`,
+//! and, when the unit sets `WatchdogSec=`, send `WATCHDOG=1` at half that
+//! interval from the same loop that does the work, so a hung aggregator
+//! stops pinging and gets restarted. Outside systemd all of this is a no-op.
+
+use std::env;
+use std::io;
+use std::os::unix::net::UnixDatagram;
+use std::process;
+use std::time::{Duration, Instant};
+
+/// How often the status line is refreshed when nothing else is sent.
+const STATUS_EVERY: Duration = Duration::from_secs(5);
+
+pub struct Notifier {
+    socket: Option<(UnixDatagram, String)>,
+    watchdog: Option<Duration>,
+    ready: bool,
+    last_ping: Instant,
+    last_status: Instant,
+}
+
+impl Notifier {
+    /// A notifier for the socket and watchdog interval systemd passed, if any.
+    pub fn from_env() -> Notifier {
+        let socket = env::var("NOTIFY_SOCKET")
+            .ok()
+            .filter(|path| !path.is_empty())
+            .and_then(|path| UnixDatagram::unbound().ok().map(|socket| (socket, path)));
+        // The interval is meant for the process systemd started, not its children
+        let for_us = env::var("WATCHDOG_PID").ok().is_none_or(|pid| pid.parse() == Ok(process::id()));
+        let watchdog = env::var("WATCHDOG_USEC")
+            .ok()
+            .and_then(|usec| usec.parse().ok())
+            .filter(|&usec| usec > 0 && for_us)
+            .map(Duration::from_micros);
+        Notifier {
+            socket,
+            watchdog,
+            ready: false,
+            last_ping: Instant::now(),
+            last_status: Instant::now(),
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.socket.is_some()
+    }
+
+    /// The watchdog interval systemd set for this process.
+    pub fn watchdog(&self) -> Option<Duration> {
+        self.watchdog.filter(|_| self.enabled())
+    }
+
+    /// How often `tick` has to be called to keep the watchdog fed.
+    pub fn ping_every(&self) -> Option<Duration> {
+        self.watchdog.filter(|_| self.enabled()).map(|interval| interval / 2)
+    }
+
+    /// Called from the work loop: reports readiness the first time, then
+    /// pings the watchdog and refreshes the status when they are due.
+    /// `status` is only evaluated when it is sent.
+    pub fn tick(&mut self, status: impl FnOnce() -> String) {
+        if !self.enabled() {
+            return;
+        }
+        let ping_due = self.ping_every().is_some_and(|every| self.last_ping.elapsed() >= every);
+        let status_due = !self.ready || self.last_status.elapsed() >= STATUS_EVERY;
+        if !ping_due && !status_due {
+            return;
+        }
+
+        let mut message = format!("STATUS={}\n", status());
+        if !self.ready {
+            message.push_str("READY=1\n");
+        }
+        if self.watchdog.is_some() {
+            message.push_str("WATCHDOG=1\n");
+        }
+        if let Err(err) = self.send(&message) {
+            eprintln!("Unable to notify systemd: {}", err);
+        }
+        self.ready = true;
+        self.last_ping = Instant::now();
+        self.last_status = Instant::now();
+    }
+
+    /// Update the status line alone, without reporting readiness or
+    /// pinging the watchdog.
+    pub fn status(&self, status: &str) {
+        if let Err(err) = self.send(&format!("STATUS={}\n", status)) {
+            eprintln!("Unable to notify systemd: {}", err);
+        }
+    }
+
+    /// Tell systemd the service is shutting down on its own.
+    pub fn stopping(&self, status: &str) {
+        if let Err(err) = self.send(&format!("STOPPING=1\nSTATUS={}\n", status)) {
+            eprintln!("Unable to notify systemd: {}", err);
+        }
+    }
+
+    fn send(&self, message: &str) -> io::Result<()> {
+        let Some((socket, path)) = &self.socket else {
+            return Ok(());
+        };
+        // A leading `@` names a socket in the abstract namespace
+        match path.strip_prefix('@') {
+            Some(name) => {
+                use std::os::linux::net::SocketAddrExt;
+                let addr = std::os::unix::net::SocketAddr::from_abstract_name(name)?;
+                socket.send_to_addr(message.as_bytes(), &addr)?;
+            }
+            None => {
+                socket.send_to(message.as_bytes(), path)?;
+            }
+        }
+        Ok(())
+    }
+}
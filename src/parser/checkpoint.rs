@@ -0,0 +1,93 @@
+//! Check Point LogExporter syslog output.
+//!
+//! Log fields arrive as `key:"value"` pairs separated by semicolons, usually
+//! wrapped in square brackets after the syslog header. Only connection logs
+//! that carry the accounting counters are turned into events.
+
+use super::{Action, FlowEvent, SkipReason, counter, non_empty, quoted_pairs, required, vlan_from_interface};
+
+pub fn parse_line(line: &str, min_fields: usize) -> Result<FlowEvent, SkipReason> {
+    let line = line.trim();
+    let body = match (line.find('['), line.rfind(']')) {
+        (Some(start), Some(end)) if start < end => &line[start + 1..end],
+        _ => line,
+    };
+    let fields = quoted_pairs(body, ':');
+    if fields.is_empty() {
+        return Err(SkipReason::Unrecognized);
+    }
+    if fields.len() < min_fields {
+        return Err(SkipReason::ShortLine);
+    }
+
+    let counter = |name: &str| counter(fields.get(name).copied());
+    let required = |name: &str| required(fields.get(name).copied());
+
+    // Check Point logs the interface the packet was seen on plus its direction
+    let interface = fields.get("ifname").copied();
+    let (ingress_interface, egress_interface) = match fields.get("ifdir").copied() {
+        Some("outbound") => (None, non_empty(interface)),
+        _ => (non_empty(interface), None),
+    };
+
+    Ok(FlowEvent {
+        firewall: required("origin")?,
+        source_ip: required("src")?,
+        destination_ip: required("dst")?,
+        source_port: non_empty(fields.get("s_port").copied()),
+        destination_port: fields.get("service").copied().unwrap_or_default().to_string(),
+        protocol: required("proto")?,
+        packets_in: counter("client_inbound_packets")?,
+        bytes_in: counter("client_inbound_bytes")?,
+        packets_out: counter("client_outbound_packets")?,
+        bytes_out: counter("client_outbound_bytes")?,
+        tcp_flags: fields.get("tcp_flags").map(|flags| flags.to_string()),
+        end_reason: fields.get("reason").map(|reason| reason.to_string()),
+        action: fields.get("action").and_then(|action| Action::from_vendor(action)),
+        nat_source_ip: non_empty(fields.get("xlatesrc").copied()),
+        nat_source_port: non_empty(fields.get("xlatesport").copied()),
+        nat_destination_ip: non_empty(fields.get("xlatedst").copied()),
+        nat_destination_port: non_empty(fields.get("xlatedport").copied()),
+        ingress_zone: non_empty(fields.get("inzone").copied()),
+        egress_zone: non_empty(fields.get("outzone").copied()),
+        ingress_interface,
+        egress_interface,
+        vlan: interface.and_then(vlan_from_interface),
+        application: non_empty(fields.get("appi_name").copied()),
+        user: non_empty(fields.get("src_user_name").or(fields.get("user")).copied()),
+        ..Default::default()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LINE: &str = r#"<134>1 2024-05-01T10:00:00Z gw-1 CheckPoint 2140 - [action:"Accept"; origin:"10.1.1.1"; src:"192.168.1.5"; dst:"8.8.8.8"; proto:"17"; service:"53"; s_port:"5353"; client_inbound_packets:"1"; client_inbound_bytes:"80"; client_outbound_packets:"2"; client_outbound_bytes:"120"; ifdir:"outbound"; ifname:"eth1.20"; src_user_name:"Jane Doe (jdoe)"]"#;
+
+    #[test]
+    fn connection_logs_parse() {
+        let event = parse_line(LINE, 0).unwrap();
+        assert_eq!(event.firewall, "10.1.1.1");
+        assert_eq!((event.source_ip.as_str(), event.destination_ip.as_str()), ("192.168.1.5", "8.8.8.8"));
+        assert_eq!((event.source_port.as_deref(), event.destination_port.as_str(), event.protocol.as_str()), (Some("5353"), "53", "17"));
+        assert_eq!((event.packets_in, event.bytes_in, event.packets_out, event.bytes_out), (1, 80, 2, 120));
+        assert_eq!(event.action, Some(Action::Allow));
+        assert_eq!((event.ingress_interface, event.egress_interface.as_deref(), event.vlan.as_deref()), (None, Some("eth1.20"), Some("20")));
+        assert_eq!(event.user.as_deref(), Some("Jane Doe (jdoe)"));
+    }
+
+    #[test]
+    fn other_logs_are_skipped() {
+        let cases: &[(&str, usize, SkipReason)] = &[
+            ("<134>1 2024-05-01T10:00:00Z gw-1 CheckPoint 2140 - []", 0, SkipReason::Unrecognized),
+            (r#"[origin:"10.1.1.1"; src:"192.168.1.5"]"#, 3, SkipReason::ShortLine),
+            (r#"[origin:"10.1.1.1"; src:"192.168.1.5"; proto:"6"]"#, 0, SkipReason::MissingField),
+            (r#"[origin:"10.1.1.1"; src:"192.168.1.5"; dst:"8.8.8.8"; proto:"6"; client_inbound_packets:""]"#, 0, SkipReason::EmptyCounters),
+            (r#"[origin:"10.1.1.1"; src:"192.168.1.5"; dst:"8.8.8.8"; proto:"6"; client_inbound_packets:"many"]"#, 0, SkipReason::ParseFailure),
+        ];
+        for (line, min_fields, reason) in cases {
+            assert_eq!(parse_line(line, *min_fields).err(), Some(*reason), "{}", line);
+        }
+    }
+}
@@ -0,0 +1,162 @@
+//! Comma-separated session-close exports.
+//!
+//! Lines have fixed column positions unless the file starts with a header
+//! naming its columns, e.g. `timestamp,src_ip,dst_ip,dst_port,proto,bytes_in`,
+//! in which case columns are matched to event fields by name through the
+//! key=value format's aliases (`--kv-alias` included) and the header itself
+//! isn't counted as a connection.
+
+use super::kv::KvParser;
+use super::pattern::assign;
+use super::{FlowEvent, SkipReason, counter, non_empty, parse_timestamp};
+
+/// Columns up to and including the last counter
+const COLUMNS: usize = 13;
+
+pub fn parse_line(line: &str, min_fields: usize) -> Result<FlowEvent, SkipReason> {
+    let parts: Vec<&str> = line.trim().split(',').collect();
+    if parts.len() < min_fields.max(COLUMNS) {
+        return Err(SkipReason::ShortLine);
+    }
+
+    let firewall_ip = parts[1];
+    let event_type = parts[2];
+    let source_ip = parts[3];
+    let destination_ip = parts[4];
+    let destination_port = parts[5];
+    let protocol_id = parts[6];
+    // Post-NAT source and destination addresses
+    let nat_source_ip = parts[7];
+    let nat_destination_ip = parts[8];
+    let packets_in = counter(Some(parts[9]))?;
+    let bytes_in = counter(Some(parts[10]))?;
+    let packets_out = counter(Some(parts[11]))?;
+    let bytes_out = counter(Some(parts[12]))?;
+
+    Ok(FlowEvent {
+        timestamp: parse_timestamp(parts[0]),
+        firewall: firewall_ip.to_string(),
+        source_ip: source_ip.to_string(),
+        destination_ip: destination_ip.to_string(),
+        destination_port: destination_port.to_string(),
+        protocol: protocol_id.to_string(),
+        packets_in,
+        bytes_in,
+        packets_out,
+        bytes_out,
+        event_type: non_empty(Some(event_type)),
+        nat_source_ip: non_empty(Some(nat_source_ip)),
+        nat_destination_ip: non_empty(Some(nat_destination_ip)),
+        ..Default::default()
+    })
+}
+
+/// Column layout named by a header line: the event field of each column,
+/// if it's one the parser knows.
+#[derive(Debug, Clone)]
+pub(super) struct Header {
+    fields: Vec<Option<&'static str>>,
+}
+
+impl Header {
+    /// `line` as a header, if it has no numeric columns and names at least
+    /// both addresses and a byte counter.
+    pub(super) fn parse(line: &str, names: &KvParser) -> Option<Header> {
+        // Rows open with a timestamp or address; checked first to keep this cheap
+        if line.trim_start().starts_with(|c: char| c.is_ascii_digit()) {
+            return None;
+        }
+        let columns: Vec<&str> = line.trim().split(',').map(|column| column.trim().trim_matches('"').trim()).collect();
+        if columns.iter().any(|column| !column.is_empty() && column.bytes().all(|b| b.is_ascii_digit() || b == b'.')) {
+            return None;
+        }
+        let fields: Vec<_> = columns.iter().map(|column| names.field(column).or_else(|| names.field(&normalize(column)))).collect();
+        let named = |field| fields.contains(&Some(field));
+        (named("source_ip") && named("destination_ip") && (named("bytes_in") || named("bytes_out"))).then_some(Header { fields })
+    }
+
+    pub(super) fn parse_line(&self, line: &str, min_fields: usize) -> Result<FlowEvent, SkipReason> {
+        let parts: Vec<&str> = line.trim().split(',').collect();
+        if parts.len() < min_fields {
+            return Err(SkipReason::ShortLine);
+        }
+        let mut event = FlowEvent::default();
+        for (field, value) in self.fields.iter().zip(&parts) {
+            match field {
+                Some(field @ ("packets_in" | "bytes_in" | "packets_out" | "bytes_out")) => {
+                    counter(Some(value.trim().trim_matches('"')))?;
+                    assign(&mut event, field, value)?;
+                }
+                Some(field) => assign(&mut event, field, value)?,
+                None => {}
+            }
+        }
+        if parts.len() < self.fields.len() && self.fields[parts.len()..].iter().flatten().any(|field| field.starts_with("bytes_")) {
+            return Err(SkipReason::ShortLine);
+        }
+        if event.source_ip.is_empty() || event.destination_ip.is_empty() {
+            return Err(SkipReason::MissingField);
+        }
+        Ok(event)
+    }
+}
+
+/// A column name as a field name: `Source IP` and `dst-port` become
+/// `source_ip` and `dst_port`.
+fn normalize(name: &str) -> String {
+    name.chars().map(|c| if matches!(c, ' ' | '-' | '.') { '_' } else { c.to_ascii_lowercase() }).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{InputFormat, LineParser, ParserOptions};
+    use super::*;
+
+    fn parser() -> LineParser {
+        LineParser::new(InputFormat::Csv, &ParserOptions { pattern: None, kv_aliases: &[], min_fields: 0 }).unwrap()
+    }
+
+    #[test]
+    fn header_lines_are_told_from_rows() {
+        let names = KvParser::new(&[]).unwrap();
+        let cases = [
+            ("timestamp,src_ip,dst_ip,dst_port,proto,bytes_in", true),
+            ("\"Source IP\", \"Destination IP\", \"Bytes Out\"", true),
+            ("srcip,dstip,dst_port", false),
+            ("src_ip,dst_ip,bytes_in,1024", false),
+            ("2025-08-29T11:38:01+00:00,192.168.29.191,,10.0.0.1,8.8.8.8,443,6,,,1,100,1,0", false),
+        ];
+        for (line, expected) in cases {
+            assert_eq!(Header::parse(line, &names).is_some(), expected, "{}", line);
+        }
+    }
+
+    #[test]
+    fn rows_are_read_by_the_header_of_their_input() {
+        let mut parser = parser();
+        parser.set_input("named.csv");
+        assert!(parser.header("dst_port,dst_ip,src_ip,comment,bytes_out"));
+        let event = parser.parse_line("53,8.8.8.8,10.0.0.1,anything,512").unwrap();
+        assert_eq!((event.source_ip.as_str(), event.destination_ip.as_str(), event.destination_port.as_str()), ("10.0.0.1", "8.8.8.8", "53"));
+        assert_eq!(event.bytes_out, 512);
+        assert_eq!(parser.parse_line("53,8.8.8.8,10.0.0.1").unwrap_err(), SkipReason::ShortLine);
+        assert_eq!(parser.parse_line("53,8.8.8.8,,x,512").unwrap_err(), SkipReason::MissingField);
+        assert!(parser.parse_line("53,8.8.8.8,10.0.0.1,x,lots").is_err());
+
+        // Another input starts out with fixed columns, and the header comes back with its own
+        parser.set_input("fixed.csv");
+        let fixed = "2025-08-29T11:38:01+00:00,192.168.29.191,,10.0.0.1,8.8.8.8,443,6,,,1,100,2,300";
+        assert_eq!(parser.parse_line(fixed).unwrap().bytes_out, 300);
+        parser.set_input("named.csv");
+        assert_eq!(parser.parse_line("53,8.8.8.8,10.0.0.1,x,7").unwrap().bytes_out, 7);
+    }
+
+    #[test]
+    fn fixed_rows_need_every_counter_column() {
+        let row = "2025-08-29T11:38:01+00:00,192.168.29.191,close,10.0.0.1,8.8.8.8,443,6,203.0.113.1,,1,100,2,300";
+        let event = parse_line(row, 0).unwrap();
+        assert_eq!((event.event_type.as_deref(), event.nat_source_ip.as_deref(), event.nat_destination_ip), (Some("close"), Some("203.0.113.1"), None));
+        assert_eq!(parse_line(row, 14).unwrap_err(), SkipReason::ShortLine);
+        assert_eq!(parse_line("2025-08-29T11:38:01+00:00,192.168.29.191,,10.0.0.1,8.8.8.8,443,6,,,1,100,2", 0).unwrap_err(), SkipReason::ShortLine);
+    }
+}
@@ -0,0 +1,102 @@
+//! pfSense/OPNsense `filterlog` lines.
+//!
+//! The CSV body starts with a fixed header (rule, sub-rule, anchor, tracker,
+//! interface, reason, action, direction, IP version) after which the layout
+//! depends on the IP version, and the port columns only exist for TCP/UDP.
+//! Each line describes a single packet, so it contributes one packet and the
+//! IP total length in the direction it was logged.
+
+use super::{Action, FlowEvent, SkipReason, icmp, non_empty, parse_timestamp, vlan_from_interface};
+
+const IPV4_FIELDS: usize = 20;
+const IPV6_FIELDS: usize = 17;
+
+pub fn parse_line(line: &str, min_fields: usize) -> Result<FlowEvent, SkipReason> {
+    let (header, host, body) = split_syslog_prefix(line.trim());
+    let parts: Vec<&str> = body.split(',').collect();
+    if parts.len() < min_fields.max(9) {
+        return Err(SkipReason::ShortLine);
+    }
+
+    let interface = parts[4];
+    let action = Action::from_vendor(parts[6]);
+    let direction = parts[7];
+
+    // (protocol id, length, source, destination, index of the first protocol-specific column)
+    let (protocol_id, length, source_ip, destination_ip, next) = match parts[8] {
+        "4" if parts.len() >= IPV4_FIELDS => (parts[15], parts[17], parts[18], parts[19], IPV4_FIELDS),
+        "6" if parts.len() >= IPV6_FIELDS => (parts[13], parts[14], parts[15], parts[16], IPV6_FIELDS),
+        "4" | "6" => return Err(SkipReason::ShortLine),
+        _ => return Err(SkipReason::Unrecognized),
+    };
+
+    let length = match length {
+        "" => return Err(SkipReason::EmptyCounters),
+        length => length.parse::<u64>().map_err(|_| SkipReason::ParseFailure)?,
+    };
+
+    let (source_port, destination_port) = match protocol_id {
+        "6" | "17" => (parts.get(next).copied(), parts.get(next + 1).copied().unwrap_or_default()),
+        _ => (None, ""),
+    };
+    // TCP adds flags after source port, destination port and data length
+    let tcp_flags = match protocol_id {
+        "6" => parts.get(next + 3).filter(|flags| !flags.is_empty()).map(|flags| flags.to_string()),
+        _ => None,
+    };
+
+    // ICMP starts with the type as a word: `request`, `unreachport`, ...
+    let icmp_type = match protocol_id {
+        "1" => parts.get(next).and_then(|word| icmp::filterlog_type(word)),
+        _ => None,
+    };
+
+    let (packets_in, bytes_in, packets_out, bytes_out) = match direction {
+        "in" => (1, length, 0, 0),
+        "out" => (0, 0, 1, length),
+        _ => return Err(SkipReason::Unrecognized),
+    };
+    let (ingress_interface, egress_interface) = match direction {
+        "in" => (non_empty(Some(interface)), None),
+        _ => (None, non_empty(Some(interface))),
+    };
+
+    Ok(FlowEvent {
+        timestamp: header.split_whitespace().nth(1).and_then(parse_timestamp),
+        firewall: host.unwrap_or(interface).to_string(),
+        source_ip: source_ip.to_string(),
+        destination_ip: destination_ip.to_string(),
+        source_port: non_empty(source_port),
+        destination_port: destination_port.to_string(),
+        protocol: protocol_id.to_string(),
+        packets_in,
+        bytes_in,
+        packets_out,
+        bytes_out,
+        tcp_flags,
+        action,
+        ingress_interface,
+        egress_interface,
+        vlan: vlan_from_interface(interface),
+        icmp_type,
+        ..Default::default()
+    })
+}
+
+/// Strip an optional BSD (`... host filterlog[pid]: body`) or RFC 5424
+/// (`... host filterlog pid - - body`) header, returning the header text before
+/// the program name and the sending host when one was present.
+fn split_syslog_prefix(line: &str) -> (&str, Option<&str>, &str) {
+    let Some(idx) = line.find("filterlog") else {
+        return ("", None, line);
+    };
+    let header = &line[..idx];
+    let host = header.split_whitespace().last();
+    let rest = &line[idx..];
+
+    let body = match rest.split_once(": ") {
+        Some((_, body)) => body,
+        None => rest.splitn(5, ' ').nth(4).unwrap_or_default(),
+    };
+    (header, host, body)
+}
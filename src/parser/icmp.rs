@@ -0,0 +1,61 @@
+//! ICMP type and code.
+//!
+//! ICMP (protocol 1) and ICMPv6 (58) have no ports. Formats with fixed
+//! columns log the type where the destination port would be, sometimes as
+//! `type/code`, and others put an identifier or just `0` there, so keying
+//! on it either hides the type or splits one flow per echo identifier.
+//! Every event of those protocols has the port column moved into its ICMP
+//! fields instead, unless the format logged them separately.
+
+use super::FlowEvent;
+
+/// Whether `protocol`, as logged, is ICMP or ICMPv6.
+fn is_icmp(protocol: &str) -> bool {
+    matches!(protocol.trim().to_ascii_lowercase().as_str(), "1" | "58" | "icmp" | "icmp6" | "icmpv6" | "ipv6-icmp")
+}
+
+/// Move an ICMP event's port column into its type and code. Other events
+/// are left alone.
+pub(super) fn normalize(mut event: FlowEvent) -> FlowEvent {
+    if !is_icmp(&event.protocol) {
+        return event;
+    }
+    let port = std::mem::take(&mut event.destination_port);
+    if event.icmp_type.is_none() {
+        let mut parts = port.splitn(2, ['/', ':']);
+        event.icmp_type = parts.next().and_then(number);
+        if event.icmp_code.is_none() {
+            event.icmp_code = event.icmp_type.and(parts.next().and_then(number));
+        }
+    }
+    event.nat_destination_port = None;
+    event
+}
+
+/// A type or code as logged: decimal, or hex like FortiGate's `0x08`.
+pub(super) fn number(value: &str) -> Option<u8> {
+    let value = value.trim();
+    match value.strip_prefix("0x").or_else(|| value.strip_prefix("0X")) {
+        Some(hex) => u8::from_str_radix(hex, 16).ok(),
+        None => value.parse().ok(),
+    }
+}
+
+/// The numeric type of one of filterlog's ICMP type words.
+pub(super) fn filterlog_type(word: &str) -> Option<u8> {
+    Some(match word {
+        "reply" => 0,
+        "unreach" | "unreachproto" | "unreachport" => 3,
+        "redirect" => 5,
+        "request" => 8,
+        "routeradvert" => 9,
+        "routersol" => 10,
+        "timexceed" => 11,
+        "paramprob" => 12,
+        "tstamp" => 13,
+        "tstampreply" => 14,
+        "maskreq" => 17,
+        "maskreply" => 18,
+        _ => return None,
+    })
+}
@@ -0,0 +1,183 @@
+//! Generic `key=value` lines, as logged by FortiGate, SonicWall, Sophos,
+//! WatchGuard and many others.
+//!
+//! Vendors disagree on key names, so each event field is matched by a list of
+//! aliases (`srcip`, `src`, `source-ip`, ...). Extra aliases can be given as
+//! `field=alias1,alias2` to cover vendors the built-in table misses. Tokens
+//! without `=`, such as the syslog header, are ignored.
+
+use std::collections::HashMap;
+
+use super::pattern::{FIELDS, assign};
+use super::{FlowEvent, SkipReason, quoted_pairs};
+
+/// Built-in aliases per event field, besides the field name itself.
+const ALIASES: &[(&str, &[&str])] = &[
+    ("timestamp", &["ts", "time", "eventtime", "@timestamp"]),
+    ("session_id", &["sessionid", "session", "sess_id", "conn_id"]),
+    ("firewall", &["devname", "device", "devid", "host", "hostname", "fw"]),
+    ("source_ip", &["srcip", "src", "src_ip", "source-ip", "source", "sip", "srcaddr"]),
+    ("destination_ip", &["dstip", "dst", "dst_ip", "destination-ip", "destination", "dip", "dstaddr"]),
+    ("source_port", &["srcport", "sport", "spt", "src_port", "source-port", "srcPort"]),
+    ("destination_port", &["dstport", "dport", "dpt", "dst_port", "destination-port", "dstPort"]),
+    ("protocol", &["proto", "protocol-id", "ipproto"]),
+    ("packets_in", &["rcvdpkt", "pkts_in", "packets-in", "rpkt"]),
+    ("bytes_in", &["rcvdbyte", "rcvd", "bytes-in", "rcvdbytes", "rbytes"]),
+    ("packets_out", &["sentpkt", "pkts_out", "packets-out", "spkt"]),
+    ("bytes_out", &["sentbyte", "sent", "bytes-out", "sentbytes", "sbytes"]),
+    ("tcp_flags", &["tcpflags", "flags"]),
+    ("event_type", &["eventtype", "event", "logtype", "log_type", "msgtype", "event-type"]),
+    ("end_reason", &["reason", "close_reason", "closereason"]),
+    ("action", &["act", "fw_action", "disposition"]),
+    ("nat_source_ip", &["transip", "natsrc", "nat_src", "xlatesrc"]),
+    ("nat_source_port", &["transport", "natsport", "xlatesport"]),
+    ("nat_destination_ip", &["tranip", "natdst", "nat_dst", "xlatedst"]),
+    ("nat_destination_port", &["tranport", "natdport", "xlatedport"]),
+    ("ingress_zone", &["srcintfrole", "srczone", "src_zone", "from_zone"]),
+    ("egress_zone", &["dstintfrole", "dstzone", "dst_zone", "to_zone"]),
+    ("ingress_interface", &["srcintf", "in_if", "inif", "src_interface"]),
+    ("egress_interface", &["dstintf", "out_if", "outif", "dst_interface"]),
+    ("vlan", &["vlanid", "vlan_id"]),
+    ("tunnel", &["tunnelid", "tunnel_id", "tunnel-id", "vpn", "vni", "vxlan_vni", "gre_key", "spi", "ipsec_spi"]),
+    ("application", &["app", "appname", "application_name"]),
+    ("user", &["username", "usr", "src_user", "srcuser"]),
+    ("source_mac", &["srcmac", "src_mac", "smac", "mac", "source-mac"]),
+    ("destination_mac", &["dstmac", "dst_mac", "dmac", "destination-mac"]),
+    ("icmp_type", &["icmptype", "icmp-type", "itype"]),
+    ("icmp_code", &["icmpcode", "icmp-code", "icode"]),
+];
+
+pub struct KvParser {
+    /// Key as logged -> event field, and the alias's priority when a line
+    /// carries several keys for the same field (lower wins)
+    aliases: HashMap<String, (&'static str, usize)>,
+}
+
+impl KvParser {
+    pub fn new(extra: &[String]) -> Result<Self, String> {
+        // User aliases come first, then the field name, then the built-ins in order
+        let mut aliases: HashMap<String, (&'static str, usize)> = FIELDS.iter().map(|field| (field.to_string(), (*field, 1))).collect();
+        for (field, names) in ALIASES {
+            for (rank, name) in names.iter().enumerate() {
+                aliases.insert(name.to_string(), (*field, rank + 2));
+            }
+        }
+
+        for spec in extra {
+            let (field, names) = spec
+                .split_once('=')
+                .ok_or_else(|| format!("alias `{}` is not in field=name1,name2 form", spec))?;
+            let field = FIELDS
+                .iter()
+                .find(|known| **known == field.trim())
+                .ok_or_else(|| format!("`{}` is not an event field (expected one of: {})", field, FIELDS.join(", ")))?;
+            for name in names.split(',').map(str::trim).filter(|name| !name.is_empty()) {
+                aliases.insert(name.to_string(), (*field, 0));
+            }
+        }
+
+        Ok(KvParser { aliases })
+    }
+
+    /// The event field `key` is an alias of.
+    pub(super) fn field(&self, key: &str) -> Option<&'static str> {
+        self.aliases.get(key).map(|(field, _)| *field)
+    }
+
+    pub fn parse_line(&self, line: &str, min_fields: usize) -> Result<FlowEvent, SkipReason> {
+        let pairs = quoted_pairs(line, '=');
+        if pairs.is_empty() {
+            return Err(SkipReason::Unrecognized);
+        }
+        if pairs.len() < min_fields {
+            return Err(SkipReason::ShortLine);
+        }
+
+        let mut chosen: HashMap<&str, (usize, &str)> = HashMap::new();
+        for (key, value) in pairs {
+            let Some((field, rank)) = self.aliases.get(key) else {
+                continue;
+            };
+            match chosen.get(field) {
+                Some((best, _)) if best <= rank => {}
+                _ => {
+                    chosen.insert(field, (*rank, value));
+                }
+            }
+        }
+
+        let mut event = FlowEvent::default();
+        for (field, (_, value)) in &chosen {
+            assign(&mut event, field, value)?;
+        }
+        let counters = chosen.contains_key("bytes_in") || chosen.contains_key("bytes_out");
+
+        // Lines without byte counters are usually non-traffic events that
+        // happen to mention an address
+        if event.source_ip.is_empty() || event.destination_ip.is_empty() {
+            return Err(SkipReason::MissingField);
+        }
+        if !counters {
+            return Err(SkipReason::EmptyCounters);
+        }
+        Ok(event)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const COUNTERS: &str = "sentbyte=100 rcvdbyte=200";
+
+    #[test]
+    fn the_best_ranked_alias_wins() {
+        let cases: &[(&[&str], &str, &str)] = &[
+            (&[], "src=10.0.0.2 srcip=10.0.0.1", "10.0.0.1"),
+            (&[], "srcip=10.0.0.1 src=10.0.0.2", "10.0.0.1"),
+            (&[], "source_ip=10.0.0.1 srcip=10.0.0.2", "10.0.0.1"),
+            (&["source_ip=origin"], "source_ip=10.0.0.2 origin=10.0.0.1", "10.0.0.1"),
+            (&["source_ip=origin, , client"], "src=10.0.0.2 client=10.0.0.1", "10.0.0.1"),
+        ];
+        for (extra, pairs, source_ip) in cases {
+            let extra: Vec<String> = extra.iter().map(|alias| alias.to_string()).collect();
+            let parser = KvParser::new(&extra).unwrap();
+            let line = format!("{} dstip=10.0.9.9 {}", pairs, COUNTERS);
+            let event = parser.parse_line(&line, 0).unwrap();
+            assert_eq!(event.source_ip, *source_ip, "{}", line);
+        }
+    }
+
+    #[test]
+    fn lines_without_a_flow_are_skipped() {
+        let parser = KvParser::new(&[]).unwrap();
+        let cases: &[(&str, usize, SkipReason)] = &[
+            ("kernel: device eth0 entered promiscuous mode", 0, SkipReason::Unrecognized),
+            ("srcip=10.0.0.1 dstip=10.0.0.2 sentbyte=1", 4, SkipReason::ShortLine),
+            ("srcip=10.0.0.1 sentbyte=1", 0, SkipReason::MissingField),
+            ("srcip=10.0.0.1 dstip=10.0.0.2 action=accept", 0, SkipReason::EmptyCounters),
+            ("srcip=10.0.0.1 dstip=10.0.0.2 sentbyte=lots", 0, SkipReason::ParseFailure),
+        ];
+        for (line, min_fields, reason) in cases {
+            assert_eq!(parser.parse_line(line, *min_fields).err(), Some(*reason), "{}", line);
+        }
+    }
+
+    #[test]
+    fn quoted_values_are_unquoted() {
+        let parser = KvParser::new(&[]).unwrap();
+        let event = parser
+            .parse_line(r#"devname="fw 1" srcip=10.0.0.1 dstip=10.0.0.2 dstport=443 proto=6 app="Web Browsing" sentbyte="100""#, 0)
+            .unwrap();
+        assert_eq!(event.firewall, "fw 1");
+        assert_eq!(event.application.as_deref(), Some("Web Browsing"));
+        assert_eq!((event.destination_port.as_str(), event.bytes_out), ("443", 100));
+    }
+
+    #[test]
+    fn bad_aliases_are_refused() {
+        for extra in ["source_ip", "nonsense=src"] {
+            assert!(KvParser::new(&[extra.to_string()]).is_err(), "{}", extra);
+        }
+    }
+}
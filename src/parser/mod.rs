@@ -0,0 +1,547 @@
+mod checkpoint;
+mod csv;
+mod filterlog;
+mod icmp;
+mod kv;
+mod pattern;
+mod srx;
+
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{self, BufRead};
+use std::sync::Arc;
+
+use chrono::{DateTime, FixedOffset};
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+
+use crate::lines::{self, DEFAULT_MAX_LINE, Encoding, Line, LineReader};
+
+/// Whether a line describes a whole session or only one end of it.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum EventKind {
+    /// Carries the final counters of a session (the only kind most formats emit)
+    #[default]
+    Complete,
+    /// Session start without counters, to be matched with a later close
+    Open,
+    /// Session end with counters, matched to its open by session id
+    Close,
+}
+
+/// Policy decision the firewall logged for a session.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    Allow,
+    Deny,
+}
+
+impl Action {
+    /// Map vendor action words (`pass`, `accept`, `block`, `drop`, ...) onto
+    /// allow/deny.
+    pub fn from_vendor(value: &str) -> Option<Action> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "pass" | "allow" | "accept" | "permit" | "allowed" => Some(Action::Allow),
+            "block" | "deny" | "drop" | "reject" | "denied" | "blocked" => Some(Action::Deny),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Action::Allow => "allow",
+            Action::Deny => "deny",
+        }
+    }
+}
+
+/// A single connection event extracted from one log line, independent of the
+/// vendor format it came from.
+#[derive(Debug, Clone, Default)]
+pub struct FlowEvent {
+    pub kind: EventKind,
+    pub timestamp: Option<DateTime<FixedOffset>>,
+    pub session_id: Option<String>,
+    /// Session duration as reported by the device or derived by correlation
+    pub duration_ms: Option<u64>,
+    pub firewall: String,
+    pub source_ip: String,
+    pub destination_ip: String,
+    /// Only logged by some formats; the flow key leaves it out
+    pub source_port: Option<String>,
+    pub destination_port: String,
+    pub protocol: String,
+    pub packets_in: u64,
+    pub bytes_in: u64,
+    pub packets_out: u64,
+    pub bytes_out: u64,
+    /// TCP flags as logged, e.g. `S`, `SA`, `FPA`
+    pub tcp_flags: Option<String>,
+    /// Event or message type as logged, e.g. `close` or `session-start`
+    pub event_type: Option<String>,
+    /// Why the session ended, e.g. `TCP FIN`, `TCP RST`, `idle Timeout`
+    pub end_reason: Option<String>,
+    pub action: Option<Action>,
+    /// Translated addresses and ports, when the device logs both sides of NAT
+    pub nat_source_ip: Option<String>,
+    pub nat_source_port: Option<String>,
+    pub nat_destination_ip: Option<String>,
+    pub nat_destination_port: Option<String>,
+    pub ingress_zone: Option<String>,
+    pub egress_zone: Option<String>,
+    pub ingress_interface: Option<String>,
+    pub egress_interface: Option<String>,
+    pub vlan: Option<String>,
+    /// Overlay the session was carried in: a GRE key, IPsec SPI or tunnel
+    /// name, VXLAN VNI, or the tunnel interface it crossed
+    pub tunnel: Option<String>,
+    /// Application identified by an NGFW (App-ID, application control)
+    pub application: Option<String>,
+    /// Authenticated user the session was attributed to
+    pub user: Option<String>,
+    /// Hardware addresses, normalised to lower-case colon form
+    pub source_mac: Option<String>,
+    pub destination_mac: Option<String>,
+    /// ICMP type and code, for ICMP and ICMPv6 events; these have no
+    /// destination port
+    pub icmp_type: Option<u8>,
+    pub icmp_code: Option<u8>,
+    /// A counter was over `--counter-limit` and kept as logged
+    pub over_limit: bool,
+    /// Input the line was read from, with `--record-sources`
+    pub source: Option<Arc<str>>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum InputFormat {
+    /// Comma-separated session-close export with fixed column positions
+    #[default]
+    Csv,
+    /// pfSense/OPNsense filterlog CSV
+    Filterlog,
+    /// Check Point LogExporter semicolon-delimited output
+    Checkpoint,
+    /// Juniper SRX RT_FLOW_SESSION_CLOSE structured-data messages
+    Srx,
+    /// Named capture groups of a user-supplied regex or Grok `--pattern`
+    Regex,
+    /// Generic `key=value` pairs, matched by field-name aliases
+    Kv,
+}
+
+/// Why a line didn't produce an event.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SkipReason {
+    /// Fewer fields than the format (or `--min-fields`) requires
+    ShortLine,
+    /// A counter column is missing or empty
+    EmptyCounters,
+    /// A numeric field doesn't parse
+    ParseFailure,
+    /// An address, protocol or device field the format requires is missing
+    MissingField,
+    /// Not a line of this format at all, e.g. another program's messages
+    Unrecognized,
+    /// Longer than `--max-line-length`; not read beyond the cap
+    TooLong,
+    /// Contains NUL bytes, e.g. a corrupted or preallocated file
+    Binary,
+    /// Not valid UTF-8 in a followed file read with `--input-encoding strict`
+    Undecodable,
+}
+
+/// Skipped lines per reason.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct SkipCounts {
+    pub total: u64,
+    pub short_line: u64,
+    pub empty_counters: u64,
+    pub parse_failure: u64,
+    pub missing_field: u64,
+    pub unrecognized: u64,
+    #[serde(default)]
+    pub too_long: u64,
+    #[serde(default)]
+    pub binary: u64,
+    #[serde(default)]
+    pub undecodable: u64,
+}
+
+impl SkipCounts {
+    pub fn add(&mut self, reason: SkipReason) {
+        self.total += 1;
+        *match reason {
+            SkipReason::ShortLine => &mut self.short_line,
+            SkipReason::EmptyCounters => &mut self.empty_counters,
+            SkipReason::ParseFailure => &mut self.parse_failure,
+            SkipReason::MissingField => &mut self.missing_field,
+            SkipReason::Unrecognized => &mut self.unrecognized,
+            SkipReason::TooLong => &mut self.too_long,
+            SkipReason::Binary => &mut self.binary,
+            SkipReason::Undecodable => &mut self.undecodable,
+        } += 1;
+    }
+
+    pub fn merge(&mut self, other: &SkipCounts) {
+        self.total += other.total;
+        self.short_line += other.short_line;
+        self.empty_counters += other.empty_counters;
+        self.parse_failure += other.parse_failure;
+        self.missing_field += other.missing_field;
+        self.unrecognized += other.unrecognized;
+        self.too_long += other.too_long;
+        self.binary += other.binary;
+        self.undecodable += other.undecodable;
+    }
+}
+
+/// Settings only some input formats use.
+#[derive(Debug, Default)]
+pub struct ParserOptions<'a> {
+    /// Regex or Grok pattern for the regex format
+    pub pattern: Option<&'a str>,
+    /// Extra `field=alias1,alias2` key aliases for the key=value format
+    pub kv_aliases: &'a [String],
+    /// Skip lines with fewer delimited columns (CSV, filterlog) or key/value
+    /// pairs (Check Point, SRX, key=value) than this; never below what the
+    /// format itself reads
+    pub min_fields: usize,
+}
+
+/// The configured input format, ready to parse lines.
+pub struct LineParser {
+    format: Format,
+    min_fields: usize,
+    /// Column names a CSV header is matched against
+    header_names: Option<kv::KvParser>,
+    /// Headers read so far, by the input they were read from
+    headers: HashMap<String, csv::Header>,
+    input: String,
+    /// The current input's header, if it had one
+    header: Option<csv::Header>,
+}
+
+enum Format {
+    Builtin(InputFormat),
+    Pattern(pattern::PatternParser),
+    Kv(kv::KvParser),
+}
+
+impl LineParser {
+    pub fn new(format: InputFormat, options: &ParserOptions) -> Result<Self, String> {
+        let format = match (format, options.pattern) {
+            (InputFormat::Regex, Some(pattern)) => Format::Pattern(pattern::PatternParser::new(pattern)?),
+            (InputFormat::Regex, None) => return Err("the regex input format needs a --pattern".to_string()),
+            (InputFormat::Kv, _) => Format::Kv(kv::KvParser::new(options.kv_aliases)?),
+            (format, _) => Format::Builtin(format),
+        };
+        let header_names = match format {
+            Format::Builtin(InputFormat::Csv) => Some(kv::KvParser::new(options.kv_aliases)?),
+            _ => None,
+        };
+        Ok(LineParser {
+            format,
+            min_fields: options.min_fields,
+            header_names,
+            headers: HashMap::new(),
+            input: String::new(),
+            header: None,
+        })
+    }
+
+    /// Name the input the following lines are read from, so a header read
+    /// from it earlier applies again, e.g. when a followed file grows.
+    pub fn set_input(&mut self, name: &str) {
+        if self.input != name {
+            self.input = name.to_string();
+            self.header = self.headers.get(name).cloned();
+        }
+    }
+
+    /// Take `line` as the current input's header if it is one, returning
+    /// whether it was; the input's lines are then read by the columns it
+    /// names. Only CSV has headers.
+    pub fn header(&mut self, line: &str) -> bool {
+        let Some(header) = self.header_names.as_ref().and_then(|names| csv::Header::parse(line, names)) else {
+            return false;
+        };
+        self.headers.insert(self.input.clone(), header.clone());
+        self.header = Some(header);
+        true
+    }
+
+    /// Parse one raw line, or say why it doesn't describe a connection with
+    /// a complete set of counters.
+    pub fn parse_line(&self, line: &str) -> Result<FlowEvent, SkipReason> {
+        let min_fields = self.min_fields;
+        let event = match &self.format {
+            Format::Builtin(InputFormat::Csv) => match &self.header {
+                Some(header) => header.parse_line(line, min_fields),
+                None => csv::parse_line(line, min_fields),
+            },
+            Format::Builtin(InputFormat::Filterlog) => filterlog::parse_line(line, min_fields),
+            Format::Builtin(InputFormat::Checkpoint) => checkpoint::parse_line(line, min_fields),
+            Format::Builtin(InputFormat::Srx) => srx::parse_line(line, min_fields),
+            Format::Builtin(InputFormat::Regex | InputFormat::Kv) => Err(SkipReason::Unrecognized),
+            Format::Pattern(pattern) => pattern.parse_line(line),
+            Format::Kv(kv) => kv.parse_line(line, min_fields),
+        };
+        event.map(icmp::normalize).map(|mut event| {
+            if event.tunnel.is_none() {
+                let interfaces = [&event.ingress_interface, &event.egress_interface];
+                event.tunnel = interfaces.into_iter().flatten().find(|interface| is_tunnel_interface(interface)).cloned();
+            }
+            event
+        })
+    }
+}
+
+impl LineParser {
+    /// Parse every line of `reader`, yielding the events and, as errors,
+    /// the lines that didn't make one. Invalid UTF-8 is replaced with
+    /// U+FFFD; lines over [`DEFAULT_MAX_LINE`] bytes are skipped unread.
+    /// A CSV header line yields nothing.
+    pub fn into_events<R: BufRead>(mut self, mut reader: R) -> impl Iterator<Item = Result<FlowEvent, ParseError>> {
+        let mut lines = LineReader::new(DEFAULT_MAX_LINE);
+        let mut index = 0;
+        let mut failed = false;
+        // Yields `Some(None)` for a header line, which `flatten` drops
+        std::iter::from_fn(move || {
+            if failed {
+                return None;
+            }
+            let line = match lines.next(&mut reader) {
+                Ok(Some(line)) => line,
+                Ok(None) => lines.finish()?,
+                Err(err) => {
+                    failed = true;
+                    return Some(Some(Err(ParseError::Io(err))));
+                }
+            };
+            index += 1;
+            let parsed = match line {
+                Line::Text(line) => match lines::decode(line, Encoding::Utf8) {
+                    Ok((line, _)) if self.header(&line) => return Some(None),
+                    Ok((line, _)) => self.parse_line(&line),
+                    Err(err) => return Some(Some(Err(ParseError::Io(io::Error::new(io::ErrorKind::InvalidData, err))))),
+                },
+                Line::TooLong => Err(SkipReason::TooLong),
+                Line::Binary => Err(SkipReason::Binary),
+            };
+            Some(Some(parsed.map_err(|reason| ParseError::Skipped { line: index, reason })))
+        })
+        .flatten()
+    }
+}
+
+/// Why [`parse_events`] yielded no event.
+#[derive(Debug)]
+pub enum ParseError {
+    /// The format can't be used without further settings, e.g. regex needs
+    /// a pattern; nothing else is yielded after this
+    Format(String),
+    /// Reading the input failed
+    Io(io::Error),
+    /// Line `line` (1-based) isn't a complete connection of the format
+    Skipped { line: u64, reason: SkipReason },
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseError::Format(message) => f.write_str(message),
+            ParseError::Io(err) => write!(f, "unable to read input: {}", err),
+            ParseError::Skipped { line, reason } => write!(f, "line {} skipped: {:?}", line, reason),
+        }
+    }
+}
+
+impl std::error::Error for ParseError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ParseError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+/// Parse every line of `reader` as `format` with the default options,
+/// without any aggregation, for consumers that build their own. Formats
+/// that need settings, like regex, only yield a [`ParseError::Format`].
+///
+/// ```no_run
+/// use std::fs::File;
+/// use std::io::BufReader;
+/// use syslog_processor::{InputFormat, parse_events};
+///
+/// let file = BufReader::new(File::open("syslog/fw1.log").unwrap());
+/// let telnet = parse_events(file, InputFormat::Csv)
+///     .filter_map(Result::ok)
+///     .filter(|event| event.destination_port == "23")
+///     .count();
+/// println!("{} telnet sessions", telnet);
+/// ```
+pub fn parse_events<'a, R: BufRead + 'a>(reader: R, format: InputFormat) -> impl Iterator<Item = Result<FlowEvent, ParseError>> + 'a {
+    let events: Box<dyn Iterator<Item = Result<FlowEvent, ParseError>> + 'a> = match LineParser::new(format, &ParserOptions::default()) {
+        Ok(parser) => Box::new(parser.into_events(reader)),
+        Err(message) => Box::new(std::iter::once(Err(ParseError::Format(message)))),
+    };
+    events
+}
+
+/// Treat empty or placeholder columns as absent.
+fn non_empty(value: Option<&str>) -> Option<String> {
+    value
+        .map(str::trim)
+        .filter(|v| !v.is_empty() && *v != "-" && *v != "N/A")
+        .map(str::to_string)
+}
+
+/// A counter that must be present: missing or empty values and malformed
+/// numbers are told apart for skip accounting.
+fn counter(value: Option<&str>) -> Result<u64, SkipReason> {
+    match value.map(str::trim) {
+        None | Some("") => Err(SkipReason::EmptyCounters),
+        Some(value) => value.parse().map_err(|_| SkipReason::ParseFailure),
+    }
+}
+
+fn required(value: Option<&str>) -> Result<String, SkipReason> {
+    value.map(str::to_string).ok_or(SkipReason::MissingField)
+}
+
+/// VLAN id from a sub-interface name such as `igb0.100` or `ge-0/0/1.20`.
+fn vlan_from_interface(interface: &str) -> Option<String> {
+    let (_, unit) = interface.rsplit_once('.')?;
+    (!unit.is_empty() && unit != "0" && unit.bytes().all(|b| b.is_ascii_digit())).then(|| unit.to_string())
+}
+
+/// Whether an interface name is a tunnel's: `gre0`, `ipsec1`, `st0`,
+/// `st0.2`, `vxlan100`, `wg0`, ...
+fn is_tunnel_interface(interface: &str) -> bool {
+    const PREFIXES: &[&str] = &["gre", "gif", "ipsec", "enc", "vti", "st0", "tun", "vxlan", "ovpn", "wg"];
+    let name = interface.to_ascii_lowercase();
+    PREFIXES
+        .iter()
+        .any(|prefix| name.strip_prefix(prefix).is_some_and(|rest| rest.is_empty() || rest.starts_with(|c: char| c.is_ascii_digit() || c == '.')))
+}
+
+/// A MAC address in colon, dash, Cisco dotted (`aabb.ccdd.eeff`) or bare hex
+/// form, as lower-case `aa:bb:cc:dd:ee:ff`.
+fn mac_address(value: &str) -> Option<String> {
+    let digits: String = value.trim().chars().filter(|c| !matches!(c, ':' | '-' | '.')).collect();
+    if digits.len() != 12 || !digits.bytes().all(|b| b.is_ascii_hexdigit()) || digits.bytes().all(|b| b == b'0') {
+        return None;
+    }
+    let digits = digits.to_ascii_lowercase();
+    Some((0..6).map(|i| &digits[i * 2..i * 2 + 2]).collect::<Vec<_>>().join(":"))
+}
+
+/// Parse an RFC 3339 timestamp, as used by ISO-dated exports and RFC 5424
+/// headers.
+fn parse_timestamp(value: &str) -> Option<DateTime<FixedOffset>> {
+    DateTime::parse_from_rfc3339(value.trim()).ok()
+}
+
+/// Split `key<sep>value` pairs separated by whitespace or semicolons, where
+/// values may be double-quoted. Tokens without a separator are ignored.
+fn quoted_pairs(body: &str, sep: char) -> HashMap<&str, &str> {
+    let mut fields = HashMap::new();
+    let is_delim = |c: char| c.is_whitespace() || c == ';';
+    let mut rest = body;
+
+    loop {
+        rest = rest.trim_start_matches(is_delim);
+        if rest.is_empty() {
+            break;
+        }
+
+        let key_end = rest.find(|c: char| c == sep || is_delim(c)).unwrap_or(rest.len());
+        let key = &rest[..key_end];
+        rest = &rest[key_end..];
+
+        let Some(after_sep) = rest.strip_prefix(sep) else {
+            continue;
+        };
+
+        let value;
+        if let Some(quoted) = after_sep.strip_prefix('"') {
+            let mut end = quoted.len();
+            let mut escaped = false;
+            for (i, c) in quoted.char_indices() {
+                match c {
+                    '\\' if !escaped => escaped = true,
+                    '"' if !escaped => {
+                        end = i;
+                        break;
+                    }
+                    _ => escaped = false,
+                }
+            }
+            value = &quoted[..end];
+            rest = quoted.get(end + 1..).unwrap_or_default();
+        } else {
+            let end = after_sep.find(is_delim).unwrap_or(after_sep.len());
+            value = &after_sep[..end];
+            rest = &after_sep[end..];
+        }
+
+        if !key.is_empty() {
+            fields.insert(key, value);
+        }
+    }
+
+    fields
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quoted_pairs_split_keys_and_values() {
+        let cases: &[(&str, &[(&str, &str)])] = &[
+            ("", &[]),
+            ("src=10.0.0.1 dst=10.0.0.2", &[("src", "10.0.0.1"), ("dst", "10.0.0.2")]),
+            ("msg=\"two words\" src=10.0.0.1", &[("msg", "two words"), ("src", "10.0.0.1")]),
+            // Escapes are kept as logged; only the quotes around the value go
+            (r#"msg="say \"hi\"" x=1"#, &[("msg", r#"say \"hi\""#), ("x", "1")]),
+            (r#"path="C:\\" x=1"#, &[("path", r"C:\\"), ("x", "1")]),
+            ("msg=\"never closed src=10.0.0.1", &[("msg", "never closed src=10.0.0.1")]),
+            ("empty= src=10.0.0.1", &[("empty", ""), ("src", "10.0.0.1")]),
+            ("=orphan src=10.0.0.1", &[("src", "10.0.0.1")]),
+            ("<134>Jan 1 fw1 src=10.0.0.1", &[("src", "10.0.0.1")]),
+            ("a=1 a=2", &[("a", "2")]),
+        ];
+        for (body, expected) in cases {
+            let expected: HashMap<&str, &str> = expected.iter().copied().collect();
+            assert_eq!(quoted_pairs(body, '='), expected, "{}", body);
+        }
+        let semicolons = quoted_pairs("src:\"10.0.0.1\"; dst:\"10.0.0.2\";", ':');
+        assert_eq!(semicolons, HashMap::from([("src", "10.0.0.1"), ("dst", "10.0.0.2")]));
+    }
+
+    #[test]
+    fn tunnel_interfaces_are_recognized_by_prefix() {
+        let cases = [
+            ("gre0", true),
+            ("ipsec1", true),
+            ("st0", true),
+            ("st0.2", true),
+            ("ST0.5", true),
+            ("vxlan100", true),
+            ("wg0", true),
+            ("tun", true),
+            ("ge-0/0/0.0", false),
+            ("eth0", false),
+            ("stack1", false),
+            ("tunnel-mgmt", false),
+            ("wghost", false),
+            ("", false),
+        ];
+        for (interface, expected) in cases {
+            assert_eq!(is_tunnel_interface(interface), expected, "{}", interface);
+        }
+    }
+}
@@ -0,0 +1,216 @@
+//! User-supplied regular expression or Grok pattern.
+//!
+//! Named capture groups are mapped onto event fields by name, e.g.
+//! `(?P<source_ip>\S+)` or `%{IP:source_ip}`. Groups that didn't participate
+//! in a match are left unset, counters default to zero, and a line only
+//! yields an event when it matches and carries both addresses.
+
+use regex::Regex;
+
+use super::{Action, FlowEvent, SkipReason, icmp, mac_address, non_empty, parse_timestamp, vlan_from_interface};
+
+/// Event fields a capture group may be named after.
+pub const FIELDS: &[&str] = &[
+    "timestamp", "session_id", "duration_ms", "firewall", "source_ip", "destination_ip", "source_port",
+    "destination_port", "protocol", "packets_in", "bytes_in", "packets_out", "bytes_out", "tcp_flags", "event_type", "end_reason", "action",
+    "nat_source_ip", "nat_source_port", "nat_destination_ip", "nat_destination_port", "ingress_zone",
+    "egress_zone", "ingress_interface", "egress_interface", "vlan", "tunnel", "application", "user",
+    "source_mac", "destination_mac", "icmp_type", "icmp_code",
+];
+
+/// Built-in Grok patterns, a subset of the Logstash core set.
+const GROK: &[(&str, &str)] = &[
+    ("INT", r"[+-]?[0-9]+"),
+    ("POSINT", r"[1-9][0-9]*"),
+    ("NONNEGINT", r"[0-9]+"),
+    ("NUMBER", r"[+-]?[0-9]+(?:\.[0-9]+)?"),
+    ("WORD", r"\w+"),
+    ("NOTSPACE", r"\S+"),
+    ("SPACE", r"\s*"),
+    ("DATA", r".*?"),
+    ("GREEDYDATA", r".*"),
+    ("QUOTEDSTRING", r#""(?:[^"\\]|\\.)*""#),
+    ("IPV4", r"(?:[0-9]{1,3}\.){3}[0-9]{1,3}"),
+    ("IPV6", r"[0-9A-Fa-f]{0,4}(?::[0-9A-Fa-f]{0,4}){2,7}(?:%\w+)?"),
+    ("IP", r"%{IPV6}|%{IPV4}"),
+    ("HOSTNAME", r"[0-9A-Za-z][0-9A-Za-z_-]*(?:\.[0-9A-Za-z][0-9A-Za-z_-]*)*"),
+    ("IPORHOST", r"%{IP}|%{HOSTNAME}"),
+    ("TIMESTAMP_ISO8601", r"[0-9]{4}-[0-9]{2}-[0-9]{2}[T ][0-9]{2}:[0-9]{2}:[0-9]{2}(?:\.[0-9]+)?(?:Z|[+-][0-9]{2}:?[0-9]{2})?"),
+];
+
+pub struct PatternParser {
+    regex: Regex,
+    groups: Vec<(usize, &'static str)>,
+}
+
+impl PatternParser {
+    /// Compile `pattern`, expanding any `%{NAME}` / `%{NAME:field}` Grok
+    /// references first.
+    pub fn new(pattern: &str) -> Result<Self, String> {
+        let regex = Regex::new(&expand_grok(pattern, 0)?).map_err(|err| err.to_string())?;
+
+        let mut groups = Vec::new();
+        for (index, name) in regex.capture_names().enumerate() {
+            let Some(name) = name else {
+                continue;
+            };
+            let field = FIELDS
+                .iter()
+                .find(|field| **field == name)
+                .ok_or_else(|| format!("capture group `{}` is not an event field (expected one of: {})", name, FIELDS.join(", ")))?;
+            groups.push((index, *field));
+        }
+        for required in ["source_ip", "destination_ip"] {
+            if !groups.iter().any(|(_, field)| *field == required) {
+                return Err(format!("pattern has no `{}` capture group", required));
+            }
+        }
+
+        Ok(PatternParser { regex, groups })
+    }
+
+    pub fn parse_line(&self, line: &str) -> Result<FlowEvent, SkipReason> {
+        let captures = self.regex.captures(line.trim_end()).ok_or(SkipReason::Unrecognized)?;
+        let mut event = FlowEvent::default();
+        for (index, field) in &self.groups {
+            if let Some(value) = captures.get(*index) {
+                assign(&mut event, field, value.as_str())?;
+            }
+        }
+        if event.source_ip.is_empty() || event.destination_ip.is_empty() {
+            return Err(SkipReason::MissingField);
+        }
+        Ok(event)
+    }
+}
+
+/// Set one event field from its textual value, failing when a numeric field
+/// doesn't parse.
+pub(super) fn assign(event: &mut FlowEvent, field: &str, value: &str) -> Result<(), SkipReason> {
+    let value = value.trim().trim_matches('"');
+    let counter = || value.parse::<u64>().map_err(|_| SkipReason::ParseFailure);
+    match field {
+        "timestamp" => event.timestamp = parse_timestamp(value),
+        "session_id" => event.session_id = non_empty(Some(value)),
+        "duration_ms" => event.duration_ms = Some(counter()?),
+        "firewall" => event.firewall = value.to_string(),
+        "source_ip" => event.source_ip = value.to_string(),
+        "destination_ip" => event.destination_ip = value.to_string(),
+        "source_port" => event.source_port = non_empty(Some(value)),
+        "destination_port" => event.destination_port = value.to_string(),
+        "protocol" => event.protocol = value.to_string(),
+        "packets_in" => event.packets_in = counter()?,
+        "bytes_in" => event.bytes_in = counter()?,
+        "packets_out" => event.packets_out = counter()?,
+        "bytes_out" => event.bytes_out = counter()?,
+        "tcp_flags" => event.tcp_flags = non_empty(Some(value)),
+        "event_type" => event.event_type = non_empty(Some(value)),
+        "end_reason" => event.end_reason = non_empty(Some(value)),
+        "action" => event.action = Action::from_vendor(value),
+        "nat_source_ip" => event.nat_source_ip = non_empty(Some(value)),
+        "nat_source_port" => event.nat_source_port = non_empty(Some(value)),
+        "nat_destination_ip" => event.nat_destination_ip = non_empty(Some(value)),
+        "nat_destination_port" => event.nat_destination_port = non_empty(Some(value)),
+        "ingress_zone" => event.ingress_zone = non_empty(Some(value)),
+        "egress_zone" => event.egress_zone = non_empty(Some(value)),
+        "ingress_interface" => {
+            event.ingress_interface = non_empty(Some(value));
+            event.vlan = event.vlan.take().or_else(|| vlan_from_interface(value));
+        }
+        "egress_interface" => event.egress_interface = non_empty(Some(value)),
+        "vlan" => event.vlan = non_empty(Some(value)),
+        "tunnel" => event.tunnel = non_empty(Some(value)),
+        "application" => event.application = non_empty(Some(value)),
+        "user" => event.user = non_empty(Some(value)),
+        "source_mac" => event.source_mac = mac_address(value),
+        "destination_mac" => event.destination_mac = mac_address(value),
+        "icmp_type" => event.icmp_type = icmp::number(value),
+        "icmp_code" => event.icmp_code = icmp::number(value),
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Replace `%{NAME}` with a non-capturing group and `%{NAME:field}` with a
+/// named one, recursing into patterns that reference other patterns.
+fn expand_grok(pattern: &str, depth: usize) -> Result<String, String> {
+    if depth > 8 {
+        return Err("Grok patterns nest too deeply".to_string());
+    }
+
+    let mut out = String::with_capacity(pattern.len());
+    let mut rest = pattern;
+    while let Some(start) = rest.find("%{") {
+        out.push_str(&rest[..start]);
+        let end = rest[start..].find('}').ok_or("unterminated `%{` in pattern")? + start;
+        let reference = &rest[start + 2..end];
+        let (name, field) = match reference.split_once(':') {
+            Some((name, field)) => (name, Some(field)),
+            None => (reference, None),
+        };
+        let definition = GROK
+            .iter()
+            .find(|(known, _)| *known == name)
+            .map(|(_, definition)| *definition)
+            .ok_or_else(|| format!("unknown Grok pattern `{}`", name))?;
+        let expanded = expand_grok(definition, depth + 1)?;
+        match field {
+            Some(field) => out.push_str(&format!("(?P<{}>{})", field, expanded)),
+            None => out.push_str(&format!("(?:{})", expanded)),
+        }
+        rest = &rest[end + 1..];
+    }
+    out.push_str(rest);
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn grok_references_expand() {
+        let ipv4 = r"(?:[0-9]{1,3}\.){3}[0-9]{1,3}";
+        let cases: &[(&str, String)] = &[
+            ("no references", "no references".to_string()),
+            ("%{INT}", r"(?:[+-]?[0-9]+)".to_string()),
+            ("%{IPV4:source_ip}", format!("(?P<source_ip>{})", ipv4)),
+            ("port %{POSINT:destination_port}!", r"port (?P<destination_port>[1-9][0-9]*)!".to_string()),
+            // References inside a built-in pattern expand in turn
+            ("%{IP}", format!("(?:(?:{})|(?:{}))", GROK.iter().find(|(name, _)| *name == "IPV6").unwrap().1, ipv4)),
+        ];
+        for (pattern, expected) in cases {
+            assert_eq!(expand_grok(pattern, 0).as_ref(), Ok(expected), "{}", pattern);
+        }
+    }
+
+    #[test]
+    fn bad_grok_references_are_refused() {
+        for pattern in ["%{NOPE:source_ip}", "%{IPV4:source_ip", "%{IP:source_ip} %{WORD"] {
+            assert!(expand_grok(pattern, 0).is_err(), "{}", pattern);
+        }
+    }
+
+    #[test]
+    fn grok_patterns_parse_lines() {
+        let parser = PatternParser::new(
+            r"%{TIMESTAMP_ISO8601:timestamp} %{HOSTNAME:firewall} %{IP:source_ip} -> %{IP:destination_ip}:%{POSINT:destination_port} %{WORD:action} %{NONNEGINT:bytes_out}",
+        )
+        .unwrap();
+        let event = parser.parse_line("2024-05-01T10:00:00Z fw-1 10.0.0.1 -> 2001:db8::1:443 permit 1500\n").unwrap();
+        assert_eq!(event.firewall, "fw-1");
+        assert_eq!((event.source_ip.as_str(), event.destination_ip.as_str()), ("10.0.0.1", "2001:db8::1"));
+        assert_eq!(event.destination_port, "443");
+        assert_eq!(event.action, Some(Action::Allow));
+        assert_eq!(event.bytes_out, 1500);
+        assert!(event.timestamp.is_some());
+        assert_eq!(parser.parse_line("something else entirely").err(), Some(SkipReason::Unrecognized));
+    }
+
+    #[test]
+    fn patterns_need_known_fields_and_both_addresses() {
+        for pattern in [r"(?P<source>\S+) (?P<destination_ip>\S+)", r"%{IP:source_ip} %{NUMBER:bytes_in}", r"(?P<source_ip>\S+) ("] {
+            assert!(PatternParser::new(pattern).is_err(), "{}", pattern);
+        }
+    }
+}
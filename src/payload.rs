@@ -0,0 +1,360 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize, Serializer};
+
+use crate::aggregate::Approximation;
+use crate::bounds::CounterBounds;
+use crate::classify;
+use crate::detect::beacon::SuspectedBeacon;
+use crate::detect::exfil::OutboundFlow;
+use crate::detect::flood::SuspectedFlood;
+use crate::detect::scan::SuspectedScan;
+use crate::direction::DirectionStats;
+use crate::distinct::Cardinality;
+use crate::enrich::blocklist::{self, IndicatorSummary};
+use crate::flush::Partial;
+use crate::hourly::HourBucket;
+use crate::ignore::Ignored;
+use crate::lineage;
+use crate::parser::SkipCounts;
+use crate::record::Record;
+use crate::query::TopK;
+use crate::resources;
+use crate::rules::RuleAlert;
+use crate::session::CorrelationStats;
+use crate::sink::SinkStatus;
+use crate::space;
+use crate::summary::{self, Totals};
+use crate::telemetry::StageTimes;
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct Metadata {
+    /// Set on intermediate outputs written with `--flush-every`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub partial: Option<Partial>,
+    /// Random ID of the run that wrote the output
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub run_id: String,
+    /// IDs of the runs whose outputs this one was seeded or merged from
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub parent_runs: Vec<String>,
+    pub start_time: u128,
+    pub end_time: u128,
+    pub elapsed_time: f64,
+    pub total_connections: u64,
+    pub session_close: String,
+    pub flows: usize,
+    /// Static labels of the run, from `--label`; also on every record
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub labels: BTreeMap<String, String>,
+    pub files_processed: Vec<String>,
+    /// Lines that didn't produce an event, by reason
+    pub skipped_lines: SkipCounts,
+    /// Inputs set aside for having too many skipped lines
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub quarantined_files: Vec<String>,
+    /// Inputs that couldn't be read, in full or from some point on
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub failed_files: Vec<FailedFile>,
+    /// Inputs skipped because an identical file was already read this run
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub duplicate_files: Vec<DuplicateFile>,
+    /// Inputs skipped because the `--seed` output or the resumed journal
+    /// already counted a file of the same size and content
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub already_counted: Vec<DuplicateFile>,
+    /// Traffic to or from blocklisted addresses, per indicator
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub matched_indicators: Vec<IndicatorSummary>,
+    /// Set when the run hit `--max-flows` and only the busiest flows are exact
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub approximation: Option<Approximation>,
+    /// Set when counters went over `--counter-limit`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub counter_bounds: Option<CounterBounds>,
+    /// What counted towards `sessionClose`, per `--session-close`
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub session_close_rule: String,
+    /// How events were oriented, with `--normalize-direction`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub flow_direction: Option<DirectionStats>,
+    /// Flows matching an `--ignore` pattern, summed instead of kept in `data`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ignored: Option<Ignored>,
+    /// Flows left out of `data` for being under `--min-bytes` or
+    /// `--min-count`; every other figure still includes them
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub below_minimum: Option<Totals>,
+    /// The flow limit and the others' totals, with `--top-k`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub top_k: Option<TopK>,
+    /// Hosts with the most distinct ports and peers, with `--distinct-counts`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub distinct_counts: Option<Cardinality>,
+    /// Totals per internal/external direction, with `--classify`
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub traffic_classes: BTreeMap<String, Totals>,
+    /// Sources that look like port scans or host sweeps, with `--detect-scans`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub suspected_scans: Vec<SuspectedScan>,
+    /// Destinations that look flooded, with `--detect-floods`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub suspected_floods: Vec<SuspectedFlood>,
+    /// Periodic low-volume flows to external destinations, with `--detect-beacons`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub suspected_beacons: Vec<SuspectedBeacon>,
+    /// Lopsided outbound flows to external destinations, with `--detect-exfil`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub exfiltration_watchlist: Vec<OutboundFlow>,
+    /// Rules with the alert action that matched flows
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub alerts: Vec<RuleAlert>,
+    /// Outcome of each configured sink
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub sinks: Vec<SinkStatus>,
+    pub processing_performance: ProcessingPerformance,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub session_correlation: Option<CorrelationStats>,
+    /// Totals per destination port
+    pub port_breakdown: BTreeMap<String, Totals>,
+    /// Totals per IP protocol number
+    pub protocol_breakdown: BTreeMap<String, Totals>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub hourly_series: Vec<HourBucket>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct ProcessingPerformance {
+    pub connections_per_second: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub peak_rss_bytes: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cpu_time_seconds: Option<f64>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub files: Vec<FileStats>,
+    /// Where the run's time went, with `--stage-timing` or a metrics exporter
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub stages: Vec<StageTiming>,
+}
+
+/// Time one stage of the run took.
+///
+/// Reading, parsing and aggregating alternate line by line, so they only
+/// have wall time; `ingest` is the three together, with its CPU time.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct StageTiming {
+    pub stage: String,
+    pub wall_seconds: f64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cpu_seconds: Option<f64>,
+}
+
+impl StageTiming {
+    pub fn new(stage: &str, wall: Duration, cpu_seconds: Option<f64>) -> Self {
+        StageTiming { stage: stage.to_string(), wall_seconds: wall.as_secs_f64(), cpu_seconds }
+    }
+
+    /// The stage that started at `started`, with `cpu` the process's CPU
+    /// time then.
+    pub fn since(stage: &str, started: Instant, cpu: Option<f64>) -> Self {
+        StageTiming::new(stage, started.elapsed(), resources::cpu_seconds_since(cpu))
+    }
+}
+
+/// How one input file's time split between the stages.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+#[serde(rename_all = "camelCase")]
+pub struct FileStages {
+    pub read_seconds: f64,
+    pub parse_seconds: f64,
+    pub aggregate_seconds: f64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cpu_seconds: Option<f64>,
+}
+
+impl FileStages {
+    pub fn new(times: &StageTimes, cpu_seconds: Option<f64>) -> Self {
+        FileStages {
+            read_seconds: times.read.as_secs_f64(),
+            parse_seconds: times.parse.as_secs_f64(),
+            aggregate_seconds: times.aggregate.as_secs_f64(),
+            cpu_seconds,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct DuplicateFile {
+    pub file: String,
+    pub duplicate_of: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct FailedFile {
+    pub file: String,
+    /// Lines read before the failure, which are in the totals
+    pub lines: u64,
+    pub error: String,
+}
+
+/// How long one input file took and how much of it was usable.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct FileStats {
+    pub file: String,
+    pub lines: u64,
+    pub skipped: u64,
+    /// Characters that weren't valid UTF-8, replaced or decoded as latin-1
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub replaced_characters: u64,
+    pub duration_seconds: f64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stages: Option<FileStages>,
+    /// Size and SHA-256 of a file read in full, by which a run seeded from
+    /// this output recognizes it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub size: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sha256: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Payload {
+    pub metadata: Metadata,
+    pub data: HashMap<Arc<str>, Record>,
+}
+
+/// Records serialized as a map by their keys, the layout of
+/// [`Payload::data`].
+pub struct ByKey<'a>(pub &'a [&'a Record]);
+
+impl Serialize for ByKey<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_map(self.0.iter().map(|record| (&record.key, *record)))
+    }
+}
+
+/// Serialize `metadata` and `records` as a payload in `format`, as
+/// [`Payload::write_to`] does.
+pub fn write_records<W: Write>(mut out: W, metadata: &Metadata, records: &[&Record], format: OutputFormat, pretty: bool) -> io::Result<()> {
+    #[derive(Serialize)]
+    struct Document<'a> {
+        metadata: &'a Metadata,
+        data: ByKey<'a>,
+    }
+    let document = Document { metadata, data: ByKey(records) };
+    match format {
+        OutputFormat::Json if pretty => serde_json::to_writer_pretty(&mut out, &document)?,
+        OutputFormat::Json => serde_json::to_writer(&mut out, &document)?,
+        OutputFormat::Ndjson => {
+            #[derive(Serialize)]
+            struct Header<'a> {
+                metadata: &'a Metadata,
+            }
+            serde_json::to_writer(&mut out, &Header { metadata })?;
+            out.write_all(b"\n")?;
+            for record in records {
+                serde_json::to_writer(&mut out, record)?;
+                out.write_all(b"\n")?;
+            }
+        }
+    }
+    out.flush()
+}
+
+/// Layout of the written payload.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    /// A single `{"metadata": ..., "data": {...}}` document
+    #[default]
+    Json,
+    /// A `{"metadata": ...}` line followed by one record per line
+    Ndjson,
+}
+
+impl Payload {
+    /// A payload for records that weren't just aggregated by a run, e.g.
+    /// exported state, with only the metadata the records themselves give.
+    pub fn describing(data: HashMap<Arc<str>, Record>) -> Payload {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_millis();
+        let sessions: u64 = data.values().map(|record| record.count).sum();
+        let metadata = Metadata {
+            run_id: lineage::new_run_id(),
+            start_time: now,
+            end_time: now,
+            total_connections: sessions,
+            session_close: format!("{} (100.00% of total connections)", sessions),
+            flows: data.len(),
+            port_breakdown: summary::group_by(data.values(), |record| &record.destination_port),
+            protocol_breakdown: summary::group_by(data.values(), |record| &record.protocol),
+            matched_indicators: blocklist::summarize(data.values()),
+            traffic_classes: classify::totals(data.values()),
+            ..Default::default()
+        };
+        Payload { metadata, data }
+    }
+
+    /// Serialize in `format`; `pretty` only affects the JSON document layout.
+    /// Records are streamed to `out` one at a time, so no serialized copy of
+    /// the payload is ever held in memory.
+    pub fn write_to<W: Write>(&self, out: W, format: OutputFormat, pretty: bool) -> io::Result<()> {
+        let records: Vec<&Record> = self.data.values().collect();
+        write_records(out, &self.metadata, &records, format, pretty)
+    }
+
+    /// Bytes [`write_to`] would write, found by serializing to a counter.
+    ///
+    /// [`write_to`]: Payload::write_to
+    pub fn size(&self, format: OutputFormat, pretty: bool) -> u64 {
+        let mut counter = space::Counter::default();
+        // Counting can't fail; a serialization error surfaces when writing
+        let _ = self.write_to(&mut counter, format, pretty);
+        counter.0
+    }
+
+    /// Read back an output in either layout.
+    pub fn load(path: &Path) -> io::Result<Payload> {
+        let mut reader = BufReader::new(File::open(path)?);
+        let mut first = String::new();
+        reader.read_line(&mut first)?;
+
+        // NDJSON opens with a complete `{"metadata": ...}` line; a pretty
+        // JSON document's first line is just `{`
+        #[derive(Deserialize)]
+        #[serde(deny_unknown_fields)]
+        struct Header {
+            metadata: Metadata,
+        }
+        if let Ok(header) = serde_json::from_str::<Header>(&first) {
+            let mut data = HashMap::new();
+            for line in reader.lines() {
+                let line = line?;
+                if line.trim().is_empty() {
+                    continue;
+                }
+                let record: Record = serde_json::from_str(&line)?;
+                data.insert(Arc::clone(&record.key), record);
+            }
+            return Ok(Payload { metadata: header.metadata, data });
+        }
+
+        let mut rest = first;
+        reader.read_to_string(&mut rest)?;
+        Ok(serde_json::from_str(&rest)?)
+    }
+}
+
+fn is_zero(value: &u64) -> bool {
+    *value == 0
+}
@@ -0,0 +1,109 @@
+//! Dropping received syslog messages by their header before parsing.
+//!
+//! A listening port often gets more than firewall traffic logs: the
+//! device's own daemons, cron, authentication messages. With a filter,
+//! messages are kept only when their `<PRI>` facility and severity and
+//! their program name (the RFC 3164 tag or RFC 5424 APP-NAME) match, so the
+//! rest neither costs a parse nor shows up as skipped lines. A message
+//! without a header can't be judged and is kept.
+
+use clap::ValueEnum;
+
+/// Syslog severities, most severe first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+pub enum Severity {
+    Emerg,
+    Alert,
+    Crit,
+    Err,
+    Warning,
+    Notice,
+    Info,
+    Debug,
+}
+
+/// Facility names, by code.
+const FACILITIES: &[&str] = &[
+    "kern", "user", "mail", "daemon", "auth", "syslog", "lpr", "news", "uucp", "cron", "authpriv", "ftp", "ntp", "security",
+    "console", "solaris-cron", "local0", "local1", "local2", "local3", "local4", "local5", "local6", "local7",
+];
+
+/// A facility as a name like `local7` or its code.
+pub fn parse_facility(value: &str) -> Result<u8, String> {
+    let value = value.trim().to_ascii_lowercase();
+    if let Some(code) = FACILITIES.iter().position(|name| *name == value) {
+        return Ok(code as u8);
+    }
+    match value.parse::<u8>() {
+        Ok(code) if (code as usize) < FACILITIES.len() => Ok(code),
+        _ => Err(format!("expected a facility name ({}) or code 0-23, got `{}`", FACILITIES.join(", "), value)),
+    }
+}
+
+/// Which received messages are parsed. Empty lists and no severity keep
+/// everything.
+#[derive(Debug, Clone, Default)]
+pub struct Prefilter {
+    pub facilities: Vec<u8>,
+    /// Least severe level kept
+    pub severity: Option<Severity>,
+    pub programs: Vec<String>,
+}
+
+impl Prefilter {
+    pub fn is_empty(&self) -> bool {
+        self.facilities.is_empty() && self.severity.is_none() && self.programs.is_empty()
+    }
+
+    /// Whether `message` should be parsed.
+    pub fn accepts(&self, message: &str) -> bool {
+        if self.is_empty() {
+            return true;
+        }
+        let Some((pri, rest)) = priority(message) else {
+            return true;
+        };
+        let (facility, severity) = (pri >> 3, pri & 7);
+        if !self.facilities.is_empty() && !self.facilities.contains(&facility) {
+            return false;
+        }
+        if let Some(least) = self.severity
+            && severity > least as u8
+        {
+            return false;
+        }
+        self.programs.is_empty() || program(rest).is_some_and(|program| self.programs.iter().any(|wanted| wanted == program))
+    }
+}
+
+/// The `<PRI>` value opening `message`, and what follows it.
+fn priority(message: &str) -> Option<(u8, &str)> {
+    let rest = message.trim_start().strip_prefix('<')?;
+    let (pri, rest) = rest.split_once('>')?;
+    if pri.is_empty() || pri.len() > 3 || !pri.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let pri: u8 = pri.parse().ok()?;
+    (pri < 192).then_some((pri, rest))
+}
+
+/// The program named by the header after `<PRI>`: RFC 5424's APP-NAME, or
+/// RFC 3164's tag, with or without a hostname before it.
+fn program(header: &str) -> Option<&str> {
+    let mut tokens = header.split_ascii_whitespace();
+    let first = tokens.next()?;
+    // RFC 5424: VERSION TIMESTAMP HOSTNAME APP-NAME ...
+    if first.bytes().all(|b| b.is_ascii_digit()) {
+        return tokens.nth(2).filter(|app| *app != "-");
+    }
+    // RFC 3164: [`Mmm dd hh:mm:ss` or an ISO timestamp] [HOSTNAME] TAG
+    let is_tag = |token: &str| token.ends_with(':') || token.contains('[');
+    let tokens: Vec<&str> = match first.len() == 3 && first.bytes().all(|b| b.is_ascii_alphabetic()) {
+        _ if is_tag(first) => vec![first],
+        true => tokens.skip(2).take(2).collect(),
+        false => tokens.take(2).collect(),
+    };
+    let tag = tokens.iter().find(|token| is_tag(token)).or(tokens.last())?;
+    let end = tag.find(['[', ':']).unwrap_or(tag.len());
+    Some(&tag[..end]).filter(|name| !name.is_empty())
+}
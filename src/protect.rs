@@ -0,0 +1,88 @@
+//! Optional signing and encryption of an output before it is shipped off the
+//! box.
+//!
+//! Signatures are raw 64-byte ed25519 detached signatures written to
+//! `<file>.sig`, checkable with e.g.
+//! `openssl pkeyutl -verify -rawin -pubin -inkey pub.pem -sigfile <file>.sig -in <file>`.
+//! Encryption writes an age file to `<file>.age` for the given `age1...`
+//! recipients. The plaintext output stays in place for the local report,
+//! graph and sinks; when both are requested the ciphertext is signed as well.
+
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Sign and/or encrypt `path`, returning the files that were written.
+pub fn apply(path: &Path, sign_key: Option<&Path>, recipients: &[String]) -> io::Result<Vec<PathBuf>> {
+    let mut written = Vec::new();
+
+    if !recipients.is_empty() {
+        let encrypted = with_suffix(path, "age");
+        encrypt(path, &encrypted, recipients)?;
+        written.push(encrypted);
+    }
+
+    if let Some(key) = sign_key {
+        let mut targets = vec![path.to_path_buf()];
+        targets.extend(written.iter().cloned());
+        for target in targets {
+            let signature = with_suffix(&target, "sig");
+            sign(&target, &signature, key)?;
+            written.push(signature);
+        }
+    }
+
+    Ok(written)
+}
+
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".");
+    name.push(suffix);
+    PathBuf::from(name)
+}
+
+fn invalid(err: impl std::fmt::Display) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, err.to_string())
+}
+
+/// The key is a PKCS#8 PEM file as produced by `openssl genpkey -algorithm ed25519`.
+#[cfg(feature = "sign")]
+fn sign(path: &Path, signature: &Path, key: &Path) -> io::Result<()> {
+    use ed25519_dalek::SigningKey;
+    use ed25519_dalek::Signer;
+    use ed25519_dalek::pkcs8::DecodePrivateKey;
+
+    let pem = std::fs::read_to_string(key)?;
+    let key = SigningKey::from_pkcs8_pem(&pem).map_err(invalid)?;
+    let data = std::fs::read(path)?;
+    crate::atomic::write(signature, &key.sign(&data).to_bytes())
+}
+
+#[cfg(not(feature = "sign"))]
+fn sign(_path: &Path, _signature: &Path, _key: &Path) -> io::Result<()> {
+    Err(invalid("signing requires building with the `sign` feature"))
+}
+
+#[cfg(feature = "encrypt")]
+fn encrypt(path: &Path, encrypted: &Path, recipients: &[String]) -> io::Result<()> {
+    use std::fs::File;
+
+    let recipients = recipients
+        .iter()
+        .map(|r| r.parse::<age::x25519::Recipient>().map_err(invalid))
+        .collect::<io::Result<Vec<_>>>()?;
+    let encryptor = age::Encryptor::with_recipients(recipients.iter().map(|r| r as &dyn age::Recipient))
+        .map_err(invalid)?;
+
+    crate::atomic::write_with(encrypted, |out| {
+        let mut writer = encryptor.wrap_output(out)?;
+        io::copy(&mut File::open(path)?, &mut writer)?;
+        writer.finish()?;
+        Ok(())
+    })
+}
+
+#[cfg(not(feature = "encrypt"))]
+fn encrypt(_path: &Path, _encrypted: &Path, _recipients: &[String]) -> io::Result<()> {
+    Err(invalid("encryption requires building with the `encrypt` feature"))
+}
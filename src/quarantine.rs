@@ -0,0 +1,53 @@
+//! Setting aside input files that mostly fail to parse.
+//!
+//! A file whose share of skipped lines exceeds the threshold is copied (or
+//! moved) into the quarantine directory and listed in the run metadata, so a
+//! broken collector or a format change shows up without anyone grepping
+//! logs. The lines that did parse are still aggregated.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::spool;
+
+pub struct Quarantine {
+    dir: PathBuf,
+    /// Percentage of skipped lines above which a file is quarantined
+    threshold: f64,
+    move_files: bool,
+}
+
+impl Quarantine {
+    pub fn new(dir: &Path, threshold: f64, move_files: bool) -> Self {
+        Quarantine {
+            dir: dir.to_path_buf(),
+            threshold,
+            move_files,
+        }
+    }
+
+    /// Quarantine `path` if too many of its lines were skipped, returning
+    /// where it was put.
+    pub fn check(&self, path: &Path, lines: u64, skipped: u64) -> io::Result<Option<PathBuf>> {
+        if lines == 0 || skipped as f64 / lines as f64 * 100.0 <= self.threshold {
+            return Ok(None);
+        }
+
+        fs::create_dir_all(&self.dir)?;
+        let name = path.file_name().unwrap_or(path.as_os_str());
+        let mut target = self.dir.join(name);
+        if target.exists() {
+            let secs = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+            target = self.dir.join(format!("{}.{}", name.to_string_lossy(), secs));
+        }
+
+        if self.move_files {
+            spool::move_file(path, &target)?;
+        } else {
+            fs::copy(path, &target)?;
+        }
+        Ok(Some(target))
+    }
+}
@@ -0,0 +1,202 @@
+//! Selecting and listing flows from a written output.
+//!
+//! Address filters take either a single address or a CIDR block; the other
+//! filters match exactly. Matching flows are sorted and printed as a table.
+
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Arc;
+
+use clap::{Args, ValueEnum};
+use serde::{Deserialize, Serialize};
+
+use crate::cidr::Cidr;
+use crate::record::Record;
+use crate::report::format_bytes;
+use crate::summary::Totals;
+
+#[derive(Args, Debug, Clone, Default)]
+pub struct Filter {
+    /// Only flows logged by this firewall
+    #[arg(long)]
+    pub firewall: Option<String>,
+    /// Only flows from this address or CIDR block
+    #[arg(long)]
+    pub source_ip: Option<String>,
+    /// Only flows to this address or CIDR block
+    #[arg(long)]
+    pub destination_ip: Option<String>,
+    /// Only flows to this destination port
+    #[arg(long)]
+    pub port: Option<String>,
+    /// Only flows of this IP protocol number
+    #[arg(long)]
+    pub protocol: Option<String>,
+}
+
+impl Filter {
+    /// Set the filter on `field` (its option name, e.g. `source-ip`).
+    pub fn set(&mut self, field: &str, value: &str) -> Result<(), String> {
+        let slot = match field {
+            "firewall" => &mut self.firewall,
+            "source-ip" => &mut self.source_ip,
+            "destination-ip" => &mut self.destination_ip,
+            "port" => &mut self.port,
+            "protocol" => &mut self.protocol,
+            _ => return Err(format!("unknown filter `{}` (firewall, source-ip, destination-ip, port, protocol)", field)),
+        };
+        *slot = Some(value.to_string());
+        Ok(())
+    }
+
+    /// The filters that are set, as `field=value`.
+    pub fn describe(&self) -> Vec<String> {
+        [
+            ("firewall", &self.firewall),
+            ("source-ip", &self.source_ip),
+            ("destination-ip", &self.destination_ip),
+            ("port", &self.port),
+            ("protocol", &self.protocol),
+        ]
+        .into_iter()
+        .filter_map(|(field, value)| value.as_ref().map(|value| format!("{}={}", field, value)))
+        .collect()
+    }
+
+    pub fn matches(&self, record: &Record) -> bool {
+        let exact = |wanted: &Option<String>, value: &str| wanted.as_deref().is_none_or(|wanted| wanted == value);
+        let address = |wanted: &Option<String>, value: &str| wanted.as_deref().is_none_or(|wanted| in_block(wanted, value));
+        exact(&self.firewall, &record.firewall)
+            && address(&self.source_ip, &record.source_ip)
+            && address(&self.destination_ip, &record.destination_ip)
+            && exact(&self.port, &record.destination_port)
+            && exact(&self.protocol, &record.protocol)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum SortKey {
+    /// Bytes in both directions, largest first
+    #[default]
+    Bytes,
+    /// Bytes received, largest first
+    #[value(alias = "bytes_in")]
+    BytesIn,
+    /// Bytes sent, largest first
+    #[value(alias = "bytes_out")]
+    BytesOut,
+    /// Packets in both directions, largest first
+    Packets,
+    /// Sessions, most first
+    Sessions,
+    /// Flow key, alphabetically
+    Key,
+}
+
+/// Matching records in `sort` order, at most `limit` of them (0 for all).
+pub fn select<'a>(records: impl IntoIterator<Item = &'a Record>, filter: &Filter, sort: SortKey, limit: usize) -> Vec<&'a Record> {
+    let mut selected: Vec<_> = records.into_iter().filter(|record| filter.matches(record)).collect();
+    sort_records(&mut selected, sort);
+    if limit > 0 {
+        selected.truncate(limit);
+    }
+    selected
+}
+
+pub fn sort_records(records: &mut [&Record], sort: SortKey) {
+    let ranked = |record: &Record| match sort {
+        SortKey::Bytes => record.bytes_in.saturating_add(record.bytes_out),
+        SortKey::BytesIn => record.bytes_in,
+        SortKey::BytesOut => record.bytes_out,
+        SortKey::Packets => record.packets_in.saturating_add(record.packets_out),
+        SortKey::Sessions => record.count,
+        SortKey::Key => 0,
+    };
+    records.sort_by(|a, b| ranked(b).cmp(&ranked(a)).then_with(|| a.key.cmp(&b.key)));
+}
+
+/// An output cut down to its largest flows, with `--top-k`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct TopK {
+    pub limit: usize,
+    pub by: SortKey,
+    /// The flows left out, summed into one bucket
+    pub other: Totals,
+}
+
+/// Keep only the first `limit` records of `records` in `sort` order,
+/// summing the rest into the other bucket.
+pub fn keep_top(records: &mut HashMap<Arc<str>, Record>, limit: usize, sort: SortKey) -> TopK {
+    let mut ranked: Vec<&Record> = records.values().collect();
+    sort_records(&mut ranked, sort);
+    let rest: Vec<Arc<str>> = ranked.iter().skip(limit).map(|record| record.key.clone()).collect();
+    let mut other = Totals::default();
+    for key in rest {
+        if let Some(record) = records.remove(&key) {
+            other.add(&record);
+        }
+    }
+    TopK { limit, by: sort, other }
+}
+
+/// Aligned text table of `records`.
+pub fn render(records: &[&Record]) -> String {
+    let rows: Vec<Vec<String>> = records
+        .iter()
+        .map(|record| {
+            vec![
+                record.firewall.to_string(),
+                record.source_ip.to_string(),
+                record.destination_ip.to_string(),
+                record.destination_port.to_string(),
+                record.protocol.to_string(),
+                record.count.to_string(),
+                record.packets_in.saturating_add(record.packets_out).to_string(),
+                format_bytes(record.bytes_in),
+                format_bytes(record.bytes_out),
+            ]
+        })
+        .collect();
+    text_table(&["Firewall", "Source", "Destination", "Port", "Proto", "Sessions", "Packets", "Bytes in", "Bytes out"], &rows, 5)
+}
+
+/// Columns padded to their widest cell; those from `numeric` on are
+/// right-aligned.
+pub fn text_table(headers: &[&str], rows: &[Vec<String>], numeric: usize) -> String {
+    let mut widths: Vec<usize> = headers.iter().map(|header| header.len()).collect();
+    for row in rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.len());
+        }
+    }
+
+    let line = |cells: Vec<&str>| {
+        let mut out = String::new();
+        for (column, (cell, width)) in cells.into_iter().zip(&widths).enumerate() {
+            match column >= numeric {
+                true => out.push_str(&format!("{:>width$}  ", cell, width = width)),
+                false => out.push_str(&format!("{:<width$}  ", cell, width = width)),
+            }
+        }
+        out.trim_end().to_string() + "\n"
+    };
+
+    let mut out = line(headers.to_vec());
+    for row in rows {
+        out.push_str(&line(row.iter().map(String::as_str).collect()));
+    }
+    out
+}
+
+/// Whether `value` is the address `block` or falls inside the CIDR `block`.
+fn in_block(block: &str, value: &str) -> bool {
+    if !block.contains('/') {
+        return block == value;
+    }
+    match (block.parse::<Cidr>(), value.parse::<IpAddr>()) {
+        (Ok(block), Ok(value)) => block.contains(value),
+        _ => false,
+    }
+}
@@ -0,0 +1,548 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::net::IpAddr;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+
+use clap::ValueEnum;
+
+use crate::bounds;
+use crate::detect::beacon::{Arrivals, Periodicity};
+use crate::enrich::blocklist::IndicatorMatch;
+use crate::intern::Interner;
+use crate::parser::{Action, FlowEvent};
+use crate::tdigest::{Percentiles, SessionDigests};
+
+/// Optional fields appended to the flow key so aggregates are split by them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Dimension {
+    /// Allow/deny decision
+    Action,
+    IngressZone,
+    EgressZone,
+    IngressInterface,
+    EgressInterface,
+    Vlan,
+    /// GRE key, IPsec SPI or tunnel, VXLAN VNI or tunnel interface
+    Tunnel,
+    Application,
+    User,
+    SourceMac,
+}
+
+/// Which side of address translation the flow key is built from.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum NatSide {
+    /// Addresses as seen before translation
+    #[default]
+    PreNat,
+    /// Translated addresses, falling back to the original when not logged
+    PostNat,
+}
+
+/// How events are grouped into flow records.
+#[derive(Debug, Clone, Default)]
+pub struct KeySpec {
+    pub nat: NatSide,
+    pub dimensions: Vec<Dimension>,
+}
+
+/// One component of a flow key: packed when the logged text parses as the
+/// expected type, otherwise the interned text itself.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum KeyPart<T> {
+    Packed(T),
+    Text(Arc<str>),
+}
+
+impl<T: FromStr> KeyPart<T> {
+    fn new(value: &str, strings: &mut Interner) -> Self {
+        match value.parse() {
+            Ok(packed) => KeyPart::Packed(packed),
+            Err(_) => KeyPart::Text(strings.intern(value)),
+        }
+    }
+}
+
+impl<T: fmt::Display> fmt::Display for KeyPart<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            KeyPart::Packed(value) => value.fmt(f),
+            KeyPart::Text(text) => f.write_str(text),
+        }
+    }
+}
+
+/// The aggregation key: firewall, source, destination, port and protocol,
+/// followed by any requested dimensions.
+///
+/// Addresses, port and protocol are stored packed so hashing and comparing
+/// keys doesn't touch the heap; the underscore-joined string form is only
+/// built for output.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct FlowKey {
+    firewall: KeyPart<IpAddr>,
+    source: KeyPart<IpAddr>,
+    destination: KeyPart<IpAddr>,
+    port: KeyPart<u16>,
+    protocol: KeyPart<u8>,
+    dimensions: Vec<Arc<str>>,
+}
+
+impl fmt::Display for FlowKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}_{}_{}_{}_{}", self.firewall, self.source, self.destination, self.port, self.protocol)?;
+        for value in &self.dimensions {
+            write!(f, "_{}", value)?;
+        }
+        Ok(())
+    }
+}
+
+/// Source, destination and destination port of `event` on the `nat` side.
+fn keyed_side(event: &FlowEvent, nat: NatSide) -> (&str, &str, &str) {
+    match nat {
+        NatSide::PreNat => (&event.source_ip, &event.destination_ip, &event.destination_port),
+        NatSide::PostNat => (
+            event.nat_source_ip.as_ref().unwrap_or(&event.source_ip),
+            event.nat_destination_ip.as_ref().unwrap_or(&event.destination_ip),
+            event.nat_destination_port.as_ref().unwrap_or(&event.destination_port),
+        ),
+    }
+}
+
+pub fn flow_key(event: &FlowEvent, spec: &KeySpec, strings: &mut Interner) -> FlowKey {
+    let (source_ip, destination_ip, destination_port) = keyed_side(event, spec.nat);
+
+    let dimensions = spec
+        .dimensions
+        .iter()
+        .map(|dimension| {
+            let value = match dimension {
+                Dimension::Action => event.action.map(Action::as_str).unwrap_or_default(),
+                Dimension::IngressZone => event.ingress_zone.as_deref().unwrap_or_default(),
+                Dimension::EgressZone => event.egress_zone.as_deref().unwrap_or_default(),
+                Dimension::IngressInterface => event.ingress_interface.as_deref().unwrap_or_default(),
+                Dimension::EgressInterface => event.egress_interface.as_deref().unwrap_or_default(),
+                Dimension::Vlan => event.vlan.as_deref().unwrap_or_default(),
+                Dimension::Tunnel => event.tunnel.as_deref().unwrap_or_default(),
+                Dimension::Application => event.application.as_deref().unwrap_or_default(),
+                Dimension::User => event.user.as_deref().unwrap_or_default(),
+                Dimension::SourceMac => event.source_mac.as_deref().unwrap_or_default(),
+            };
+            strings.intern(value)
+        })
+        .collect();
+
+    // ICMP has no port; its type and code take the port's place, as `8/0`
+    let port = match (event.icmp_type, event.icmp_code) {
+        (Some(icmp_type), Some(icmp_code)) => KeyPart::Text(strings.intern(&format!("{}/{}", icmp_type, icmp_code))),
+        (Some(icmp_type), None) => KeyPart::Text(strings.intern(&icmp_type.to_string())),
+        (None, _) => KeyPart::new(destination_port, strings),
+    };
+
+    FlowKey {
+        firewall: KeyPart::new(&event.firewall, strings),
+        source: KeyPart::new(source_ip, strings),
+        destination: KeyPart::new(destination_ip, strings),
+        port,
+        protocol: KeyPart::new(&event.protocol, strings),
+        dimensions,
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Record {
+    /// String form of the flow key, filled in when aggregation finishes and
+    /// shared with the key of the map the record is stored in
+    pub key: Arc<str>,
+    #[serde(default)]
+    pub firewall: Arc<str>,
+    #[serde(rename = "source-ip")]
+    pub source_ip: Arc<str>,
+    #[serde(rename = "destination-ip")]
+    pub destination_ip: Arc<str>,
+    #[serde(rename = "destination-port", default)]
+    pub destination_port: Arc<str>,
+    #[serde(default)]
+    pub protocol: Arc<str>,
+    #[serde(rename = "packets-in")]
+    pub packets_in: u64,
+    #[serde(rename = "bytes-in")]
+    pub bytes_in: u64,
+    #[serde(rename = "packets-out")]
+    pub packets_out: u64,
+    #[serde(rename = "bytes-out")]
+    pub bytes_out: u64,
+    pub count: u64,
+    /// Summed duration of the sessions that reported or were correlated to one
+    #[serde(rename = "duration-ms", default, skip_serializing_if = "Option::is_none")]
+    pub duration_ms: Option<u64>,
+    /// Sessions per logged TCP flag combination
+    #[serde(rename = "tcp-flags", default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub tcp_flags: BTreeMap<String, u64>,
+    /// Sessions per end reason
+    #[serde(rename = "end-reasons", default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub end_reasons: BTreeMap<String, u64>,
+    /// Sessions the firewall permitted, when the format logs an action
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub allowed: Option<u64>,
+    /// Sessions the firewall blocked, when the format logs an action
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub denied: Option<u64>,
+    #[serde(rename = "nat-source-ip", default, skip_serializing_if = "Option::is_none")]
+    pub nat_source_ip: Option<String>,
+    #[serde(rename = "nat-source-port", default, skip_serializing_if = "Option::is_none")]
+    pub nat_source_port: Option<String>,
+    #[serde(rename = "nat-destination-ip", default, skip_serializing_if = "Option::is_none")]
+    pub nat_destination_ip: Option<String>,
+    #[serde(rename = "nat-destination-port", default, skip_serializing_if = "Option::is_none")]
+    pub nat_destination_port: Option<String>,
+    #[serde(rename = "ingress-zone", default, skip_serializing_if = "Option::is_none")]
+    pub ingress_zone: Option<String>,
+    #[serde(rename = "egress-zone", default, skip_serializing_if = "Option::is_none")]
+    pub egress_zone: Option<String>,
+    #[serde(rename = "ingress-interface", default, skip_serializing_if = "Option::is_none")]
+    pub ingress_interface: Option<String>,
+    #[serde(rename = "egress-interface", default, skip_serializing_if = "Option::is_none")]
+    pub egress_interface: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub vlan: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tunnel: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub application: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user: Option<String>,
+    #[serde(rename = "source-mac", default, skip_serializing_if = "Option::is_none")]
+    pub source_mac: Option<String>,
+    #[serde(rename = "destination-mac", default, skip_serializing_if = "Option::is_none")]
+    pub destination_mac: Option<String>,
+    /// ICMP type and code of ICMP and ICMPv6 records, which are keyed on
+    /// them in place of the destination port
+    #[serde(rename = "icmp-type", default, skip_serializing_if = "Option::is_none")]
+    pub icmp_type: Option<u8>,
+    #[serde(rename = "icmp-code", default, skip_serializing_if = "Option::is_none")]
+    pub icmp_code: Option<u8>,
+    /// Percentiles of the sessions' durations, when they carry one
+    #[serde(rename = "duration-percentiles-ms", default, skip_serializing_if = "Option::is_none")]
+    pub duration_percentiles: Option<Percentiles>,
+    /// Percentiles of the bytes (both directions) per session
+    #[serde(rename = "session-bytes-percentiles", default, skip_serializing_if = "Option::is_none")]
+    pub session_bytes_percentiles: Option<Percentiles>,
+    /// Per-session digests while aggregating with `--percentiles`
+    #[serde(skip)]
+    pub digests: Option<Box<SessionDigests>>,
+    /// Regularity of the sessions' start times, with `--detect-beacons`
+    #[serde(rename = "inter-arrival", default, skip_serializing_if = "Option::is_none")]
+    pub inter_arrival: Option<Periodicity>,
+    /// Session timestamps while aggregating with `--detect-beacons`
+    #[serde(skip)]
+    pub arrivals: Option<Box<Arrivals>>,
+    /// Approximate distinct destination ports and addresses of the source,
+    /// and sources of the destination, over the whole run
+    #[serde(rename = "source-distinct-ports", default, skip_serializing_if = "Option::is_none")]
+    pub source_distinct_ports: Option<u64>,
+    #[serde(rename = "source-distinct-destinations", default, skip_serializing_if = "Option::is_none")]
+    pub source_distinct_destinations: Option<u64>,
+    #[serde(rename = "destination-distinct-sources", default, skip_serializing_if = "Option::is_none")]
+    pub destination_distinct_sources: Option<u64>,
+    /// Raw lines behind the record, reservoir-sampled with `--sample-lines`
+    #[serde(rename = "sample-lines", default, skip_serializing_if = "Vec::is_empty")]
+    pub sample_lines: Vec<String>,
+    /// Context joined on after aggregation, keyed `<side>-<name>`
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub enrichment: BTreeMap<String, String>,
+    /// Sessions per input they were read from, with `--record-sources`
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub sources: BTreeMap<String, u64>,
+    /// Static labels of the run that produced the record, from `--label`
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub labels: BTreeMap<String, String>,
+    /// Blocklist entries the source or destination matched
+    #[serde(rename = "matched-indicators", default, skip_serializing_if = "Vec::is_empty")]
+    pub matched_indicators: Vec<IndicatorMatch>,
+    /// Tags added by rules, and by `--counter-over-limit flag`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    /// Names of the rules the record matched
+    #[serde(rename = "matched-rules", default, skip_serializing_if = "Vec::is_empty")]
+    pub matched_rules: Vec<String>,
+    /// Matched a rule with the export action; only meaningful within a run
+    #[serde(skip)]
+    pub exported: bool,
+    /// Sequence number of the event that last changed the record while it
+    /// was live, to find the records changed since a checkpoint
+    #[serde(skip)]
+    pub updated: u64,
+}
+
+impl Record {
+    /// A record of `event`, with the addresses and port of the `nat` side
+    /// its key was built from.
+    pub fn new(event: &FlowEvent, nat: NatSide, strings: &mut Interner) -> Self {
+        let (source_ip, destination_ip, destination_port) = keyed_side(event, nat);
+        let mut record = Record {
+            key: strings.intern(""),
+            firewall: strings.intern(&event.firewall),
+            source_ip: strings.intern(source_ip),
+            destination_ip: strings.intern(destination_ip),
+            destination_port: strings.intern(destination_port),
+            protocol: strings.intern(&event.protocol),
+            packets_in: 0,
+            bytes_in: 0,
+            packets_out: 0,
+            bytes_out: 0,
+            count: 0,
+            duration_ms: None,
+            tcp_flags: BTreeMap::new(),
+            end_reasons: BTreeMap::new(),
+            allowed: None,
+            denied: None,
+            nat_source_ip: event.nat_source_ip.clone(),
+            nat_source_port: event.nat_source_port.clone(),
+            nat_destination_ip: event.nat_destination_ip.clone(),
+            nat_destination_port: event.nat_destination_port.clone(),
+            ingress_zone: event.ingress_zone.clone(),
+            egress_zone: event.egress_zone.clone(),
+            ingress_interface: event.ingress_interface.clone(),
+            egress_interface: event.egress_interface.clone(),
+            vlan: event.vlan.clone(),
+            tunnel: event.tunnel.clone(),
+            application: event.application.clone(),
+            user: event.user.clone(),
+            source_mac: event.source_mac.clone(),
+            destination_mac: event.destination_mac.clone(),
+            icmp_type: event.icmp_type,
+            icmp_code: event.icmp_code,
+            duration_percentiles: None,
+            session_bytes_percentiles: None,
+            digests: None,
+            inter_arrival: None,
+            arrivals: None,
+            source_distinct_ports: None,
+            source_distinct_destinations: None,
+            destination_distinct_sources: None,
+            sample_lines: Vec::new(),
+            enrichment: BTreeMap::new(),
+            sources: BTreeMap::new(),
+            labels: BTreeMap::new(),
+            matched_indicators: Vec::new(),
+            tags: Vec::new(),
+            matched_rules: Vec::new(),
+            exported: false,
+            updated: 0,
+        };
+        record.add(event);
+        record
+    }
+
+    /// Feed the event into the per-session digests.
+    pub fn sample(&mut self, event: &FlowEvent) {
+        let digests = self.digests.get_or_insert_with(Default::default);
+        digests.bytes.add(event.bytes_in.saturating_add(event.bytes_out) as f64);
+        if let Some(ms) = event.duration_ms {
+            digests.duration_ms.add(ms as f64);
+        }
+    }
+
+    /// Note the event's timestamp for the inter-arrival statistics.
+    pub fn arrive(&mut self, event: &FlowEvent) {
+        if let Some(timestamp) = event.timestamp {
+            self.arrivals.get_or_insert_with(Default::default).note(timestamp.timestamp_millis());
+        }
+    }
+
+    /// Turn the digests and timestamps into the reported statistics.
+    pub fn finish_digests(&mut self) {
+        if let Some(arrivals) = self.arrivals.take() {
+            self.inter_arrival = arrivals.finish();
+        }
+        if let Some(mut digests) = self.digests.take() {
+            self.session_bytes_percentiles = Some(digests.bytes.percentiles());
+            if digests.duration_ms.count() > 0.0 {
+                self.duration_percentiles = Some(digests.duration_ms.percentiles());
+            }
+        }
+    }
+
+    pub fn add(&mut self, event: &FlowEvent) {
+        self.packets_in = self.packets_in.saturating_add(event.packets_in);
+        self.bytes_in = self.bytes_in.saturating_add(event.bytes_in);
+        self.packets_out = self.packets_out.saturating_add(event.packets_out);
+        self.bytes_out = self.bytes_out.saturating_add(event.bytes_out);
+        self.count = self.count.saturating_add(1);
+        if let Some(ms) = event.duration_ms {
+            let duration = self.duration_ms.get_or_insert(0);
+            *duration = duration.saturating_add(ms);
+        }
+        if let Some(source) = &event.source {
+            add_sessions(self.sources.entry(source.to_string()).or_insert(0), 1);
+        }
+        if event.over_limit && !self.tags.iter().any(|tag| tag == bounds::TAG) {
+            self.tags.push(bounds::TAG.to_string());
+        }
+        if let Some(flags) = &event.tcp_flags {
+            add_sessions(self.tcp_flags.entry(flags.clone()).or_insert(0), 1);
+        }
+        if let Some(reason) = &event.end_reason {
+            add_sessions(self.end_reasons.entry(reason.clone()).or_insert(0), 1);
+        }
+        if let Some(action) = event.action {
+            let allowed = self.allowed.get_or_insert(0);
+            let denied = self.denied.get_or_insert(0);
+            match action {
+                Action::Allow => add_sessions(allowed, 1),
+                Action::Deny => add_sessions(denied, 1),
+            }
+        }
+    }
+
+    /// Fold in the totals of the same flow from another output.
+    pub fn merge(&mut self, other: Record) {
+        self.packets_in = self.packets_in.saturating_add(other.packets_in);
+        self.bytes_in = self.bytes_in.saturating_add(other.bytes_in);
+        self.packets_out = self.packets_out.saturating_add(other.packets_out);
+        self.bytes_out = self.bytes_out.saturating_add(other.bytes_out);
+        let own = self.count;
+        self.count = self.count.saturating_add(other.count);
+        self.duration_ms = sum_optional(self.duration_ms, other.duration_ms);
+        for (flags, sessions) in other.tcp_flags {
+            add_sessions(self.tcp_flags.entry(flags).or_insert(0), sessions);
+        }
+        for (reason, sessions) in other.end_reasons {
+            add_sessions(self.end_reasons.entry(reason).or_insert(0), sessions);
+        }
+        self.allowed = sum_optional(self.allowed, other.allowed);
+        self.denied = sum_optional(self.denied, other.denied);
+        // Percentiles and inter-arrival statistics can't be combined without the
+        // digests and timestamps; keep the busier run's
+        if other.count > own {
+            self.duration_percentiles = other.duration_percentiles.or(self.duration_percentiles.take());
+            self.session_bytes_percentiles = other.session_bytes_percentiles.or(self.session_bytes_percentiles.take());
+            self.inter_arrival = other.inter_arrival.or(self.inter_arrival.take());
+        }
+        // Distinct counts of different runs can't be added; the larger is a lower bound
+        self.source_distinct_ports = self.source_distinct_ports.max(other.source_distinct_ports);
+        self.source_distinct_destinations = self.source_distinct_destinations.max(other.source_distinct_destinations);
+        self.destination_distinct_sources = self.destination_distinct_sources.max(other.destination_distinct_sources);
+        // Keep as many samples as either side had, alternating between them
+        let size = self.sample_lines.len().max(other.sample_lines.len());
+        let mut mine = std::mem::take(&mut self.sample_lines).into_iter();
+        let mut theirs = other.sample_lines.into_iter();
+        while self.sample_lines.len() < size {
+            let (a, b) = (mine.next(), theirs.next());
+            if a.is_none() && b.is_none() {
+                break;
+            }
+            self.sample_lines.extend(a.into_iter().chain(b).take(size - self.sample_lines.len()));
+        }
+        for (name, value) in other.enrichment {
+            self.enrichment.entry(name).or_insert(value);
+        }
+        for (source, sessions) in other.sources {
+            add_sessions(self.sources.entry(source).or_insert(0), sessions);
+        }
+        merge_labels(&mut self.labels, other.labels);
+        for indicator in other.matched_indicators {
+            if !self.matched_indicators.contains(&indicator) {
+                self.matched_indicators.push(indicator);
+            }
+        }
+        for tag in other.tags {
+            if !self.tags.contains(&tag) {
+                self.tags.push(tag);
+            }
+        }
+        for rule in other.matched_rules {
+            if !self.matched_rules.contains(&rule) {
+                self.matched_rules.push(rule);
+            }
+        }
+    }
+}
+
+/// Parse a `KEY=VALUE` label.
+pub fn parse_label(value: &str) -> Result<(String, String), String> {
+    match value.split_once('=') {
+        Some((key, value)) if !key.trim().is_empty() => Ok((key.trim().to_string(), value.trim().to_string())),
+        _ => Err(format!("`{}` is not KEY=VALUE", value)),
+    }
+}
+
+/// Add `other` to `labels`. A label the two give different values keeps
+/// both, comma-separated, so merged outputs stay attributable to each run.
+pub fn merge_labels(labels: &mut BTreeMap<String, String>, other: BTreeMap<String, String>) {
+    for (key, value) in other {
+        match labels.get_mut(&key) {
+            Some(existing) => {
+                if !existing.split(',').any(|known| known == value) {
+                    existing.push(',');
+                    existing.push_str(&value);
+                }
+            }
+            None => {
+                labels.insert(key, value);
+            }
+        }
+    }
+}
+
+fn add_sessions(total: &mut u64, sessions: u64) {
+    *total = total.saturating_add(sessions);
+}
+
+fn sum_optional(a: Option<u64>, b: Option<u64>) -> Option<u64> {
+    match (a, b) {
+        (None, None) => None,
+        (a, b) => Some(a.unwrap_or(0).saturating_add(b.unwrap_or(0))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(count: u64) -> Record {
+        let event = FlowEvent {
+            firewall: "fw1".to_string(),
+            source_ip: "10.0.0.1".to_string(),
+            destination_ip: "10.0.0.2".to_string(),
+            destination_port: "443".to_string(),
+            protocol: "6".to_string(),
+            bytes_in: 100,
+            tcp_flags: Some("SA".to_string()),
+            ..FlowEvent::default()
+        };
+        let mut record = Record::new(&event, NatSide::PreNat, &mut Interner::default());
+        record.count = count;
+        record
+    }
+
+    #[test]
+    fn merged_counts_saturate() {
+        let mut merged = record(u64::MAX - 1);
+        merged.tcp_flags.insert("SA".to_string(), u64::MAX);
+        merged.merge(record(5));
+        merged.add(&FlowEvent { bytes_in: u64::MAX, ..FlowEvent::default() });
+        assert_eq!((merged.count, merged.bytes_in, merged.tcp_flags["SA"]), (u64::MAX, u64::MAX, u64::MAX));
+    }
+
+    #[test]
+    fn merges_keep_the_busier_runs_percentiles() {
+        let percentiles = |max| Some(Percentiles { p50: max, p90: max, p99: max, max });
+        let mut merged = record(10);
+        merged.session_bytes_percentiles = percentiles(1);
+        let mut quieter = record(3);
+        quieter.session_bytes_percentiles = percentiles(2);
+        merged.merge(quieter);
+        assert_eq!((merged.count, merged.session_bytes_percentiles.as_ref().map(|p| p.max)), (13, Some(1)));
+
+        let mut busier = record(20);
+        busier.session_bytes_percentiles = percentiles(3);
+        merged.merge(busier);
+        assert_eq!((merged.count, merged.session_bytes_percentiles.as_ref().map(|p| p.max)), (33, Some(3)));
+    }
+}
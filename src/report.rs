@@ -0,0 +1,339 @@
+//! Human-readable run reports.
+//!
+//! The report is built as a list of titled tables and rendered either as
+//! Markdown or as a self-contained HTML page (inline CSS, no external assets)
+//! that can be opened straight from a mail attachment or file share.
+//!
+//! Byte counts are in binary units (KiB, MiB, ...) unless the report asks
+//! for decimal ones or plain bytes, and its numbers can have their
+//! thousands grouped, so a report says which convention it uses rather
+//! than leaving the reader to guess.
+
+use std::collections::{BTreeMap, HashMap};
+
+use chrono::DateTime;
+use clap::ValueEnum;
+
+use crate::payload::Payload;
+use crate::record::Record;
+use crate::summary::{self, Totals};
+
+const TOP_N: usize = 10;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ReportFormat {
+    Html,
+    Md,
+}
+
+impl ReportFormat {
+    pub fn extension(self) -> &'static str {
+        match self {
+            ReportFormat::Html => "html",
+            ReportFormat::Md => "md",
+        }
+    }
+}
+
+/// Units byte counts are rendered in.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum ByteUnits {
+    /// Powers of 1024: KiB, MiB, GiB, ...
+    #[default]
+    Iec,
+    /// Powers of 1000: kB, MB, GB, ...
+    Si,
+    /// Plain byte counts
+    Bytes,
+}
+
+/// How the digits of large numbers are grouped.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum Grouping {
+    /// `1234567.89`
+    #[default]
+    None,
+    /// `1,234,567.89`
+    Comma,
+    /// `1 234 567.89`
+    Space,
+    /// `1.234.567,89`
+    Period,
+}
+
+/// How a report renders its numbers.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NumberFormat {
+    pub units: ByteUnits,
+    pub grouping: Grouping,
+}
+
+impl NumberFormat {
+    pub fn bytes(&self, bytes: u64) -> String {
+        let (base, units) = match self.units {
+            ByteUnits::Iec => (1024.0, ["B", "KiB", "MiB", "GiB", "TiB", "PiB"]),
+            ByteUnits::Si => (1000.0, ["B", "kB", "MB", "GB", "TB", "PB"]),
+            ByteUnits::Bytes => return format!("{} B", self.count(bytes)),
+        };
+        let mut value = bytes as f64;
+        let mut unit = 0;
+        while value >= base && unit + 1 < units.len() {
+            value /= base;
+            unit += 1;
+        }
+        match unit {
+            0 => format!("{} B", self.count(bytes)),
+            _ => format!("{} {}", self.decimal(value), units[unit]),
+        }
+    }
+
+    pub fn count(&self, count: u64) -> String {
+        self.group(&count.to_string())
+    }
+
+    /// `value` to two decimal places.
+    fn decimal(&self, value: f64) -> String {
+        let text = format!("{:.2}", value);
+        let (whole, fraction) = text.split_once('.').unwrap_or((&text, ""));
+        let mark = if self.grouping == Grouping::Period { ',' } else { '.' };
+        format!("{}{}{}", self.group(whole), mark, fraction)
+    }
+
+    fn group(&self, digits: &str) -> String {
+        let separator = match self.grouping {
+            Grouping::None => return digits.to_string(),
+            Grouping::Comma => ',',
+            Grouping::Space => ' ',
+            Grouping::Period => '.',
+        };
+        let mut out = String::with_capacity(digits.len() + digits.len() / 3);
+        for (i, digit) in digits.chars().enumerate() {
+            if i > 0 && (digits.len() - i).is_multiple_of(3) {
+                out.push(separator);
+            }
+            out.push(digit);
+        }
+        out
+    }
+}
+
+struct Table {
+    title: String,
+    headers: Vec<&'static str>,
+    rows: Vec<Vec<String>>,
+}
+
+pub fn render(payload: &Payload, format: ReportFormat, numbers: &NumberFormat) -> String {
+    let tables = build_tables(payload, numbers);
+    match format {
+        ReportFormat::Md => render_markdown(&tables),
+        ReportFormat::Html => render_html(&tables),
+    }
+}
+
+fn build_tables(payload: &Payload, numbers: &NumberFormat) -> Vec<Table> {
+    let metadata = &payload.metadata;
+    let sources = summary::group_by(payload.data.values(), |record| &record.source_ip);
+    let destinations = summary::group_by(payload.data.values(), |record| &record.destination_ip);
+    let protocols = summary::group_by(payload.data.values(), |record| &record.protocol);
+    let sessions: u64 = payload.data.values().map(|record| record.count).sum();
+
+    let mut tables = vec![Table {
+        title: "Run summary".to_string(),
+        headers: vec!["Metric", "Value"],
+        rows: vec![
+            vec!["Started".to_string(), format_millis(metadata.start_time)],
+            vec!["Finished".to_string(), format_millis(metadata.end_time)],
+            vec!["Duration".to_string(), format!("{:.3} s", metadata.elapsed_time)],
+            vec!["Inputs".to_string(), metadata.files_processed.len().to_string()],
+            vec!["Flows".to_string(), numbers.count(metadata.flows as u64)],
+            vec!["Sessions".to_string(), numbers.count(sessions)],
+        ],
+    }];
+    if let Some(ignored) = &metadata.ignored {
+        let rows = &mut tables[0].rows;
+        rows.push(vec!["Ignored flows".to_string(), numbers.count(ignored.totals.flows)]);
+        rows.push(vec!["Ignored bytes".to_string(), numbers.bytes(ignored.totals.bytes())]);
+    }
+    if let Some(dropped) = &metadata.below_minimum {
+        let rows = &mut tables[0].rows;
+        rows.push(vec!["Flows under the minimum, left out".to_string(), numbers.count(dropped.flows)]);
+        rows.push(vec!["Their sessions".to_string(), numbers.count(dropped.sessions)]);
+        rows.push(vec!["Their bytes".to_string(), numbers.bytes(dropped.bytes())]);
+    }
+    if let Some(top) = &metadata.top_k {
+        let rows = &mut tables[0].rows;
+        rows.push(vec!["Flows past the top K, in other".to_string(), numbers.count(top.other.flows)]);
+        rows.push(vec!["Other sessions".to_string(), numbers.count(top.other.sessions)]);
+        rows.push(vec!["Other bytes".to_string(), numbers.bytes(top.other.bytes())]);
+    }
+
+    let skipped = &metadata.skipped_lines;
+    let mut errors = vec![
+        vec!["Lines read".to_string(), numbers.count(metadata.total_connections)],
+        vec!["Lines skipped".to_string(), format!("{} ({})", numbers.count(skipped.total), percent(skipped.total, metadata.total_connections))],
+    ];
+    for (reason, count) in [
+        ("Too few fields", skipped.short_line),
+        ("Empty counters", skipped.empty_counters),
+        ("Unparseable numbers", skipped.parse_failure),
+        ("Missing required fields", skipped.missing_field),
+        ("Unrecognized lines", skipped.unrecognized),
+        ("Lines over the length limit", skipped.too_long),
+        ("Binary lines", skipped.binary),
+        ("Undecodable lines", skipped.undecodable),
+    ] {
+        if count > 0 {
+            errors.push(vec![format!("  {}", reason), numbers.count(count)]);
+        }
+    }
+    let replaced: u64 = metadata.processing_performance.files.iter().map(|file| file.replaced_characters).sum();
+    if replaced > 0 {
+        errors.push(vec!["Characters not valid UTF-8".to_string(), numbers.count(replaced)]);
+    }
+    if let Some(bounds) = &metadata.counter_bounds {
+        errors.push(vec![format!("Counters over the limit of {}", numbers.count(bounds.limit)), numbers.count(bounds.values)]);
+    }
+    if let Some(correlation) = &metadata.session_correlation {
+        errors.push(vec!["Session closes without an open".to_string(), numbers.count(correlation.unmatched_closes)]);
+        errors.push(vec!["Session opens that expired".to_string(), numbers.count(correlation.expired_opens)]);
+        errors.push(vec!["Sessions still open at end".to_string(), numbers.count(correlation.open_at_end)]);
+    }
+    tables.push(Table {
+        title: "Error statistics".to_string(),
+        headers: vec!["Metric", "Value"],
+        rows: errors,
+    });
+
+    let sources = with_hostnames(sources, payload, "source-hostname", |record| &record.source_ip);
+    let destinations = with_hostnames(destinations, payload, "destination-hostname", |record| &record.destination_ip);
+    tables.push(totals_table(format!("Top {} sources by bytes", TOP_N), "Source", sources, true, numbers));
+    tables.push(totals_table(format!("Top {} destinations by bytes", TOP_N), "Destination", destinations, true, numbers));
+    let protocols = protocols.into_iter().map(|(proto, totals)| (protocol_name(&proto), totals)).collect();
+    tables.push(totals_table("Protocol breakdown".to_string(), "Protocol", protocols, false, numbers));
+
+    tables
+}
+
+fn totals_table(title: String, label: &'static str, totals: BTreeMap<String, Totals>, top_only: bool, numbers: &NumberFormat) -> Table {
+    let mut entries: Vec<_> = totals.into_iter().collect();
+    entries.sort_by(|(a_key, a), (b_key, b)| b.bytes().cmp(&a.bytes()).then_with(|| a_key.cmp(b_key)));
+    if top_only {
+        entries.truncate(TOP_N);
+    }
+
+    Table {
+        title,
+        headers: vec![label, "Bytes in", "Bytes out", "Sessions", "Flows"],
+        rows: entries
+            .into_iter()
+            .map(|(key, totals)| {
+                vec![
+                    key,
+                    numbers.bytes(totals.bytes_in),
+                    numbers.bytes(totals.bytes_out),
+                    numbers.count(totals.sessions),
+                    numbers.count(totals.flows),
+                ]
+            })
+            .collect(),
+    }
+}
+
+/// Label addresses with the hostname enrichment gave them, as
+/// `address (hostname)`.
+fn with_hostnames(totals: BTreeMap<String, Totals>, payload: &Payload, key: &str, address: fn(&Record) -> &str) -> BTreeMap<String, Totals> {
+    let hostnames: HashMap<&str, &str> = payload
+        .data
+        .values()
+        .filter_map(|record| record.enrichment.get(key).map(|hostname| (address(record), hostname.as_str())))
+        .collect();
+    totals
+        .into_iter()
+        .map(|(address, totals)| match hostnames.get(address.as_str()) {
+            Some(hostname) => (format!("{} ({})", address, hostname), totals),
+            None => (address, totals),
+        })
+        .collect()
+}
+
+fn protocol_name(protocol: &str) -> String {
+    match protocol {
+        "1" => "ICMP (1)".to_string(),
+        "6" => "TCP (6)".to_string(),
+        "17" => "UDP (17)".to_string(),
+        "47" => "GRE (47)".to_string(),
+        "50" => "ESP (50)".to_string(),
+        "58" => "ICMPv6 (58)".to_string(),
+        "" => "unknown".to_string(),
+        other => other.to_string(),
+    }
+}
+
+fn format_millis(millis: u128) -> String {
+    DateTime::from_timestamp_millis(millis as i64)
+        .map(|ts| ts.format("%Y-%m-%d %H:%M:%S UTC").to_string())
+        .unwrap_or_default()
+}
+
+fn percent(part: u64, whole: u64) -> String {
+    if whole == 0 {
+        return "0.00%".to_string();
+    }
+    format!("{:.2}%", part as f64 / whole as f64 * 100.0)
+}
+
+/// `bytes` in binary units, as everything but reports renders them.
+pub(crate) fn format_bytes(bytes: u64) -> String {
+    NumberFormat::default().bytes(bytes)
+}
+
+fn render_markdown(tables: &[Table]) -> String {
+    let mut out = String::from("# Syslog processing report\n");
+    for table in tables {
+        out.push_str(&format!("\n## {}\n\n", table.title));
+        out.push_str(&format!("| {} |\n", table.headers.join(" | ")));
+        out.push_str(&format!("|{}\n", " --- |".repeat(table.headers.len())));
+        for row in &table.rows {
+            let cells: Vec<String> = row.iter().map(|cell| cell.replace('|', "\\|")).collect();
+            out.push_str(&format!("| {} |\n", cells.join(" | ")));
+        }
+    }
+    out
+}
+
+fn render_html(tables: &[Table]) -> String {
+    let mut out = String::from(concat!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>Syslog processing report</title>\n",
+        "<style>\nbody { font-family: sans-serif; margin: 2em; color: #222; }\n",
+        "table { border-collapse: collapse; margin-bottom: 2em; }\n",
+        "th, td { border: 1px solid #ccc; padding: 4px 10px; text-align: left; }\n",
+        "th { background: #f0f0f0; }\ntd:not(:first-child) { text-align: right; }\n</style>\n",
+        "</head>\n<body>\n<h1>Syslog processing report</h1>\n",
+    ));
+    for table in tables {
+        out.push_str(&format!("<h2>{}</h2>\n<table>\n<tr>", escape_html(&table.title)));
+        for header in &table.headers {
+            out.push_str(&format!("<th>{}</th>", escape_html(header)));
+        }
+        out.push_str("</tr>\n");
+        for row in &table.rows {
+            out.push_str("<tr>");
+            for cell in row {
+                out.push_str(&format!("<td>{}</td>", escape_html(cell)));
+            }
+            out.push_str("</tr>\n");
+        }
+        out.push_str("</table>\n");
+    }
+    out.push_str("</body>\n</html>\n");
+    out
+}
+
+fn escape_html(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
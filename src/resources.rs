@@ -0,0 +1,32 @@
+//! Process resource usage, read from procfs where available.
+
+use std::fs;
+
+/// Clock ticks per second used by `/proc/<pid>/stat` (USER_HZ, fixed at 100
+/// in the Linux ABI).
+const USER_HZ: f64 = 100.0;
+
+/// Peak resident set size in bytes (`VmHWM`).
+pub fn peak_rss_bytes() -> Option<u64> {
+    let status = fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|line| line.starts_with("VmHWM:"))?;
+    let kib: u64 = line.split_whitespace().nth(1)?.parse().ok()?;
+    Some(kib * 1024)
+}
+
+/// User plus system CPU time consumed so far, in seconds.
+pub fn cpu_time_seconds() -> Option<f64> {
+    let stat = fs::read_to_string("/proc/self/stat").ok()?;
+    // The command name may contain spaces, so count fields after its closing paren
+    let fields: Vec<&str> = stat.rsplit_once(')')?.1.split_whitespace().collect();
+    let utime: u64 = fields.get(11)?.parse().ok()?;
+    let stime: u64 = fields.get(12)?.parse().ok()?;
+    Some((utime + stime) as f64 / USER_HZ)
+}
+
+/// CPU time consumed since [`cpu_time_seconds`] returned `before`.
+pub fn cpu_seconds_since(before: Option<f64>) -> Option<f64> {
+    let now = cpu_time_seconds()?;
+    // Whole ticks, without the float noise of the subtraction
+    Some(((now - before?) * USER_HZ).round() / USER_HZ)
+}
@@ -0,0 +1,96 @@
+//! Subnet roll-ups: flows re-aggregated per source and destination block.
+//!
+//! Each level pairs an IPv4 prefix length with an IPv6 one, so `/24` with
+//! `/64` folds both families at a comparable granularity. The levels are
+//! written next to the output as a much smaller companion file for
+//! network-level capacity views.
+
+use std::collections::BTreeMap;
+use std::net::IpAddr;
+
+use serde::Serialize;
+
+use crate::cidr::Cidr;
+use crate::record::Record;
+use crate::summary::Totals;
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Level {
+    pub ipv4_prefix: u32,
+    pub ipv6_prefix: u32,
+    pub pairs: Vec<Pair>,
+}
+
+/// Totals of the flows between two groups of addresses.
+#[derive(Serialize, Debug)]
+pub struct Pair {
+    pub source: String,
+    pub destination: String,
+    #[serde(flatten)]
+    pub totals: Totals,
+}
+
+/// `/24` or `24` as a prefix length of at most `width` bits.
+fn parse_prefix(value: &str, width: u32) -> Result<u32, String> {
+    value
+        .trim()
+        .trim_start_matches('/')
+        .parse::<u32>()
+        .ok()
+        .filter(|prefix| *prefix <= width)
+        .ok_or_else(|| format!("`{}` is not a prefix length between /0 and /{}", value, width))
+}
+
+pub fn parse_v4_prefix(value: &str) -> Result<u32, String> {
+    parse_prefix(value, 32)
+}
+
+pub fn parse_v6_prefix(value: &str) -> Result<u32, String> {
+    parse_prefix(value, 128)
+}
+
+/// The block of `address` at the level's prefix for its family; anything
+/// that isn't an address is kept as it is.
+fn block(address: &str, ipv4_prefix: u32, ipv6_prefix: u32) -> String {
+    match address.parse::<IpAddr>() {
+        Ok(ip) => Cidr::of(ip, if ip.is_ipv4() { ipv4_prefix } else { ipv6_prefix }).to_string(),
+        Err(_) => address.to_string(),
+    }
+}
+
+/// One level per IPv4 prefix, paired with the IPv6 prefix at the same
+/// position (or the last one given).
+pub fn build<'a>(records: impl IntoIterator<Item = &'a Record> + Clone, ipv4: &[u32], ipv6: &[u32]) -> Vec<Level> {
+    ipv4.iter()
+        .enumerate()
+        .map(|(position, &ipv4_prefix)| {
+            let ipv6_prefix = ipv6.get(position).or(ipv6.last()).copied().unwrap_or(64);
+            let pairs = pairs(records.clone(), |address| block(address, ipv4_prefix, ipv6_prefix));
+            Level { ipv4_prefix, ipv6_prefix, pairs }
+        })
+        .collect()
+}
+
+/// Totals per (group of the source, group of the destination), busiest first.
+pub fn pairs<'a>(records: impl IntoIterator<Item = &'a Record>, group: impl Fn(&str) -> String) -> Vec<Pair> {
+    let mut pairs: BTreeMap<(String, String), Totals> = BTreeMap::new();
+    for record in records {
+        pairs.entry((group(&record.source_ip), group(&record.destination_ip))).or_default().add(record);
+    }
+    let mut pairs: Vec<Pair> = pairs
+        .into_iter()
+        .map(|((source, destination), totals)| Pair { source, destination, totals })
+        .collect();
+    pairs.sort_by_key(|pair| std::cmp::Reverse(pair.totals.bytes()));
+    pairs
+}
+
+#[derive(Serialize)]
+struct Rollups<'a> {
+    levels: &'a [Level],
+}
+
+pub fn render(levels: &[Level]) -> String {
+    serde_json::to_string_pretty(&Rollups { levels }).expect("Unable to serialize rollups")
+}
@@ -0,0 +1,416 @@
+//! Detection rules over the aggregated flows, defined in YAML.
+//!
+//! A rules file names CIDR sets and lists rules; every condition of a rule
+//! must hold for a flow to match, and each action then applies to it:
+//!
+//! ```yaml
+//! sets:
+//!   internal: [10.0.0.0/8, 172.16.0.0/12, 192.168.0.0/16]
+//! rules:
+//!   - name: smb-leaving
+//!     description: SMB sessions to outside addresses
+//!     severity: high
+//!     when:
+//!       - { field: destination-port, op: eq, value: 445 }
+//!       - { field: destination-ip, op: not-in, set: internal }
+//!       - { field: bytes, op: gt, value: 1000000 }
+//!     actions: [alert, { tag: smb-external }, export]
+//! ```
+//!
+//! Fields are the record's own names (`bytes` and `packets` sum both
+//! directions, `sessions` is `count`) or `enrichment.<key>`. `gt`/`ge`/
+//! `lt`/`le` compare numbers, `eq`/`ne`/`contains` compare text, and
+//! `in`/`not-in` take `values` or a named `set`, matching addresses by CIDR.
+//! Rules are evaluated once, when the run's records are final.
+//!
+//! An alerting rule may set `key` (a field, the flow key by default) and
+//! `suppress-hours`: a key that alerted within that many hours is held back,
+//! and its repeats are counted into its next alert instead (see `alerts`).
+
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::net::IpAddr;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::cidr::Cidr;
+use crate::record::Record;
+
+/// Flow keys listed per alert, busiest first
+const ALERT_EXAMPLES: usize = 10;
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RulesFile {
+    #[serde(default)]
+    sets: HashMap<String, Vec<String>>,
+    #[serde(default)]
+    rules: Vec<RuleSpec>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+struct RuleSpec {
+    name: String,
+    /// Field identifying "the same" alert for suppression
+    #[serde(default)]
+    key: Option<String>,
+    #[serde(default)]
+    suppress_hours: Option<f64>,
+    #[serde(default)]
+    description: Option<String>,
+    #[serde(default = "default_severity")]
+    severity: String,
+    #[serde(default)]
+    when: Vec<ConditionSpec>,
+    actions: Vec<Action>,
+}
+
+fn default_severity() -> String {
+    "medium".to_string()
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ConditionSpec {
+    field: String,
+    op: Op,
+    #[serde(default)]
+    value: Option<serde_yaml::Value>,
+    #[serde(default)]
+    values: Vec<serde_yaml::Value>,
+    #[serde(default)]
+    set: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+enum Op {
+    Eq,
+    Ne,
+    Gt,
+    Ge,
+    Lt,
+    Le,
+    Contains,
+    In,
+    NotIn,
+}
+
+/// An action as written: a bare name or `{ tag: NAME }`.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ActionSpec {
+    Name(String),
+    Tag { tag: String },
+}
+
+impl TryFrom<ActionSpec> for Action {
+    type Error = String;
+
+    fn try_from(spec: ActionSpec) -> Result<Self, Self::Error> {
+        match spec {
+            ActionSpec::Tag { tag } => Ok(Action::Tag(tag)),
+            ActionSpec::Name(name) => match name.as_str() {
+                "alert" => Ok(Action::Alert),
+                "export" => Ok(Action::Export),
+                _ => Err(format!("unknown action `{}` (alert, export or {{ tag: NAME }})", name)),
+            },
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(try_from = "ActionSpec")]
+pub enum Action {
+    /// Add a tag to the matching records
+    Tag(String),
+    /// Report the matches in the output's `alerts` (and the alert webhook)
+    Alert,
+    /// Include the matching records in `--threat-export`
+    Export,
+}
+
+enum Operand {
+    Text(String),
+    Number(u64),
+    /// `in`/`not-in` members: exact values plus CIDR blocks for addresses
+    Members(Vec<String>, Vec<Cidr>),
+}
+
+struct Condition {
+    field: Field,
+    op: Op,
+    operand: Operand,
+}
+
+pub struct Rule {
+    pub name: String,
+    key: Field,
+    /// Don't alert on the same key again within this long
+    pub suppress_for: Option<Duration>,
+    description: Option<String>,
+    severity: String,
+    conditions: Vec<Condition>,
+    actions: Vec<Action>,
+}
+
+/// A rule's matches in one run.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct RuleAlert {
+    pub rule: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    pub severity: String,
+    pub flows: usize,
+    pub sessions: u64,
+    pub bytes: u64,
+    /// The busiest matching flows
+    pub flow_keys: Vec<String>,
+    /// Alert keys that matched and were not held back
+    pub new_keys: usize,
+    /// The busiest of them
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub keys: Vec<String>,
+    /// Matching keys held back because they alerted within the suppression window
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub suppressed_keys: usize,
+    /// Earlier held-back matches of `keys`, folded into this alert
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub repeats: u64,
+}
+
+fn is_zero<T: Default + PartialEq>(value: &T) -> bool {
+    *value == T::default()
+}
+
+impl RuleAlert {
+    /// Whether anything is left to notify about after suppression.
+    pub fn notifies(&self) -> bool {
+        self.new_keys > 0
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Field {
+    Number(fn(&Record) -> Option<u64>),
+    Text(fn(&Record) -> Option<&str>),
+    Enrichment(String),
+}
+
+fn field(name: &str) -> Option<Field> {
+    if let Some(key) = name.strip_prefix("enrichment.") {
+        return Some(Field::Enrichment(key.to_string()));
+    }
+    Some(match name {
+        "bytes" => Field::Number(|record| Some(record.bytes_in.saturating_add(record.bytes_out))),
+        "bytes-in" => Field::Number(|record| Some(record.bytes_in)),
+        "bytes-out" => Field::Number(|record| Some(record.bytes_out)),
+        "packets" => Field::Number(|record| Some(record.packets_in.saturating_add(record.packets_out))),
+        "packets-in" => Field::Number(|record| Some(record.packets_in)),
+        "packets-out" => Field::Number(|record| Some(record.packets_out)),
+        "sessions" | "count" => Field::Number(|record| Some(record.count)),
+        "duration-ms" => Field::Number(|record| record.duration_ms),
+        "allowed" => Field::Number(|record| record.allowed),
+        "denied" => Field::Number(|record| record.denied),
+        "source-distinct-ports" => Field::Number(|record| record.source_distinct_ports),
+        "source-distinct-destinations" => Field::Number(|record| record.source_distinct_destinations),
+        "destination-distinct-sources" => Field::Number(|record| record.destination_distinct_sources),
+        "icmp-type" => Field::Number(|record| record.icmp_type.map(u64::from)),
+        "icmp-code" => Field::Number(|record| record.icmp_code.map(u64::from)),
+        "key" => Field::Text(|record| Some(&record.key)),
+        "firewall" => Field::Text(|record| Some(&record.firewall)),
+        "source-ip" => Field::Text(|record| Some(&record.source_ip)),
+        "destination-ip" => Field::Text(|record| Some(&record.destination_ip)),
+        "destination-port" => Field::Text(|record| Some(&record.destination_port)),
+        "protocol" => Field::Text(|record| Some(&record.protocol)),
+        "nat-source-ip" => Field::Text(|record| record.nat_source_ip.as_deref()),
+        "nat-destination-ip" => Field::Text(|record| record.nat_destination_ip.as_deref()),
+        "ingress-zone" => Field::Text(|record| record.ingress_zone.as_deref()),
+        "egress-zone" => Field::Text(|record| record.egress_zone.as_deref()),
+        "ingress-interface" => Field::Text(|record| record.ingress_interface.as_deref()),
+        "egress-interface" => Field::Text(|record| record.egress_interface.as_deref()),
+        "vlan" => Field::Text(|record| record.vlan.as_deref()),
+        "tunnel" => Field::Text(|record| record.tunnel.as_deref()),
+        "application" => Field::Text(|record| record.application.as_deref()),
+        "user" => Field::Text(|record| record.user.as_deref()),
+        "source-mac" => Field::Text(|record| record.source_mac.as_deref()),
+        "destination-mac" => Field::Text(|record| record.destination_mac.as_deref()),
+        _ => return None,
+    })
+}
+
+fn scalar(value: &serde_yaml::Value) -> Option<String> {
+    match value {
+        serde_yaml::Value::String(text) => Some(text.clone()),
+        serde_yaml::Value::Number(number) => Some(number.to_string()),
+        serde_yaml::Value::Bool(flag) => Some(flag.to_string()),
+        _ => None,
+    }
+}
+
+impl Field {
+    fn text(&self, record: &Record) -> Option<String> {
+        match self {
+            Field::Number(get) => get(record).map(|number| number.to_string()),
+            Field::Text(get) => get(record).map(str::to_string),
+            Field::Enrichment(key) => record.enrichment.get(key).cloned(),
+        }
+    }
+}
+
+impl Condition {
+    fn compile(spec: ConditionSpec, sets: &HashMap<String, Vec<String>>) -> Result<Self, String> {
+        let field = field(&spec.field).ok_or_else(|| format!("unknown field `{}`", spec.field))?;
+        let operand = match spec.op {
+            Op::In | Op::NotIn => {
+                let mut members: Vec<String> = Vec::new();
+                if let Some(set) = &spec.set {
+                    members.extend(sets.get(set).ok_or_else(|| format!("unknown set `{}`", set))?.iter().cloned());
+                }
+                for value in &spec.values {
+                    members.push(scalar(value).ok_or_else(|| format!("`{}` values must be scalars", spec.field))?);
+                }
+                if members.is_empty() {
+                    return Err(format!("`{:?}` on `{}` needs `values` or a `set`", spec.op, spec.field).to_lowercase());
+                }
+                let (blocks, exact): (Vec<String>, Vec<String>) = members.into_iter().partition(|member| member.contains('/'));
+                let blocks = blocks
+                    .iter()
+                    .map(|block| block.parse::<Cidr>().map_err(|err| format!("`{}`: {}", block, err)))
+                    .collect::<Result<_, _>>()?;
+                Operand::Members(exact, blocks)
+            }
+            op => {
+                let value = spec.value.as_ref().and_then(scalar).ok_or_else(|| format!("`{}` needs a scalar `value`", spec.field))?;
+                match op {
+                    Op::Gt | Op::Ge | Op::Lt | Op::Le => Operand::Number(
+                        value.parse().map_err(|_| format!("`{}` compares numbers, got `{}`", spec.field, value))?,
+                    ),
+                    _ => Operand::Text(value),
+                }
+            }
+        };
+        Ok(Condition { field, op: spec.op, operand })
+    }
+
+    fn holds(&self, record: &Record) -> bool {
+        let Some(text) = self.field.text(record) else {
+            // An absent field only satisfies negations
+            return matches!(self.op, Op::Ne | Op::NotIn);
+        };
+        match (&self.operand, self.op) {
+            (Operand::Number(wanted), op) => {
+                let Ok(value) = text.parse::<u64>() else {
+                    return false;
+                };
+                match op {
+                    Op::Gt => value > *wanted,
+                    Op::Ge => value >= *wanted,
+                    Op::Lt => value < *wanted,
+                    _ => value <= *wanted,
+                }
+            }
+            (Operand::Text(wanted), Op::Eq) => text == *wanted,
+            (Operand::Text(wanted), Op::Ne) => text != *wanted,
+            (Operand::Text(wanted), _) => text.contains(wanted.as_str()),
+            (Operand::Members(exact, blocks), op) => {
+                let address = text.parse::<IpAddr>().ok();
+                let member = exact.contains(&text) || address.is_some_and(|address| blocks.iter().any(|block| block.contains(address)));
+                member == (op == Op::In)
+            }
+        }
+    }
+}
+
+impl Rule {
+    fn matches(&self, record: &Record) -> bool {
+        self.conditions.iter().all(|condition| condition.holds(record))
+    }
+}
+
+/// Read and check every rules file.
+pub fn load(paths: &[impl AsRef<Path>]) -> Result<Vec<Rule>, String> {
+    let mut rules = Vec::new();
+    for path in paths {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path).map_err(|err| format!("{}: {}", path.display(), err))?;
+        let file: RulesFile = serde_yaml::from_str(&text).map_err(|err| format!("{}: {}", path.display(), err))?;
+        for spec in file.rules {
+            let name = spec.name.clone();
+            let invalid = |err: String| format!("{}: rule `{}`: {}", path.display(), name, err);
+            let key_name = spec.key.as_deref().unwrap_or("key");
+            let key = field(key_name).ok_or_else(|| invalid(format!("unknown key field `{}`", key_name)))?;
+            let suppress_for = match spec.suppress_hours {
+                Some(hours) if hours.is_finite() && hours >= 0.0 => Some(Duration::from_secs_f64(hours * 3600.0)),
+                Some(hours) => return Err(invalid(format!("suppress-hours must not be negative, got {}", hours))),
+                None => None,
+            };
+            let conditions = spec
+                .when
+                .into_iter()
+                .map(|condition| Condition::compile(condition, &file.sets))
+                .collect::<Result<_, _>>()
+                .map_err(invalid)?;
+            rules.push(Rule {
+                name,
+                key,
+                suppress_for,
+                description: spec.description,
+                severity: spec.severity,
+                conditions,
+                actions: spec.actions,
+            });
+        }
+    }
+    Ok(rules)
+}
+
+pub fn exports(rules: &[Rule]) -> bool {
+    rules.iter().any(|rule| rule.actions.contains(&Action::Export))
+}
+
+/// Apply every rule to the records: tag them, mark them for export and
+/// collect the alerts of rules that matched anything.
+pub fn evaluate(rules: &[Rule], records: &mut HashMap<Arc<str>, Record>) -> Vec<RuleAlert> {
+    let mut alerts = Vec::new();
+    for rule in rules {
+        let mut matched: Vec<&mut Record> = records.values_mut().filter(|record| rule.matches(record)).collect();
+        if matched.is_empty() {
+            continue;
+        }
+        for record in matched.iter_mut() {
+            record.matched_rules.push(rule.name.clone());
+            for action in &rule.actions {
+                match action {
+                    Action::Tag(tag) if !record.tags.contains(tag) => record.tags.push(tag.clone()),
+                    Action::Export => record.exported = true,
+                    _ => {}
+                }
+            }
+        }
+        if rule.actions.contains(&Action::Alert) {
+            matched.sort_by(|a, b| b.bytes_in.saturating_add(b.bytes_out).cmp(&a.bytes_in.saturating_add(a.bytes_out)).then_with(|| a.key.cmp(&b.key)));
+            let mut seen = HashSet::new();
+            let keys: Vec<String> = matched.iter().filter_map(|record| rule.key.text(record)).filter(|key| seen.insert(key.clone())).collect();
+            alerts.push(RuleAlert {
+                rule: rule.name.clone(),
+                description: rule.description.clone(),
+                severity: rule.severity.clone(),
+                flows: matched.len(),
+                sessions: matched.iter().map(|record| record.count).sum(),
+                bytes: matched.iter().map(|record| record.bytes_in.saturating_add(record.bytes_out)).fold(0, u64::saturating_add),
+                flow_keys: matched.iter().take(ALERT_EXAMPLES).map(|record| record.key.to_string()).collect(),
+                new_keys: keys.len(),
+                keys,
+                suppressed_keys: 0,
+                repeats: 0,
+            });
+        }
+    }
+    alerts
+}
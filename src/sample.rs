@@ -0,0 +1,40 @@
+//! Reservoir sampling of the raw lines behind each flow record.
+//!
+//! Every line of a flow has the same chance of being kept, however many
+//! lines the flow has, using Algorithm R over the record's session count.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Small splitmix64 generator; sampling needs speed, not cryptography.
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn from_time() -> Self {
+        Rng(SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos() as u64)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+
+    /// Uniform in `0..bound`.
+    pub fn below(&mut self, bound: u64) -> u64 {
+        ((self.next_u64() as u128 * bound as u128) >> 64) as u64
+    }
+}
+
+/// Offer the `seen`-th line (1-based) of a flow to its reservoir of `size`.
+pub fn offer(reservoir: &mut Vec<String>, size: usize, seen: u64, line: &str, rng: &mut Rng) {
+    if reservoir.len() < size {
+        reservoir.push(line.to_string());
+        return;
+    }
+    let slot = rng.below(seen) as usize;
+    if slot < size {
+        reservoir[slot] = line.to_string();
+    }
+}
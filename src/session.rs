@@ -0,0 +1,170 @@
+//! Correlation of separately logged session opens and closes.
+//!
+//! Devices that log a session's start and end as two lines tag both with a
+//! session id. Opens are held until their close arrives so the session's
+//! duration can be derived from the two timestamps; opens that see no close
+//! within the timeout (measured against the newest timestamp seen) are
+//! dropped and counted as expired. An open logged without a timestamp
+//! times out from when it arrived: the newest timestamp seen by then, or
+//! the clock when none has been.
+
+use std::collections::HashMap;
+
+use chrono::{DateTime, FixedOffset, TimeDelta, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::parser::FlowEvent;
+
+/// Number of correlated events between sweeps for expired opens.
+const SWEEP_INTERVAL: u64 = 4096;
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct CorrelationStats {
+    pub opened: u64,
+    pub matched: u64,
+    pub unmatched_closes: u64,
+    pub expired_opens: u64,
+    pub open_at_end: u64,
+}
+
+/// An open waiting for its close.
+struct Open {
+    /// When it was logged, if the line says
+    logged: Option<DateTime<FixedOffset>>,
+    /// What its timeout counts from
+    since: DateTime<FixedOffset>,
+}
+
+pub struct SessionCorrelator {
+    timeout: TimeDelta,
+    open: HashMap<(String, String), Open>,
+    latest: Option<DateTime<FixedOffset>>,
+    since_sweep: u64,
+    stats: CorrelationStats,
+}
+
+impl SessionCorrelator {
+    pub fn new(timeout_secs: u64) -> Self {
+        // A timeout past what a TimeDelta holds never expires anything anyway
+        let timeout = i64::try_from(timeout_secs).ok().and_then(TimeDelta::try_seconds).unwrap_or(TimeDelta::MAX);
+        SessionCorrelator {
+            timeout,
+            open: HashMap::new(),
+            latest: None,
+            since_sweep: 0,
+            stats: CorrelationStats::default(),
+        }
+    }
+
+    /// Remember a session start until its close arrives.
+    pub fn open(&mut self, event: &FlowEvent) {
+        self.stats.opened += 1;
+        self.observe(event.timestamp);
+        if let Some(id) = &event.session_id {
+            let since = event.timestamp.unwrap_or_else(|| self.now());
+            self.open.insert((event.firewall.clone(), id.clone()), Open { logged: event.timestamp, since });
+        }
+    }
+
+    /// Match a session end to its open, filling in the duration from the
+    /// two timestamps when both are known.
+    pub fn close(&mut self, event: &mut FlowEvent) {
+        self.observe(event.timestamp);
+        let open = event
+            .session_id
+            .as_ref()
+            .and_then(|id| self.open.remove(&(event.firewall.clone(), id.clone())));
+
+        let Some(open) = open else {
+            self.stats.unmatched_closes += 1;
+            return;
+        };
+        self.stats.matched += 1;
+
+        if let (Some(start), Some(end)) = (open.logged, event.timestamp)
+            && let Ok(ms) = u64::try_from((end - start).num_milliseconds())
+        {
+            event.duration_ms = Some(ms);
+        }
+    }
+
+    /// Whether any open or close event went through the correlator.
+    pub fn is_active(&self) -> bool {
+        self.stats.opened > 0 || self.stats.matched > 0 || self.stats.unmatched_closes > 0
+    }
+
+    /// Finish the run; sessions still waiting for a close are reported as
+    /// never closed.
+    pub fn finish(mut self) -> CorrelationStats {
+        self.sweep();
+        self.stats.open_at_end = self.open.len() as u64;
+        self.stats
+    }
+
+    fn observe(&mut self, timestamp: Option<DateTime<FixedOffset>>) {
+        if let Some(ts) = timestamp
+            && self.latest.is_none_or(|latest| ts > latest)
+        {
+            self.latest = Some(ts);
+        }
+
+        self.since_sweep += 1;
+        if self.since_sweep >= SWEEP_INTERVAL {
+            self.sweep();
+        }
+    }
+
+    /// The newest timestamp seen, or the clock before there is one.
+    fn now(&self) -> DateTime<FixedOffset> {
+        self.latest.unwrap_or_else(|| Utc::now().fixed_offset())
+    }
+
+    fn sweep(&mut self) {
+        self.since_sweep = 0;
+        // Nothing can be older than a cutoff before the earliest time there is
+        let Some(cutoff) = self.now().checked_sub_signed(self.timeout) else {
+            return;
+        };
+        let before = self.open.len();
+        self.open.retain(|_, open| open.since >= cutoff);
+        self.stats.expired_opens += (before - self.open.len()) as u64;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(id: &str, at: &str) -> FlowEvent {
+        FlowEvent {
+            firewall: "fw1".to_string(),
+            session_id: Some(id.to_string()),
+            timestamp: Some(DateTime::parse_from_rfc3339(at).unwrap()),
+            ..FlowEvent::default()
+        }
+    }
+
+    #[test]
+    fn opens_expire_after_the_timeout() {
+        let mut correlator = SessionCorrelator::new(60);
+        correlator.open(&event("1", "2025-08-29T11:00:00+00:00"));
+        correlator.open(&event("2", "2025-08-29T11:02:00+00:00"));
+        let mut close = event("2", "2025-08-29T11:02:30+00:00");
+        correlator.close(&mut close);
+        assert_eq!(close.duration_ms, Some(30_000));
+        let stats = correlator.finish();
+        assert_eq!((stats.opened, stats.matched, stats.expired_opens, stats.open_at_end), (2, 1, 1, 0));
+    }
+
+    #[test]
+    fn timeouts_past_what_a_time_delta_holds_never_expire() {
+        for timeout in [u64::MAX, i64::MAX as u64, 1 << 50] {
+            let mut correlator = SessionCorrelator::new(timeout);
+            correlator.open(&event("1", "2025-08-29T11:00:00+00:00"));
+            correlator.open(&event("2", "2025-08-29T11:02:00+00:00"));
+            let stats = correlator.finish();
+            assert_eq!((stats.expired_opens, stats.open_at_end), (0, 2), "{}", timeout);
+        }
+    }
+}
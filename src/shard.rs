@@ -0,0 +1,168 @@
+//! Live flow records spread over worker threads.
+//!
+//! Each worker owns the records of the flow keys that hash to it, so no
+//! record is ever shared and the workers need no locks. Events are routed
+//! in batches to keep channel traffic low; a drain or a request for the
+//! changed records is queued behind them, so it always sees every event
+//! sent before it.
+
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::sync::mpsc::{self, Receiver, Sender, SyncSender};
+use std::thread::{self, JoinHandle};
+
+use crate::aggregate::{Approximation, FlowOptions, Flows};
+use crate::intern::Interner;
+use crate::parser::FlowEvent;
+use crate::record::{FlowKey, Record};
+
+/// Events sent to a worker at a time.
+const BATCH: usize = 1024;
+/// Batches queued per worker before the parsing thread waits.
+const QUEUED: usize = 16;
+
+/// An event with its flow key, raw line and sequence number.
+type Keyed = (FlowKey, FlowEvent, String, u64);
+
+/// Records changed since a sequence number, and the flows evicted since.
+type Since = (Vec<(FlowKey, Record)>, Vec<FlowKey>);
+
+enum Message {
+    Events(Vec<Keyed>),
+    Drain(Sender<Vec<(FlowKey, Record)>>),
+    Since { since: u64, forget: u64, reply: Sender<Since> },
+}
+
+struct Worker {
+    tx: SyncSender<Message>,
+    handle: JoinHandle<Option<Approximation>>,
+}
+
+pub(crate) struct Shards {
+    workers: Vec<Worker>,
+    pending: Vec<Vec<Keyed>>,
+    options: FlowOptions,
+    /// The flow cap before it was split
+    max_flows: Option<usize>,
+}
+
+impl Shards {
+    /// Start `count` workers. A flow cap is split evenly between them.
+    pub(crate) fn start(count: usize, mut options: FlowOptions) -> Shards {
+        let requested = options.max_flows.map(|(max_flows, _, _)| max_flows);
+        if let Some((max_flows, width, depth)) = options.max_flows {
+            options.max_flows = Some((max_flows.div_ceil(count), width, depth));
+        }
+        let workers = (0..count)
+            .map(|_| {
+                let (tx, rx) = mpsc::sync_channel(QUEUED);
+                let handle = thread::spawn(move || work(rx, options));
+                Worker { tx, handle }
+            })
+            .collect();
+        Shards {
+            workers,
+            pending: (0..count).map(|_| Vec::with_capacity(BATCH)).collect(),
+            options,
+            max_flows: requested,
+        }
+    }
+
+    pub(crate) fn update(&mut self, key: FlowKey, event: FlowEvent, line: &str, sequence: u64) {
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+        let shard = (hasher.finish() % self.workers.len() as u64) as usize;
+        // Only sampling needs the raw line
+        let line = if self.options.sample_lines > 0 { line.to_string() } else { String::new() };
+        self.pending[shard].push((key, event, line, sequence));
+        if self.pending[shard].len() >= BATCH {
+            self.send(shard);
+        }
+    }
+
+    fn send(&mut self, shard: usize) {
+        let batch = std::mem::replace(&mut self.pending[shard], Vec::with_capacity(BATCH));
+        self.workers[shard].tx.send(Message::Events(batch)).expect("Shard worker stopped");
+    }
+
+    /// Every worker's live records, taken out of it.
+    pub(crate) fn drain(&mut self) -> Vec<(FlowKey, Record)> {
+        let replies = self.ask(Message::Drain);
+        replies.into_iter().flat_map(|rx| rx.recv().expect("Shard worker stopped")).collect()
+    }
+
+    /// Copies of every worker's records changed after `since`, and the
+    /// flows evicted after it, as [`Flows::since`] gives them.
+    pub(crate) fn since(&mut self, since: u64, forget: u64) -> Since {
+        let replies = self.ask(|reply| Message::Since { since, forget, reply });
+        let (mut records, mut evicted) = (Vec::new(), Vec::new());
+        for rx in replies {
+            let (changed, gone) = rx.recv().expect("Shard worker stopped");
+            records.extend(changed);
+            evicted.extend(gone);
+        }
+        (records, evicted)
+    }
+
+    /// Send every worker the message `request` makes, behind its pending
+    /// events, returning where each replies.
+    fn ask<T>(&mut self, request: impl Fn(Sender<T>) -> Message) -> Vec<Receiver<T>> {
+        let mut replies = Vec::with_capacity(self.workers.len());
+        for shard in 0..self.workers.len() {
+            if !self.pending[shard].is_empty() {
+                self.send(shard);
+            }
+            let (tx, rx) = mpsc::channel();
+            self.workers[shard].tx.send(request(tx)).expect("Shard worker stopped");
+            replies.push(rx);
+        }
+        replies
+    }
+
+    /// Stop the workers, combining their overflow statistics. Records have
+    /// to be drained first.
+    pub(crate) fn finish(self) -> Option<Approximation> {
+        let mut combined: Option<Approximation> = None;
+        for worker in self.workers {
+            drop(worker.tx);
+            let Some(stats) = worker.handle.join().expect("Shard worker panicked") else {
+                continue;
+            };
+            match &mut combined {
+                Some(combined) => {
+                    combined.overflow_events += stats.overflow_events;
+                    combined.overflow_bytes = combined.overflow_bytes.saturating_add(stats.overflow_bytes);
+                    combined.overflow_packets = combined.overflow_packets.saturating_add(stats.overflow_packets);
+                    combined.evicted_flows += stats.evicted_flows;
+                }
+                None => combined = Some(stats),
+            }
+        }
+        // Reported against the cap that was asked for, not a shard's part of it
+        if let (Some(combined), Some(max_flows)) = (&mut combined, self.max_flows) {
+            combined.max_flows = max_flows;
+        }
+        combined
+    }
+}
+
+fn work(rx: Receiver<Message>, options: FlowOptions) -> Option<Approximation> {
+    let mut flows = Flows::new();
+    let mut strings = Interner::default();
+    for message in rx {
+        match message {
+            Message::Events(events) => {
+                for (key, event, line, sequence) in events {
+                    flows.update(key, &event, &line, sequence, &options, &mut strings);
+                }
+            }
+            Message::Drain(reply) => {
+                let _ = reply.send(flows.drain());
+            }
+            Message::Since { since, forget, reply } => {
+                let _ = reply.send(flows.since(since, forget));
+            }
+        }
+    }
+    flows.approximation()
+}
@@ -0,0 +1,136 @@
+//! ClickHouse sink over the HTTP interface.
+//!
+//! Records are inserted in batches with `FORMAT JSONEachRow`, one row per
+//! flow tagged with the window (the run's start time, UTC) and the run's
+//! ID. Unknown
+//! columns are skipped, so the table only needs the columns it cares about,
+//! for example:
+//!
+//! ```sql
+//! CREATE TABLE flows (
+//!     window DateTime64(3), run_id String, key String, source_ip String, destination_ip String,
+//!     packets_in UInt64, bytes_in UInt64, packets_out UInt64, bytes_out UInt64, count UInt64
+//! ) ENGINE = MergeTree ORDER BY (window, key)
+//! ```
+
+use chrono::DateTime;
+use serde::Serialize;
+use ureq::Agent;
+
+use super::{Batch, Sink, SinkError};
+
+#[derive(Serialize)]
+struct Row<'a> {
+    window: &'a str,
+    run_id: &'a str,
+    key: &'a str,
+    source_ip: &'a str,
+    destination_ip: &'a str,
+    packets_in: u64,
+    bytes_in: u64,
+    packets_out: u64,
+    bytes_out: u64,
+    count: u64,
+}
+
+pub struct ClickHouseSink {
+    agent: Agent,
+    url: String,
+    table: String,
+    user: Option<String>,
+    password: Option<String>,
+    batch_size: usize,
+    /// Rows serialized but not sent yet
+    body: Vec<u8>,
+    rows: usize,
+}
+
+impl ClickHouseSink {
+    pub fn new(url: &str, table: &str, user: Option<&str>, password: Option<&str>, batch_size: usize) -> Self {
+        ClickHouseSink {
+            agent: super::http_agent(),
+            url: url.to_string(),
+            table: table.to_string(),
+            user: user.map(str::to_string),
+            password: password.map(str::to_string),
+            batch_size: batch_size.max(1),
+            body: Vec::new(),
+            rows: 0,
+        }
+    }
+
+    fn send(&mut self) -> Result<(), ureq::Error> {
+        let body = std::mem::take(&mut self.body);
+        self.rows = 0;
+        let query = format!("INSERT INTO {} FORMAT JSONEachRow", self.table);
+        let mut request = self
+            .agent
+            .post(&self.url)
+            .query("query", &query)
+            .query("input_format_skip_unknown_fields", "1")
+            .query("date_time_input_format", "best_effort");
+        if let Some(user) = &self.user {
+            request = request.header("X-ClickHouse-User", user);
+        }
+        if let Some(password) = &self.password {
+            request = request.header("X-ClickHouse-Key", password);
+        }
+        request.send(&body[..])?;
+        Ok(())
+    }
+}
+
+impl Sink for ClickHouseSink {
+    fn name(&self) -> &'static str {
+        "clickhouse"
+    }
+
+    fn target(&self) -> &str {
+        &self.table
+    }
+
+    fn batch_size(&self) -> Option<usize> {
+        Some(self.batch_size)
+    }
+
+    /// Insert every record, sending a request per `batch_size` rows; the
+    /// last, partly filled one waits for `flush`.
+    fn write_batch(&mut self, batch: &Batch) -> Result<usize, SinkError> {
+        // Rows left from an attempt that failed are part of this batch again
+        self.body.clear();
+        self.rows = 0;
+        let window = DateTime::from_timestamp_millis(batch.window as i64)
+            .unwrap_or_default()
+            .format("%Y-%m-%d %H:%M:%S%.3f")
+            .to_string();
+
+        for record in batch.records {
+            let row = Row {
+                window: &window,
+                run_id: batch.run_id,
+                key: &record.key,
+                source_ip: &record.source_ip,
+                destination_ip: &record.destination_ip,
+                packets_in: record.packets_in,
+                bytes_in: record.bytes_in,
+                packets_out: record.packets_out,
+                bytes_out: record.bytes_out,
+                count: record.count,
+            };
+            serde_json::to_writer(&mut self.body, &row).expect("Unable to serialize ClickHouse row");
+            self.body.push(b'\n');
+            self.rows += 1;
+            if self.rows == self.batch_size {
+                self.send()?;
+            }
+        }
+        Ok(batch.records.len())
+    }
+
+    fn flush(&mut self) -> Result<(), SinkError> {
+        if self.rows > 0 {
+            self.send()?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,146 @@
+//! Dead-letter directory for sink deliveries that were given up on.
+//!
+//! When a sink still fails after its retries, the records it hadn't
+//! received yet are kept as `<window>-<sink>.json` together with what is needed
+//! to send them again. `redeliver` replays the directory against the sinks
+//! configured at that point and removes each entry once it is accepted.
+
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+
+use super::Batch;
+use crate::atomic;
+use crate::payload::ByKey;
+use crate::record::Record;
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Entry<'a> {
+    sink: &'a str,
+    target: &'a str,
+    window: u128,
+    run_id: &'a str,
+    connections: u64,
+    session_close: u64,
+    error: &'a str,
+    records: ByKey<'a>,
+}
+
+/// A delivery read back from the dead-letter directory.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DeadLetter {
+    pub sink: String,
+    /// Where the failed delivery was going
+    pub target: String,
+    /// Start time of the run the records were aggregated in
+    pub window: u128,
+    /// The run that aggregated them, so redelivered entries keep its ID
+    #[serde(default)]
+    pub run_id: String,
+    pub connections: u64,
+    pub session_close: u64,
+    pub error: String,
+    pub records: HashMap<Arc<str>, Record>,
+}
+
+/// Keep the records a sink failed to take, returning the entry's path.
+pub fn store(dir: &Path, status: &super::SinkStatus, batch: &Batch) -> io::Result<PathBuf> {
+    fs::create_dir_all(dir)?;
+    let path = dir.join(format!("{}-{}.json", batch.window, status.sink));
+    let entry = Entry {
+        sink: &status.sink,
+        target: &status.target,
+        window: batch.window,
+        run_id: batch.run_id,
+        connections: batch.connections,
+        session_close: batch.session_close,
+        error: status.error.as_deref().unwrap_or_default(),
+        records: ByKey(batch.records),
+    };
+    atomic::write_with(&path, |out| serde_json::to_writer(out, &entry).map_err(io::Error::from))?;
+    Ok(path)
+}
+
+impl DeadLetter {
+    /// The kept records, one by one.
+    pub fn records(&self) -> Vec<&Record> {
+        self.records.values().collect()
+    }
+
+    /// The kept `records`, to hand to the sink again.
+    pub fn batch<'a>(&'a self, records: &'a [&'a Record]) -> Batch<'a> {
+        Batch {
+            window: self.window,
+            run_id: &self.run_id,
+            connections: self.connections,
+            session_close: self.session_close,
+            records,
+        }
+    }
+}
+
+pub fn load(path: &Path) -> io::Result<DeadLetter> {
+    Ok(serde_json::from_slice(&fs::read(path)?)?)
+}
+
+/// Entries waiting in `dir`, oldest window first.
+pub fn pending(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut paths: Vec<_> = fs::read_dir(dir)?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| {
+            let name = path.file_name().map(|name| name.to_string_lossy()).unwrap_or_default();
+            // Dotfiles are entries still being written
+            !name.starts_with('.') && name.ends_with(".json")
+        })
+        .collect();
+    paths.sort();
+    Ok(paths)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intern::Interner;
+    use crate::parser::FlowEvent;
+    use crate::record::NatSide;
+    use crate::sink::SinkStatus;
+
+    #[test]
+    fn stored_deliveries_are_read_back_oldest_first() {
+        let dir = std::env::temp_dir().join(format!("syslog_processor-deadletter-{}", std::process::id()));
+        let event = FlowEvent { source_ip: "10.0.0.1".to_string(), destination_ip: "10.0.0.2".to_string(), ..FlowEvent::default() };
+        let mut record = Record::new(&event, NatSide::PreNat, &mut Interner::default());
+        record.key = Arc::from("10.0.0.1_10.0.0.2");
+        let records = [&record];
+        let status = SinkStatus {
+            sink: "redis".to_string(),
+            target: "redis://localhost".to_string(),
+            delivered: false,
+            attempts: 3,
+            written: None,
+            error: Some("connection refused".to_string()),
+            dead_letter: None,
+        };
+        for window in [20, 10] {
+            let batch = Batch { window, run_id: "run", connections: 7, session_close: 5, records: &records };
+            store(&dir, &status, &batch).unwrap();
+        }
+        fs::write(dir.join(".30-redis.json"), "{").unwrap();
+
+        let paths = pending(&dir).unwrap();
+        let names: Vec<_> = paths.iter().map(|path| path.file_name().unwrap().to_string_lossy().into_owned()).collect();
+        assert_eq!(names, ["10-redis.json", "20-redis.json"]);
+        let letter = load(&paths[0]).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!((letter.sink.as_str(), letter.target.as_str(), letter.error.as_str()), ("redis", "redis://localhost", "connection refused"));
+        assert_eq!((letter.window, letter.run_id.as_str(), letter.connections, letter.session_close), (10, "run", 7, 5));
+        assert_eq!(letter.records().len(), 1);
+        assert!(letter.records.contains_key("10.0.0.1_10.0.0.2"));
+    }
+}
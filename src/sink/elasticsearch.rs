@@ -0,0 +1,129 @@
+//! Elasticsearch (or OpenSearch) sink over the `_bulk` API.
+//!
+//! Each record is indexed as one document carrying the window (the run's
+//! start time, UTC), the run's ID and its key. Document IDs are derived from both, so a
+//! retried delivery overwrites the documents it already wrote instead of
+//! duplicating them.
+
+use std::collections::HashMap;
+
+use chrono::DateTime;
+use serde::{Deserialize, Serialize};
+use ureq::Agent;
+
+use super::{Batch, Sink, SinkError};
+use crate::record::Record;
+
+#[derive(Serialize)]
+struct Document<'a> {
+    window: &'a str,
+    run_id: &'a str,
+    #[serde(flatten)]
+    record: &'a Record,
+}
+
+#[derive(Deserialize)]
+struct BulkResponse {
+    errors: bool,
+    #[serde(default)]
+    items: Vec<HashMap<String, BulkItem>>,
+}
+
+#[derive(Deserialize)]
+struct BulkItem {
+    #[serde(default)]
+    error: Option<serde_json::Value>,
+}
+
+pub struct ElasticsearchSink {
+    agent: Agent,
+    url: String,
+    index: String,
+    api_key: Option<String>,
+    batch_size: usize,
+    /// Bulk actions serialized but not sent yet
+    body: Vec<u8>,
+    documents: usize,
+}
+
+impl ElasticsearchSink {
+    pub fn new(url: &str, index: &str, api_key: Option<&str>, batch_size: usize) -> Self {
+        ElasticsearchSink {
+            agent: super::http_agent(),
+            url: url.trim_end_matches('/').to_string(),
+            index: index.to_string(),
+            api_key: api_key.map(str::to_string),
+            batch_size: batch_size.max(1),
+            body: Vec::new(),
+            documents: 0,
+        }
+    }
+
+    fn send(&mut self) -> Result<(), String> {
+        let body = std::mem::take(&mut self.body);
+        self.documents = 0;
+        let mut request = self
+            .agent
+            .post(&format!("{}/_bulk", self.url))
+            .header("Content-Type", "application/x-ndjson");
+        if let Some(api_key) = &self.api_key {
+            request = request.header("Authorization", &format!("ApiKey {}", api_key));
+        }
+        let response: BulkResponse = request
+            .send(&body[..])
+            .and_then(|mut response| response.body_mut().read_json())
+            .map_err(|err| err.to_string())?;
+
+        // A 200 response can still carry per-document failures
+        if response.errors {
+            let failed: Vec<_> = response.items.iter().flat_map(|item| item.values()).filter_map(|item| item.error.as_ref()).collect();
+            let first = failed.first().map(|error| error.to_string()).unwrap_or_default();
+            return Err(format!("{} documents rejected, first: {}", failed.len(), first));
+        }
+        Ok(())
+    }
+}
+
+impl Sink for ElasticsearchSink {
+    fn name(&self) -> &'static str {
+        "elasticsearch"
+    }
+
+    fn target(&self) -> &str {
+        &self.index
+    }
+
+    fn batch_size(&self) -> Option<usize> {
+        Some(self.batch_size)
+    }
+
+    /// Index every record, sending a bulk request per `batch_size`
+    /// documents; the last, partly filled one waits for `flush`.
+    fn write_batch(&mut self, batch: &Batch) -> Result<usize, SinkError> {
+        // Documents left from an attempt that failed are part of this batch again
+        self.body.clear();
+        self.documents = 0;
+        let window_text = DateTime::from_timestamp_millis(batch.window as i64).unwrap_or_default().to_rfc3339();
+
+        for record in batch.records {
+            let action = serde_json::json!({ "index": { "_index": self.index, "_id": format!("{}_{}", batch.window, record.key) } });
+            serde_json::to_writer(&mut self.body, &action).expect("Unable to serialize Elasticsearch action");
+            self.body.push(b'\n');
+            let document = Document { window: &window_text, run_id: batch.run_id, record };
+            serde_json::to_writer(&mut self.body, &document).expect("Unable to serialize Elasticsearch document");
+            self.body.push(b'\n');
+            self.documents += 1;
+            if self.documents == self.batch_size {
+                self.send()?;
+            }
+        }
+        Ok(batch.records.len())
+    }
+
+    fn flush(&mut self) -> Result<(), SinkError> {
+        if self.documents > 0 {
+            self.send()?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,123 @@
+//! The output file, as a sink.
+//!
+//! The payload is streamed into a hidden temporary file next to the output,
+//! and `flush` puts it in place and adds it to the manifest of its
+//! directory, so readers only ever see complete outputs. A path of `-`
+//! writes to stdout instead, with no manifest. Failures are the run's own
+//! [`Error`]s, boxed.
+
+use std::fs;
+use std::io::{self, BufWriter};
+use std::path::{Path, PathBuf};
+
+use super::{Batch, Sink, SinkError};
+use crate::atomic::Staged;
+use crate::error::{self, Error};
+use crate::manifest::{HashingWriter, ManifestEntry};
+use crate::payload::{self, Metadata, OutputFormat};
+use crate::record::Record;
+use crate::space;
+
+pub struct FileSink<'a> {
+    /// The output file, or `-` for stdout
+    path: String,
+    metadata: &'a Metadata,
+    format: OutputFormat,
+    /// Free space to leave beyond the output's size, in percent; `None`
+    /// skips the check
+    space_margin: Option<f64>,
+    time_range: Option<(String, String)>,
+    /// The output written but not in place yet, with its digest and flows
+    staged: Option<(Staged, (String, u64), usize)>,
+}
+
+impl<'a> FileSink<'a> {
+    pub fn new(path: &str, metadata: &'a Metadata, format: OutputFormat, space_margin: Option<f64>, time_range: Option<(String, String)>) -> Self {
+        FileSink {
+            path: path.to_string(),
+            metadata,
+            format,
+            space_margin,
+            time_range,
+            staged: None,
+        }
+    }
+
+    /// The run's error a failure of this sink boxes.
+    pub fn error(&self, err: SinkError) -> Error {
+        match err.downcast::<Error>() {
+            Ok(err) => *err,
+            Err(err) => Error::Write { path: self.path.clone().into(), source: io::Error::other(err.to_string()) },
+        }
+    }
+
+    fn dir(&self) -> PathBuf {
+        let dir = Path::new(&self.path).parent().filter(|dir| !dir.as_os_str().is_empty());
+        dir.unwrap_or(Path::new(".")).to_path_buf()
+    }
+
+    fn write(&mut self, records: &[&Record]) -> error::Result<()> {
+        // Dropping an earlier attempt's output removes it
+        self.staged = None;
+        if self.path == "-" {
+            // A closed pipe (e.g. `| head`) just means the reader has seen enough
+            return match payload::write_records(BufWriter::new(io::stdout().lock()), self.metadata, records, self.format, false) {
+                Err(err) if err.kind() != io::ErrorKind::BrokenPipe => Err(Error::Write { path: "<stdout>".into(), source: err }),
+                _ => Ok(()),
+            };
+        }
+
+        let dir = self.dir();
+        fs::create_dir_all(&dir).map_err(|source| Error::CreateDir { path: dir.clone(), source })?;
+        if let Some(margin) = self.space_margin
+            && let Ok(available) = space::available(&dir)
+        {
+            let mut counter = space::Counter::default();
+            // Counting can't fail; a serialization error surfaces when writing
+            let _ = payload::write_records(&mut counter, self.metadata, records, self.format, true);
+            let needed = space::needed(counter.0, margin);
+            if needed > available {
+                return Err(Error::NoSpace { path: self.path.clone().into(), needed, available });
+            }
+        }
+
+        let mut staged = Staged::create(Path::new(&self.path)).map_err(Error::write(&self.path))?;
+        let mut out = HashingWriter::new(staged.out());
+        payload::write_records(&mut out, self.metadata, records, self.format, true).map_err(Error::write(&self.path))?;
+        let digest = out.finish();
+        self.staged = Some((staged, digest, records.len()));
+        Ok(())
+    }
+
+    fn commit(&mut self) -> error::Result<()> {
+        let Some((staged, digest, flows)) = self.staged.take() else {
+            return Ok(());
+        };
+        staged.commit().map_err(Error::write(&self.path))?;
+        let dir = self.dir();
+        ManifestEntry::for_written(Path::new(&self.path), digest, flows, self.time_range.clone())
+            .append_to(&dir)
+            .map_err(|source| Error::Manifest { path: dir, source })
+    }
+}
+
+impl Sink for FileSink<'_> {
+    fn name(&self) -> &'static str {
+        "file"
+    }
+
+    fn target(&self) -> &str {
+        &self.path
+    }
+
+    /// Write the payload of the run's metadata and the batch's records;
+    /// the file is only put in place by `flush`.
+    fn write_batch(&mut self, batch: &Batch) -> Result<usize, SinkError> {
+        self.write(batch.records)?;
+        Ok(batch.records.len())
+    }
+
+    fn flush(&mut self) -> Result<(), SinkError> {
+        Ok(self.commit()?)
+    }
+}
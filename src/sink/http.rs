@@ -0,0 +1,125 @@
+//! Generic HTTP sink, for services that just want the records POSTed to
+//! them.
+//!
+//! The body is either one JSON document per run, holding the run's window,
+//! ID and counts next to its records by key, or NDJSON with one record per
+//! line, split into requests of a set number of records. Either can be
+//! gzip-compressed, and a bearer token sent with it.
+
+use std::io::Write;
+
+use flate2::Compression;
+use flate2::write::GzEncoder;
+use serde::Serialize;
+use ureq::Agent;
+
+use super::{Batch, Sink, SinkError};
+use crate::payload::{ByKey, OutputFormat};
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Document<'a> {
+    window: u128,
+    run_id: &'a str,
+    connections: u64,
+    session_close: u64,
+    data: ByKey<'a>,
+}
+
+pub struct HttpSink {
+    agent: Agent,
+    url: String,
+    token: Option<String>,
+    format: OutputFormat,
+    gzip: bool,
+    batch_size: usize,
+    /// NDJSON lines serialized but not sent yet
+    body: Vec<u8>,
+    records: usize,
+}
+
+impl HttpSink {
+    pub fn new(url: &str, token: Option<&str>, format: OutputFormat, gzip: bool, batch_size: usize) -> Self {
+        HttpSink {
+            agent: super::http_agent(),
+            url: url.to_string(),
+            token: token.map(str::to_string),
+            format,
+            gzip,
+            batch_size: batch_size.max(1),
+            body: Vec::new(),
+            records: 0,
+        }
+    }
+
+    fn send(&mut self, content_type: &str) -> Result<(), SinkError> {
+        let mut body = std::mem::take(&mut self.body);
+        self.records = 0;
+        let mut request = self.agent.post(&self.url).header("Content-Type", content_type);
+        if self.gzip {
+            let mut encoder = GzEncoder::new(Vec::with_capacity(body.len() / 8), Compression::default());
+            encoder.write_all(&body)?;
+            body = encoder.finish()?;
+            request = request.header("Content-Encoding", "gzip");
+        }
+        if let Some(token) = &self.token {
+            request = request.header("Authorization", &format!("Bearer {}", token));
+        }
+        request.send(&body[..])?;
+        Ok(())
+    }
+}
+
+impl Sink for HttpSink {
+    fn name(&self) -> &'static str {
+        "http"
+    }
+
+    fn target(&self) -> &str {
+        &self.url
+    }
+
+    /// The whole run for a JSON document, `batch_size` records for NDJSON.
+    fn batch_size(&self) -> Option<usize> {
+        (self.format == OutputFormat::Ndjson).then_some(self.batch_size)
+    }
+
+    /// POST the run as one JSON document, or as NDJSON requests of
+    /// `batch_size` records; the last, partly filled one waits for `flush`.
+    fn write_batch(&mut self, batch: &Batch) -> Result<usize, SinkError> {
+        // Records left from an attempt that failed are part of this batch again
+        self.body.clear();
+        self.records = 0;
+        match self.format {
+            OutputFormat::Json => {
+                let document = Document {
+                    window: batch.window,
+                    run_id: batch.run_id,
+                    connections: batch.connections,
+                    session_close: batch.session_close,
+                    data: ByKey(batch.records),
+                };
+                serde_json::to_writer(&mut self.body, &document)?;
+                self.send("application/json")?;
+            }
+            OutputFormat::Ndjson => {
+                for record in batch.records {
+                    serde_json::to_writer(&mut self.body, record)?;
+                    self.body.push(b'\n');
+                    self.records += 1;
+                    if self.records == self.batch_size {
+                        self.send("application/x-ndjson")?;
+                    }
+                }
+            }
+        }
+        Ok(batch.records.len())
+    }
+
+    fn flush(&mut self) -> Result<(), SinkError> {
+        if self.records > 0 {
+            self.send("application/x-ndjson")?;
+        }
+        Ok(())
+    }
+}
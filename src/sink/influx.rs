@@ -0,0 +1,112 @@
+//! InfluxDB line-protocol metrics.
+//!
+//! At the end of each window the run's headline series are written to an
+//! InfluxDB-compatible write endpoint (`/write?db=...` or
+//! `/api/v2/write?org=...&bucket=...`) with nanosecond timestamps:
+//!
+//! - `syslog_run`: flows, connections and closed sessions for the window,
+//!   with the run's ID as a string field (not a tag, to keep cardinality flat)
+//! - `syslog_firewall,firewall=...`: byte/packet/flow totals per firewall
+//! - `syslog_port,port=...,protocol=...`: the busiest destination ports by bytes
+
+use std::collections::HashMap;
+use std::fmt::Write;
+
+use ureq::Agent;
+
+use super::{Batch, Sink, SinkError};
+use crate::record::Record;
+use crate::summary::{self, Totals};
+
+fn fields(totals: &Totals) -> String {
+    format!(
+        "packets_in={}i,bytes_in={}i,packets_out={}i,bytes_out={}i,flows={}i,sessions={}i",
+        totals.packets_in, totals.bytes_in, totals.packets_out, totals.bytes_out, totals.flows, totals.sessions
+    )
+}
+
+pub struct InfluxSink {
+    agent: Agent,
+    url: String,
+    token: Option<String>,
+    top_ports: usize,
+}
+
+impl InfluxSink {
+    pub fn new(url: &str, token: Option<&str>, top_ports: usize) -> Self {
+        InfluxSink {
+            agent: super::http_agent(),
+            url: url.to_string(),
+            token: token.map(str::to_string),
+            top_ports,
+        }
+    }
+
+    fn lines(&self, timestamp_ns: u128, run_id: &str, connections: u64, session_close: u64, records: &[&Record]) -> String {
+        let by_firewall = summary::group_by(records.iter().copied(), |record| &record.firewall);
+        let mut by_port: HashMap<(&str, &str), Totals> = HashMap::new();
+        for record in records {
+            by_port.entry((&record.destination_port, &record.protocol)).or_default().add(record);
+        }
+
+        let mut out = String::new();
+        writeln!(
+            out,
+            "syslog_run flows={}i,connections={}i,session_close={}i,run_id=\"{}\" {}",
+            records.len(), connections, session_close, run_id, timestamp_ns
+        )
+        .unwrap();
+
+        for (firewall, totals) in &by_firewall {
+            writeln!(out, "syslog_firewall,firewall={} {} {}", escape_tag(firewall), fields(totals), timestamp_ns).unwrap();
+        }
+
+        let mut ports: Vec<_> = by_port.into_iter().filter(|((port, _), _)| !port.is_empty()).collect();
+        ports.sort_by_key(|(_, totals)| std::cmp::Reverse(totals.bytes()));
+        for ((port, protocol), totals) in ports.into_iter().take(self.top_ports) {
+            writeln!(
+                out,
+                "syslog_port,port={},protocol={} {} {}",
+                escape_tag(port), escape_tag(protocol), fields(&totals), timestamp_ns
+            )
+            .unwrap();
+        }
+
+        out
+    }
+}
+
+impl Sink for InfluxSink {
+    fn name(&self) -> &'static str {
+        "influx"
+    }
+
+    fn target(&self) -> &str {
+        &self.url
+    }
+
+    /// Write the window's series, returning the number of points sent.
+    fn write_batch(&mut self, batch: &Batch) -> Result<usize, SinkError> {
+        let lines = self.lines(batch.window * 1_000_000, batch.run_id, batch.connections, batch.session_close, batch.records);
+        let points = lines.lines().count();
+
+        let mut request = self.agent.post(&self.url).header("Content-Type", "text/plain; charset=utf-8");
+        if let Some(token) = &self.token {
+            request = request.header("Authorization", &format!("Token {}", token));
+        }
+        request.send(&lines)?;
+        Ok(points)
+    }
+}
+
+/// Escape commas, spaces and equals signs in tag values.
+fn escape_tag(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        if matches!(c, ',' | ' ' | '=') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    if escaped.is_empty() { "unknown".to_string() } else { escaped }
+}
@@ -0,0 +1,304 @@
+//! Destinations the aggregated records are delivered to: the output file
+//! and any configured services. Every sink is delivered to independently,
+//! in batches of its own size, each with its own retries, and its outcome
+//! is recorded in the output metadata.
+//!
+//! A destination is a [`Sink`]; the binary builds the configured services
+//! from a [`Registry`] of named constructors, so adding one means
+//! implementing the trait and registering it. The output file is a
+//! [`file::FileSink`], delivered after the others because its metadata
+//! records how they went.
+
+pub mod clickhouse;
+pub mod deadletter;
+pub mod elasticsearch;
+pub mod file;
+pub mod http;
+pub mod influx;
+pub mod redis;
+
+use std::error::Error;
+use std::path::PathBuf;
+use std::thread;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use ureq::Agent;
+
+use crate::record::Record;
+
+/// Why a sink couldn't take a batch.
+pub type SinkError = Box<dyn Error + Send + Sync>;
+
+/// The records of one run, or a batch of them, as handed to a sink.
+#[derive(Clone, Copy)]
+pub struct Batch<'a> {
+    /// Start time of the run, in milliseconds
+    pub window: u128,
+    pub run_id: &'a str,
+    pub connections: u64,
+    pub session_close: u64,
+    pub records: &'a [&'a Record],
+}
+
+/// A destination for aggregated records.
+pub trait Sink {
+    /// Name kept in the sink's status and dead letters, e.g. `redis`
+    fn name(&self) -> &'static str;
+
+    /// Address, URL or stream written to
+    fn target(&self) -> &str;
+
+    /// Most records to hand to `write_batch` at once; `None` takes the whole
+    /// run in one batch.
+    fn batch_size(&self) -> Option<usize> {
+        None
+    }
+
+    /// Take the batch's records, returning how many entries they make. A
+    /// sink may hold some back until `flush`.
+    fn write_batch(&mut self, batch: &Batch) -> Result<usize, SinkError>;
+
+    /// Send whatever `write_batch` held back.
+    fn flush(&mut self) -> Result<(), SinkError> {
+        Ok(())
+    }
+
+    /// Release connections once the sink is done with.
+    fn close(&mut self) -> Result<(), SinkError> {
+        Ok(())
+    }
+}
+
+/// Builds the sink registered as a name, if `C` configures it.
+pub type Factory<C> = fn(&C) -> Option<Box<dyn Sink>>;
+
+/// Named sink constructors over some configuration `C`, e.g. parsed
+/// command-line options.
+pub struct Registry<C> {
+    factories: Vec<(&'static str, Factory<C>)>,
+}
+
+impl<C> Default for Registry<C> {
+    fn default() -> Self {
+        Registry { factories: Vec::new() }
+    }
+}
+
+impl<C> Registry<C> {
+    /// Add a sink; a name registered again replaces the earlier one.
+    pub fn register(&mut self, name: &'static str, factory: Factory<C>) {
+        self.factories.retain(|(registered, _)| *registered != name);
+        self.factories.push((name, factory));
+    }
+
+    /// The sink registered as `name`, if `config` sets it up.
+    pub fn build(&self, name: &str, config: &C) -> Option<Box<dyn Sink>> {
+        self.factories.iter().find(|(registered, _)| *registered == name).and_then(|(_, factory)| factory(config))
+    }
+
+    /// Every sink `config` sets up, in registration order.
+    pub fn configured(&self, config: &C) -> Vec<Box<dyn Sink>> {
+        self.factories.iter().filter_map(|(_, factory)| factory(config)).collect()
+    }
+}
+
+/// HTTP client shared by the sinks and exporters that talk to web APIs.
+pub(crate) fn http_agent() -> Agent {
+    Agent::config_builder()
+        .timeout_global(Some(Duration::from_secs(60)))
+        .build()
+        .into()
+}
+
+/// Outcome of delivering the run's records to one sink, kept in the output
+/// metadata.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct SinkStatus {
+    pub sink: String,
+    /// Address, URL or stream the sink writes to
+    pub target: String,
+    pub delivered: bool,
+    /// Writes tried, over all of its batches
+    pub attempts: u32,
+    /// Records, rows or points accepted by the sink
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub written: Option<usize>,
+    /// Why the last attempt failed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// Where the undelivered records were kept for `redeliver`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dead_letter: Option<PathBuf>,
+}
+
+/// How often a failed sink delivery is retried, and how long to wait first.
+#[derive(Debug, Clone, Copy)]
+pub struct Retry {
+    pub retries: u32,
+    /// Wait before the first retry, doubled for each one after
+    pub backoff: Duration,
+}
+
+impl Retry {
+    /// Write `batch` to `sink` in batches of its size, flushing each and
+    /// trying it again until that succeeds or its retries are used up.
+    /// Returns how delivery went and the records it didn't get to, from the
+    /// batch that failed on. A sink failing never affects the others; a
+    /// retried batch may repeat entries that were written before the
+    /// failure.
+    pub fn deliver<'a>(&self, sink: &mut dyn Sink, batch: &Batch<'a>) -> (SinkStatus, Batch<'a>) {
+        let size = sink.batch_size().unwrap_or(batch.records.len()).max(1);
+        // A run without records is still one batch, for sinks that report on the run
+        let chunks: Vec<&[&Record]> = match batch.records.is_empty() {
+            true => vec![batch.records],
+            false => batch.records.chunks(size).collect(),
+        };
+        let mut attempts = 0;
+        let (mut written, mut delivered, mut error) = (0, 0, None);
+        for records in chunks {
+            match self.attempt(sink, &Batch { records, ..*batch }, &mut attempts) {
+                Ok(entries) => {
+                    written += entries;
+                    delivered += records.len();
+                }
+                Err(err) => {
+                    error = Some(err);
+                    break;
+                }
+            }
+        }
+        if let Err(err) = sink.close() {
+            eprintln!("Unable to close {} {}: {}", sink.name(), sink.target(), err);
+        }
+        let status = SinkStatus {
+            sink: sink.name().to_string(),
+            target: sink.target().to_string(),
+            delivered: error.is_none(),
+            attempts,
+            written: (error.is_none() || written > 0).then_some(written),
+            error,
+            dead_letter: None,
+        };
+        (status, Batch { records: &batch.records[delivered..], ..*batch })
+    }
+
+    /// Write one batch and flush it, with retries, returning the entries it
+    /// made or why its last attempt failed.
+    fn attempt(&self, sink: &mut dyn Sink, batch: &Batch, attempts: &mut u32) -> Result<usize, String> {
+        let mut backoff = self.backoff;
+        let mut retries = 0;
+        loop {
+            *attempts += 1;
+            let error = match sink.write_batch(batch).and_then(|written| sink.flush().map(|()| written)) {
+                Ok(written) => return Ok(written),
+                Err(err) => err.to_string(),
+            };
+            if retries == self.retries {
+                return Err(error);
+            }
+            retries += 1;
+            eprintln!("Unable to write to {} {} ({}), retrying in {:?}", sink.name(), sink.target(), error, backoff);
+            thread::sleep(backoff);
+            backoff *= 2;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intern::Interner;
+    use crate::parser::FlowEvent;
+    use crate::record::NatSide;
+
+    /// Takes two records at a time, failing the writes numbered in `failing`.
+    struct Flaky {
+        failing: Vec<u32>,
+        writes: u32,
+        written: usize,
+        closed: bool,
+    }
+
+    impl Sink for Flaky {
+        fn name(&self) -> &'static str {
+            "flaky"
+        }
+
+        fn target(&self) -> &str {
+            "test"
+        }
+
+        fn batch_size(&self) -> Option<usize> {
+            Some(2)
+        }
+
+        fn write_batch(&mut self, batch: &Batch) -> Result<usize, SinkError> {
+            self.writes += 1;
+            if self.failing.contains(&self.writes) {
+                return Err(format!("write {} refused", self.writes).into());
+            }
+            self.written += batch.records.len();
+            Ok(batch.records.len())
+        }
+
+        fn close(&mut self) -> Result<(), SinkError> {
+            self.closed = true;
+            Ok(())
+        }
+    }
+
+    fn records(count: usize) -> Vec<Record> {
+        (0..count)
+            .map(|n| {
+                let event = FlowEvent { source_ip: format!("10.0.0.{}", n), destination_ip: "10.0.0.254".to_string(), ..FlowEvent::default() };
+                Record::new(&event, NatSide::PreNat, &mut Interner::default())
+            })
+            .collect()
+    }
+
+    fn batch<'a>(records: &'a [&'a Record]) -> Batch<'a> {
+        Batch { window: 0, run_id: "run", connections: 0, session_close: 0, records }
+    }
+
+    #[test]
+    fn a_failed_batch_is_retried() {
+        let records = records(5);
+        let records: Vec<&Record> = records.iter().collect();
+        let mut sink = Flaky { failing: vec![1], writes: 0, written: 0, closed: false };
+        let retry = Retry { retries: 2, backoff: Duration::ZERO };
+        let (status, left) = retry.deliver(&mut sink, &batch(&records));
+
+        assert!(status.delivered && status.error.is_none());
+        assert_eq!((status.attempts, status.written, left.records.len()), (4, Some(5), 0));
+        assert_eq!(sink.written, 5);
+        assert!(sink.closed);
+    }
+
+    #[test]
+    fn giving_up_on_a_batch_leaves_the_rest_undelivered() {
+        let records = records(5);
+        let records: Vec<&Record> = records.iter().collect();
+        let mut sink = Flaky { failing: vec![2, 3], writes: 0, written: 0, closed: false };
+        let retry = Retry { retries: 1, backoff: Duration::ZERO };
+        let (status, left) = retry.deliver(&mut sink, &batch(&records));
+
+        assert!(!status.delivered);
+        assert_eq!(status.error.as_deref(), Some("write 3 refused"));
+        assert_eq!((status.attempts, status.written), (3, Some(2)));
+        // The failed batch and everything after it
+        assert_eq!(left.records.len(), 3);
+        assert!(std::ptr::eq(left.records[0], records[2]));
+        assert!(sink.closed);
+    }
+
+    #[test]
+    fn a_run_without_records_is_still_delivered_once() {
+        let mut sink = Flaky { failing: Vec::new(), writes: 0, written: 0, closed: false };
+        let retry = Retry { retries: 0, backoff: Duration::ZERO };
+        let (status, _) = retry.deliver(&mut sink, &batch(&[]));
+        assert!(status.delivered);
+        assert_eq!((sink.writes, status.written), (1, Some(0)));
+    }
+}
@@ -0,0 +1,156 @@
+//! Redis Streams sink.
+//!
+//! Each record is added to the stream with `XADD` as four fields: the window
+//! it belongs to (the run's start time in milliseconds), the run's ID, its
+//! key and the record as JSON. Commands are pipelined in batches; if the connection drops
+//! the batch is retried on a fresh connection, so a reconnect can duplicate
+//! the entries of at most one batch.
+
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+use std::thread;
+use std::time::Duration;
+
+use super::{Batch, Sink, SinkError};
+
+const BATCH_SIZE: usize = 1000;
+const MAX_ATTEMPTS: u32 = 4;
+
+pub struct RedisSink {
+    addr: String,
+    stream: String,
+    conn: Option<BufReader<TcpStream>>,
+}
+
+impl RedisSink {
+    pub fn new(addr: &str, stream: &str) -> Self {
+        RedisSink {
+            addr: addr.to_string(),
+            stream: stream.to_string(),
+            conn: None,
+        }
+    }
+
+    fn send_with_retry(&mut self, commands: &[Vec<u8>]) -> io::Result<usize> {
+        let mut attempt = 0;
+        loop {
+            match self.send_pipeline(commands) {
+                Ok(written) => return Ok(written),
+                Err(err) if attempt + 1 < MAX_ATTEMPTS && is_connection_error(&err) => {
+                    attempt += 1;
+                    eprintln!("Redis connection to {} failed ({}), reconnecting (attempt {})", self.addr, err, attempt);
+                    self.conn = None;
+                    thread::sleep(Duration::from_secs(1 << (attempt - 1)));
+                }
+                Err(err) => {
+                    // Unread replies would desynchronise the next pipeline
+                    self.conn = None;
+                    return Err(err);
+                }
+            }
+        }
+    }
+
+    fn send_pipeline(&mut self, commands: &[Vec<u8>]) -> io::Result<usize> {
+        if self.conn.is_none() {
+            self.conn = Some(BufReader::new(TcpStream::connect(&self.addr)?));
+        }
+        let conn = self.conn.as_mut().unwrap();
+
+        let mut buf = Vec::new();
+        for command in commands {
+            buf.extend_from_slice(command);
+        }
+        conn.get_mut().write_all(&buf)?;
+
+        for _ in commands {
+            read_reply(conn)?;
+        }
+        Ok(commands.len())
+    }
+}
+
+impl Sink for RedisSink {
+    fn name(&self) -> &'static str {
+        "redis"
+    }
+
+    fn target(&self) -> &str {
+        &self.stream
+    }
+
+    fn batch_size(&self) -> Option<usize> {
+        Some(BATCH_SIZE)
+    }
+
+    /// XADD every record, returning the number of entries written.
+    fn write_batch(&mut self, batch: &Batch) -> Result<usize, SinkError> {
+        let window = batch.window.to_string();
+        let mut commands = Vec::with_capacity(BATCH_SIZE);
+        let mut written = 0;
+
+        for record in batch.records {
+            let json = serde_json::to_string(record)?;
+            commands.push(encode_command(&["XADD", &self.stream, "*", "window", &window, "run", batch.run_id, "key", &record.key, "record", &json]));
+            if commands.len() == BATCH_SIZE {
+                written += self.send_with_retry(&commands)?;
+                commands.clear();
+            }
+        }
+        if !commands.is_empty() {
+            written += self.send_with_retry(&commands)?;
+        }
+
+        Ok(written)
+    }
+
+    fn close(&mut self) -> Result<(), SinkError> {
+        self.conn = None;
+        Ok(())
+    }
+}
+
+fn is_connection_error(err: &io::Error) -> bool {
+    !matches!(err.kind(), io::ErrorKind::Other | io::ErrorKind::InvalidData)
+}
+
+fn encode_command(args: &[&str]) -> Vec<u8> {
+    let mut buf = format!("*{}\r\n", args.len()).into_bytes();
+    for arg in args {
+        buf.extend_from_slice(format!("${}\r\n", arg.len()).as_bytes());
+        buf.extend_from_slice(arg.as_bytes());
+        buf.extend_from_slice(b"\r\n");
+    }
+    buf
+}
+
+/// Read one RESP reply, turning `-ERR` replies into errors.
+fn read_reply(conn: &mut BufReader<TcpStream>) -> io::Result<()> {
+    let mut line = String::new();
+    if conn.read_line(&mut line)? == 0 {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    let line = line.trim_end();
+    let (kind, rest) = line.split_at(1.min(line.len()));
+
+    match kind {
+        "+" | ":" => Ok(()),
+        "-" => Err(io::Error::other(format!("Redis error: {}", rest))),
+        "$" => {
+            let len: i64 = rest.parse().map_err(|_| io::Error::from(io::ErrorKind::InvalidData))?;
+            if len >= 0 {
+                let mut body = vec![0; len as usize + 2];
+                conn.read_exact(&mut body)?;
+            }
+            Ok(())
+        }
+        "*" => {
+            let len: i64 = rest.parse().map_err(|_| io::Error::from(io::ErrorKind::InvalidData))?;
+            for _ in 0..len.max(0) {
+                read_reply(conn)?;
+            }
+            Ok(())
+        }
+        _ => Err(io::ErrorKind::InvalidData.into()),
+    }
+}
@@ -0,0 +1,102 @@
+//! Site-to-site traffic matrices from a prefix → site mapping.
+//!
+//! The mapping file has one `prefix name` pair per line (a comma works as
+//! the separator too; `#` starts a comment). Names are hierarchies joined
+//! by `/`, e.g. `emea/london/dmz`, and an address belongs to the longest
+//! prefix that contains it. One matrix is built per depth of the
+//! hierarchy, so the same file yields region → region, site → site and
+//! zone → zone views. Addresses outside every prefix are `unmapped`; names
+//! shallower than a level appear there under their full name.
+
+use std::fs;
+use std::io;
+use std::net::IpAddr;
+use std::path::Path;
+
+use serde::Serialize;
+
+use crate::cidr::Cidr;
+use crate::record::Record;
+use crate::rollup::{self, Pair};
+
+/// Name of addresses no prefix covers
+const UNMAPPED: &str = "unmapped";
+
+pub struct SiteMap {
+    /// Longest prefixes first, so the first match is the most specific
+    prefixes: Vec<(Cidr, Vec<String>)>,
+    depth: usize,
+}
+
+#[derive(Serialize, Debug)]
+pub struct Matrix {
+    pub depth: usize,
+    pub pairs: Vec<Pair>,
+}
+
+#[derive(Serialize)]
+struct Matrices<'a> {
+    levels: &'a [Matrix],
+}
+
+impl SiteMap {
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let text = fs::read_to_string(path)?;
+        let mut prefixes = Vec::new();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or_default().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidData, format!("line {}: {}", number + 1, message));
+            let (prefix, name) = line
+                .split_once(|c: char| c == ',' || c.is_whitespace())
+                .ok_or_else(|| invalid("expected a prefix and a site name".to_string()))?;
+            let prefix: Cidr = prefix.parse().map_err(invalid)?;
+            let path: Vec<String> = name
+                .trim_start_matches([',', ' ', '\t'])
+                .trim()
+                .split('/')
+                .map(str::trim)
+                .filter(|part| !part.is_empty())
+                .map(str::to_string)
+                .collect();
+            if path.is_empty() {
+                return Err(invalid("empty site name".to_string()));
+            }
+            prefixes.push((prefix, path));
+        }
+        prefixes.sort_by_key(|(prefix, _)| std::cmp::Reverse(prefix.prefix()));
+        let depth = prefixes.iter().map(|(_, path)| path.len()).max().unwrap_or(0);
+        Ok(SiteMap { prefixes, depth })
+    }
+
+    pub fn len(&self) -> usize {
+        self.prefixes.len()
+    }
+
+    /// The site of `address` cut to `depth` levels.
+    fn site(&self, address: &str, depth: usize) -> String {
+        let path = address
+            .parse::<IpAddr>()
+            .ok()
+            .and_then(|address| self.prefixes.iter().find(|(prefix, _)| prefix.contains(address)));
+        match path {
+            Some((_, path)) => path[..depth.min(path.len())].join("/"),
+            None => UNMAPPED.to_string(),
+        }
+    }
+
+    pub fn matrices<'a>(&self, records: impl IntoIterator<Item = &'a Record> + Clone) -> Vec<Matrix> {
+        (1..=self.depth)
+            .map(|depth| Matrix {
+                depth,
+                pairs: rollup::pairs(records.clone(), |address| self.site(address, depth)),
+            })
+            .collect()
+    }
+}
+
+pub fn render(levels: &[Matrix]) -> String {
+    serde_json::to_string_pretty(&Matrices { levels }).expect("Unable to serialize site matrices")
+}
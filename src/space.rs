@@ -0,0 +1,49 @@
+//! Checking the output filesystem has room before writing to it.
+//!
+//! Appliances tend to keep logs and outputs on one small partition, and an
+//! output that fills it halfway through is lost along with the run that
+//! produced it. The payload's size is measured first, by serializing it to
+//! a counter rather than memory, and the write is refused up front unless
+//! the filesystem has that much free plus a margin.
+
+use std::ffi::CString;
+use std::io::{self, Write};
+use std::mem::MaybeUninit;
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+
+/// Share of the estimate kept free on top of it by default, in percent.
+pub const DEFAULT_MARGIN: f64 = 10.0;
+
+/// Bytes free to unprivileged users on the filesystem holding `dir`.
+pub fn available(dir: &Path) -> io::Result<u64> {
+    let path = CString::new(dir.as_os_str().as_bytes()).map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+    let mut stats = MaybeUninit::<libc::statvfs>::uninit();
+    if unsafe { libc::statvfs(path.as_ptr(), stats.as_mut_ptr()) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let stats = unsafe { stats.assume_init() };
+    // The fields' widths vary by platform
+    #[allow(clippy::unnecessary_cast)]
+    Ok((stats.f_bavail as u64).saturating_mul(stats.f_frsize as u64))
+}
+
+/// `estimate` bytes plus `margin` percent of them.
+pub fn needed(estimate: u64, margin: f64) -> u64 {
+    estimate.saturating_add((estimate as f64 * margin / 100.0).ceil() as u64)
+}
+
+/// A writer that only counts what it is given.
+#[derive(Default)]
+pub struct Counter(pub u64);
+
+impl Write for Counter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0 += buf.len() as u64;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,60 @@
+//! What happens to input files once their records are safely in the output.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum AfterProcessing {
+    /// Leave the files in the spool directory
+    #[default]
+    None,
+    /// Move them into this archive directory
+    Move(PathBuf),
+    Delete,
+}
+
+impl FromStr for AfterProcessing {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "none" => Ok(AfterProcessing::None),
+            "delete" => Ok(AfterProcessing::Delete),
+            _ => match value.strip_prefix("move:") {
+                Some(dir) if !dir.is_empty() => Ok(AfterProcessing::Move(PathBuf::from(dir))),
+                _ => Err(format!("expected `none`, `delete` or `move:<dir>`, got `{}`", value)),
+            },
+        }
+    }
+}
+
+impl AfterProcessing {
+    /// Apply the action to one processed file. Files that are already gone,
+    /// e.g. because they were quarantined with `--quarantine-move`, are left
+    /// alone.
+    pub fn apply(&self, path: &Path) -> io::Result<()> {
+        if !path.exists() {
+            return Ok(());
+        }
+        match self {
+            AfterProcessing::None => Ok(()),
+            AfterProcessing::Delete => fs::remove_file(path),
+            AfterProcessing::Move(dir) => {
+                fs::create_dir_all(dir)?;
+                move_file(path, &dir.join(path.file_name().unwrap_or(path.as_os_str())))
+            }
+        }
+    }
+}
+
+/// Move `path` to `target`, which may be on another filesystem.
+pub fn move_file(path: &Path, target: &Path) -> io::Result<()> {
+    // Renames fail across filesystems, so fall back to copy and remove
+    if fs::rename(path, target).is_err() {
+        fs::copy(path, target)?;
+        fs::remove_file(path)?;
+    }
+    Ok(())
+}
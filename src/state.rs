@@ -0,0 +1,369 @@
+//! Persistent aggregation state for continuous aggregation across restarts.
+//!
+//! The store is a directory holding a compacted snapshot of every record
+//! and the run's counters (`records.json`) plus a journal of what changed
+//! since (`journal-<generation>.jsonl`): one whole record per line, later
+//! lines replacing earlier ones, a line per flow `--max-flows` evicted and
+//! the counters as of each checkpoint. Checkpoints only append to the
+//! journal; compaction rewrites the snapshot atomically under the next
+//! generation and starts a fresh journal, so a crash at any point leaves
+//! either the old snapshot with its journal or the new one with an empty
+//! journal.
+//!
+//! This takes the place of an embedded key-value store such as sled or
+//! RocksDB. The store is only read once, at startup, and written at
+//! checkpoints rather than per event, so random access by key would buy
+//! nothing; RocksDB would bring a C++ build into every build of the
+//! binary, and sled's on-disk format still changes between releases.
+//! JSON lines of the records as they are already serialized keep the store
+//! readable with the usual tools, and a line a crash cut short is the
+//! only thing it can lose.
+//!
+//! Without a store, a serving process can still be carried across a
+//! planned restart with an [`AggregationSnapshot`] of its current window:
+//! taken every `--snapshot-every` seconds or on request by `state
+//! snapshot`, and staged by `state restore` for the next run to resume from.
+
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use crate::aggregate::{Aggregator, Changes};
+use crate::atomic;
+use crate::record::Record;
+
+const RECORDS_FILE: &str = "records.json";
+
+#[derive(Serialize, Deserialize)]
+struct Snapshot {
+    generation: u64,
+    /// Absent from stores written before the counters were kept
+    #[serde(default)]
+    counters: Counters,
+    records: Vec<Record>,
+}
+
+/// The run's counters, over every run the store's records cover.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct Counters {
+    pub connections: u64,
+    pub session_close: u64,
+}
+
+impl Counters {
+    pub fn of(aggregator: &Aggregator) -> Self {
+        Counters { connections: aggregator.connections, session_close: aggregator.session_close }
+    }
+}
+
+/// One line of a store's journal.
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum Line<R> {
+    Counters { counters: Counters },
+    Removed { removed: Arc<str> },
+    Record(R),
+}
+
+/// What a store holds.
+#[derive(Default)]
+pub struct Stored {
+    pub records: HashMap<Arc<str>, Record>,
+    pub counters: Counters,
+}
+
+pub struct StateStore {
+    dir: PathBuf,
+    generation: u64,
+    journal: File,
+    journal_lines: usize,
+    /// Records in the snapshot
+    snapshot_records: usize,
+}
+
+fn journal_path(dir: &Path, generation: u64) -> PathBuf {
+    dir.join(format!("journal-{}.jsonl", generation))
+}
+
+impl StateStore {
+    /// Open (or create) the store in `dir`, returning it with what it holds.
+    pub fn open(dir: &Path) -> io::Result<(Self, Stored)> {
+        fs::create_dir_all(dir)?;
+        let snapshot: Snapshot = match fs::read(dir.join(RECORDS_FILE)) {
+            Ok(bytes) => serde_json::from_slice(&bytes)?,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Snapshot { generation: 0, counters: Counters::default(), records: Vec::new() },
+            Err(err) => return Err(err),
+        };
+        let snapshot_records = snapshot.records.len();
+        let mut stored = Stored {
+            records: snapshot.records.into_iter().map(|record| (Arc::clone(&record.key), record)).collect(),
+            counters: snapshot.counters,
+        };
+
+        let mut journal_lines = 0;
+        let path = journal_path(dir, snapshot.generation);
+        if let Ok(journal) = File::open(&path) {
+            for line in BufReader::new(journal).lines() {
+                // A line cut short by a crash is the only thing lost, and is
+                // cut off below before anything is appended after it
+                let Ok(line) = serde_json::from_str::<Line<Record>>(&line?) else {
+                    continue;
+                };
+                match line {
+                    Line::Counters { counters } => stored.counters = counters,
+                    Line::Removed { removed } => {
+                        stored.records.remove(&removed);
+                    }
+                    Line::Record(record) => {
+                        stored.records.insert(Arc::clone(&record.key), record);
+                    }
+                }
+                journal_lines += 1;
+            }
+        }
+        remove_stale_journals(dir, snapshot.generation);
+
+        let journal = atomic::open_lines(&path)?;
+        let store = StateStore {
+            dir: dir.to_path_buf(),
+            generation: snapshot.generation,
+            journal,
+            journal_lines,
+            snapshot_records,
+        };
+        Ok((store, stored))
+    }
+
+    /// Journal `changes` and the counters as of them.
+    pub fn append(&mut self, changes: &Changes, counters: Counters) -> io::Result<()> {
+        let mut lines = Vec::new();
+        let removed = changes.removed.iter().map(|key| Line::Removed { removed: Arc::clone(key) });
+        let changed = changes.records.iter().map(Line::Record).chain(removed);
+        for line in changed.chain([Line::Counters { counters }]) {
+            serde_json::to_writer(&mut lines, &line)?;
+            lines.push(b'\n');
+            self.journal_lines += 1;
+        }
+        self.journal.write_all(&lines)?;
+        self.journal.sync_data()
+    }
+
+    /// Replace the snapshot with `records` and `counters`, and start an
+    /// empty journal.
+    pub fn compact(&mut self, records: &HashMap<Arc<str>, Record>, counters: Counters) -> io::Result<()> {
+        #[derive(Serialize)]
+        struct SnapshotRef<'a> {
+            generation: u64,
+            counters: Counters,
+            records: Vec<&'a Record>,
+        }
+        let generation = self.generation + 1;
+        let journal = OpenOptions::new().create(true).write(true).truncate(true).open(journal_path(&self.dir, generation))?;
+        atomic::write_with(&self.dir.join(RECORDS_FILE), |out| {
+            let snapshot = SnapshotRef { generation, counters, records: records.values().collect() };
+            serde_json::to_writer(out, &snapshot).map_err(io::Error::from)
+        })?;
+        let _ = fs::remove_file(journal_path(&self.dir, self.generation));
+        self.generation = generation;
+        self.journal = journal;
+        self.journal_lines = 0;
+        self.snapshot_records = records.len();
+        Ok(())
+    }
+
+    pub fn journal_lines(&self) -> usize {
+        self.journal_lines
+    }
+
+    pub fn snapshot_records(&self) -> usize {
+        self.snapshot_records
+    }
+}
+
+/// Journals of other generations are left over from an interrupted compaction.
+fn remove_stale_journals(dir: &Path, generation: u64) {
+    let current = journal_path(dir, generation);
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        let name = entry.file_name();
+        let name = name.to_string_lossy();
+        if name.starts_with("journal-") && name.ends_with(".jsonl") && path != current {
+            let _ = fs::remove_file(path);
+        }
+    }
+}
+
+/// The current window of a running aggregation.
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AggregationSnapshot {
+    /// Milliseconds since the epoch
+    pub taken_at: u128,
+    pub connections: u64,
+    pub session_close: u64,
+    pub records: Vec<Record>,
+}
+
+impl AggregationSnapshot {
+    /// Write the aggregator's records as they stand to `path` with its
+    /// counters, returning how many records were written.
+    pub fn take(aggregator: &mut Aggregator, path: &Path) -> io::Result<usize> {
+        #[derive(Serialize)]
+        #[serde(rename_all = "camelCase")]
+        struct SnapshotRef<'a> {
+            taken_at: u128,
+            connections: u64,
+            session_close: u64,
+            records: Vec<&'a Record>,
+        }
+        if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            fs::create_dir_all(dir)?;
+        }
+        let records = aggregator.records();
+        let snapshot = SnapshotRef {
+            taken_at: SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_millis(),
+            connections: aggregator.connections,
+            session_close: aggregator.session_close,
+            records: records.values().collect(),
+        };
+        atomic::write_with(path, |out| serde_json::to_writer(out, &snapshot).map_err(io::Error::from))?;
+        Ok(snapshot.records.len())
+    }
+
+    pub fn read(path: &Path) -> io::Result<Self> {
+        Ok(serde_json::from_slice(&fs::read(path)?)?)
+    }
+
+    /// Add the snapshot's window to the aggregator's.
+    pub fn restore(self, aggregator: &mut Aggregator) {
+        aggregator.connections += self.connections;
+        aggregator.session_close += self.session_close;
+        aggregator.seed(self.records.into_iter().map(|record| (Arc::clone(&record.key), record)).collect());
+    }
+}
+
+/// Present while `state snapshot` waits for a serving process to take one.
+pub fn request_path(snapshot: &Path) -> PathBuf {
+    sibling(snapshot, "request")
+}
+
+/// A snapshot staged by `state restore` for the next run.
+pub fn staged_path(snapshot: &Path) -> PathBuf {
+    sibling(snapshot, "restore")
+}
+
+fn sibling(path: &Path, extension: &str) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".");
+    name.push(extension);
+    path.with_file_name(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::{InputFormat, LineParser, ParserOptions};
+    use crate::record::KeySpec;
+
+    fn aggregator() -> Aggregator {
+        let options = ParserOptions { pattern: None, kv_aliases: &[], min_fields: 0 };
+        Aggregator::new(LineParser::new(InputFormat::Csv, &options).unwrap(), KeySpec::default(), 60)
+    }
+
+    fn line(flow: u64) -> String {
+        format!("2025-08-29T11:38:0{flow}+00:00,192.168.29.191,,10.0.0.{flow},8.8.8.{flow},443,6,,,1,{},1,0", flow * 100)
+    }
+
+    fn dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("syslog_processor-state-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn counts(stored: &Stored) -> Vec<(String, u64)> {
+        let mut counts: Vec<(String, u64)> = stored.records.values().map(|record| (record.key.to_string(), record.count)).collect();
+        counts.sort();
+        counts
+    }
+
+    #[test]
+    fn restores_records_and_counters_across_restarts() {
+        let dir = dir("restart");
+        let mut aggregator = aggregator();
+        let cursor = aggregator.track_changes();
+        let (mut store, stored) = StateStore::open(&dir).unwrap();
+        assert!(stored.records.is_empty());
+
+        aggregator.ingest(&line(1));
+        aggregator.ingest(&line(2));
+        store.append(&aggregator.changes(cursor), Counters::of(&aggregator)).unwrap();
+        aggregator.ingest(&line(2));
+        aggregator.ingest("not a flow at all");
+        store.append(&aggregator.changes(cursor), Counters::of(&aggregator)).unwrap();
+        drop(store);
+
+        let (mut store, stored) = StateStore::open(&dir).unwrap();
+        let expected = Counters { connections: 4, session_close: 3 };
+        assert_eq!(stored.counters, expected);
+        assert_eq!(counts(&stored).iter().map(|(_, count)| count).collect::<Vec<_>>(), [&1, &2]);
+
+        store.compact(&stored.records, stored.counters).unwrap();
+        assert_eq!(store.journal_lines(), 0);
+        drop(store);
+        let (_, compacted) = StateStore::open(&dir).unwrap();
+        assert_eq!((counts(&compacted), compacted.counters), (counts(&stored), expected));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn a_torn_last_line_is_cut_off() {
+        let dir = dir("torn");
+        let mut aggregator = aggregator();
+        let cursor = aggregator.track_changes();
+        let (mut store, _) = StateStore::open(&dir).unwrap();
+        aggregator.ingest(&line(1));
+        store.append(&aggregator.changes(cursor), Counters::of(&aggregator)).unwrap();
+        drop(store);
+        let mut journal = OpenOptions::new().append(true).open(journal_path(&dir, 0)).unwrap();
+        journal.write_all(br#"{"key":"192.168.29.191_10.0"#).unwrap();
+
+        let (mut store, stored) = StateStore::open(&dir).unwrap();
+        assert_eq!((stored.records.len(), stored.counters.connections), (1, 1));
+        aggregator.ingest(&line(2));
+        store.append(&aggregator.changes(cursor), Counters::of(&aggregator)).unwrap();
+        drop(store);
+        let (_, stored) = StateStore::open(&dir).unwrap();
+        assert_eq!((stored.records.len(), stored.counters.connections), (2, 2));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn evicted_flows_are_dropped() {
+        let dir = dir("evicted");
+        let mut aggregator = aggregator();
+        aggregator.max_flows = Some((1, 64, 4));
+        let cursor = aggregator.track_changes();
+        let (mut store, _) = StateStore::open(&dir).unwrap();
+        aggregator.ingest(&line(1));
+        store.append(&aggregator.changes(cursor), Counters::of(&aggregator)).unwrap();
+        // Busier, so it takes the only record's place
+        aggregator.ingest(&line(2));
+        let changes = aggregator.changes(cursor);
+        assert_eq!(changes.removed.len(), 1);
+        store.append(&changes, Counters::of(&aggregator)).unwrap();
+        drop(store);
+
+        let (_, stored) = StateStore::open(&dir).unwrap();
+        assert_eq!(counts(&stored), [("192.168.29.191_10.0.0.2_8.8.8.2_443_6".to_string(), 1)]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,77 @@
+//! Roll-ups of flow records along a single attribute.
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+
+use crate::record::Record;
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Totals {
+    pub packets_in: u64,
+    pub bytes_in: u64,
+    pub packets_out: u64,
+    pub bytes_out: u64,
+    pub flows: u64,
+    pub sessions: u64,
+}
+
+impl Totals {
+    pub fn add(&mut self, record: &Record) {
+        self.packets_in = self.packets_in.saturating_add(record.packets_in);
+        self.bytes_in = self.bytes_in.saturating_add(record.bytes_in);
+        self.packets_out = self.packets_out.saturating_add(record.packets_out);
+        self.bytes_out = self.bytes_out.saturating_add(record.bytes_out);
+        self.flows += 1;
+        self.sessions += record.count;
+    }
+
+    pub fn merge(&mut self, other: &Totals) {
+        self.packets_in = self.packets_in.saturating_add(other.packets_in);
+        self.bytes_in = self.bytes_in.saturating_add(other.bytes_in);
+        self.packets_out = self.packets_out.saturating_add(other.packets_out);
+        self.bytes_out = self.bytes_out.saturating_add(other.bytes_out);
+        self.flows += other.flows;
+        self.sessions += other.sessions;
+    }
+
+    pub fn bytes(&self) -> u64 {
+        self.bytes_in.saturating_add(self.bytes_out)
+    }
+}
+
+/// Take the records with fewer than `min_bytes` bytes or `min_count`
+/// sessions out of `records`, returning their totals.
+pub fn drop_below(records: &mut HashMap<Arc<str>, Record>, min_bytes: u64, min_count: u64) -> Totals {
+    let mut dropped = Totals::default();
+    records.retain(|_, record| {
+        let keep = record.bytes_in.saturating_add(record.bytes_out) >= min_bytes && record.count >= min_count;
+        if !keep {
+            dropped.add(record);
+        }
+        keep
+    });
+    dropped
+}
+
+/// Totals grouped by the value `group` picks out of each record.
+pub fn group_by<'a, F>(records: impl IntoIterator<Item = &'a Record>, group: F) -> BTreeMap<String, Totals>
+where
+    F: Fn(&Record) -> &str,
+{
+    let mut groups: BTreeMap<String, Totals> = BTreeMap::new();
+    for record in records {
+        let value = group(record);
+        match groups.get_mut(value) {
+            Some(totals) => totals.add(record),
+            None => {
+                let mut totals = Totals::default();
+                totals.add(record);
+                groups.insert(value.to_string(), totals);
+            }
+        }
+    }
+    groups
+}
@@ -0,0 +1,165 @@
+//! Following live log files, `tail -F` style.
+//!
+//! Each file is polled for new complete lines; a partial last line is held
+//! back until its newline arrives. A file that shrinks is assumed truncated
+//! and re-read from the start, and when the path starts pointing at a new
+//! inode (rename-based rotation) the old file is drained, including an
+//! unterminated last line, before switching.
+//! Following stops once no file has grown for the idle timeout.
+
+use std::fs::{self, File};
+use std::io::{self, BufReader, Seek, SeekFrom};
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::aggregate::Aggregator;
+use crate::lines::{Line, LineReader};
+use crate::parser::SkipReason;
+use crate::payload::FileStats;
+
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+struct Followed {
+    path: PathBuf,
+    reader: Option<BufReader<File>>,
+    inode: u64,
+    position: u64,
+    /// Holds a partial last line until its newline arrives
+    partial: LineReader,
+    counts: Counts,
+    started: Instant,
+}
+
+#[derive(Default)]
+struct Counts {
+    lines: u64,
+    skipped: u64,
+    replaced_characters: u64,
+}
+
+impl Counts {
+    /// Ingest `line`, counting it as skipped if a strict encoding refuses
+    /// it; a followed file has no end to stop reading it at.
+    fn ingest(&mut self, line: Line<'_>, aggregator: &mut Aggregator) {
+        let skipped_before = aggregator.skipped.total;
+        let replaced_before = aggregator.replaced_characters;
+        if aggregator.ingest_line(line).is_err() {
+            aggregator.reject(SkipReason::Undecodable);
+        }
+        self.lines += 1;
+        self.skipped += aggregator.skipped.total - skipped_before;
+        self.replaced_characters += aggregator.replaced_characters - replaced_before;
+    }
+}
+
+impl Followed {
+    fn new(path: &Path, from_start: bool, partial: LineReader) -> Self {
+        let mut followed = Followed {
+            path: path.to_path_buf(),
+            reader: None,
+            inode: 0,
+            position: 0,
+            partial,
+            counts: Counts::default(),
+            started: Instant::now(),
+        };
+        if let Err(err) = followed.reopen(!from_start, None) {
+            eprintln!("Waiting for {}: {}", path.display(), err);
+        }
+        followed
+    }
+
+    fn reopen(&mut self, at_end: bool, aggregator: Option<&mut Aggregator>) -> io::Result<()> {
+        let mut file = File::open(&self.path)?;
+        let metadata = file.metadata()?;
+        // The old file won't grow any more, so what is left of its last
+        // line is all of it; a truncated one is being rewritten instead
+        match aggregator {
+            Some(aggregator) if metadata.ino() != self.inode => {
+                if let Some(line) = self.partial.finish() {
+                    self.counts.ingest(line, aggregator);
+                }
+                self.partial.clear();
+            }
+            _ => self.partial.clear(),
+        }
+        self.position = if at_end { file.seek(SeekFrom::End(0))? } else { 0 };
+        self.inode = metadata.ino();
+        self.reader = Some(BufReader::new(file));
+        Ok(())
+    }
+
+    /// Feed every complete line written since the last poll, returning how
+    /// many were read.
+    fn poll(&mut self, aggregator: &mut Aggregator) -> u64 {
+        let mut read = self.drain(aggregator);
+
+        // A new inode means rotation (the old file was drained above), a
+        // shorter file means truncation; either way start over from the top
+        let replaced = match fs::metadata(&self.path) {
+            Ok(metadata) => self.reader.is_none() || metadata.ino() != self.inode || metadata.len() < self.position,
+            Err(_) => false,
+        };
+        if replaced && self.reopen(false, Some(aggregator)).is_ok() {
+            read += self.drain(aggregator);
+        }
+        read
+    }
+
+    fn drain(&mut self, aggregator: &mut Aggregator) -> u64 {
+        let Some(reader) = self.reader.as_mut() else {
+            return 0;
+        };
+        aggregator.set_source(&self.path.display().to_string());
+        let mut read = 0;
+        let consumed = self.partial.consumed;
+        while let Ok(Some(line)) = self.partial.next(reader) {
+            self.counts.ingest(line, aggregator);
+            read += 1;
+        }
+        self.position += self.partial.consumed - consumed;
+        read
+    }
+}
+
+/// Follow `paths` until none of them has grown for `idle_timeout`, calling
+/// `checkpoint` with the aggregator after every poll.
+pub fn follow(
+    paths: &[PathBuf],
+    from_start: bool,
+    idle_timeout: Duration,
+    aggregator: &mut Aggregator,
+    mut checkpoint: impl FnMut(&mut Aggregator),
+) -> Vec<FileStats> {
+    let mut files: Vec<Followed> = paths
+        .iter()
+        .map(|path| Followed::new(path, from_start, LineReader::new(aggregator.max_line_length).throttled(aggregator.throttle.clone())))
+        .collect();
+    let mut last_line = Instant::now();
+
+    while last_line.elapsed() < idle_timeout {
+        let read: u64 = files.iter_mut().map(|file| file.poll(aggregator)).sum();
+        checkpoint(aggregator);
+        if read > 0 {
+            last_line = Instant::now();
+        } else {
+            thread::sleep(POLL_INTERVAL);
+        }
+    }
+
+    files
+        .into_iter()
+        .map(|file| FileStats {
+            file: file.path.display().to_string(),
+            lines: file.counts.lines,
+            skipped: file.counts.skipped,
+            replaced_characters: file.counts.replaced_characters,
+            duration_seconds: file.started.elapsed().as_secs_f64(),
+            stages: None,
+            size: None,
+            sha256: None,
+        })
+        .collect()
+}
@@ -0,0 +1,124 @@
+//! Merging t-digest for percentiles of per-session values.
+//!
+//! Values are buffered and periodically merged into centroids whose size is
+//! bounded by the k1 scale function, so the tails stay accurate while the
+//! whole digest holds at most about `COMPRESSION` centroids however many
+//! values it has seen.
+
+use serde::{Deserialize, Serialize};
+
+const COMPRESSION: f64 = 100.0;
+/// Values buffered before they are merged into the centroids
+const BUFFER: usize = 256;
+
+#[derive(Debug, Clone, Default)]
+pub struct TDigest {
+    /// (mean, weight), sorted by mean
+    centroids: Vec<(f64, f64)>,
+    buffer: Vec<f64>,
+    count: f64,
+    min: f64,
+    max: f64,
+}
+
+/// Percentiles reported per record.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Percentiles {
+    pub p50: u64,
+    pub p90: u64,
+    pub p99: u64,
+    pub max: u64,
+}
+
+impl TDigest {
+    pub fn add(&mut self, value: f64) {
+        if self.count == 0.0 {
+            (self.min, self.max) = (value, value);
+        }
+        self.min = self.min.min(value);
+        self.max = self.max.max(value);
+        self.count += 1.0;
+        self.buffer.push(value);
+        if self.buffer.len() >= BUFFER {
+            self.compress();
+        }
+    }
+
+    fn compress(&mut self) {
+        let mut all: Vec<(f64, f64)> = std::mem::take(&mut self.centroids);
+        all.extend(self.buffer.drain(..).map(|value| (value, 1.0)));
+        all.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+        let total: f64 = all.iter().map(|(_, weight)| weight).sum();
+        let k = |q: f64| COMPRESSION / (2.0 * std::f64::consts::PI) * (2.0 * q.clamp(0.0, 1.0) - 1.0).asin();
+        let mut merged: Vec<(f64, f64)> = Vec::with_capacity(all.len().min(COMPRESSION as usize * 2));
+        let mut seen = 0.0;
+        let mut k_start = k(0.0);
+        for (mean, weight) in all {
+            match merged.last_mut() {
+                Some((last_mean, last_weight)) if k((seen + weight) / total) - k_start <= 1.0 => {
+                    *last_mean += (mean - *last_mean) * weight / (*last_weight + weight);
+                    *last_weight += weight;
+                }
+                Some(_) => {
+                    k_start = k(seen / total);
+                    merged.push((mean, weight));
+                }
+                None => merged.push((mean, weight)),
+            }
+            seen += weight;
+        }
+        self.centroids = merged;
+    }
+
+    /// Estimated value at quantile `q` (0 to 1), interpolating between
+    /// centroid means.
+    pub fn quantile(&mut self, q: f64) -> f64 {
+        if !self.buffer.is_empty() {
+            self.compress();
+        }
+        let Some(first) = self.centroids.first() else {
+            return 0.0;
+        };
+        if self.centroids.len() == 1 {
+            return first.0;
+        }
+        let target = q.clamp(0.0, 1.0) * self.count;
+        let mut seen = 0.0;
+        let mut previous = (self.min, 0.0);
+        for (mean, weight) in &self.centroids {
+            // A centroid's mean sits at the middle of its weight
+            let middle = seen + weight / 2.0;
+            if target < middle {
+                let span = middle - previous.1;
+                let fraction = if span > 0.0 { (target - previous.1) / span } else { 0.0 };
+                return previous.0 + (mean - previous.0) * fraction;
+            }
+            previous = (*mean, middle);
+            seen += weight;
+        }
+        let span = self.count - previous.1;
+        let fraction = if span > 0.0 { (target - previous.1) / span } else { 1.0 };
+        previous.0 + (self.max - previous.0) * fraction
+    }
+
+    pub fn count(&self) -> f64 {
+        self.count
+    }
+
+    pub fn percentiles(&mut self) -> Percentiles {
+        Percentiles {
+            p50: self.quantile(0.5).round() as u64,
+            p90: self.quantile(0.9).round() as u64,
+            p99: self.quantile(0.99).round() as u64,
+            max: self.max.round() as u64,
+        }
+    }
+}
+
+/// Digests kept per record while aggregating.
+#[derive(Debug, Clone, Default)]
+pub struct SessionDigests {
+    pub duration_ms: TDigest,
+    pub bytes: TDigest,
+}
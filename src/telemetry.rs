@@ -0,0 +1,327 @@
+//! OpenTelemetry export of pipeline spans and counters over OTLP/HTTP JSON.
+//!
+//! A run produces one trace: a root `run` span with child spans for reading
+//! each input, serializing the payload and delivering it to each sink. Parse
+//! and aggregate work is interleaved line by line, so it's reported as
+//! cumulative stage durations in the `pipeline.stage.duration` metric rather
+//! than as spans.
+//!
+//! A cron run is usually over before anything could scrape it, so the same
+//! counters can also be pushed when the run ends: to a Prometheus
+//! pushgateway, which holds them until the next scrape, or to a StatsD
+//! daemon, with attributes either folded into the metric name or sent as
+//! DogStatsD tags.
+
+use std::collections::hash_map::RandomState;
+use std::fmt::Write as _;
+use std::hash::{BuildHasher, Hasher};
+use std::io;
+use std::net::UdpSocket;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use clap::ValueEnum;
+use serde_json::{Value, json};
+
+const SERVICE_NAME: &str = "syslog_processor";
+
+/// How StatsD metrics carry their attributes.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum StatsdFormat {
+    /// Appended to the metric name, e.g. `pipeline.stage.duration.parse`
+    #[default]
+    Statsd,
+    /// As DogStatsD tags, e.g. `pipeline.stage.duration|c|#stage:parse`
+    Dogstatsd,
+}
+
+/// Time spent in each stage, summed over all lines of a run.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct StageTimes {
+    pub read: Duration,
+    pub parse: Duration,
+    pub aggregate: Duration,
+}
+
+impl StageTimes {
+    /// Time spent since `earlier` was taken.
+    pub fn since(&self, earlier: &StageTimes) -> StageTimes {
+        StageTimes {
+            read: self.read.saturating_sub(earlier.read),
+            parse: self.parse.saturating_sub(earlier.parse),
+            aggregate: self.aggregate.saturating_sub(earlier.aggregate),
+        }
+    }
+}
+
+struct Span {
+    name: String,
+    span_id: String,
+    start: SystemTime,
+    end: SystemTime,
+    attributes: Vec<(String, String)>,
+}
+
+struct Counter {
+    name: String,
+    unit: &'static str,
+    value: u64,
+    attributes: Vec<(String, String)>,
+}
+
+pub struct Telemetry {
+    trace_id: String,
+    root_span_id: String,
+    start: SystemTime,
+    spans: Vec<Span>,
+    counters: Vec<Counter>,
+    /// The tenant of the options file this run is, to tell tenants' data apart
+    pub tenant: Option<String>,
+}
+
+impl Telemetry {
+    pub fn new(start: SystemTime) -> Self {
+        Telemetry {
+            trace_id: random_hex(16),
+            root_span_id: random_hex(8),
+            start,
+            spans: Vec::new(),
+            counters: Vec::new(),
+            tenant: None,
+        }
+    }
+
+    /// Record a finished child span of the run.
+    pub fn span(&mut self, name: &str, start: SystemTime, attributes: &[(&str, String)]) {
+        self.spans.push(Span {
+            name: name.to_string(),
+            span_id: random_hex(8),
+            start,
+            end: SystemTime::now(),
+            attributes: attributes.iter().map(|(k, v)| (k.to_string(), v.clone())).collect(),
+        });
+    }
+
+    /// Record a monotonic counter value for the run.
+    pub fn counter(&mut self, name: &str, unit: &'static str, value: u64, attributes: &[(&str, String)]) {
+        self.counters.push(Counter {
+            name: name.to_string(),
+            unit,
+            value,
+            attributes: attributes.iter().map(|(k, v)| (k.to_string(), v.clone())).collect(),
+        });
+    }
+
+    pub fn stage_times(&mut self, times: &StageTimes) {
+        for (stage, duration) in [("read", times.read), ("parse", times.parse), ("aggregate", times.aggregate)] {
+            self.counter("pipeline.stage.duration", "us", duration.as_micros() as u64, &[("stage", stage.to_string())]);
+        }
+    }
+
+    /// Close the root span and send traces and metrics to the collector at
+    /// `endpoint` (e.g. `http://localhost:4318`).
+    pub fn export(&self, endpoint: &str) -> Result<(), ureq::Error> {
+        let end = SystemTime::now();
+        let agent = crate::sink::http_agent();
+        let endpoint = endpoint.trim_end_matches('/');
+
+        agent.post(&format!("{}/v1/traces", endpoint)).send_json(self.traces(end))?;
+        agent.post(&format!("{}/v1/metrics", endpoint)).send_json(self.metrics(end))?;
+        Ok(())
+    }
+
+    /// Replace this run's group on the Prometheus pushgateway at `url` with
+    /// its counters, plus when it ended and how long it took.
+    pub fn push_gateway(&self, url: &str, job: &str) -> Result<(), ureq::Error> {
+        let end = SystemTime::now();
+        let mut body = String::new();
+        let mut typed = Vec::new();
+        for counter in &self.counters {
+            let unit = match counter.unit {
+                "us" => "_microseconds",
+                _ => "",
+            };
+            let name = format!("{}_{}{}_total", SERVICE_NAME, prometheus_name(&counter.name), unit);
+            let labels: Vec<String> = counter.attributes.iter().map(|(key, value)| format!("{}=\"{}\"", prometheus_name(key), prometheus_value(value))).collect();
+            // A metric's type is declared once, before its first sample
+            if !typed.contains(&name) {
+                let _ = writeln!(body, "# TYPE {} counter", name);
+                typed.push(name.clone());
+            }
+            let _ = match labels.is_empty() {
+                true => writeln!(body, "{} {}", name, counter.value),
+                false => writeln!(body, "{}{{{}}} {}", name, labels.join(","), counter.value),
+            };
+        }
+        let ended = end.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs_f64();
+        let took = end.duration_since(self.start).unwrap_or_default().as_secs_f64();
+        let _ = writeln!(body, "# TYPE {0}_last_run_timestamp_seconds gauge\n{0}_last_run_timestamp_seconds {1}", SERVICE_NAME, ended);
+        let _ = writeln!(body, "# TYPE {0}_run_duration_seconds gauge\n{0}_run_duration_seconds {1}", SERVICE_NAME, took);
+
+        let mut group = format!("{}/metrics/job/{}", url.trim_end_matches('/'), path_segment(job));
+        if let Some(tenant) = &self.tenant {
+            group.push_str(&format!("/tenant/{}", path_segment(tenant)));
+        }
+        crate::sink::http_agent().put(&group).header("Content-Type", "text/plain; version=0.0.4").send(body)?;
+        Ok(())
+    }
+
+    /// Send the counters, and the run's duration as a timer, to the StatsD
+    /// daemon at `addr`, one datagram each.
+    pub fn statsd(&self, addr: &str, format: StatsdFormat) -> io::Result<()> {
+        let end = SystemTime::now();
+        let socket = UdpSocket::bind("0.0.0.0:0")?;
+        socket.connect(addr)?;
+        let mut tenant = Vec::new();
+        if let Some(name) = &self.tenant {
+            tenant.push(("tenant".to_string(), name.clone()));
+        }
+        let took = end.duration_since(self.start).unwrap_or_default().as_millis() as u64;
+        let metrics = self
+            .counters
+            .iter()
+            .map(|counter| (counter.name.as_str(), counter.value, "c", &counter.attributes[..]))
+            .chain([("run.duration", took, "ms", &[][..])]);
+        for (name, value, kind, attributes) in metrics {
+            let attributes: Vec<_> = tenant.iter().chain(attributes).collect();
+            let line = match format {
+                StatsdFormat::Statsd => {
+                    let mut name = format!("{}.{}", SERVICE_NAME, name);
+                    for (_, value) in attributes {
+                        name.push('.');
+                        name.push_str(&statsd_name(value));
+                    }
+                    format!("{}:{}|{}", name, value, kind)
+                }
+                StatsdFormat::Dogstatsd => {
+                    let tags: Vec<String> = attributes.iter().map(|(key, value)| format!("{}:{}", statsd_name(key), statsd_name(value))).collect();
+                    match tags.is_empty() {
+                        true => format!("{}.{}:{}|{}", SERVICE_NAME, name, value, kind),
+                        false => format!("{}.{}:{}|{}|#{}", SERVICE_NAME, name, value, kind, tags.join(",")),
+                    }
+                }
+            };
+            socket.send(line.as_bytes())?;
+        }
+        Ok(())
+    }
+
+    fn resource(&self) -> Value {
+        let mut resource = vec![("service.name".to_string(), SERVICE_NAME.to_string())];
+        if let Some(tenant) = &self.tenant {
+            resource.push(("tenant".to_string(), tenant.clone()));
+        }
+        json!({ "attributes": attributes(&resource) })
+    }
+
+    fn traces(&self, end: SystemTime) -> Value {
+        let mut spans = vec![json!({
+            "traceId": self.trace_id,
+            "spanId": self.root_span_id,
+            "name": "run",
+            "kind": 1,
+            "startTimeUnixNano": unix_nanos(self.start),
+            "endTimeUnixNano": unix_nanos(end),
+        })];
+        for span in &self.spans {
+            spans.push(json!({
+                "traceId": self.trace_id,
+                "spanId": span.span_id,
+                "parentSpanId": self.root_span_id,
+                "name": span.name,
+                "kind": 1,
+                "startTimeUnixNano": unix_nanos(span.start),
+                "endTimeUnixNano": unix_nanos(span.end),
+                "attributes": attributes(&span.attributes),
+            }));
+        }
+
+        json!({
+            "resourceSpans": [{
+                "resource": self.resource(),
+                "scopeSpans": [{ "scope": { "name": SERVICE_NAME }, "spans": spans }],
+            }]
+        })
+    }
+
+    fn metrics(&self, end: SystemTime) -> Value {
+        let metrics: Vec<Value> = self
+            .counters
+            .iter()
+            .map(|counter| {
+                json!({
+                    "name": counter.name,
+                    "unit": counter.unit,
+                    "sum": {
+                        "aggregationTemporality": 1,
+                        "isMonotonic": true,
+                        "dataPoints": [{
+                            "asInt": counter.value.to_string(),
+                            "startTimeUnixNano": unix_nanos(self.start),
+                            "timeUnixNano": unix_nanos(end),
+                            "attributes": attributes(&counter.attributes),
+                        }],
+                    },
+                })
+            })
+            .collect();
+
+        json!({
+            "resourceMetrics": [{
+                "resource": self.resource(),
+                "scopeMetrics": [{ "scope": { "name": SERVICE_NAME }, "metrics": metrics }],
+            }]
+        })
+    }
+}
+
+
+fn attributes(attributes: &[(String, String)]) -> Value {
+    attributes
+        .iter()
+        .map(|(key, value)| json!({ "key": key, "value": { "stringValue": value } }))
+        .collect()
+}
+
+/// `name` with everything Prometheus doesn't allow in names turned into `_`.
+fn prometheus_name(name: &str) -> String {
+    name.chars().map(|c| if c.is_ascii_alphanumeric() || c == '_' { c } else { '_' }).collect()
+}
+
+fn prometheus_value(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+/// `value` with the characters StatsD uses as separators turned into `_`.
+fn statsd_name(value: &str) -> String {
+    value.chars().map(|c| if matches!(c, ':' | '|' | '@' | '#' | ',') || c.is_whitespace() { '_' } else { c }).collect()
+}
+
+/// `value` percent-encoded for use as one segment of a URL path.
+fn path_segment(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    for byte in value.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => out.push(byte as char),
+            _ => {
+                let _ = write!(out, "%{:02X}", byte);
+            }
+        }
+    }
+    out
+}
+
+fn unix_nanos(time: SystemTime) -> String {
+    time.duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos().to_string()
+}
+
+/// Random id of `bytes` length as lowercase hex, from std's randomly keyed hasher.
+fn random_hex(bytes: usize) -> String {
+    let mut out = String::with_capacity(bytes * 2);
+    while out.len() < bytes * 2 {
+        let mut hasher = RandomState::new().build_hasher();
+        hasher.write_u128(SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos());
+        out.push_str(&format!("{:016x}", hasher.finish()));
+    }
+    out.truncate(bytes * 2);
+    out
+}
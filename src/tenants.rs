@@ -0,0 +1,250 @@
+//! Running every tenant of an options file from one command.
+//!
+//! Each tenant runs as a child process of this one, given the same command
+//! line plus `--tenant NAME`, so a tenant that fails or exits can't take
+//! the others' state or memory with it. Their output is passed through
+//! with each line prefixed by the tenant's name, and this process exits
+//! once all of them have, with the worst of their exit codes.
+//!
+//! Under a `Type=notify` unit systemd only listens to the process it
+//! started, so the tenants notify this one instead, each on a socket of
+//! its own, and it reports to systemd for all of them: `READY=1` once every
+//! tenant still running is, a status line gathering theirs, and watchdog
+//! pings only while none of them has missed its own.
+
+use std::env;
+use std::ffi::OsString;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::os::linux::net::SocketAddrExt;
+use std::os::unix::net::{SocketAddr, UnixDatagram};
+use std::path::Path;
+use std::process::{self, Command, Stdio};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::notify::Notifier;
+
+use crate::console::Console;
+
+/// How often the tenants' health is checked and their notify sockets read.
+const POLL: Duration = Duration::from_secs(1);
+
+/// How one tenant's run ended.
+struct Outcome {
+    name: String,
+    code: i32,
+    seconds: f64,
+}
+
+/// What a tenant last told this process on its notify socket.
+#[derive(Default)]
+struct Health {
+    ready: bool,
+    /// When it became ready or last pinged its watchdog
+    last_ping: Option<Instant>,
+    status: String,
+    stopping: bool,
+    exited: bool,
+}
+
+impl Health {
+    fn record(&mut self, message: &str) {
+        for line in message.lines() {
+            match line.split_once('=') {
+                Some(("READY", "1")) => {
+                    self.ready = true;
+                    self.last_ping = Some(Instant::now());
+                }
+                Some(("WATCHDOG", "1")) => self.last_ping = Some(Instant::now()),
+                Some(("STATUS", status)) => self.status = status.to_string(),
+                Some(("STOPPING", "1")) => self.stopping = true,
+                _ => {}
+            }
+        }
+    }
+}
+
+/// Where the tenants' notifications go, when this process has systemd's.
+#[derive(Clone)]
+struct Relay {
+    health: Arc<Mutex<Vec<Health>>>,
+    watchdog: Option<Duration>,
+}
+
+/// Run each of `tenants` to completion, returning the exit code for this
+/// process.
+pub fn supervise(tenants: &[String], console: &Console) -> i32 {
+    let exe = match env::current_exe() {
+        Ok(exe) => exe,
+        Err(err) => {
+            eprintln!("Unable to find this program to run its tenants: {}", err);
+            return 1;
+        }
+    };
+    let args: Vec<_> = env::args_os().skip(1).collect();
+    let mut notifier = Notifier::from_env();
+    let relay = notifier.enabled().then(|| Relay {
+        health: Arc::new(Mutex::new(tenants.iter().map(|_| Health::default()).collect())),
+        watchdog: notifier.watchdog(),
+    });
+
+    console.info(format!("Running {} tenants: {}.", tenants.len(), tenants.join(", ")));
+    let running: Vec<_> = tenants
+        .iter()
+        .enumerate()
+        .map(|(index, name)| {
+            let (exe, args, name, relay) = (exe.clone(), args.clone(), name.clone(), relay.clone());
+            thread::spawn(move || {
+                let started = Instant::now();
+                let code = run(&exe, &args, &name, relay.map(|relay| (relay, index)));
+                Outcome { name, code, seconds: started.elapsed().as_secs_f64() }
+            })
+        })
+        .collect();
+
+    if let Some(relay) = &relay {
+        let mut stalled = Vec::new();
+        while !running.iter().all(thread::JoinHandle::is_finished) {
+            thread::sleep(notifier.ping_every().map_or(POLL, |every| every.min(POLL)));
+            report(&mut notifier, tenants, relay, &mut stalled);
+        }
+        notifier.stopping("All tenants exited");
+    }
+    let outcomes: Vec<Outcome> = running.into_iter().map(|tenant| tenant.join().expect("Tenant supervisor panicked")).collect();
+
+    for outcome in &outcomes {
+        match outcome.code {
+            0 => console.info(format!("Tenant {} finished in {:.1}s.", outcome.name, outcome.seconds)),
+            code => eprintln!("Tenant {} exited with status {} after {:.1}s", outcome.name, code, outcome.seconds),
+        }
+    }
+    outcomes.iter().map(|outcome| outcome.code).max().unwrap_or(0)
+}
+
+/// Tell systemd how the tenants are doing: ready and alive once all the
+/// running ones are, otherwise which of them stopped pinging.
+fn report(notifier: &mut Notifier, tenants: &[String], relay: &Relay, stalled: &mut Vec<String>) {
+    let health = relay.health.lock().expect("Unable to lock tenant health");
+    let running: Vec<(&String, &Health)> = tenants.iter().zip(health.iter()).filter(|(_, health)| !health.exited && !health.stopping).collect();
+    if running.iter().any(|(_, health)| !health.ready) {
+        return;
+    }
+    let now_stalled: Vec<String> = running
+        .iter()
+        .filter(|(_, health)| relay.watchdog.is_some_and(|limit| health.last_ping.is_none_or(|ping| ping.elapsed() > limit)))
+        .map(|(name, _)| name.to_string())
+        .collect();
+    if !now_stalled.is_empty() {
+        if now_stalled != *stalled {
+            notifier.status(&format!("Tenants not responding: {}", now_stalled.join(", ")));
+        }
+        *stalled = now_stalled;
+        return;
+    }
+    stalled.clear();
+    notifier.tick(|| {
+        if running.is_empty() {
+            return "Waiting for the tenants to finish".to_string();
+        }
+        let statuses: Vec<String> = running.iter().map(|(name, health)| format!("{}: {}", name, health.status)).collect();
+        format!("{} tenants running; {}", running.len(), statuses.join("; "))
+    });
+}
+
+/// Run one tenant, relaying its output and, given a `relay`, its
+/// notifications, and return its exit code.
+fn run(exe: &Path, args: &[OsString], name: &str, relay: Option<(Relay, usize)>) -> i32 {
+    let mut command = Command::new(exe);
+    command.args(args).arg("--tenant").arg(name).stdout(Stdio::piped()).stderr(Stdio::piped());
+    let listener = match relay {
+        Some((relay, index)) => match listen(&mut command, relay, index) {
+            Ok(listener) => Some(listener),
+            Err(err) => {
+                eprintln!("[{}] Unable to open a notify socket: {}", name, err);
+                return 1;
+            }
+        },
+        None => None,
+    };
+    let spawned = command.spawn();
+    let code = match spawned {
+        Ok(child) => wait(child, name),
+        Err(err) => {
+            eprintln!("[{}] Unable to start: {}", name, err);
+            1
+        }
+    };
+    if let Some((relay, index, listener)) = listener {
+        relay.health.lock().expect("Unable to lock tenant health")[index].exited = true;
+        let _ = listener.join();
+    }
+    code
+}
+
+fn wait(mut child: process::Child, name: &str) -> i32 {
+    let mut relays = Vec::new();
+    if let Some(stdout) = child.stdout.take() {
+        relays.push(relay(name.to_string(), stdout, false));
+    }
+    if let Some(stderr) = child.stderr.take() {
+        relays.push(relay(name.to_string(), stderr, true));
+    }
+    let code = match child.wait() {
+        Ok(status) => status.code().unwrap_or(1),
+        Err(err) => {
+            eprintln!("[{}] Unable to wait for the tenant's run: {}", name, err);
+            1
+        }
+    };
+    for relay in relays {
+        let _ = relay.join();
+    }
+    code
+}
+
+/// Give the tenant `command` starts a notify socket of its own, with this
+/// process's watchdog interval, and read what it sends there into its
+/// health until it has exited.
+fn listen(command: &mut Command, relay: Relay, index: usize) -> io::Result<(Relay, usize, thread::JoinHandle<()>)> {
+    let name = format!("syslog_processor-{}-tenant-{}", process::id(), index);
+    let socket = UnixDatagram::bind_addr(&SocketAddr::from_abstract_name(&name)?)?;
+    socket.set_read_timeout(Some(POLL))?;
+    command.env("NOTIFY_SOCKET", format!("@{}", name)).env_remove("WATCHDOG_PID");
+    match relay.watchdog {
+        Some(interval) => command.env("WATCHDOG_USEC", interval.as_micros().to_string()),
+        None => command.env_remove("WATCHDOG_USEC"),
+    };
+    let health = Arc::clone(&relay.health);
+    let listener = thread::spawn(move || {
+        let mut message = vec![0; 4096];
+        loop {
+            let received = socket.recv(&mut message);
+            let mut health = health.lock().expect("Unable to lock tenant health");
+            if let Ok(n) = received {
+                health[index].record(&String::from_utf8_lossy(&message[..n]));
+            }
+            if health[index].exited {
+                return;
+            }
+        }
+    });
+    Ok((relay, index, listener))
+}
+
+/// Copy a child's output to ours a line at a time, prefixed by its tenant.
+fn relay(name: String, output: impl Read + Send + 'static, to_stderr: bool) -> thread::JoinHandle<()> {
+    thread::spawn(move || {
+        let mut output = BufReader::new(output);
+        let mut line = Vec::new();
+        while matches!(output.read_until(b'\n', &mut line), Ok(n) if n > 0) {
+            let text = String::from_utf8_lossy(&line);
+            let text = text.trim_end_matches(['\r', '\n']);
+            let _ = match to_stderr {
+                true => writeln!(io::stderr().lock(), "[{}] {}", name, text),
+                false => writeln!(io::stdout().lock(), "[{}] {}", name, text),
+            };
+            line.clear();
+        }
+    })
+}
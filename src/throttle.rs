@@ -0,0 +1,200 @@
+//! Rate-limited reading for runs that share a log server.
+//!
+//! With `--throttle`, reading is paced to at most so many bytes or lines a
+//! second so a background batch run doesn't starve rsyslog or a SIEM
+//! forwarder of disk bandwidth. Every clone of a [`Throttle`] draws on the
+//! same budget, so the limit holds for the run as a whole however many
+//! threads read. Each clone settles with the shared budget in small
+//! batches to keep the lock out of the per-line path.
+
+use std::io::{self, Read};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Bytes or lines a clone reads before settling with the shared budget.
+const SETTLE_BYTES: u64 = 64 * 1024;
+const SETTLE_LINES: u64 = 256;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Rate {
+    BytesPerSecond(f64),
+    LinesPerSecond(f64),
+}
+
+impl FromStr for Rate {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("expected a rate like `20MB/s`, `512KiB/s` or `5000lines/s`, got `{}`", value);
+        let per_second = value.trim().strip_suffix("/s").ok_or_else(invalid)?;
+        let split = per_second.find(|c: char| !c.is_ascii_digit() && c != '.').unwrap_or(per_second.len());
+        let (amount, unit) = per_second.split_at(split);
+        let amount: f64 = amount.parse().map_err(|_| invalid())?;
+        if amount.is_nan() || amount <= 0.0 {
+            return Err(invalid());
+        }
+        let bytes = |scale: f64| Ok(Rate::BytesPerSecond(amount * scale));
+        match unit.trim().to_ascii_lowercase().as_str() {
+            "lines" | "line" => Ok(Rate::LinesPerSecond(amount)),
+            "b" => bytes(1.0),
+            "kb" => bytes(1e3),
+            "mb" => bytes(1e6),
+            "gb" => bytes(1e9),
+            "kib" => bytes(1024.0),
+            "mib" => bytes(1024.0 * 1024.0),
+            "gib" => bytes(1024.0 * 1024.0 * 1024.0),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+/// The budget the clones share: a token bucket holding at most a second's
+/// worth, which may go into debt by one batch.
+struct Budget {
+    per_second: f64,
+    available: f64,
+    refilled: Instant,
+}
+
+pub struct Throttle {
+    rate: Rate,
+    budget: Arc<Mutex<Budget>>,
+    pending: u64,
+}
+
+impl Throttle {
+    pub fn new(rate: Rate) -> Self {
+        let per_second = match rate {
+            Rate::BytesPerSecond(per_second) | Rate::LinesPerSecond(per_second) => per_second,
+        };
+        Throttle {
+            rate,
+            budget: Arc::new(Mutex::new(Budget {
+                per_second,
+                available: per_second,
+                refilled: Instant::now(),
+            })),
+            pending: 0,
+        }
+    }
+
+    /// Account for `bytes` read holding `lines` complete lines, sleeping
+    /// when the run is ahead of its rate.
+    pub fn read(&mut self, bytes: usize, lines: usize) {
+        let (amount, settle_at) = match self.rate {
+            Rate::BytesPerSecond(_) => (bytes as u64, SETTLE_BYTES),
+            Rate::LinesPerSecond(_) => (lines as u64, SETTLE_LINES),
+        };
+        self.pending += amount;
+        if self.pending >= settle_at {
+            self.settle();
+        }
+    }
+
+    fn settle(&mut self) {
+        let amount = std::mem::take(&mut self.pending) as f64;
+        let wait = {
+            let mut budget = self.budget.lock().expect("throttle budget poisoned");
+            let now = Instant::now();
+            let refill = now.duration_since(budget.refilled).as_secs_f64() * budget.per_second;
+            budget.available = (budget.available + refill).min(budget.per_second);
+            budget.refilled = now;
+            budget.available -= amount;
+            (budget.available < 0.0).then(|| Duration::from_secs_f64(-budget.available / budget.per_second))
+        };
+        if let Some(wait) = wait {
+            thread::sleep(wait);
+        }
+    }
+}
+
+/// Another handle on the same budget, with nothing pending of its own.
+impl Clone for Throttle {
+    fn clone(&self) -> Self {
+        Throttle {
+            rate: self.rate,
+            budget: Arc::clone(&self.budget),
+            pending: 0,
+        }
+    }
+}
+
+/// What is still pending is settled when a handle goes away, so many
+/// short-lived handles can't read for free.
+impl Drop for Throttle {
+    fn drop(&mut self) {
+        if self.pending > 0 {
+            self.settle();
+        }
+    }
+}
+
+/// A reader whose reads count against a throttle, for passes over a file
+/// that don't split it into lines.
+pub struct Throttled<'a, R> {
+    inner: R,
+    throttle: &'a mut Throttle,
+}
+
+impl<'a, R: Read> Throttled<'a, R> {
+    pub fn new(inner: R, throttle: &'a mut Throttle) -> Self {
+        Throttled { inner, throttle }
+    }
+}
+
+impl<R: Read> Read for Throttled<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.throttle.read(read, 0);
+        Ok(read)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rates_parse_with_their_units() {
+        let cases = [
+            ("20MB/s", Some(Rate::BytesPerSecond(20e6))),
+            ("512KiB/s", Some(Rate::BytesPerSecond(512.0 * 1024.0))),
+            ("1.5 gb/s", Some(Rate::BytesPerSecond(1.5e9))),
+            ("100b/s", Some(Rate::BytesPerSecond(100.0))),
+            ("5000lines/s", Some(Rate::LinesPerSecond(5000.0))),
+            ("1line/s", Some(Rate::LinesPerSecond(1.0))),
+            ("20MB", None),
+            ("0MB/s", None),
+            ("MB/s", None),
+            ("20furlongs/s", None),
+        ];
+        for (value, expected) in cases {
+            assert_eq!(value.parse::<Rate>().ok(), expected, "{}", value);
+        }
+    }
+
+    #[test]
+    fn clones_share_the_budget() {
+        // A second's worth is free; what is read beyond it waits for the rate
+        let throttle = Throttle::new(Rate::LinesPerSecond(10_000.0));
+        let started = Instant::now();
+        let readers: Vec<_> = (0..2)
+            .map(|_| {
+                let mut throttle = throttle.clone();
+                thread::spawn(move || {
+                    for _ in 0..25 {
+                        throttle.read(1 << 20, SETTLE_LINES as usize);
+                    }
+                })
+            })
+            .collect();
+        for reader in readers {
+            reader.join().unwrap();
+        }
+        let elapsed = started.elapsed();
+        assert!(elapsed >= Duration::from_millis(200), "{:?}", elapsed);
+        assert!(elapsed < Duration::from_secs(5), "{:?}", elapsed);
+    }
+}
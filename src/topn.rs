@@ -0,0 +1,161 @@
+//! Sliding-window top talkers while `serve` is receiving.
+//!
+//! Traffic is counted per source address in 10-second slices, each a
+//! Space-Saving summary holding at most `capacity` addresses, so memory
+//! stays fixed however many hosts talk. The 1, 5 and 15-minute views merge
+//! the slices they cover. Counts can overestimate addresses that entered a
+//! full summary late; `guaranteedBytes` is the part that is certain.
+
+use std::collections::{HashMap, VecDeque};
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+
+/// Views served, as (name, length)
+pub const WINDOWS: &[(&str, Duration)] = &[
+    ("1m", Duration::from_secs(60)),
+    ("5m", Duration::from_secs(300)),
+    ("15m", Duration::from_secs(900)),
+];
+
+const SLICE: Duration = Duration::from_secs(10);
+
+/// Space-Saving heavy hitters: address -> (counted bytes, possible overcount).
+struct Summary {
+    capacity: usize,
+    counts: HashMap<Arc<str>, (u64, u64)>,
+}
+
+impl Summary {
+    fn add(&mut self, address: Arc<str>, bytes: u64) {
+        if let Some((count, _)) = self.counts.get_mut(&address) {
+            *count += bytes;
+            return;
+        }
+        if self.counts.len() < self.capacity {
+            self.counts.insert(address, (bytes, 0));
+            return;
+        }
+        // Replace the smallest entry; the newcomer may have sent up to its count already
+        let smallest = self.counts.iter().min_by_key(|(_, (count, _))| *count).map(|(address, (count, _))| (Arc::clone(address), *count));
+        if let Some((evicted, floor)) = smallest {
+            self.counts.remove(&evicted);
+            self.counts.insert(address, (floor + bytes, floor));
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Talker {
+    pub address: String,
+    pub bytes: u64,
+    pub guaranteed_bytes: u64,
+}
+
+pub struct SlidingTopN {
+    capacity: usize,
+    slices: VecDeque<(Instant, Summary)>,
+}
+
+impl SlidingTopN {
+    pub fn new(capacity: usize) -> Self {
+        SlidingTopN { capacity: capacity.max(1), slices: VecDeque::new() }
+    }
+
+    pub fn add(&mut self, now: Instant, address: Arc<str>, bytes: u64) {
+        let longest = WINDOWS.iter().map(|(_, length)| *length).max().unwrap_or(SLICE);
+        while let Some((start, _)) = self.slices.front()
+            && now.duration_since(*start) > longest
+        {
+            self.slices.pop_front();
+        }
+        let current = matches!(self.slices.back(), Some((start, _)) if now.duration_since(*start) < SLICE);
+        if !current {
+            let summary = Summary { capacity: self.capacity, counts: HashMap::new() };
+            self.slices.push_back((now, summary));
+        }
+        if let Some((_, summary)) = self.slices.back_mut() {
+            summary.add(address, bytes);
+        }
+    }
+
+    /// The `n` busiest sources of the slices started within `window` of `now`.
+    pub fn top(&self, now: Instant, window: Duration, n: usize) -> Vec<Talker> {
+        let mut merged: HashMap<&str, (u64, u64)> = HashMap::new();
+        for (start, summary) in &self.slices {
+            if now.saturating_duration_since(*start) > window {
+                continue;
+            }
+            for (address, (count, error)) in &summary.counts {
+                let entry = merged.entry(address).or_insert((0, 0));
+                entry.0 += count;
+                entry.1 += count - error;
+            }
+        }
+        let mut talkers: Vec<Talker> = merged
+            .into_iter()
+            .map(|(address, (bytes, guaranteed_bytes))| Talker { address: address.to_string(), bytes, guaranteed_bytes })
+            .collect();
+        talkers.sort_by(|a, b| b.bytes.cmp(&a.bytes).then_with(|| a.address.cmp(&b.address)));
+        talkers.truncate(n);
+        talkers
+    }
+}
+
+/// Answer `GET /top[?n=N]` on `addr` with the current views as JSON.
+pub fn serve(addr: &str, talkers: Arc<Mutex<SlidingTopN>>, n: usize) -> io::Result<()> {
+    let listener = TcpListener::bind(addr)?;
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            if let Err(err) = respond(stream, &talkers, n) {
+                eprintln!("Top talkers request failed: {}", err);
+            }
+        }
+    });
+    Ok(())
+}
+
+fn respond(stream: TcpStream, talkers: &Mutex<SlidingTopN>, default_n: usize) -> io::Result<()> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut request = String::new();
+    reader.read_line(&mut request)?;
+    // Drain the headers so the client sees a clean response
+    let mut header = String::new();
+    while reader.read_line(&mut header)? > 2 {
+        header.clear();
+    }
+
+    let target = request.split_whitespace().nth(1).unwrap_or_default();
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    let (status, body) = match path {
+        "/top" => {
+            let n = query
+                .split('&')
+                .find_map(|pair| pair.strip_prefix("n="))
+                .and_then(|n| n.parse().ok())
+                .unwrap_or(default_n);
+            let now = Instant::now();
+            let talkers = talkers.lock().expect("Unable to lock top talkers");
+            let windows: serde_json::Map<String, serde_json::Value> = WINDOWS
+                .iter()
+                .map(|(name, length)| (name.to_string(), serde_json::json!(talkers.top(now, *length, n))))
+                .collect();
+            ("200 OK", serde_json::json!({ "windows": windows }).to_string())
+        }
+        _ => ("404 Not Found", r#"{"error":"not found"}"#.to_string()),
+    };
+
+    let mut stream = stream;
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    )
+}
@@ -0,0 +1,135 @@
+//! Trend ledger: headline numbers of every run, appended to `trends.jsonl`
+//! in the output directory, and a week-over-week view of them.
+//!
+//! The ledger is append-only and one short line per run, so it stays cheap
+//! to keep for years. Weeks are ISO weeks of the runs' start times (UTC).
+
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::Path;
+
+use chrono::{DateTime, Datelike, Days, NaiveDate, Weekday};
+use serde::{Deserialize, Serialize};
+
+use crate::payload::Payload;
+use crate::query::text_table;
+use crate::report::format_bytes;
+
+pub const TRENDS_FILE: &str = "trends.jsonl";
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct TrendEntry {
+    /// Milliseconds since the epoch the run started
+    pub start_time: u128,
+    pub total_bytes: u64,
+    pub total_packets: u64,
+    pub flows: usize,
+    pub sessions: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub top_destination: Option<TopDestination>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TopDestination {
+    pub address: String,
+    pub bytes: u64,
+}
+
+impl TrendEntry {
+    pub fn of(payload: &Payload) -> Self {
+        let mut destinations: HashMap<&str, u64> = HashMap::new();
+        for record in payload.data.values() {
+            let bytes = destinations.entry(&record.destination_ip).or_default();
+            *bytes = bytes.saturating_add(record.bytes_in.saturating_add(record.bytes_out));
+        }
+        let top_destination = destinations
+            .into_iter()
+            .max_by(|a, b| a.1.cmp(&b.1).then_with(|| b.0.cmp(a.0)))
+            .map(|(address, bytes)| TopDestination { address: address.to_string(), bytes });
+        let records = payload.data.values();
+        TrendEntry {
+            start_time: payload.metadata.start_time,
+            total_bytes: records.clone().map(|record| record.bytes_in.saturating_add(record.bytes_out)).fold(0, u64::saturating_add),
+            total_packets: records.clone().map(|record| record.packets_in.saturating_add(record.packets_out)).fold(0, u64::saturating_add),
+            flows: payload.data.len(),
+            sessions: records.map(|record| record.count).sum(),
+            top_destination,
+        }
+    }
+
+    pub fn append_to(&self, dir: &Path) -> io::Result<()> {
+        let mut ledger = OpenOptions::new().create(true).append(true).open(dir.join(TRENDS_FILE))?;
+        let mut line = serde_json::to_vec(self)?;
+        line.push(b'\n');
+        ledger.write_all(&line)
+    }
+}
+
+/// Every entry of the ledger in `dir`; unreadable lines are skipped.
+pub fn load(dir: &Path) -> io::Result<Vec<TrendEntry>> {
+    let ledger = File::open(dir.join(TRENDS_FILE))?;
+    let mut entries = Vec::new();
+    for line in BufReader::new(ledger).lines() {
+        if let Ok(entry) = serde_json::from_str(&line?) {
+            entries.push(entry);
+        }
+    }
+    Ok(entries)
+}
+
+#[derive(Default)]
+struct Week {
+    runs: usize,
+    bytes: u64,
+    sessions: u64,
+    flows: usize,
+    top: Option<TopDestination>,
+}
+
+fn change(now: u64, before: Option<u64>) -> String {
+    match before {
+        Some(0) | None => "-".to_string(),
+        Some(before) => format!("{:+.1}%", (now as f64 - before as f64) / before as f64 * 100.0),
+    }
+}
+
+/// Table of the last `weeks` ISO weeks with runs, with their change over
+/// the week before.
+pub fn render(entries: &[TrendEntry], weeks: usize) -> String {
+    let mut by_week: BTreeMap<(i32, u32), Week> = BTreeMap::new();
+    for entry in entries {
+        let started = DateTime::from_timestamp_millis(entry.start_time as i64).unwrap_or_default().iso_week();
+        let week = by_week.entry((started.year(), started.week())).or_default();
+        week.runs += 1;
+        week.bytes = week.bytes.saturating_add(entry.total_bytes);
+        week.sessions += entry.sessions;
+        week.flows += entry.flows;
+        if let Some(top) = &entry.top_destination
+            && week.top.as_ref().is_none_or(|current| top.bytes > current.bytes)
+        {
+            week.top = Some(top.clone());
+        }
+    }
+
+    let mut rows = Vec::new();
+    for (&(year, number), week) in &by_week {
+        // Only the calendar week right before counts; a gap has no change
+        let previous = NaiveDate::from_isoywd_opt(year, number, Weekday::Mon)
+            .map(|monday| (monday - Days::new(7)).iso_week())
+            .and_then(|before| by_week.get(&(before.year(), before.week())));
+        rows.push(vec![
+            format!("{}-W{:02}", year, number),
+            week.runs.to_string(),
+            format_bytes(week.bytes),
+            change(week.bytes, previous.map(|previous| previous.bytes)),
+            week.sessions.to_string(),
+            change(week.sessions, previous.map(|previous| previous.sessions)),
+            week.flows.to_string(),
+            week.top.as_ref().map_or_else(String::new, |top| format!("{} ({})", top.address, format_bytes(top.bytes))),
+        ]);
+    }
+    let rows = rows.split_off(rows.len().saturating_sub(weeks));
+    text_table(&["Week", "Runs", "Bytes", "Change", "Sessions", "Change", "Flows", "Top destination"], &rows, 1)
+}
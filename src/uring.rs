@@ -0,0 +1,527 @@
+//! Overlapping file reads through io_uring (Linux, `io-uring` feature).
+//!
+//! Batch runs read their inputs one after the other, so on spinning disks
+//! most of the wall time is spent waiting for the next file's first blocks.
+//! [`ReadAhead`] keeps a read in flight for each of the next `depth` files
+//! while the current one is consumed, so the disk can order the seeks
+//! itself. Files are still handed out whole and in order, as readers, so
+//! what is done with their lines doesn't change.
+
+use std::collections::{HashMap, VecDeque};
+use std::fs::File;
+use std::io::{self, Read};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::path::PathBuf;
+use std::ptr;
+use std::sync::atomic::{AtomicU32, Ordering};
+
+/// Bytes asked for by each read.
+const CHUNK: usize = 256 * 1024;
+/// Chunks buffered per file ahead of its reader.
+const BUFFERED: usize = 2;
+
+const IORING_OFF_SQ_RING: i64 = 0;
+const IORING_OFF_CQ_RING: i64 = 0x800_0000;
+const IORING_OFF_SQES: i64 = 0x1000_0000;
+const IORING_OP_READ: u8 = 22;
+const IORING_ENTER_GETEVENTS: u32 = 1;
+
+// Kernel layout; not every field is read
+#[allow(dead_code)]
+#[repr(C)]
+#[derive(Default)]
+struct SqringOffsets {
+    head: u32,
+    tail: u32,
+    ring_mask: u32,
+    ring_entries: u32,
+    flags: u32,
+    dropped: u32,
+    array: u32,
+    resv1: u32,
+    user_addr: u64,
+}
+
+// Kernel layout; not every field is read
+#[allow(dead_code)]
+#[repr(C)]
+#[derive(Default)]
+struct CqringOffsets {
+    head: u32,
+    tail: u32,
+    ring_mask: u32,
+    ring_entries: u32,
+    overflow: u32,
+    cqes: u32,
+    flags: u32,
+    resv1: u32,
+    user_addr: u64,
+}
+
+// Kernel layout; not every field is read
+#[allow(dead_code)]
+#[repr(C)]
+#[derive(Default)]
+struct Params {
+    sq_entries: u32,
+    cq_entries: u32,
+    flags: u32,
+    sq_thread_cpu: u32,
+    sq_thread_idle: u32,
+    features: u32,
+    wq_fd: u32,
+    resv: [u32; 3],
+    sq_off: SqringOffsets,
+    cq_off: CqringOffsets,
+}
+
+// Kernel layout; not every field is read
+#[allow(dead_code)]
+#[repr(C)]
+struct Sqe {
+    opcode: u8,
+    flags: u8,
+    ioprio: u16,
+    fd: i32,
+    off: u64,
+    addr: u64,
+    len: u32,
+    rw_flags: u32,
+    user_data: u64,
+    buf_index: u16,
+    personality: u16,
+    splice_fd_in: i32,
+    addr3: u64,
+    pad: u64,
+}
+
+// Kernel layout; not every field is read
+#[allow(dead_code)]
+#[repr(C)]
+struct Cqe {
+    user_data: u64,
+    res: i32,
+    flags: u32,
+}
+
+/// A shared mapping of one of the ring's regions.
+struct Mapping {
+    addr: *mut u8,
+    len: usize,
+}
+
+impl Mapping {
+    fn new(fd: RawFd, len: usize, offset: i64) -> io::Result<Mapping> {
+        // Safety: a fresh shared mapping of the ring fd; the kernel checks the offset and length
+        let addr = unsafe { libc::mmap(ptr::null_mut(), len, libc::PROT_READ | libc::PROT_WRITE, libc::MAP_SHARED | libc::MAP_POPULATE, fd, offset) };
+        if addr == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Mapping { addr: addr.cast(), len })
+    }
+
+    /// Safety: `offset` must be a field of the region the kernel laid out.
+    unsafe fn at<T>(&self, offset: u32) -> *mut T {
+        unsafe { self.addr.add(offset as usize).cast() }
+    }
+}
+
+impl Drop for Mapping {
+    fn drop(&mut self) {
+        // Safety: unmapping exactly what `new` mapped
+        unsafe {
+            libc::munmap(self.addr.cast(), self.len);
+        }
+    }
+}
+
+/// The smallest useful io_uring: read submissions and their completions.
+struct Ring {
+    // Field order matters: the mappings go before the fd is closed
+    sq: Mapping,
+    cq: Mapping,
+    sqes: Mapping,
+    params: Params,
+    fd: OwnedFd,
+}
+
+impl Ring {
+    fn new(entries: u32) -> io::Result<Ring> {
+        let mut params = Params::default();
+        // Safety: `params` is a correctly laid out io_uring_params the kernel fills in
+        let fd = unsafe { libc::syscall(libc::SYS_io_uring_setup, entries, &mut params as *mut Params) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // Safety: the syscall returned a new descriptor we now own
+        let fd = unsafe { OwnedFd::from_raw_fd(fd as RawFd) };
+        let raw = fd.as_raw_fd();
+        let sq_len = params.sq_off.array as usize + params.sq_entries as usize * size_of::<u32>();
+        let cq_len = params.cq_off.cqes as usize + params.cq_entries as usize * size_of::<Cqe>();
+        let sq = Mapping::new(raw, sq_len, IORING_OFF_SQ_RING)?;
+        let cq = Mapping::new(raw, cq_len, IORING_OFF_CQ_RING)?;
+        let sqes = Mapping::new(raw, params.sq_entries as usize * size_of::<Sqe>(), IORING_OFF_SQES)?;
+        Ok(Ring { sq, cq, sqes, params, fd })
+    }
+
+    /// Queue a read of `buf` from `offset` of `fd`, unless the submission
+    /// queue is full. The buffer and `fd` have to stay put until its
+    /// completion is reaped.
+    fn queue_read(&mut self, fd: RawFd, buf: &mut [u8], offset: u64, user_data: u64) -> bool {
+        let off = &self.params.sq_off;
+        // Safety: offsets come from the kernel; only this thread touches the submission side
+        unsafe {
+            let head = (*self.sq.at::<AtomicU32>(off.head)).load(Ordering::Acquire);
+            let tail = &*self.sq.at::<AtomicU32>(off.tail);
+            let entries = *self.sq.at::<u32>(off.ring_entries);
+            if tail.load(Ordering::Relaxed).wrapping_sub(head) >= entries {
+                return false;
+            }
+            let mask = *self.sq.at::<u32>(off.ring_mask);
+            let index = tail.load(Ordering::Relaxed) & mask;
+            self.sqes.at::<Sqe>(0).add(index as usize).write(Sqe {
+                opcode: IORING_OP_READ,
+                flags: 0,
+                ioprio: 0,
+                fd,
+                off: offset,
+                addr: buf.as_mut_ptr() as u64,
+                len: buf.len() as u32,
+                rw_flags: 0,
+                user_data,
+                buf_index: 0,
+                personality: 0,
+                splice_fd_in: 0,
+                addr3: 0,
+                pad: 0,
+            });
+            *self.sq.at::<u32>(off.array).add(index as usize) = index;
+            tail.fetch_add(1, Ordering::Release);
+        }
+        true
+    }
+
+    /// Submit what was queued and wait for at least one completion.
+    fn submit_and_wait(&mut self, submit: u32) -> io::Result<()> {
+        loop {
+            // Safety: a plain io_uring_enter on our own ring
+            let result = unsafe { libc::syscall(libc::SYS_io_uring_enter, self.fd.as_raw_fd(), submit, 1u32, IORING_ENTER_GETEVENTS, ptr::null::<u8>(), 0usize) };
+            if result >= 0 {
+                return Ok(());
+            }
+            let err = io::Error::last_os_error();
+            if err.kind() != io::ErrorKind::Interrupted {
+                return Err(err);
+            }
+        }
+    }
+
+    /// Completions so far, as `(user_data, result)`.
+    fn reap(&mut self) -> Vec<(u64, i32)> {
+        let off = &self.params.cq_off;
+        let mut done = Vec::new();
+        // Safety: offsets come from the kernel; entries between head and tail are ours to read
+        unsafe {
+            let head = &*self.cq.at::<AtomicU32>(off.head);
+            let tail = (*self.cq.at::<AtomicU32>(off.tail)).load(Ordering::Acquire);
+            let mask = *self.cq.at::<u32>(off.ring_mask);
+            let mut at = head.load(Ordering::Relaxed);
+            while at != tail {
+                let cqe = &*self.cq.at::<Cqe>(off.cqes).add((at & mask) as usize);
+                done.push((cqe.user_data, cqe.res));
+                at = at.wrapping_add(1);
+            }
+            head.store(at, Ordering::Release);
+        }
+        done
+    }
+}
+
+/// Whether this kernel (and its sandboxing) lets us set up a ring.
+pub fn available() -> io::Result<()> {
+    Ring::new(1).map(drop)
+}
+
+/// One of the files in the read-ahead window.
+struct Slot {
+    path: PathBuf,
+    file: io::Result<File>,
+    offset: u64,
+    ready: VecDeque<io::Result<Vec<u8>>>,
+    reading: bool,
+    eof: bool,
+}
+
+/// Reads `paths` in order with reads for the next `depth` of them in flight.
+pub struct ReadAhead {
+    ring: Ring,
+    depth: usize,
+    pending: VecDeque<PathBuf>,
+    window: VecDeque<Slot>,
+    /// Index of the window's first file among all of them
+    first: u64,
+    /// Buffers the kernel is reading into, by file index
+    in_flight: HashMap<u64, Vec<u8>>,
+    /// Files passed over with a read still queued or in flight, kept open
+    /// until it completes so that its descriptor can't be reused meanwhile
+    retired: HashMap<u64, File>,
+    queued: u32,
+    /// Whether the first file of the window was handed out
+    handed_out: bool,
+}
+
+impl ReadAhead {
+    pub fn new(paths: Vec<PathBuf>, depth: usize) -> io::Result<ReadAhead> {
+        let depth = depth.max(1);
+        Ok(ReadAhead {
+            ring: Ring::new(depth.next_power_of_two() as u32)?,
+            depth,
+            pending: paths.into(),
+            window: VecDeque::new(),
+            first: 0,
+            in_flight: HashMap::new(),
+            retired: HashMap::new(),
+            queued: 0,
+            handed_out: false,
+        })
+    }
+
+    /// The next file in order, with a reader for its contents or the error
+    /// opening it.
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(&mut self) -> Option<(PathBuf, io::Result<FileReader<'_>>)> {
+        // Whatever is left of the previous file is dropped, even mid-read
+        if self.handed_out
+            && let Some(slot) = self.window.pop_front()
+        {
+            if slot.reading
+                && let Ok(file) = slot.file
+            {
+                self.retired.insert(self.first, file);
+            }
+            self.first += 1;
+            self.handed_out = false;
+        }
+        self.fill();
+        let front = self.window.front_mut()?;
+        let path = front.path.clone();
+        if let Err(err) = &front.file {
+            let err = io::Error::new(err.kind(), err.to_string());
+            self.handed_out = true;
+            return Some((path, Err(err)));
+        }
+        self.handed_out = true;
+        Some((path, Ok(FileReader { ahead: self, chunk: Vec::new(), at: 0 })))
+    }
+
+    /// Open files up to the window size and start reads for those that
+    /// have room for another chunk.
+    fn fill(&mut self) {
+        while self.window.len() < self.depth
+            && let Some(path) = self.pending.pop_front()
+        {
+            let file = File::open(&path);
+            self.window.push_back(Slot {
+                path,
+                file,
+                offset: 0,
+                ready: VecDeque::new(),
+                reading: false,
+                eof: false,
+            });
+        }
+        for (position, slot) in self.window.iter_mut().enumerate() {
+            let Ok(file) = &slot.file else {
+                continue;
+            };
+            if slot.reading || slot.eof || slot.ready.len() >= BUFFERED {
+                continue;
+            }
+            let index = self.first + position as u64;
+            let mut buf = vec![0; CHUNK];
+            if !self.ring.queue_read(file.as_raw_fd(), &mut buf, slot.offset, index) {
+                break;
+            }
+            self.in_flight.insert(index, buf);
+            self.queued += 1;
+            slot.reading = true;
+        }
+    }
+
+    /// The front file's next chunk, empty at its end.
+    fn chunk(&mut self) -> io::Result<Vec<u8>> {
+        loop {
+            let front = self.window.front_mut().expect("a file is being read");
+            if let Some(chunk) = front.ready.pop_front() {
+                return chunk;
+            }
+            if front.eof {
+                return Ok(Vec::new());
+            }
+            self.fill();
+            let submit = std::mem::take(&mut self.queued);
+            self.ring.submit_and_wait(submit)?;
+            self.complete();
+        }
+    }
+
+    fn complete(&mut self) {
+        for (index, result) in self.ring.reap() {
+            let Some(mut buf) = self.in_flight.remove(&index) else {
+                continue;
+            };
+            // Completions for files already passed over only free their buffer and file
+            self.retired.remove(&index);
+            let Some(slot) = index.checked_sub(self.first).and_then(|position| self.window.get_mut(position as usize)) else {
+                continue;
+            };
+            slot.reading = false;
+            match result {
+                0 => slot.eof = true,
+                read if read > 0 => {
+                    buf.truncate(read as usize);
+                    slot.offset += read as u64;
+                    slot.ready.push_back(Ok(buf));
+                }
+                errno => {
+                    slot.eof = true;
+                    slot.ready.push_back(Err(io::Error::from_raw_os_error(-errno)));
+                }
+            }
+        }
+    }
+}
+
+impl Drop for ReadAhead {
+    fn drop(&mut self) {
+        // The kernel may still write into buffers that are in flight
+        while !self.in_flight.is_empty() {
+            let submit = std::mem::take(&mut self.queued);
+            if self.ring.submit_and_wait(submit).is_err() {
+                // Without a working ring nothing else can complete; keep the buffers and files alive
+                std::mem::forget(std::mem::take(&mut self.in_flight));
+                std::mem::forget(std::mem::take(&mut self.retired));
+                return;
+            }
+            for (index, _) in self.ring.reap() {
+                self.in_flight.remove(&index);
+                self.retired.remove(&index);
+            }
+        }
+    }
+}
+
+/// The contents of the file [`ReadAhead::next`] handed out.
+pub struct FileReader<'a> {
+    ahead: &'a mut ReadAhead,
+    chunk: Vec<u8>,
+    at: usize,
+}
+
+impl Read for FileReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.at == self.chunk.len() {
+            self.chunk = self.ahead.chunk()?;
+            self.at = 0;
+        }
+        let n = buf.len().min(self.chunk.len() - self.at);
+        buf[..n].copy_from_slice(&self.chunk[self.at..self.at + n]);
+        self.at += n;
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::fs;
+
+    /// The contents of each of `paths` read through `ReadAhead`, or the
+    /// kind of error opening or reading it.
+    fn read_ahead(paths: &[PathBuf], depth: usize) -> Vec<Result<Vec<u8>, io::ErrorKind>> {
+        let mut ahead = ReadAhead::new(paths.to_vec(), depth).expect("Unable to set up io_uring");
+        let mut contents = Vec::new();
+        while let Some((_, file)) = ahead.next() {
+            let mut bytes = Vec::new();
+            contents.push(file.and_then(|mut file| file.read_to_end(&mut bytes)).map(|_| bytes).map_err(|err| err.kind()));
+        }
+        contents
+    }
+
+    fn read_plain(paths: &[PathBuf]) -> Vec<Result<Vec<u8>, io::ErrorKind>> {
+        paths.iter().map(|path| fs::read(path).map_err(|err| err.kind())).collect()
+    }
+
+    #[test]
+    fn reads_match_plain_file_reads() {
+        if let Err(err) = available() {
+            eprintln!("Skipping: io_uring is unavailable here ({})", err);
+            return;
+        }
+        let dir = std::env::temp_dir().join(format!("syslog_processor-uring-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let mut paths = Vec::new();
+        for (name, size) in [("empty", 0), ("small", 100), ("chunk", CHUNK), ("chunks", 3 * CHUNK + 17), ("tiny", 1)] {
+            let path = dir.join(name);
+            fs::write(&path, (0..size).map(|at| (at * 31 % 251) as u8).collect::<Vec<u8>>()).unwrap();
+            paths.push(path);
+        }
+        // A directory opens but can't be read; a missing file doesn't open
+        paths.insert(2, dir.clone());
+        paths.insert(4, dir.join("missing"));
+        for name in ["after-1", "after-2", "after-3"] {
+            let path = dir.join(name);
+            fs::write(&path, format!("{}\n", name).repeat(1000)).unwrap();
+            paths.push(path);
+        }
+
+        let expected = read_plain(&paths);
+        assert!(matches!(expected[2], Err(io::ErrorKind::IsADirectory)));
+        assert!(matches!(expected[4], Err(io::ErrorKind::NotFound)));
+        for depth in [1, 2, 4, 16] {
+            assert_eq!(read_ahead(&paths, depth), expected, "depth {}", depth);
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn files_can_be_passed_over_mid_read() {
+        if available().is_err() {
+            return;
+        }
+        let dir = std::env::temp_dir().join(format!("syslog_processor-uring-skip-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let paths: Vec<PathBuf> = (0..8)
+            .map(|index| {
+                let path = dir.join(index.to_string());
+                fs::write(&path, vec![index as u8; 2 * CHUNK + index]).unwrap();
+                path
+            })
+            .collect();
+
+        // Read only the first byte of every other file
+        let mut ahead = ReadAhead::new(paths.clone(), 3).unwrap();
+        let mut index = 0;
+        while let Some((_, file)) = ahead.next() {
+            let mut file = file.unwrap();
+            let mut bytes = Vec::new();
+            match index % 2 {
+                0 => {
+                    let mut byte = [0];
+                    file.read_exact(&mut byte).unwrap();
+                    bytes.push(byte[0]);
+                }
+                _ => {
+                    file.read_to_end(&mut bytes).unwrap();
+                    assert_eq!(bytes, fs::read(&paths[index]).unwrap());
+                }
+            }
+            assert_eq!(bytes[0], index as u8);
+            index += 1;
+        }
+        assert_eq!(index, paths.len());
+        drop(ahead);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}